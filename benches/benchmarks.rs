@@ -1,15 +1,42 @@
 #[macro_use]
 extern crate criterion;
 extern crate fuzzy_phrase;
-extern crate fst;
 extern crate reqwest;
 extern crate itertools;
 extern crate rand;
 extern crate test_utils;
 extern crate tempfile;
 
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use criterion::Criterion;
 
+// counts every allocation, so that benchmarks can check how many a query makes as well as how
+// long it takes
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// How many allocations have been made so far.
+pub fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
 mod prefix;
 mod phrase;
 mod fuzzy;
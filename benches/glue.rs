@@ -2,6 +2,7 @@ use criterion::{Criterion, Fun, Bencher};
 use fuzzy_phrase::glue::*;
 use test_utils::*;
 use std::rc::Rc;
+use std::env;
 use tempfile;
 use rand;
 use rand::Rng;
@@ -18,7 +19,15 @@ pub fn benchmark(c: &mut Criterion) {
     let dir = tempfile::tempdir().unwrap();
     let resp_dir = tempfile::tempdir().unwrap();
 
-    let phrases = get_data("phrase", "us", "en", "latn", true);
+    // set GLUE_BENCH_GENERATED to a phrase count to use a synthetic corpus of that size instead
+    // of downloading one
+    let phrases = match env::var("GLUE_BENCH_GENERATED") {
+        Ok(count) => datagen::generate(&datagen::CorpusConfig {
+            phrase_count: count.parse().expect("GLUE_BENCH_GENERATED should be a phrase count"),
+            ..Default::default()
+        }),
+        Err(..) => get_data("phrase", "us", "en", "latn", true),
+    };
 
     let set: FuzzyPhraseSet = {
         let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
@@ -5,7 +5,7 @@ use std::rc::Rc;
 use std::env;
 use rand::{thread_rng, Rng};
 use criterion::{Criterion, Fun, Bencher};
-use fuzzy_phrase::{PhraseSet, PhraseSetBuilder};
+use fuzzy_phrase::{PhraseSet, PhraseSetBuilder, PhraseMatcher, WordTrie, LoudsTrie};
 use fuzzy_phrase::phrase::query::QueryWord;
 
 fn tokenize(s: &str) -> Vec<String> {
@@ -150,7 +150,9 @@ pub fn benchmark(c: &mut Criterion) {
         word_to_id: BTreeMap<String, u32>,
         sample_full: Vec<Vec<QueryWord>>,
         sample_prefix: Vec<Vec<QueryWord>>,
-        phrase_set: PhraseSet
+        phrase_set: PhraseSet,
+        word_trie: WordTrie,
+        louds_trie: LoudsTrie
     };
     let data_basename = match env::var("PHRASE_BENCH") {
         Ok(f) => {
@@ -166,8 +168,17 @@ pub fn benchmark(c: &mut Criterion) {
     let sample_loc = format!("{}_sample.txt", data_basename);
     let (sample_full, sample_prefix) = load_sample(&sample_loc, &word_to_id);
 
+    // the experimental backends, built over the same phrases, to compare against the 3-byte
+    // encoding of the phrase set
+    let word_trie = WordTrie::from_phrase_set(&phrase_set).unwrap();
+    let louds_trie = LoudsTrie::from_phrase_set(&phrase_set).unwrap();
+    println!(
+        "phrase set: {} bytes; word trie: {} bytes, {} nodes; LOUDS trie: {} bytes",
+        phrase_set.as_fst().as_bytes().len(), word_trie.to_bytes().len(), word_trie.node_count(), louds_trie.to_bytes().len()
+    );
+
     // move the prebuilt data into a reference-counted struct
-    let shared_data = Rc::new(BenchData { word_to_id, sample_full, sample_prefix, phrase_set });
+    let shared_data = Rc::new(BenchData { word_to_id, sample_full, sample_prefix, phrase_set, word_trie, louds_trie });
 
     // make a vector I'm going to fill with closures to bench-test
     let mut to_bench = Vec::new();
@@ -214,6 +225,72 @@ pub fn benchmark(c: &mut Criterion) {
         });
     }));
 
+    // the recursive matcher shares one path buffer across branches, so all it should allocate
+    // is the list of results and each result's phrase; count to make sure
+    let (mut queries, mut results, mut allocations) = (0, 0, 0);
+    for phrase in shared_data.sample_full.iter() {
+        let query_phrase: Vec<Vec<QueryWord>> = phrase.iter().map(|word| vec![*word]).collect();
+        let before = ::allocations();
+        let result = shared_data.phrase_set.match_combinations_as_windows(&query_phrase, 0, false).unwrap();
+        allocations += ::allocations() - before;
+        queries += 1;
+        results += result.len();
+    }
+    println!(
+        "match_combinations_as_windows: {:.2} allocations per query, for {:.2} results per query",
+        allocations as f64 / queries as f64, results as f64 / queries as f64
+    );
+    assert!(allocations <= queries + results, "{} allocations for {} queries with {} results", allocations, queries, results);
+
+    // this one exercises the recursive matcher rather than plain lookups: each query word gets
+    // its own slot, and we look for any phrases contained in the query
+    let data = shared_data.clone();
+    to_bench.push(Fun::new("match_combinations_as_windows", move |b: &mut Bencher, _i| {
+        let slotted: Vec<Vec<Vec<QueryWord>>> = data.sample_full.iter().map(|phrase| {
+            phrase.iter().map(|word| vec![*word]).collect()
+        }).collect();
+        let mut cycle = slotted.iter().cycle();
+
+        b.iter(|| {
+            let query_phrase = cycle.next().unwrap();
+            let _result = data.phrase_set.match_combinations_as_windows(&query_phrase, 0, false).unwrap();
+        });
+    }));
+
+    // the same, and prefix matching, against each backend through `PhraseMatcher`
+    fn matchers(data: &BenchData) -> Vec<(&'static str, &dyn PhraseMatcher)> {
+        vec![("phrase_set", &data.phrase_set), ("word_trie", &data.word_trie), ("louds_trie", &data.louds_trie)]
+    }
+    for (i, (name, _)) in matchers(&shared_data).into_iter().enumerate() {
+        let data = shared_data.clone();
+        to_bench.push(Fun::new(&format!("match_combinations_as_windows_{}", name), move |b: &mut Bencher, _i| {
+            let slotted: Vec<Vec<Vec<QueryWord>>> = data.sample_full.iter().map(|phrase| {
+                phrase.iter().map(|word| vec![*word]).collect()
+            }).collect();
+            let mut cycle = slotted.iter().cycle();
+            let matcher = matchers(&data)[i].1;
+
+            b.iter(|| {
+                let query_phrase = cycle.next().unwrap();
+                let _result = matcher.match_combinations_as_windows(&query_phrase, 0, false).unwrap();
+            });
+        }));
+
+        let data = shared_data.clone();
+        to_bench.push(Fun::new(&format!("match_combinations_as_prefixes_{}", name), move |b: &mut Bencher, _i| {
+            let slotted: Vec<Vec<Vec<QueryWord>>> = data.sample_prefix.iter().map(|phrase| {
+                phrase.iter().map(|word| vec![*word]).collect()
+            }).collect();
+            let mut cycle = slotted.iter().cycle();
+            let matcher = matchers(&data)[i].1;
+
+            b.iter(|| {
+                let query_phrase = cycle.next().unwrap();
+                let _result = matcher.match_combinations_as_prefixes(&query_phrase, 0).unwrap();
+            });
+        }));
+    }
+
     // run the accumulated list of benchmarks
     c.bench_functions("phrase", to_bench, ());
 }
@@ -1,7 +1,7 @@
 use criterion::{Criterion, Fun, Bencher};
 use reqwest;
 use fuzzy_phrase::PrefixSet;
-use fst::raw::Output;
+use fuzzy_phrase::fst_compat::raw::Output;
 use std::rc::Rc;
 use itertools::Itertools;
 
@@ -0,0 +1,265 @@
+//! Command-line access to building, querying, and inspecting fuzzy phrase sets, for operations
+//! and debugging. Built with the `cli` feature:
+//!
+//! ```text
+//! fuzzy-phrase build <corpus> <index-dir> [--word-frequencies] [--jsonl]
+//! fuzzy-phrase query <index-dir> [--max-word-dist N] [--max-phrase-dist N] [--ending non|word|any] [--windows] [phrase]
+//! fuzzy-phrase inspect <index-dir> [--dump]
+//! fuzzy-phrase verify <index-dir> [corpus]
+//! ```
+//!
+//! Corpora are plain text files with one raw phrase per line, or, for `build --jsonl`, JSONL files
+//! with one record (see `CorpusRecord`) per line. `query` reads queries from stdin,
+//! one per line, if one isn't given on the command line. `verify` checks that the index's files
+//! all come from the same build and still match the checksums recorded when it was built, and,
+//! given the corpus, that every phrase in it made it into the index.
+
+extern crate fuzzy_phrase;
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+
+use fuzzy_phrase::glue::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, EndingType};
+
+const USAGE: &str = "usage:
+    fuzzy-phrase build <corpus> <index-dir> [--word-frequencies] [--jsonl]
+    fuzzy-phrase query <index-dir> [--max-word-dist N] [--max-phrase-dist N] [--ending non|word|any] [--windows] [phrase]
+    fuzzy-phrase inspect <index-dir> [--dump]
+    fuzzy-phrase verify <index-dir> [corpus]";
+
+// positional arguments, plus flags (with their values, for the ones that take one)
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    // `flags` are the ones a command accepts on their own, and `flags_with_values` the ones that
+    // take a value; anything else starting with "--" is a usage error
+    fn parse(args: &[String], flags: &[&str], flags_with_values: &[&str]) -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args { positional: Vec::new(), flags: Vec::new() };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg.starts_with("--") {
+                let value = if flags_with_values.contains(&arg.as_str()) {
+                    Some(args.next().ok_or_else(|| format!("{} needs a value", arg))?.to_owned())
+                } else if flags.contains(&arg.as_str()) {
+                    None
+                } else {
+                    return Err(format!("unknown flag {}\n{}", arg, USAGE).into());
+                };
+                parsed.flags.push((arg.to_owned(), value));
+            } else {
+                parsed.positional.push(arg.to_owned());
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn has(&self, flag: &str) -> bool {
+        self.flags.iter().any(|(name, _)| name == flag)
+    }
+
+    fn value(&self, flag: &str) -> Option<&str> {
+        self.flags.iter().find(|(name, _)| name == flag).and_then(|(_, value)| value.as_ref().map(|v| v.as_str()))
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str, Box<dyn Error>> {
+        self.positional.get(index).map(|arg| arg.as_str()).ok_or_else(|| format!("missing {}\n{}", name, USAGE).into())
+    }
+}
+
+fn read_corpus(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut phrases: Vec<String> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            phrases.push(line);
+        }
+    }
+    Ok(phrases)
+}
+
+fn build(args: &Args, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let corpus = args.positional(0, "corpus")?;
+    let mut builder = FuzzyPhraseSetBuilder::new(args.positional(1, "index directory")?)?;
+    builder.set_store_word_frequencies(args.has("--word-frequencies"));
+    let count = if args.has("--jsonl") {
+        builder.insert_jsonl(BufReader::new(File::open(corpus)?))?.len()
+    } else {
+        let phrases = read_corpus(corpus)?;
+        for phrase in &phrases {
+            builder.insert_raw(phrase)?;
+        }
+        phrases.len()
+    };
+    builder.finish()?;
+    writeln!(out, "built {} phrases", count)?;
+    Ok(())
+}
+
+fn query(args: &Args, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let set = FuzzyPhraseSet::from_path(args.positional(0, "index directory")?)?;
+    let max_word_dist: u8 = args.value("--max-word-dist").unwrap_or("1").parse()?;
+    let max_phrase_dist: u8 = args.value("--max-phrase-dist").unwrap_or("1").parse()?;
+    let ending_type = match args.value("--ending").unwrap_or("non") {
+        "non" => EndingType::NonPrefix,
+        "word" => EndingType::WordBoundaryPrefix,
+        "any" => EndingType::AnyPrefix,
+        other => return Err(format!("unknown ending type {:?}", other).into()),
+    };
+    let windows = args.has("--windows");
+
+    let run = |line: &str, out: &mut dyn Write| -> Result<(), Box<dyn Error>> {
+        let tokens = set.normalize(line);
+        if windows {
+            for result in set.fuzzy_match_windows(&tokens, max_word_dist, max_phrase_dist, ending_type)? {
+                writeln!(out, "{}\t{}\t{:?}\t{}..{}\t{}-{}", result.edit_distance, result.phrase.join(" "), result.ending_type,
+                    result.start_position, result.start_position + result.tokens_consumed, result.phrase_id_range.0, result.phrase_id_range.1)?;
+            }
+        } else {
+            for result in set.fuzzy_match(&tokens, max_word_dist, max_phrase_dist, ending_type)? {
+                writeln!(out, "{}\t{}\t{:?}\t{}-{}", result.edit_distance, result.phrase.join(" "), result.ending_type,
+                    result.phrase_id_range.0, result.phrase_id_range.1)?;
+            }
+        }
+        Ok(())
+    };
+
+    if !args.positional[1..].is_empty() {
+        run(&args.positional[1..].join(" "), out)?;
+    } else {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line?;
+            writeln!(out, "> {}", line)?;
+            run(&line, out)?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn inspect(args: &Args, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let set = FuzzyPhraseSet::from_path(args.positional(0, "index directory")?)?;
+    // without phrase IDs, looking phrases up by ID would list nonsense
+    if args.has("--dump") && !set.phrase_set().has_ids() {
+        return Err("--dump needs a phrase graph that stores phrase IDs".into());
+    }
+    writeln!(out, "words\t{}", set.word_count())?;
+    writeln!(out, "phrases\t{}", set.phrase_count())?;
+    writeln!(out, "max edit distance\t{}", set.max_edit_distance())?;
+    for (key, value) in set.build_info() {
+        writeln!(out, "{}\t{}", key, value)?;
+    }
+    if args.has("--dump") {
+        for id in 0..set.phrase_count() {
+            if let Some(phrase) = set.get_by_phrase_id(id as u32)? {
+                writeln!(out, "{}\t{}", id, phrase.join(" "))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn verify(args: &Args, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    // loading checks that all the components come from the same build
+    let directory = args.positional(0, "index directory")?;
+    let set = FuzzyPhraseSet::from_path(directory)?;
+    writeln!(out, "components ok")?;
+    let checked = FuzzyPhraseSet::verify_checksums(directory)?;
+    if checked.is_empty() {
+        writeln!(out, "no checksums recorded")?;
+    } else {
+        writeln!(out, "checksums ok ({} files)", checked.len())?;
+    }
+    if let Some(corpus) = args.positional.get(1) {
+        let report = set.verify_corpus(read_corpus(corpus)?)?;
+        for issue in &report.issues {
+            writeln!(out, "{}\t{}\t{:?}", issue.index, issue.phrase, issue.failure)?;
+        }
+        writeln!(out, "{} of {} phrases found", report.phrases_found, report.phrases_checked)?;
+        if !report.is_ok() {
+            return Err("corpus verification failed".into());
+        }
+    }
+    Ok(())
+}
+
+// run the command named by the first argument, writing its results to `out`
+fn run(args: &[String], out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    match args.first().map(|command| command.as_str()) {
+        Some("build") => build(&Args::parse(&args[1..], &["--word-frequencies", "--jsonl"], &[])?, out),
+        Some("query") => query(&Args::parse(&args[1..], &["--windows"], &["--max-word-dist", "--max-phrase-dist", "--ending"])?, out),
+        Some("inspect") => inspect(&Args::parse(&args[1..], &["--dump"], &[])?, out),
+        Some("verify") => verify(&Args::parse(&args[1..], &[], &[])?, out),
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let stdout = io::stdout();
+    let result = run(&args, &mut stdout.lock());
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+
+    fn run_str(args: &[&str]) -> Result<String, Box<dyn Error>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut out: Vec<u8> = Vec::new();
+        run(&args, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn cli_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let corpus = dir.path().join("corpus.txt");
+        File::create(&corpus).unwrap().write_all(b"100 Main Street\n200 Main Street\n\nMain Avenue\n").unwrap();
+        let corpus = corpus.to_str().unwrap();
+        let index = dir.path().join("index");
+        let index = index.to_str().unwrap();
+
+        assert_eq!(run_str(&["build", corpus, index]).unwrap(), "built 3 phrases\n");
+
+        let found = run_str(&["query", index, "--max-word-dist", "1", "100", "main", "stret"]).unwrap();
+        let lines: Vec<&str> = found.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("1\t100 main street\tNonPrefix\t"), "{:?}", lines[0]);
+        let found = run_str(&["query", index, "--ending", "any", "--windows", "main", "ave"]).unwrap();
+        assert!(found.starts_with("0\tmain ave\tAnyPrefix\t0..2\t"), "{:?}", found);
+
+        let inspected = run_str(&["inspect", index, "--dump"]).unwrap();
+        assert!(inspected.contains("phrases\t3\n"));
+        assert!(inspected.contains("\t200 main street\n"));
+
+        let verified = run_str(&["verify", index, corpus]).unwrap();
+        assert!(verified.starts_with("components ok\n"));
+        assert!(verified.ends_with("3 of 3 phrases found\n"));
+    }
+
+    #[test]
+    fn cli_unknown_flags() {
+        let error = run_str(&["inspect", "index", "--dupm"]).unwrap_err();
+        assert!(error.to_string().starts_with("unknown flag --dupm\nusage:"));
+        // a flag another command takes is still unknown to this one
+        let error = run_str(&["build", "corpus", "index", "--windows"]).unwrap_err();
+        assert!(error.to_string().starts_with("unknown flag --windows\n"));
+        let error = run_str(&["query", "index", "--max-word-dist"]).unwrap_err();
+        assert_eq!(error.to_string(), "--max-word-dist needs a value");
+        assert!(run_str(&["frobnicate"]).unwrap_err().to_string().starts_with("usage:"));
+    }
+}
@@ -0,0 +1,201 @@
+//! Optional zstd framing for on-disk artifacts. The FSTs compress well, so this makes indexes
+//! much cheaper to ship around; compressed files are recognized by the zstd magic number and
+//! decompressed transparently by `from_bytes` and (via a temporary file) by the mmap loaders.
+
+use std::fs;
+use std::io::{self, Write, BufReader, BufWriter};
+#[cfg(feature = "mmap")]
+use std::io::Read;
+use std::path::Path;
+#[cfg(feature = "mmap")]
+use std::process;
+#[cfg(feature = "mmap")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fst_compat::raw::Fst;
+use fst_compat::{self, Error as FstError};
+use zstd;
+
+/// The magic number at the start of every zstd frame, in the order it appears on disk.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compression level used when none is specified.
+pub const DEFAULT_LEVEL: i32 = 19;
+
+/// Check whether a buffer starts with a zstd frame.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.len() >= ZSTD_MAGIC.len() && bytes[..ZSTD_MAGIC.len()] == ZSTD_MAGIC
+}
+
+pub fn compress(bytes: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    zstd::stream::encode_all(bytes, level)
+}
+
+/// Decompress a buffer if it's zstd-framed; otherwise, hand it back as-is.
+pub fn decompress_if_framed(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    if is_compressed(&bytes) {
+        zstd::stream::decode_all(&bytes[..])
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Compress the file at `from` (an FST or any other artifact) into `to`, streaming so the whole
+/// file never has to be held in memory.
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, level: i32) -> io::Result<()> {
+    let reader = BufReader::new(fs::File::open(from)?);
+    let mut writer = BufWriter::new(fs::File::create(to)?);
+    zstd::stream::copy_encode(reader, &mut writer, level)?;
+    writer.flush()
+}
+
+#[cfg(feature = "mmap")]
+static TEMPFILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Open an FST file for mmap-backed access, whether it's compressed or not. Uncompressed files
+/// are mapped directly; compressed ones are streamed into an unlinked temporary file, and that's
+/// mapped instead, so memory use stays the same as for a raw file.
+///
+/// # Safety
+///
+/// Same as `fst_compat::from_path`: the file mustn't be modified while it's mapped.
+#[cfg(feature = "mmap")]
+pub unsafe fn fst_from_path<P: AsRef<Path>>(path: P) -> Result<Fst, FstError> {
+    let mut file = fs::File::open(&path)?;
+    let mut magic = [0u8; 4];
+    let is_framed = match file.read_exact(&mut magic) {
+        Ok(()) => is_compressed(&magic),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e.into()),
+    };
+    if !is_framed {
+        return fst_compat::from_path(path);
+    }
+
+    let tmp_path = ::std::env::temp_dir().join(format!(
+        "fuzzy-phrase-{}-{}.fst", process::id(), TEMPFILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let result = (|| {
+        let mut tmp = fs::OpenOptions::new().read(true).write(true).create_new(true).open(&tmp_path)?;
+        {
+            let mut writer = BufWriter::new(&mut tmp);
+            zstd::stream::copy_decode(BufReader::new(fs::File::open(&path)?), &mut writer)?;
+            writer.flush()?;
+        }
+        Ok(tmp)
+    })();
+    // the mapping keeps the data alive, so the file can go away as soon as it's been mapped
+    let tmp: fs::File = match result {
+        Ok(tmp) => tmp,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(FstError::Io(e));
+        },
+    };
+    let fst = fst_compat::from_file(&tmp);
+    let _ = fs::remove_file(&tmp_path);
+    fst
+}
+
+/// Without the `mmap` feature, load an FST file, compressed or not, into memory.
+///
+/// # Safety
+///
+/// None needed; this is only `unsafe` to match the memory-mapped version.
+#[cfg(not(feature = "mmap"))]
+pub unsafe fn fst_from_path<P: AsRef<Path>>(path: P) -> Result<Fst, FstError> {
+    fst_compat::from_bytes(decompress_if_framed(fs::read(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fst_compat::raw::Builder;
+    extern crate tempfile;
+
+    fn build_fst() -> Vec<u8> {
+        let mut builder = Builder::memory();
+        for word in &["bar", "baz", "foo"] {
+            builder.add(word).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let raw = build_fst();
+        let compressed = compress(&raw, DEFAULT_LEVEL).unwrap();
+        assert!(is_compressed(&compressed));
+        assert!(!is_compressed(&raw));
+        assert_eq!(decompress_if_framed(compressed).unwrap(), raw);
+        assert_eq!(decompress_if_framed(raw.clone()).unwrap(), raw);
+    }
+
+    #[test]
+    fn compress_fst_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw_path = dir.path().join("raw.fst");
+        let compressed_path = dir.path().join("compressed.fst");
+        fs::write(&raw_path, build_fst()).unwrap();
+        compress_file(&raw_path, &compressed_path, DEFAULT_LEVEL).unwrap();
+
+        for path in &[&raw_path, &compressed_path] {
+            let fst = unsafe { fst_from_path(path) }.unwrap();
+            assert_eq!(fst.len(), 3);
+            assert!(fst.contains_key("baz"));
+        }
+    }
+
+    #[test]
+    fn compress_glue_from_path() {
+        use ::glue::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, EndingType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(dir.path()).unwrap();
+        builder.insert_str("100 main street").unwrap();
+        builder.insert_str("300 mlk blvd").unwrap();
+        builder.finish().unwrap();
+
+        for name in &["prefix.fst", "phrase.fst", "fuzzy.fst"] {
+            let path = dir.path().join(name);
+            let compressed_path = dir.path().join("tmp.zst");
+            compress_file(&path, &compressed_path, DEFAULT_LEVEL).unwrap();
+            fs::rename(&compressed_path, &path).unwrap();
+        }
+
+        let set = FuzzyPhraseSet::from_path(dir.path()).unwrap();
+        assert!(set.contains_str("300 mlk blvd", EndingType::NonPrefix).unwrap());
+        assert!(!set.contains_str("300 main street", EndingType::NonPrefix).unwrap());
+    }
+
+    #[test]
+    fn compress_glue_builder() {
+        use ::glue::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, EndingType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(dir.path()).unwrap();
+        builder.set_compression_level(Some(DEFAULT_LEVEL));
+        builder.insert_str("100 main street").unwrap();
+        builder.insert_str("300 mlk blvd").unwrap();
+        builder.finish().unwrap();
+
+        for name in &["prefix.fst", "phrase.fst", "fuzzy.fst"] {
+            assert!(is_compressed(&fs::read(dir.path().join(name)).unwrap()), "{} isn't compressed", name);
+        }
+        assert!(!dir.path().join("fuzzy.zst").exists());
+
+        let set = FuzzyPhraseSet::from_path(dir.path()).unwrap();
+        assert!(set.contains_str("300 mlk blvd", EndingType::NonPrefix).unwrap());
+        assert_eq!(set.fuzzy_match_str("300 mkl blvd", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+
+        // as are variant tables, which are read in rather than mapped
+        let mut builder = FuzzyPhraseSetBuilder::new(dir.path()).unwrap();
+        builder.set_compression_level(Some(DEFAULT_LEVEL));
+        builder.set_small_index_threshold(100);
+        builder.insert_str("300 mlk blvd").unwrap();
+        builder.finish().unwrap();
+        assert!(is_compressed(&fs::read(dir.path().join("fuzzy.tbl")).unwrap()));
+        let set = FuzzyPhraseSet::from_path(dir.path()).unwrap();
+        assert_eq!(set.fuzzy_match_str("300 mkl blvd", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,100 @@
+//! The parts of `fst` the rest of the crate builds on, gathered in one place so that the raw
+//! traversal code (phrase and prefix lookups, fuzzy map searches, the builders) can be compiled
+//! against either the 0.3 series (the default) or, with the `fst04` feature, the 0.4 series.
+//!
+//! The two releases agree on nodes, transitions, outputs, builders and streams, so those are just
+//! re-exported under `raw`. Where they differ is in what an `Fst` owns and how one is opened: 0.3
+//! has its own mmap wrapper and a concrete `Fst` type, while 0.4's `Fst` is generic over its
+//! backing storage. `raw::Fst` is always the concrete type the crate stores, and the constructors
+//! below are the only way the rest of the crate creates one.
+
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::path::Path;
+
+#[cfg(not(feature = "fst04"))]
+pub use fst::{Error, IntoStreamer, Streamer};
+#[cfg(not(feature = "fst04"))]
+pub use fst::automaton::{AlwaysMatch, Automaton};
+
+#[cfg(feature = "fst04")]
+pub use fst_04::{Error, IntoStreamer, Streamer};
+#[cfg(feature = "fst04")]
+pub use fst_04::automaton::{AlwaysMatch, Automaton};
+
+#[cfg(not(feature = "fst04"))]
+pub mod raw {
+    pub use fst::raw::{Builder, CompiledAddr, Fst, Node, OpBuilder, Output, Stream};
+}
+
+#[cfg(feature = "fst04")]
+pub mod raw {
+    pub use fst_04::raw::{Builder, CompiledAddr, Node, OpBuilder, Output, Stream};
+
+    pub type Fst = ::fst_04::raw::Fst<FstData>;
+
+    /// The storage behind an `Fst`: either an owned buffer or a read-only mapping of a file.
+    pub enum FstData {
+        Bytes(Vec<u8>),
+        #[cfg(feature = "mmap")]
+        Mmap(::memmap::Mmap),
+    }
+
+    impl AsRef<[u8]> for FstData {
+        fn as_ref(&self) -> &[u8] {
+            match *self {
+                FstData::Bytes(ref bytes) => bytes,
+                #[cfg(feature = "mmap")]
+                FstData::Mmap(ref mmap) => mmap,
+            }
+        }
+    }
+}
+
+/// Load an FST from an in-memory buffer.
+#[cfg(not(feature = "fst04"))]
+pub fn from_bytes(bytes: Vec<u8>) -> Result<raw::Fst, Error> {
+    raw::Fst::from_bytes(bytes)
+}
+
+#[cfg(feature = "fst04")]
+pub fn from_bytes(bytes: Vec<u8>) -> Result<raw::Fst, Error> {
+    raw::Fst::new(raw::FstData::Bytes(bytes))
+}
+
+/// Map the FST file at `path` into memory.
+///
+/// # Safety
+///
+/// The file mustn't be modified while it's mapped.
+#[cfg(feature = "mmap")]
+pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> Result<raw::Fst, Error> {
+    from_file(&File::open(path)?)
+}
+
+/// Without the `mmap` feature (e.g., for targets like wasm that can't map files), read the FST
+/// file at `path` into memory instead.
+///
+/// # Safety
+///
+/// None needed; this is only `unsafe` to match the memory-mapped version.
+#[cfg(not(feature = "mmap"))]
+pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> Result<raw::Fst, Error> {
+    from_bytes(::std::fs::read(path)?)
+}
+
+/// Map an already-open FST file into memory. The mapping stays valid after `file` is closed.
+///
+/// # Safety
+///
+/// The file mustn't be modified while it's mapped.
+#[cfg(all(feature = "mmap", not(feature = "fst04")))]
+pub unsafe fn from_file(file: &File) -> Result<raw::Fst, Error> {
+    raw::Fst::from_mmap(::fst::raw::MmapReadOnly::open(file)?)
+}
+
+#[cfg(all(feature = "mmap", feature = "fst04"))]
+pub unsafe fn from_file(file: &File) -> Result<raw::Fst, Error> {
+    let mmap = ::memmap::Mmap::map(file)?;
+    raw::Fst::new(raw::FstData::Mmap(mmap))
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Write, Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+use fuzzy::mapped::FileBytes;
+
+// The IDs of the words behind each variant that's shared by more than one word, indexed by the
+// value stored for the variant in the fuzzy map's FST.
+pub enum IdList {
+    // maps from before the flat format, which have to be decoded all at once
+    Eager(Vec<Vec<u32>>),
+    Flat(FlatIdList),
+}
+
+impl IdList {
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> &[u32] {
+        match *self {
+            IdList::Eager(ref lists) => &lists[index],
+            IdList::Flat(ref flat) => flat.get(index),
+        }
+    }
+}
+
+// The flat format is a little-endian u32 count of entries, then count + 1 u32 offsets (entry i
+// is the IDs from offsets[i] up to offsets[i + 1]), then the IDs themselves. Loading it maps the
+// file and checks the offsets; each entry is decoded the first time it's looked up, so services
+// that only ever touch part of the vocabulary only pay for that part.
+pub struct FlatIdList {
+    data: FileBytes,
+    // where the list starts in `data`, after any header
+    start: usize,
+    entries: Vec<OnceLock<Vec<u32>>>,
+}
+
+impl FlatIdList {
+    #[cfg(test)]
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<FlatIdList, IoError> {
+        FlatIdList::new(FileBytes::Bytes(bytes), 0)
+    }
+
+    // Map the file at `path` into memory (see `FileBytes`), with the list starting `start` bytes
+    // in. The file mustn't be modified while it's mapped.
+    pub unsafe fn from_path(path: &Path, start: usize) -> Result<FlatIdList, IoError> {
+        FlatIdList::new(FileBytes::open(path)?, start)
+    }
+
+    fn new(data: FileBytes, start: usize) -> Result<FlatIdList, IoError> {
+        let invalid = || IoError::new(IoErrorKind::InvalidData, "Malformed fuzzy ID list");
+        if data.as_ref().len() < start + 4 {
+            return Err(invalid());
+        }
+        let bytes = &data.as_ref()[start..];
+        let count = LittleEndian::read_u32(&bytes[0..4]) as usize;
+        let ids_start = count.checked_add(2).and_then(|words| words.checked_mul(4)).ok_or_else(invalid)?;
+        if bytes.len() < ids_start {
+            return Err(invalid());
+        }
+        let mut previous: u32 = 0;
+        for i in 0..=count {
+            let offset = LittleEndian::read_u32(&bytes[(4 + 4 * i)..(8 + 4 * i)]);
+            if offset < previous {
+                return Err(invalid());
+            }
+            previous = offset;
+        }
+        if bytes.len() != ids_start + 4 * previous as usize {
+            return Err(invalid());
+        }
+        let entries = (0..count).map(|_| OnceLock::new()).collect();
+        Ok(FlatIdList { data, start, entries })
+    }
+
+    // whatever comes before the list in its file
+    pub fn header(&self) -> &[u8] {
+        &self.data.as_ref()[..self.start]
+    }
+
+    pub fn get(&self, index: usize) -> &[u32] {
+        self.entries[index].get_or_init(|| {
+            let bytes = &self.data.as_ref()[self.start..];
+            let ids_start = 4 * (self.entries.len() + 2);
+            let start = LittleEndian::read_u32(&bytes[(4 + 4 * index)..]) as usize;
+            let end = LittleEndian::read_u32(&bytes[(8 + 4 * index)..]) as usize;
+            let mut ids = vec![0u32; end - start];
+            LittleEndian::read_u32_into(&bytes[(ids_start + 4 * start)..(ids_start + 4 * end)], &mut ids);
+            ids
+        })
+    }
+}
+
+pub fn write_flat<W: Write>(lists: &[Vec<u32>], mut writer: W) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(lists.len() as u32)?;
+    let mut offset: u32 = 0;
+    writer.write_u32::<LittleEndian>(offset)?;
+    for list in lists {
+        offset += list.len() as u32;
+        writer.write_u32::<LittleEndian>(offset)?;
+    }
+    for list in lists {
+        for id in list {
+            writer.write_u32::<LittleEndian>(*id)?;
+        }
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+    use super::*;
+
+    #[test]
+    fn flat_id_list_round_trip() {
+        let lists = vec![vec![1, 2], vec![], vec![70_000, 3, 5]];
+        let mut bytes: Vec<u8> = Vec::new();
+        write_flat(&lists, &mut bytes).unwrap();
+        let flat = FlatIdList::from_bytes(bytes.clone()).unwrap();
+        for (i, list) in lists.iter().enumerate().rev() {
+            assert_eq!(flat.get(i), &list[..]);
+        }
+
+        assert!(FlatIdList::from_bytes(bytes[..(bytes.len() - 1)].to_vec()).is_err());
+
+        // lists in files can be preceded by a header
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fuzzy.ids");
+        ::std::fs::write(&path, [&b"header"[..], &bytes].concat()).unwrap();
+        let flat = unsafe { FlatIdList::from_path(&path, 6).unwrap() };
+        assert_eq!(flat.header(), b"header");
+        assert_eq!(flat.get(2), &lists[2][..]);
+        assert!(unsafe { FlatIdList::from_path(&path, 7) }.is_err());
+        assert!(FlatIdList::from_bytes(vec![255, 255, 255, 255]).is_err());
+        assert!(FlatIdList::from_bytes(Vec::new()).is_err());
+    }
+}
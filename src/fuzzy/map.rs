@@ -1,27 +1,62 @@
 use std::fs;
 use std::error::Error;
 use std::cmp::{min, Ordering};
+use std::fmt;
 use itertools::Itertools;
-use fst::raw;
-use fst::Error as FstError;
-#[cfg(feature = "mmap")]
+use fst_compat::raw;
+use fst_compat::Error as FstError;
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use serde::{Deserialize, Serialize};
-use rmps::{Deserializer, Serializer};
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::io::{BufReader, BufWriter, Error as IoError, ErrorKind as IoErrorKind};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use serde::Deserialize;
+use rmps::Deserializer;
 
 use fuzzy::util::multi_modified_damlev_hint;
+use fuzzy::ids::{IdList, FlatIdList, write_flat};
+use fuzzy::table::{VariantTable, write_table};
+use fuzzy::membership::MembershipFilter;
+use fuzzy::script::{ScriptStrategies, VariantStrategy, strip_marks, cluster_boundaries, cluster_damlev_hint};
+use fuzzy::{VariantBuffer, VariantList};
+use instrument::StageTimer;
+use progress::{BuildPhase, ProgressReporter, ProgressTracker, check_cancelled};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 static MULTI_FLAG: u64 = 1 << 63;
 static MULTI_MASK: u64 = !(1 << 63);
 
+// where the variants live: an FST, or for small maps built to have one, a hash table
+enum VariantIndex {
+    Fst(raw::Fst),
+    Table(VariantTable),
+}
+
 pub struct FuzzyMap {
-    id_list: Vec<Vec<u32>>,
-    fst: raw::Fst
+    id_list: IdList,
+    variants: VariantIndex,
+    filter: Option<MembershipFilter>,
+    strategies: ScriptStrategies,
+    edit_distance: Option<u8>,
+    distance_min_lengths: Vec<u8>,
 }
 
+/// Returned by `FuzzyMap::lookup` for an edit distance greater than the one the map was built
+/// with, since the variants needed to find everything at that distance were never generated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EditDistanceError {
+    pub requested: u8,
+    pub supported: u8,
+}
+
+impl fmt::Display for EditDistanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fuzzy map was built with an edit distance of {}; {} requested", self.supported, self.requested)
+    }
+}
+
+impl Error for EditDistanceError {}
+
 #[derive(Serialize, Deserialize)]
 pub struct SerializableIdList(Vec<Vec<u32>>);
 
@@ -45,16 +80,105 @@ impl PartialOrd for FuzzyMapLookupResult {
 }
 
 impl FuzzyMap {
-    #[cfg(feature = "mmap")]
     pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, FstError> {
         let file_start = path.as_ref();
-        let fst = raw::Fst::from_path(file_start.with_extension("fst"))?;
-        let mf_reader = BufReader::new(fs::File::open(file_start.with_extension("msg"))?);
-        let id_list: SerializableIdList = Deserialize::deserialize(&mut Deserializer::new(mf_reader)).unwrap();
-        Ok(FuzzyMap { id_list: id_list.0, fst: fst })
+        let table_path = file_start.with_extension("tbl");
+        let variants = if table_path.exists() {
+            VariantIndex::Table(VariantTable::from_path(&table_path)?)
+        } else {
+            #[cfg(feature = "compression")]
+            let fst = ::compress::fst_from_path(file_start.with_extension("fst"))?;
+            #[cfg(not(feature = "compression"))]
+            let fst = ::fst_compat::from_path(file_start.with_extension("fst"))?;
+            VariantIndex::Fst(fst)
+        };
+        let build_id = match variants {
+            VariantIndex::Fst(ref fst) => fst.fst_type(),
+            VariantIndex::Table(ref table) => table.build_id(),
+        };
+        let ids_path = file_start.with_extension("ids");
+        let msg_path = file_start.with_extension("msg");
+        let id_list = if ids_path.exists() {
+            let flat = FlatIdList::from_path(&ids_path, 8)?;
+            check_stamp(flat.header(), build_id, "ID list")?;
+            IdList::Flat(flat)
+        } else if msg_path.exists() {
+            // maps built before the flat format have a msgpack-encoded list instead
+            let mf_reader = BufReader::new(fs::File::open(&msg_path)?);
+            let id_list: SerializableIdList = Deserialize::deserialize(&mut Deserializer::new(mf_reader))
+                .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Malformed fuzzy map ID list"))?;
+            IdList::Eager(id_list.0)
+        } else {
+            return Err(IoError::new(IoErrorKind::NotFound, format!(
+                "Fuzzy map ID list does not exist (expected {})", ids_path.display()
+            )).into());
+        };
+        let filter_path = file_start.with_extension("flt");
+        let filter = if filter_path.exists() {
+            Some(MembershipFilter::from_bytes(&read_stamped(&filter_path, build_id, "membership filter")?)?)
+        } else {
+            None
+        };
+        Ok(FuzzyMap {
+            id_list,
+            variants,
+            filter,
+            strategies: ScriptStrategies::default(),
+            edit_distance: None,
+            distance_min_lengths: Vec::new(),
+        })
     }
 
-    fn find_matching_variants(&self, query: &[u8], indices: &[usize], position: usize, edit_distance: usize, node: &raw::Node, so_far: u64, out: &mut Vec<u64>) {
+    /// False if `key` is definitely neither a word in the map nor one of their variants,
+    /// according to the map's membership filter (see `FuzzyMapBuilder::set_membership_filter`);
+    /// always true for maps built without one.
+    #[inline(always)]
+    pub fn may_contain(&self, key: &str) -> bool {
+        match self.filter {
+            Some(ref filter) => filter.may_contain(key),
+            None => true,
+        }
+    }
+
+    /// Use `strategies` to decide how queries in each script are matched. These need to be the
+    /// same as the ones the map was built with (see `FuzzyMapBuilder::set_script_strategies`);
+    /// the map doesn't record them, so until this is called, every script gets
+    /// `VariantStrategy::Deletion`. `FuzzyPhraseSet` keeps them in its index options.
+    pub fn set_script_strategies(&mut self, strategies: ScriptStrategies) {
+        self.strategies = strategies;
+    }
+
+    /// Tell the map the maximum edit distance and per-length limits it was built with (see
+    /// `FuzzyMapBuilder::set_distance_min_lengths`), which its files don't record: `lookup` then
+    /// refuses to look further than it can, and drops matches too far from short words.
+    /// `FuzzyPhraseSet` keeps them in its index options.
+    pub fn set_edit_distance_limits(&mut self, edit_distance: u8, distance_min_lengths: Vec<u8>) {
+        self.edit_distance = Some(edit_distance);
+        self.distance_min_lengths = distance_min_lengths;
+    }
+
+    /// The maximum edit distance the map was built with, which is as far as `lookup` can look;
+    /// `None` if it hasn't been set with `set_edit_distance_limits`.
+    pub fn edit_distance(&self) -> Option<u8> {
+        self.edit_distance
+    }
+
+    /// The shortest words (in characters) the map was built to find at each edit distance,
+    /// starting with 1 (see `FuzzyMapBuilder::set_distance_min_lengths`).
+    pub fn distance_min_lengths(&self) -> &[u8] {
+        &self.distance_min_lengths
+    }
+
+    /// The build identifier this map was stamped with by `FuzzyMapBuilder::new_with_build_id`
+    /// (or 0, if it wasn't).
+    pub fn build_id(&self) -> u64 {
+        match self.variants {
+            VariantIndex::Fst(ref fst) => fst.fst_type(),
+            VariantIndex::Table(ref table) => table.build_id(),
+        }
+    }
+
+    fn find_matching_variants(fst: &raw::Fst, query: &[u8], indices: &[usize], position: usize, edit_distance: usize, node: &raw::Node, so_far: u64, out: &mut Vec<u64>) {
         if (indices.len() - 1 - position) <= edit_distance {
             // we're to the end of our string or within the edit distance
             // so if we're on a final string, emit output
@@ -71,19 +195,19 @@ impl FuzzyMap {
                 if let Some(x) = search_node.find_input(*byte) {
                     let trans = search_node.transition(x);
                     search_output += trans.out.value();
-                    search_node = self.fst.node(trans.addr);
+                    search_node = fst.node(trans.addr);
                 } else {
                     found = false;
                     break;
                 }
             }
             if found {
-                self.find_matching_variants(query, indices, i + 1, edit_distance - (i - position), &search_node, so_far + search_output, out);
+                FuzzyMap::find_matching_variants(fst, query, indices, i + 1, edit_distance - (i - position), &search_node, so_far + search_output, out);
             }
         }
     }
 
-    fn find_matching_variants_ascii(&self, query: &[u8], position: usize, edit_distance: usize, node: &raw::Node, so_far: u64, out: &mut Vec<u64>) {
+    fn find_matching_variants_ascii(fst: &raw::Fst, query: &[u8], position: usize, edit_distance: usize, node: &raw::Node, so_far: u64, out: &mut Vec<u64>) {
         if (query.len() - position) <= edit_distance {
             // we're to the end of our string or within the edit distance
             // so if we're on a final string, emit output
@@ -95,27 +219,130 @@ impl FuzzyMap {
         for i in position..min(position + edit_distance + 1, query.len()) {
             if let Some(x) = node.find_input(query[i]) {
                 let trans = node.transition(x);
-                self.find_matching_variants_ascii(query, i + 1, edit_distance - (i - position), &self.fst.node(trans.addr), so_far + trans.out.value(), out);
+                FuzzyMap::find_matching_variants_ascii(fst, query, i + 1, edit_distance - (i - position), &fst.node(trans.addr), so_far + trans.out.value(), out);
             }
         }
     }
 
+    /// The FST the map's variants are stored in, unless it's small enough to have been written as
+    /// a hashed variant table instead.
+    pub fn as_fst(&self) -> Option<&raw::Fst> {
+        match self.variants {
+            VariantIndex::Fst(ref fst) => Some(fst),
+            VariantIndex::Table(_) => None,
+        }
+    }
+
+    /// True if words like `word` are only ever matched exactly, because they're in a script that
+    /// uses `VariantStrategy::Exact`; looking them up at any edit distance is the same as calling
+    /// `lookup_exact`.
+    #[inline(always)]
+    pub fn is_exact_only(&self, word: &str) -> bool {
+        self.strategies.for_word(word) == VariantStrategy::Exact
+    }
+
+    /// Same as `lookup` with an edit distance of 0, but just probes the map for the query itself
+    /// rather than going through the general matching machinery.
+    pub fn lookup_exact<'a, F>(&self, query: &str, lookup_fn: F) -> Vec<FuzzyMapLookupResult> where F: Fn(u32) -> &'a str {
+        let strategy = self.strategies.for_word(query);
+        if strategy == VariantStrategy::StripMarks {
+            self.lookup_exact_impl(&strip_marks(query), true, lookup_fn)
+        } else {
+            self.lookup_exact_impl(query, false, lookup_fn)
+        }
+    }
+
+    fn lookup_exact_impl<'a, F>(&self, query: &str, strip: bool, lookup_fn: F) -> Vec<FuzzyMapLookupResult> where F: Fn(u32) -> &'a str {
+        if !self.may_contain(query) {
+            return Vec::new();
+        }
+        let uidx = match self.variants {
+            VariantIndex::Fst(ref fst) => fst.get(query.as_bytes()).map(|output| output.value()),
+            VariantIndex::Table(ref table) => table.get(query),
+        };
+        let mut ids: Vec<u32> = match uidx {
+            Some(uidx) if uidx & MULTI_FLAG != 0 => self.id_list.get((uidx & MULTI_MASK) as usize).to_vec(),
+            Some(uidx) => vec![uidx as u32],
+            None => return Vec::new(),
+        };
+        ids.sort();
+        ids.dedup();
+
+        // the key also holds the words it's a deletion variant of, so keep only the query itself
+        // (or, ignoring marks, the words that are the same as it)
+        ids.into_iter()
+            .filter_map(|id| {
+                let word = lookup_fn(id);
+                let matched = if strip { strip_marks(word) == query } else { word == query };
+                if matched {
+                    Some(FuzzyMapLookupResult { word: word.to_owned(), id, edit_distance: 0 })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Find the words within `edit_distance` of `query`, closest first. Fails with an
+    /// `EditDistanceError` if that's further than the map was built to look, rather than quietly
+    /// returning only some of them. Words shorter than the map's length limits allow at a
+    /// distance (see `distance_min_lengths`) aren't found at that distance.
     pub fn lookup<'a, F>(&self, query: &str, edit_distance: u8, lookup_fn: F) -> Result<Vec<FuzzyMapLookupResult>, Box<Error>> where F: Fn(u32) -> &'a str {
+        if let Some(supported) = self.edit_distance {
+            if edit_distance > supported {
+                return Err(Box::new(EditDistanceError { requested: edit_distance, supported }));
+            }
+        }
+
         let mut matches = Vec::<u32>::new();
 
-        let mut variant_ids: Vec<u64> = Vec::new();
-        if query.is_ascii() {
-            self.find_matching_variants_ascii(query.as_bytes(), 0, edit_distance as usize, &self.fst.root(), 0, &mut variant_ids);
+        let strategy = self.strategies.for_word(query);
+        let stripped: String;
+        let query = if strategy == VariantStrategy::StripMarks {
+            stripped = strip_marks(query);
+            &stripped
         } else {
-            let mut query_indices = query.char_indices().map(|(i, _c)| i).collect::<Vec<_>>();
-            query_indices.push(query.len());
-            self.find_matching_variants(query.as_bytes(), &query_indices, 0, edit_distance as usize, &self.fst.root(), 0, &mut variant_ids);
+            query
+        };
+        let edit_distance = if strategy == VariantStrategy::Exact { 0 } else { edit_distance };
+        if edit_distance == 0 {
+            return Ok(self.lookup_exact_impl(query, strategy == VariantStrategy::StripMarks, lookup_fn));
+        }
+
+        let variant_generation = StageTimer::start("variant_generation");
+        let mut variant_ids: Vec<u64> = Vec::new();
+        match self.variants {
+            VariantIndex::Fst(ref fst) => {
+                if strategy == VariantStrategy::Clusters {
+                    let query_indices = cluster_boundaries(query);
+                    FuzzyMap::find_matching_variants(fst, query.as_bytes(), &query_indices, 0, edit_distance as usize, &fst.root(), 0, &mut variant_ids);
+                } else if query.is_ascii() {
+                    FuzzyMap::find_matching_variants_ascii(fst, query.as_bytes(), 0, edit_distance as usize, &fst.root(), 0, &mut variant_ids);
+                } else {
+                    let mut query_indices = query.char_indices().map(|(i, _c)| i).collect::<Vec<_>>();
+                    query_indices.push(query.len());
+                    FuzzyMap::find_matching_variants(fst, query.as_bytes(), &query_indices, 0, edit_distance as usize, &fst.root(), 0, &mut variant_ids);
+                }
+            },
+            VariantIndex::Table(ref table) => {
+                // the same deletion variants the FST walk would find, just generated up front
+                let mut query_variants = VariantBuffer::new();
+                if strategy == VariantStrategy::Clusters {
+                    query_variants.fill_clusters(query, edit_distance);
+                } else {
+                    query_variants.fill(query, edit_distance);
+                }
+                variant_ids.extend(::std::iter::once(query).chain(query_variants.iter())
+                    .filter(|variant| self.may_contain(variant))
+                    .filter_map(|variant| table.get(variant)));
+            },
         }
+        variant_generation.stop();
 
         // check the query itself and the variants
         for uidx in variant_ids {
             if uidx & MULTI_FLAG != 0 {
-                for x in &(self.id_list)[(uidx & MULTI_MASK) as usize] {
+                for x in self.id_list.get((uidx & MULTI_MASK) as usize) {
                     matches.push(*x as u32);
                 }
             } else {
@@ -127,13 +354,21 @@ impl FuzzyMap {
         matches.dedup();
 
         let match_words = matches.iter().map(|id| lookup_fn(*id)).collect::<Vec<_>>();
-        let distances = multi_modified_damlev_hint(query, &match_words, edit_distance as u32);
+        let distances = match strategy {
+            VariantStrategy::StripMarks => {
+                let stripped_words = match_words.iter().map(|word| strip_marks(word)).collect::<Vec<_>>();
+                multi_modified_damlev_hint(query.to_owned(), &stripped_words, edit_distance as u32)
+            },
+            VariantStrategy::Clusters => cluster_damlev_hint(query, &match_words, edit_distance as u32),
+            _ => multi_modified_damlev_hint(query, &match_words, edit_distance as u32),
+        };
 
         let mut out = matches
             .into_iter()
             .enumerate()
             .filter_map(|(i, id)| {
-                if distances[i] <= edit_distance as u32 {
+                let allowed = distance_for_length(edit_distance, &self.distance_min_lengths, match_words[i].chars().count());
+                if distances[i] <= allowed as u32 {
                     Some(FuzzyMapLookupResult { word: match_words[i].to_owned(), id: id as u32, edit_distance: distances[i] as u8 })
                 } else {
                     None
@@ -145,28 +380,143 @@ impl FuzzyMap {
     }
 }
 
+// read one of the files written alongside the variants, which start with the build ID, and
+// check that it's from the same build
+fn read_stamped(path: &Path, build_id: u64, what: &str) -> Result<Vec<u8>, IoError> {
+    let mut bytes = fs::read(path)?;
+    if bytes.len() < 8 {
+        return Err(IoError::new(IoErrorKind::InvalidData, format!("Malformed fuzzy map {}", what)));
+    }
+    check_stamp(&bytes[0..8], build_id, what)?;
+    bytes.drain(0..8);
+    Ok(bytes)
+}
+
+fn check_stamp(stamp: &[u8], build_id: u64, what: &str) -> Result<(), IoError> {
+    let stamped = LittleEndian::read_u64(stamp);
+    if stamped != build_id {
+        return Err(IoError::new(IoErrorKind::InvalidData, format!(
+            "Fuzzy map {} has build ID {}, but the map has build ID {}", what, stamped, build_id
+        )));
+    }
+    Ok(())
+}
+
+// how far words of `length` characters are indexed, given the maximum distance and the shortest
+// words indexed at each distance from 1 up (with no limit past the end of the list)
+pub(crate) fn distance_for_length(edit_distance: u8, distance_min_lengths: &[u8], length: usize) -> u8 {
+    (0..edit_distance)
+        .take_while(|&d| distance_min_lengths.get(d as usize).is_none_or(|&min| length >= min as usize))
+        .count() as u8
+}
+
+enum VariantWriter<'a> {
+    Fst(raw::Builder<BufWriter<File>>),
+    Table(Vec<(&'a str, u64)>),
+}
+
+impl<'a> VariantWriter<'a> {
+    // tables are written all at once at the end
+    fn bytes_written(&self) -> u64 {
+        match *self {
+            VariantWriter::Fst(ref builder) => builder.bytes_written(),
+            VariantWriter::Table(_) => 0,
+        }
+    }
+}
+
 pub struct FuzzyMapBuilder {
     id_builder: Vec<Vec<u32>>,
-    builder: raw::Builder<BufWriter<File>>,
+    build_id: u64,
     file_path: PathBuf,
-    word_variants: Vec<(String, u32)>,
+    word_variants: VariantList,
+    variant_buffer: VariantBuffer,
     edit_distance: u8,
+    distance_min_lengths: Vec<u8>,
+    strategies: ScriptStrategies,
+    small_index_threshold: usize,
+    filter_bits_per_key: u32,
+    words_inserted: u64,
+    progress: Option<ProgressTracker>,
+    cancel: Option<Arc<AtomicBool>>,
 }
 
 impl FuzzyMapBuilder {
     pub fn new<P: AsRef<Path>>(path: P, edit_distance: u8) -> Result<Self, Box<Error>> {
+        FuzzyMapBuilder::new_with_build_id(path, edit_distance, 0)
+    }
+
+    /// Stamp everything the map is written as with `build_id`: the variant FST (in its header's
+    /// type field) or table, and each of the files written next to it, which `FuzzyMap::from_path`
+    /// refuses to load if they disagree. `FuzzyMap::build_id` reads it back, for checking against
+    /// whatever else was built from the same vocabulary.
+    pub fn new_with_build_id<P: AsRef<Path>>(path: P, edit_distance: u8, build_id: u64) -> Result<Self, Box<dyn Error>> {
         let file_start = path.as_ref().to_owned();
-        let fst_wtr = BufWriter::new(fs::File::create(file_start.with_extension("fst"))?);
 
         Ok(FuzzyMapBuilder {
-            builder: raw::Builder::new_type(fst_wtr, 0)?,
             id_builder: Vec::<Vec<u32>>::new(),
+            build_id,
             file_path: file_start,
-            word_variants: Vec::<(String, u32)>::new(),
+            word_variants: VariantList::new(),
+            variant_buffer: VariantBuffer::new(),
             edit_distance: edit_distance,
+            distance_min_lengths: Vec::new(),
+            strategies: ScriptStrategies::default(),
+            small_index_threshold: 0,
+            filter_bits_per_key: 0,
+            words_inserted: 0,
+            progress: None,
+            cancel: None,
         })
     }
 
+    /// Abort `finish` (with an `Interrupted` error, removing any files written) if `cancel` gets
+    /// set while it's running.
+    pub fn set_cancellation_flag(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+
+    /// Use `strategies` to decide how variants are generated for words in each script. By default,
+    /// words in every script get `VariantStrategy::Deletion`.
+    pub fn set_script_strategies(&mut self, strategies: ScriptStrategies) {
+        self.strategies = strategies;
+    }
+
+    /// Only generate variants at each edit distance, from 1 up, for words of at least this many
+    /// characters; shorter ones are indexed only as far as the lengths before it allow. Short
+    /// words in a dense vocabulary have many near neighbours, so indexing them far out costs a lot
+    /// of variants for matches that are mostly noise. Lookups don't return words beyond the
+    /// distance they were indexed at, once the map is given the same limits (see
+    /// `FuzzyMap::set_edit_distance_limits`). Distances past the end of the list have no limit.
+    pub fn set_distance_min_lengths(&mut self, distance_min_lengths: Vec<u8>) {
+        self.distance_min_lengths = distance_min_lengths;
+    }
+
+    /// Write maps of fewer than `max_words` words as hashed variant tables instead of FSTs. Tables
+    /// are faster to query, and for small vocabularies (a city's worth of street names, say) the
+    /// extra space they take doesn't matter; the choice is transparent to `FuzzyMap` users. By
+    /// default (or with 0), FSTs are always written.
+    pub fn set_small_index_threshold(&mut self, max_words: usize) {
+        self.small_index_threshold = max_words;
+    }
+
+    /// Also write a Bloom filter over the map's keys, at `bits_per_key` bits per key (10 makes for
+    /// about 1% false positives), which lets lookups rule out strings that definitely aren't in
+    /// the map without probing it (see `FuzzyMap::may_contain`). Pass 0 to not write one.
+    pub fn set_membership_filter(&mut self, bits_per_key: u32) {
+        self.filter_bits_per_key = bits_per_key;
+    }
+
+    /// Call `reporter` every `interval` words inserted, and every `interval` variants written
+    /// when the map is finished.
+    pub fn set_progress_reporter<R: ProgressReporter + Send + 'static>(&mut self, reporter: R, interval: u64) {
+        self.progress = Some(ProgressTracker::new(reporter, interval));
+    }
+
+    pub(crate) fn set_progress_tracker(&mut self, progress: Option<ProgressTracker>) {
+        self.progress = progress;
+    }
+
     pub fn build_from_iter<'a, T, P: AsRef<Path>>(path: P, words: T, edit_distance: u8) -> Result<(), Box<Error>> where T: Iterator<Item=&'a str> {
         let mut fuzzy_map_builder = FuzzyMapBuilder::new(path, edit_distance)?;
 
@@ -178,32 +528,103 @@ impl FuzzyMapBuilder {
     }
 
     pub fn insert(&mut self, key: &str, id: u32) -> () {
-        self.word_variants.push((key.to_owned(), id));
-        let variants = super::get_variants(&key, self.edit_distance);
-        for j in variants.into_iter() {
-            self.word_variants.push((j, id));
+        self.word_variants.push(key, id);
+        let edit_distance = distance_for_length(self.edit_distance, &self.distance_min_lengths, key.chars().count());
+        match self.strategies.for_word(key) {
+            VariantStrategy::Deletion => self.variant_buffer.fill(key, edit_distance),
+            VariantStrategy::Exact => self.variant_buffer.fill(key, 0),
+            VariantStrategy::StripMarks => {
+                let stripped = strip_marks(key);
+                self.variant_buffer.fill(&stripped, edit_distance);
+                self.variant_buffer.push(&stripped);
+            },
+            VariantStrategy::Clusters => self.variant_buffer.fill_clusters(key, edit_distance),
         }
+        self.word_variants.extend(&self.variant_buffer, id);
+        self.words_inserted += 1;
+        if let Some(ref progress) = self.progress {
+            progress.tick(BuildPhase::FuzzyVariants, self.words_inserted, 0);
+        }
+    }
+
+    pub fn finish(self) -> Result<(), FstError> {
+        let file_path = self.file_path.clone();
+        let cancel = self.cancel.clone();
+        let result = self.write();
+        if result.is_err() && check_cancelled(&cancel).is_err() {
+            // don't leave half-written files lying around
+            let _ = fs::remove_file(file_path.with_extension("fst"));
+            let _ = fs::remove_file(file_path.with_extension("tbl"));
+            let _ = fs::remove_file(file_path.with_extension("ids"));
+            let _ = fs::remove_file(file_path.with_extension("flt"));
+        }
+        result
     }
 
-    pub fn finish(mut self) -> Result<(), FstError> {
+    fn write(mut self) -> Result<(), FstError> {
+        check_cancelled(&self.cancel)?;
         self.word_variants.sort();
 
-        for (key, group) in &(&self.word_variants).iter().dedup().group_by(|t| &t.0) {
-            let opts = group.collect::<Vec<_>>();
+        // small maps get a table instead of an FST; make sure there isn't a stale one of the
+        // other kind lying around, since the table takes precedence when loading
+        let mut variants = if self.words_inserted < self.small_index_threshold as u64 {
+            let _ = fs::remove_file(self.file_path.with_extension("fst"));
+            VariantWriter::Table(Vec::new())
+        } else {
+            let _ = fs::remove_file(self.file_path.with_extension("tbl"));
+            let fst_wtr = BufWriter::new(fs::File::create(self.file_path.with_extension("fst"))?);
+            VariantWriter::Fst(raw::Builder::new_type(fst_wtr, self.build_id)?)
+        };
+
+        let mut filter = if self.filter_bits_per_key > 0 {
+            Some(MembershipFilter::new(self.word_variants.len(), self.filter_bits_per_key))
+        } else {
+            let _ = fs::remove_file(self.file_path.with_extension("flt"));
+            None
+        };
+
+        let mut keys_written: u64 = 0;
+        for (key, group) in &self.word_variants.iter().dedup().group_by(|&(key, _)| key) {
+            let opts = group.map(|(_, id)| id).collect::<Vec<_>>();
             let id = if opts.len() == 1 {
-                opts[0].1 as u64
+                opts[0] as u64
             } else {
-                self.id_builder.push((&opts).iter().map(|t| t.1).collect::<Vec<_>>());
+                self.id_builder.push(opts);
                 (self.id_builder.len() - 1) as u64 | MULTI_FLAG
             };
-            self.builder.insert(key, id)?;
+            match variants {
+                VariantWriter::Fst(ref mut builder) => builder.insert(key, id)?,
+                VariantWriter::Table(ref mut table) => table.push((key, id)),
+            }
+            if let Some(ref mut filter) = filter {
+                filter.insert(key);
+            }
+            keys_written += 1;
+            check_cancelled(&self.cancel)?;
+            if let Some(ref progress) = self.progress {
+                progress.tick(BuildPhase::FuzzyMap, keys_written, variants.bytes_written());
+            }
+        }
+        if let Some(ref progress) = self.progress {
+            progress.report(BuildPhase::FuzzyMap, keys_written, variants.bytes_written());
+        }
+        // the other files all start with the build ID, so they can't be mixed up with another
+        // build's
+        let mut ids_wtr = BufWriter::new(fs::File::create(self.file_path.with_extension("ids"))?);
+        ids_wtr.write_u64::<LittleEndian>(self.build_id)?;
+        write_flat(&self.id_builder, ids_wtr)?;
+        if let Some(filter) = filter {
+            let mut filter_wtr = BufWriter::new(fs::File::create(self.file_path.with_extension("flt"))?);
+            filter_wtr.write_u64::<LittleEndian>(self.build_id)?;
+            filter.write(filter_wtr)?;
+        }
+        match variants {
+            VariantWriter::Fst(builder) => builder.finish(),
+            VariantWriter::Table(table) => {
+                let table_wtr = BufWriter::new(fs::File::create(self.file_path.with_extension("tbl"))?);
+                Ok(write_table(self.build_id, &table, table_wtr)?)
+            },
         }
-        let mf_wtr = BufWriter::new(fs::File::create(self.file_path.with_extension("msg"))?);
-        match SerializableIdList(self.id_builder).serialize(&mut Serializer::new(mf_wtr)) {
-            Err(_e) => return Err(FstError::Io(IoError::new(IoErrorKind::InvalidInput, "File exists and is not a directory"))),
-            Ok(()) => ()
-        };
-        self.builder.finish()
     }
 }
 
@@ -242,14 +663,18 @@ mod tests {
             let file_start = dir.path().join("fuzzy");
             FuzzyMapBuilder::build_from_iter(&file_start, WORDS.iter().cloned(), 1).unwrap();
 
-            unsafe { FuzzyMap::from_path(&file_start).unwrap() }
+            let mut map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+            map.set_edit_distance_limits(1, Vec::new());
+            map
         };
         static ref MAP_D2: FuzzyMap = {
             let dir = tempfile::tempdir().unwrap();
             let file_start = dir.path().join("fuzzy");
             FuzzyMapBuilder::build_from_iter(&file_start, WORDS.iter().cloned(), 2).unwrap();
 
-            unsafe { FuzzyMap::from_path(&file_start).unwrap() }
+            let mut map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+            map.set_edit_distance_limits(2, Vec::new());
+            map
         };
     }
 
@@ -324,6 +749,172 @@ mod tests {
         lazy_static::initialize(&MAP_D2);
     }
 
+    #[test]
+    fn lookup_test_edit_distance_guard() {
+        assert_eq!(MAP_D1.edit_distance(), Some(1));
+        assert_eq!(MAP_D2.edit_distance(), Some(2));
+
+        let error = MAP_D1.lookup("Shleton", 2, get_word).unwrap_err();
+        assert_eq!(error.downcast_ref::<EditDistanceError>(), Some(&EditDistanceError { requested: 2, supported: 1 }));
+        assert_eq!(error.to_string(), "Fuzzy map was built with an edit distance of 1; 2 requested");
+        // looking less far than the map was built for is fine
+        assert_eq!(MAP_D2.lookup("Shleton", 1, get_word).unwrap(), [expect("Shelton", "Shleton")]);
+
+        // maps that haven't been told their distance can't be checked
+        let dir = tempfile::tempdir().unwrap();
+        let file_start = dir.path().join("fuzzy");
+        FuzzyMapBuilder::build_from_iter(&file_start, WORDS.iter().cloned(), 1).unwrap();
+        let map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+        assert_eq!(map.edit_distance(), None);
+        assert!(map.lookup("Shleton", 2, get_word).is_ok());
+    }
+
+    #[test]
+    fn lookup_test_distance_min_lengths() {
+        static LENGTH_WORDS: [&str; 4] = ["cat", "coat", "shelton", "skelter"];
+        let get_length_word = |id: u32| LENGTH_WORDS[id as usize];
+        let dir = tempfile::tempdir().unwrap();
+        let file_start = dir.path().join("fuzzy");
+        let mut builder = FuzzyMapBuilder::new(&file_start, 2).unwrap();
+        builder.set_distance_min_lengths(vec![0, 5]);
+        for (id, word) in LENGTH_WORDS.iter().enumerate() {
+            builder.insert(word, id as u32);
+        }
+        builder.finish().unwrap();
+        let mut map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+        map.set_edit_distance_limits(2, vec![0, 5]);
+        assert_eq!(map.edit_distance(), Some(2));
+        assert_eq!(map.distance_min_lengths(), &[0, 5]);
+
+        let found = |query: &str| map.lookup(query, 2, get_length_word).unwrap()
+            .into_iter().map(|r| (r.word, r.edit_distance)).collect::<Vec<_>>();
+        // short words are only found within one edit, long ones within two
+        assert_eq!(found("cot"), vec![("cat".to_owned(), 1), ("coat".to_owned(), 1)]);
+        assert_eq!(found("ct"), vec![("cat".to_owned(), 1)]);
+        assert_eq!(found("shltn"), vec![("shelton".to_owned(), 2)]);
+
+        assert_eq!(distance_for_length(2, &[0, 5], 4), 1);
+        assert_eq!(distance_for_length(2, &[0, 5], 5), 2);
+        assert_eq!(distance_for_length(1, &[0, 5], 5), 1);
+        assert_eq!(distance_for_length(2, &[], 1), 2);
+        assert_eq!(distance_for_length(2, &[3], 2), 0);
+    }
+
+    #[test]
+    fn lookup_test_script_strategies() {
+        static SCRIPT_WORDS: [&str; 5] = ["東京", "東京都", "كِتَاب", "نمستے", "नमस्ते"];
+        let get_script_word = |id: u32| SCRIPT_WORDS[id as usize];
+        let dir = tempfile::tempdir().unwrap();
+        let file_start = dir.path().join("fuzzy");
+        let build = |strategies: &ScriptStrategies| {
+            let mut builder = FuzzyMapBuilder::new(&file_start, 1).unwrap();
+            builder.set_script_strategies(strategies.clone());
+            for (i, word) in SCRIPT_WORDS.iter().enumerate() {
+                builder.insert(word, i as u32);
+            }
+            builder.finish().unwrap();
+            let mut map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+            map.set_script_strategies(strategies.clone());
+            map
+        };
+        let found = |map: &FuzzyMap, query: &str| map.lookup(query, 1, get_script_word).unwrap()
+            .into_iter().map(|r| (r.word, r.edit_distance)).collect::<Vec<_>>();
+
+        // by default, every script is matched by deletion
+        FuzzyMapBuilder::build_from_iter(&file_start, SCRIPT_WORDS.iter().cloned(), 1).unwrap();
+        let mut map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+        assert_eq!(found(&map, "東都"), vec![("東京".to_owned(), 1), ("東京都".to_owned(), 1)]);
+        assert!(!map.is_exact_only("東京"));
+
+        map = build(&ScriptStrategies::script_aware());
+
+        // CJK words only match exactly
+        assert_eq!(found(&map, "東京"), vec![("東京".to_owned(), 0)]);
+        assert_eq!(found(&map, "東都"), vec![]);
+        assert!(map.is_exact_only("東京") && !map.is_exact_only("كتاب"));
+        assert_eq!(map.lookup_exact("كتاب", get_script_word)[0].word, "كِتَاب");
+        assert_eq!(map.lookup_exact("كتب", get_script_word), vec![]);
+        // Arabic words match regardless of vowel marks
+        assert_eq!(found(&map, "كتاب"), vec![("كِتَاب".to_owned(), 0)]);
+        assert_eq!(found(&map, "كتب"), vec![("كِتَاب".to_owned(), 1)]);
+        // Devanagari words are edited a cluster at a time
+        assert_eq!(found(&map, "नमस"), vec![("नमस्ते".to_owned(), 1)]);
+        assert_eq!(found(&map, "मस्ते"), vec![("नमस्ते".to_owned(), 1)]);
+
+        // strategies can be overridden, as long as it's done the same way when building
+        let mut strategies = ScriptStrategies::script_aware();
+        strategies.set(::glue::unicode_ranges::Script::Han, VariantStrategy::Deletion);
+        map = build(&strategies);
+        assert_eq!(found(&map, "東京"), vec![("東京".to_owned(), 0), ("東京都".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn lookup_test_msgpack_id_list() {
+        use serde::Serialize;
+
+        static WORDS: [&str; 3] = ["cat", "coat", "cot"];
+        let get_word = |id: u32| WORDS[id as usize];
+        let dir = tempfile::tempdir().unwrap();
+        let file_start = dir.path().join("fuzzy");
+        FuzzyMapBuilder::build_from_iter(&file_start, WORDS.iter().cloned(), 1).unwrap();
+        let expected = unsafe { FuzzyMap::from_path(&file_start).unwrap() }.lookup("ct", 1, get_word).unwrap();
+
+        // rewrite the ID list the way maps built before the flat format had it
+        let ids_path = file_start.with_extension("ids");
+        let bytes = fs::read(&ids_path).unwrap();
+        let flat = FlatIdList::from_bytes(bytes[8..].to_vec()).unwrap();
+        let lists = (0..LittleEndian::read_u32(&bytes[8..12])).map(|i| flat.get(i as usize).to_vec()).collect();
+        fs::remove_file(&ids_path).unwrap();
+        let writer = BufWriter::new(fs::File::create(file_start.with_extension("msg")).unwrap());
+        SerializableIdList(lists).serialize(&mut ::rmps::Serializer::new(writer)).unwrap();
+        let map = unsafe { FuzzyMap::from_path(&file_start).unwrap() };
+        assert_eq!(map.lookup("ct", 1, get_word).unwrap(), expected);
+
+        fs::write(file_start.with_extension("msg"), b"nonsense").unwrap();
+        assert!(unsafe { FuzzyMap::from_path(&file_start) }.is_err());
+        fs::remove_file(file_start.with_extension("msg")).unwrap();
+        let error = unsafe { FuzzyMap::from_path(&file_start) }.err().unwrap();
+        assert!(format!("{}", error.source().unwrap_or(&error)).starts_with("Fuzzy map ID list does not exist"));
+    }
+
+    #[test]
+    fn lookup_test_small_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let fst_start = dir.path().join("fuzzy_fst");
+        let table_start = dir.path().join("fuzzy_table");
+        for &(file_start, threshold) in &[(&fst_start, 0), (&table_start, 100_000)] {
+            let mut builder = FuzzyMapBuilder::new(file_start, 2).unwrap();
+            builder.set_small_index_threshold(threshold);
+            for (i, word) in WORDS.iter().enumerate() {
+                builder.insert(word, i as u32);
+            }
+            builder.finish().unwrap();
+        }
+        assert!(fst_start.with_extension("fst").exists() && !fst_start.with_extension("tbl").exists());
+        assert!(table_start.with_extension("tbl").exists() && !table_start.with_extension("fst").exists());
+
+        let fst_map = unsafe { FuzzyMap::from_path(&fst_start).unwrap() };
+        let table_map = unsafe { FuzzyMap::from_path(&table_start).unwrap() };
+        // the two kinds of map should find exactly the same things, at any edit distance
+        for word in WORDS.iter().step_by(7) {
+            let mut chars: Vec<char> = word.chars().collect();
+            let mut queries = vec![word.to_string(), chars[1..].iter().collect::<String>()];
+            if chars.len() > 2 {
+                chars.swap(1, 2);
+                queries.push(chars.iter().collect());
+            }
+            for query in &queries {
+                for edit_distance in 0..3 {
+                    assert_eq!(
+                        table_map.lookup(query, edit_distance, get_word).unwrap(),
+                        fst_map.lookup(query, edit_distance, get_word).unwrap()
+                    );
+                }
+                assert_eq!(table_map.lookup_exact(query, get_word), fst_map.lookup_exact(query, get_word));
+            }
+        }
+    }
+
     #[test]
     fn lookup_test_cases_d_2() {
         let query = "Keedy";
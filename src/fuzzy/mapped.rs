@@ -0,0 +1,47 @@
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// The contents of one of the fuzzy map's files: mapped into memory where possible, and otherwise
+// (without the `mmap` feature, or for zstd-compressed files) read in whole.
+pub enum FileBytes {
+    #[cfg(any(test, not(feature = "mmap"), feature = "compression"))]
+    Bytes(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mmap(::memmap::Mmap),
+}
+
+impl FileBytes {
+    // The file mustn't be modified while it's mapped.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open(path: &Path) -> io::Result<FileBytes> {
+        let mmap = ::memmap::Mmap::map(&File::open(path)?)?;
+        #[cfg(feature = "compression")]
+        {
+            if ::compress::is_compressed(&mmap) {
+                return Ok(FileBytes::Bytes(::compress::decompress_if_framed(mmap.to_vec())?));
+            }
+        }
+        Ok(FileBytes::Mmap(mmap))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    pub unsafe fn open(path: &Path) -> io::Result<FileBytes> {
+        let bytes = ::std::fs::read(path)?;
+        #[cfg(feature = "compression")]
+        let bytes = ::compress::decompress_if_framed(bytes)?;
+        Ok(FileBytes::Bytes(bytes))
+    }
+}
+
+impl AsRef<[u8]> for FileBytes {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            #[cfg(any(test, not(feature = "mmap"), feature = "compression"))]
+            FileBytes::Bytes(ref bytes) => bytes,
+            #[cfg(feature = "mmap")]
+            FileBytes::Mmap(ref mmap) => mmap,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::{self, Write, Error as IoError, ErrorKind as IoErrorKind};
+
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+// A Bloom filter over every key in a fuzzy map (words and their variants), which can say for
+// certain that a string isn't one of them without touching the map itself. Positions come from
+// double hashing a 64-bit FNV-1a hash of the key, so the filter reads the same on every platform.
+//
+// On disk, it's a little-endian u32 number of hash functions, a u64 number of bits, and then the
+// bits, 64 at a time.
+pub struct MembershipFilter {
+    num_hashes: u32,
+    num_bits: u64,
+    bits: Vec<u64>,
+}
+
+#[inline(always)]
+fn hash_pair(key: &[u8]) -> (u64, u64) {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    // derive the second hash with the splitmix64 finalizer; it has to be odd so that it can
+    // step through every bit position
+    let mut second = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    second = (second ^ (second >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    second = (second ^ (second >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    second ^= second >> 31;
+    (hash, second | 1)
+}
+
+impl MembershipFilter {
+    /// An empty filter sized for `num_keys` keys at `bits_per_key` bits each (10 bits per key
+    /// gives about a 1% false positive rate).
+    pub fn new(num_keys: usize, bits_per_key: u32) -> MembershipFilter {
+        let num_bits = ((num_keys as u64) * u64::from(bits_per_key)).max(64);
+        // the optimal number of hash functions is ln(2) times the bits per key
+        let num_hashes = ((f64::from(bits_per_key) * 0.69).round() as u32).clamp(1, 16);
+        MembershipFilter { num_hashes, num_bits, bits: vec![0; num_bits.div_ceil(64) as usize] }
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        let (first, second) = hash_pair(key.as_bytes());
+        for i in 0..u64::from(self.num_hashes) {
+            let bit = first.wrapping_add(i.wrapping_mul(second)) % self.num_bits;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// False if `key` definitely isn't in the filter; true if it probably is.
+    #[inline(always)]
+    pub fn may_contain(&self, key: &str) -> bool {
+        let (first, second) = hash_pair(key.as_bytes());
+        (0..u64::from(self.num_hashes)).all(|i| {
+            let bit = first.wrapping_add(i.wrapping_mul(second)) % self.num_bits;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MembershipFilter, IoError> {
+        let invalid = || IoError::new(IoErrorKind::InvalidData, "Malformed fuzzy membership filter");
+        if bytes.len() < 12 {
+            return Err(invalid());
+        }
+        let num_hashes = LittleEndian::read_u32(&bytes[0..4]);
+        let num_bits = LittleEndian::read_u64(&bytes[4..12]);
+        if num_hashes == 0 || num_bits == 0 || (bytes.len() - 12) as u64 != num_bits.div_ceil(64) * 8 {
+            return Err(invalid());
+        }
+        let mut bits: Vec<u64> = vec![0; (bytes.len() - 12) / 8];
+        LittleEndian::read_u64_into(&bytes[12..], &mut bits);
+        Ok(MembershipFilter { num_hashes, num_bits, bits })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.num_hashes)?;
+        writer.write_u64::<LittleEndian>(self.num_bits)?;
+        for block in &self.bits {
+            writer.write_u64::<LittleEndian>(*block)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn membership_filter_round_trip() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("word{}", i)).collect();
+        let mut filter = MembershipFilter::new(keys.len(), 10);
+        for key in &keys {
+            filter.insert(key);
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        filter.write(&mut bytes).unwrap();
+        let filter = MembershipFilter::from_bytes(&bytes).unwrap();
+
+        assert!(keys.iter().all(|key| filter.may_contain(key)));
+        let false_positives = (0..1000).filter(|i| filter.may_contain(&format!("other{}", i))).count();
+        assert!(false_positives < 50);
+
+        assert!(MembershipFilter::from_bytes(&bytes[..(bytes.len() - 1)]).is_err());
+        assert!(MembershipFilter::from_bytes(&[]).is_err());
+    }
+}
@@ -1,36 +1,157 @@
-use std::collections::HashSet;
+use std::str;
 pub mod map;
+pub mod script;
 mod util;
+mod ids;
+mod mapped;
+mod table;
+mod membership;
 pub use self::map::FuzzyMap;
-pub use self::map::FuzzyMapBuilder;
+pub use self::map::{FuzzyMapBuilder, EditDistanceError};
+pub use self::script::{ScriptStrategies, VariantStrategy};
+pub(crate) use self::util::multi_modified_damlev_hint;
 
-#[inline(always)]
-fn get_variants<'a>(word: &str, edit_distance: u8) -> HashSet<String> {
-    let mut variants: HashSet<String> = HashSet::new();
-    get_variants_recursive(word, 1, edit_distance, &mut variants);
-    variants
+/// The deletion variants of a word (every string made by deleting up to some number of its
+/// characters), generated into a single byte buffer that can be reused from one word to the next,
+/// so that producing them doesn't allocate a string apiece.
+#[derive(Default)]
+pub(crate) struct VariantBuffer {
+    bytes: Vec<u8>,
+    spans: Vec<(usize, usize)>,
 }
 
-fn get_variants_recursive<'a>(word: &str, edit_distance: u8, max_distance: u8, delete_variants: &'a mut HashSet<String>) -> () {
-    let mut iter = word.char_indices().peekable();
+impl VariantBuffer {
+    pub(crate) fn new() -> VariantBuffer {
+        VariantBuffer::default()
+    }
+
+    /// Replace the contents of the buffer with the distinct variants of `word` within
+    /// `edit_distance` deletions (not including `word` itself).
+    pub(crate) fn fill(&mut self, word: &str, edit_distance: u8) {
+        self.clear(word);
+        if edit_distance > 0 {
+            self.fill_recursive(0, word.len(), 1, edit_distance);
+        }
+        self.dedup();
+    }
 
-    while let Some((pos, _char)) = iter.next() {
-        let mut deleted_item = String::with_capacity(word.len());
-        deleted_item.push_str(&word[..pos]);
+    fn fill_recursive(&mut self, start: usize, end: usize, edit_distance: u8, max_distance: u8) {
+        let mut pos = start;
+        while pos < end {
+            // deletions are of whole characters, so skip over any continuation bytes
+            let mut next = pos + 1;
+            while next < end && self.bytes[next] & 0xC0 == 0x80 {
+                next += 1;
+            }
+            let variant_start = self.bytes.len();
+            self.bytes.extend_from_within(start..pos);
+            self.bytes.extend_from_within(next..end);
+            let variant_end = self.bytes.len();
+            self.spans.push((variant_start, variant_end));
 
-        if let Some((next_pos, _)) = iter.peek() {
-            deleted_item.push_str(&word[*next_pos..]);
+            if edit_distance < max_distance {
+                self.fill_recursive(variant_start, variant_end, edit_distance + 1, max_distance);
+            }
+            pos = next;
         }
+    }
+
+    // start over with just `word` in the buffer, for variants to be made from
+    fn clear(&mut self, word: &str) {
+        self.bytes.clear();
+        self.spans.clear();
+        self.bytes.extend_from_slice(word.as_bytes());
+    }
+
+    fn push(&mut self, variant: &str) {
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(variant.as_bytes());
+        self.spans.push((start, self.bytes.len()));
+    }
+
+    fn dedup(&mut self) {
+        let bytes = &self.bytes;
+        self.spans.sort_by(|a, b| bytes[a.0..a.1].cmp(&bytes[b.0..b.1]));
+        self.spans.dedup_by(|a, b| bytes[a.0..a.1] == bytes[b.0..b.1]);
+    }
 
-        if edit_distance < max_distance {
-            get_variants_recursive(&deleted_item, edit_distance + 1, max_distance, delete_variants);
+    fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &str> {
+        self.spans.iter().map(move |&(start, end)| self.span(start, end))
+    }
+
+    #[inline(always)]
+    fn span(&self, start: usize, end: usize) -> &str {
+        // every span is a whole string with whole characters cut out of it, so it's still UTF-8
+        unsafe { str::from_utf8_unchecked(&self.bytes[start..end]) }
+    }
+}
+
+/// Variants of many words, each with the ID of the word it's a variant of, packed into a single
+/// byte buffer as `VariantBuffer`'s are, so that a whole vocabulary's worth can be collected,
+/// sorted, and written out without a string apiece.
+#[derive(Default)]
+pub(crate) struct VariantList {
+    bytes: Vec<u8>,
+    // the start and length of each variant in `bytes`, and its word ID
+    entries: Vec<(usize, u32, u32)>,
+}
+
+impl VariantList {
+    pub(crate) fn new() -> VariantList {
+        VariantList::default()
+    }
+
+    pub(crate) fn push(&mut self, variant: &str, id: u32) {
+        self.entries.push((self.bytes.len(), variant.len() as u32, id));
+        self.bytes.extend_from_slice(variant.as_bytes());
+    }
+
+    /// Add every variant in `variants`, for the word `id`.
+    pub(crate) fn extend(&mut self, variants: &VariantBuffer, id: u32) {
+        self.entries.reserve(variants.len());
+        for variant in variants.iter() {
+            self.push(variant, id);
         }
-        delete_variants.insert(deleted_item);
+    }
+
+    /// Sort by variant, then word ID.
+    pub(crate) fn sort(&mut self) {
+        let bytes = &self.bytes;
+        self.entries.sort_unstable_by(|a, b| {
+            bytes[a.0..(a.0 + a.1 as usize)].cmp(&bytes[b.0..(b.0 + b.1 as usize)]).then(a.2.cmp(&b.2))
+        });
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.entries.iter().map(move |&(start, len, id)| {
+            // only whole strings are pushed
+            (unsafe { str::from_utf8_unchecked(&self.bytes[start..(start + len as usize)]) }, id)
+        })
     }
 }
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use super::*;
+
+    fn get_variants(word: &str, edit_distance: u8) -> HashSet<String> {
+        let mut buffer = VariantBuffer::new();
+        buffer.fill(word, edit_distance);
+        let variants: HashSet<String> = buffer.iter().map(|variant| variant.to_owned()).collect();
+        assert_eq!(variants.len(), buffer.len());
+        variants
+    }
+
     #[test]
     fn get_variants_test_edit_distance_1() {
         let query = "hello";
@@ -61,4 +182,30 @@ mod tests {
         result.insert("llo".to_owned());
         assert_eq!(query_variants, result);
     }
+
+    #[test]
+    fn get_variants_test_multibyte() {
+        assert_eq!(get_variants("東京都", 1), ["京都", "東都", "東京"].iter().map(|s| s.to_string()).collect());
+        assert_eq!(get_variants("é", 2), ["".to_owned()].iter().cloned().collect());
+        // the buffer can be reused
+        let mut buffer = VariantBuffer::new();
+        buffer.fill("hello", 2);
+        buffer.fill("ab", 1);
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn variant_list_sorted() {
+        let mut buffer = VariantBuffer::new();
+        let mut list = VariantList::new();
+        list.push("ab", 2);
+        buffer.fill("ab", 1);
+        list.extend(&buffer, 2);
+        list.push("b", 1);
+        buffer.fill("東京", 1);
+        list.extend(&buffer, 0);
+        list.sort();
+        assert_eq!(list.len(), 6);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![("a", 2), ("ab", 2), ("b", 1), ("b", 2), ("京", 0), ("東", 0)]);
+    }
 }
@@ -0,0 +1,245 @@
+//! Per-script strategies for generating deletion variants and comparing words. Plain
+//! single-character deletion assumes an alphabetic script; for logographic scripts a single
+//! character carries too much meaning to be deleted, in abjads like Arabic the vowel marks are
+//! optional and usually left out, and in the Brahmic scripts of South Asia what reads as a single
+//! letter is often several code points.
+
+use std::collections::HashMap;
+
+use glue::unicode_ranges::{get_script, Script};
+use fuzzy::util::multi_modified_damlev_hint;
+use fuzzy::VariantBuffer;
+
+/// How variants of a word are generated and compared.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VariantStrategy {
+    /// Delete single characters; the default, suited to alphabetic scripts.
+    Deletion,
+    /// Don't generate variants, and only match words exactly. For Chinese, Japanese, Korean, and
+    /// other scripts where deleting a single character leaves an entirely different word.
+    Exact,
+    /// Ignore optional diacritics (e.g., Arabic harakat or Hebrew niqqud) when indexing and
+    /// comparing words, and delete single characters otherwise.
+    StripMarks,
+    /// Delete whole clusters (a base character along with its vowel signs, viramas, and any
+    /// consonants conjoined to it) rather than single characters, and count edit distance in
+    /// clusters as well.
+    Clusters,
+}
+
+/// The strategy to use for each script. Every script uses `Deletion` unless it's given another
+/// strategy with `set`; `script_aware` starts from a strategy suited to each script instead. A
+/// map has to be queried with the same strategies it was built with.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScriptStrategies {
+    overrides: HashMap<Script, VariantStrategy>,
+}
+
+impl ScriptStrategies {
+    pub fn new() -> ScriptStrategies {
+        ScriptStrategies::default()
+    }
+
+    /// `Exact` for the CJK scripts, `StripMarks` for Arabic, Hebrew and Syriac, `Clusters` for
+    /// the Brahmic scripts of South Asia, and `Deletion` for everything else.
+    pub fn script_aware() -> ScriptStrategies {
+        let mut strategies = ScriptStrategies::new();
+        for &script in SCRIPT_AWARE_STRATEGIES.iter() {
+            strategies.set(script.0, script.1);
+        }
+        strategies
+    }
+
+    /// Use `strategy` for words in `script` instead of `Deletion`.
+    pub fn set(&mut self, script: Script, strategy: VariantStrategy) {
+        if strategy == VariantStrategy::Deletion {
+            self.overrides.remove(&script);
+        } else {
+            self.overrides.insert(script, strategy);
+        }
+    }
+
+    pub fn for_script(&self, script: Script) -> VariantStrategy {
+        match self.overrides.get(&script) {
+            Some(strategy) => *strategy,
+            None => VariantStrategy::Deletion,
+        }
+    }
+
+    /// The scripts that don't use `Deletion`, and the strategies they use instead.
+    pub fn iter(&self) -> impl Iterator<Item = (Script, VariantStrategy)> + '_ {
+        self.overrides.iter().map(|(script, strategy)| (*script, *strategy))
+    }
+
+    /// The strategy for a word, based on the script it's detected as being in (see
+    /// `detect_script`). Words without any script-specific characters use `Deletion`.
+    pub fn for_word(&self, word: &str) -> VariantStrategy {
+        match detect_script(word) {
+            Some(script) => self.for_script(script),
+            None => VariantStrategy::Deletion,
+        }
+    }
+}
+
+/// The script of the first character in `word` that belongs to a specific one (digits,
+/// punctuation, combining marks and so on are shared between scripts, so they're skipped).
+pub fn detect_script(word: &str) -> Option<Script> {
+    word.chars()
+        .map(get_script)
+        .find(|script| *script != Script::Common && *script != Script::Inherited && *script != Script::Unknown)
+}
+
+const SCRIPT_AWARE_STRATEGIES: [(Script, VariantStrategy); 18] = [
+    (Script::Han, VariantStrategy::Exact), (Script::Hiragana, VariantStrategy::Exact),
+    (Script::Katakana, VariantStrategy::Exact), (Script::Hangul, VariantStrategy::Exact),
+    (Script::Bopomofo, VariantStrategy::Exact), (Script::Yi, VariantStrategy::Exact),
+    (Script::Arabic, VariantStrategy::StripMarks), (Script::Hebrew, VariantStrategy::StripMarks),
+    (Script::Syriac, VariantStrategy::StripMarks),
+    (Script::Devanagari, VariantStrategy::Clusters), (Script::Bengali, VariantStrategy::Clusters),
+    (Script::Gurmukhi, VariantStrategy::Clusters), (Script::Gujarati, VariantStrategy::Clusters),
+    (Script::Oriya, VariantStrategy::Clusters), (Script::Tamil, VariantStrategy::Clusters),
+    (Script::Telugu, VariantStrategy::Clusters), (Script::Kannada, VariantStrategy::Clusters),
+    (Script::Malayalam, VariantStrategy::Clusters),
+];
+
+// the optional vowel and cantillation marks of Hebrew, Arabic, and Syriac, plus the Arabic tatweel
+// (a purely typographic elongation)
+fn is_optional_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0591}'..='\u{05BD}' | '\u{05BF}' | '\u{05C1}'..='\u{05C2}' | '\u{05C4}'..='\u{05C5}' | '\u{05C7}' |
+        '\u{0610}'..='\u{061A}' | '\u{0640}' | '\u{064B}'..='\u{065F}' | '\u{0670}' |
+        '\u{06D6}'..='\u{06DC}' | '\u{06DF}'..='\u{06E4}' | '\u{06E7}'..='\u{06E8}' | '\u{06EA}'..='\u{06ED}' |
+        '\u{0711}' | '\u{0730}'..='\u{074A}'
+    )
+}
+
+pub(super) fn strip_marks(word: &str) -> String {
+    word.chars().filter(|c| !is_optional_mark(*c)).collect()
+}
+
+// the nine Brahmic scripts encoded after ISCII (Devanagari through Malayalam) share a layout, with
+// each character at the same offset within its script's 128-character block
+fn brahmic_offset(c: char) -> Option<u32> {
+    let c = c as u32;
+    if (0x0900..=0x0D7F).contains(&c) { Some(c & 0x7F) } else { None }
+}
+
+const VIRAMA_OFFSET: u32 = 0x4D;
+
+// signs that attach to the preceding letter: candrabindu, anusvara, and visarga; nukta; dependent
+// vowel signs and virama; length marks; and the zero-width (non-)joiners
+fn is_dependent_sign(c: char) -> bool {
+    if c == '\u{200C}' || c == '\u{200D}' {
+        return true;
+    }
+    matches!(brahmic_offset(c), Some(0x00..=0x03) | Some(0x3A..=0x3C) | Some(0x3E..=0x4D) | Some(0x51..=0x57) | Some(0x62..=0x63))
+}
+
+/// Byte offsets of the boundaries between the clusters in `word`, including 0 and the word's
+/// length. A cluster is a character plus any dependent signs following it, and a consonant
+/// following a virama joins the cluster before it (forming a conjunct).
+pub(super) fn cluster_boundaries(word: &str) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = Vec::with_capacity(word.len() + 1);
+    let mut after_virama = false;
+    for (pos, c) in word.char_indices() {
+        if boundaries.is_empty() || !(after_virama || is_dependent_sign(c)) {
+            boundaries.push(pos);
+        }
+        after_virama = brahmic_offset(c) == Some(VIRAMA_OFFSET);
+    }
+    boundaries.push(word.len());
+    boundaries
+}
+
+impl VariantBuffer {
+    /// Like `fill`, but deleting whole clusters instead of single characters.
+    pub(super) fn fill_clusters(&mut self, word: &str, edit_distance: u8) {
+        self.clear(word);
+        if edit_distance > 0 {
+            self.fill_clusters_recursive(0, word.len(), 1, edit_distance);
+        }
+        self.dedup();
+    }
+
+    fn fill_clusters_recursive(&mut self, start: usize, end: usize, edit_distance: u8, max_distance: u8) {
+        for cluster in cluster_boundaries(self.span(start, end)).windows(2) {
+            let variant_start = self.bytes.len();
+            self.bytes.extend_from_within(start..(start + cluster[0]));
+            self.bytes.extend_from_within((start + cluster[1])..end);
+            let variant_end = self.bytes.len();
+            self.spans.push((variant_start, variant_end));
+
+            if edit_distance < max_distance {
+                self.fill_clusters_recursive(variant_start, variant_end, edit_distance + 1, max_distance);
+            }
+        }
+    }
+}
+
+/// Same as `multi_modified_damlev_hint`, but counting edits in clusters. Each distinct cluster is
+/// swapped for a stand-in character from the private use planes, and the results are compared as
+/// usual.
+pub(super) fn cluster_damlev_hint<T: AsRef<str>>(target: &str, sources: &[T], max_hint: u32) -> Vec<u32> {
+    let mut stand_ins: HashMap<String, char> = HashMap::new();
+    let target = clusters_to_stand_ins(target, &mut stand_ins);
+    let sources = sources.iter()
+        .map(|source| clusters_to_stand_ins(source.as_ref(), &mut stand_ins))
+        .collect::<Vec<_>>();
+    multi_modified_damlev_hint(target, &sources, max_hint)
+}
+
+fn clusters_to_stand_ins(word: &str, stand_ins: &mut HashMap<String, char>) -> String {
+    cluster_boundaries(word).windows(2).map(|cluster| {
+        let next = stand_ins.len() as u32;
+        *stand_ins.entry(word[cluster[0]..cluster[1]].to_owned())
+            .or_insert_with(|| ::std::char::from_u32(0xF0000 + next).unwrap_or('\u{FFFD}'))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_strategies() {
+        let mut strategies = ScriptStrategies::new();
+        assert_eq!(detect_script("100"), None);
+        assert_eq!(detect_script("1-東京"), Some(Script::Han));
+        // everything is deleted from unless told otherwise
+        assert_eq!(strategies.for_word("東京"), VariantStrategy::Deletion);
+        assert_eq!(strategies.iter().count(), 0);
+
+        strategies = ScriptStrategies::script_aware();
+        assert_eq!(strategies.for_word("main"), VariantStrategy::Deletion);
+        assert_eq!(strategies.for_word("100"), VariantStrategy::Deletion);
+        assert_eq!(strategies.for_word("東京"), VariantStrategy::Exact);
+        assert_eq!(strategies.for_word("كتاب"), VariantStrategy::StripMarks);
+        assert_eq!(strategies.for_word("नमस्ते"), VariantStrategy::Clusters);
+
+        strategies.set(Script::Han, VariantStrategy::Deletion);
+        assert_eq!(strategies.for_word("東京"), VariantStrategy::Deletion);
+        assert_eq!(strategies.iter().count(), 17);
+    }
+
+    #[test]
+    fn script_strip_marks() {
+        assert_eq!(strip_marks("كِتَاب"), "كتاب");
+        assert_eq!(strip_marks("كـتـاب"), "كتاب");
+        assert_eq!(strip_marks("שָׁלוֹם"), "שלום");
+        assert_eq!(strip_marks("main"), "main");
+    }
+
+    #[test]
+    fn script_clusters() {
+        // न | म | स्ते (sa + virama + ta + vowel sign e)
+        assert_eq!(cluster_boundaries("नमस्ते"), vec![0, 3, 6, 18]);
+        assert_eq!(cluster_boundaries("main"), vec![0, 1, 2, 3, 4]);
+        assert_eq!(cluster_boundaries(""), vec![0]);
+
+        let mut buffer = VariantBuffer::new();
+        buffer.fill_clusters("नमस्ते", 1);
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), vec!["नम", "नस्ते", "मस्ते"]);
+
+        assert_eq!(cluster_damlev_hint("नमस्ते", &["नमस्ते", "नम", "नमस", "मनस्ते"], 5), vec![0, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::{self, Write, Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+use xxhash_rust::xxh64::xxh64;
+
+use fuzzy::mapped::FileBytes;
+
+// marks a bucket with no variant in it
+const EMPTY: u32 = u32::MAX;
+const HEADER_LEN: usize = 16;
+
+// For small vocabularies, the fuzzy map's variants can go in a hash table instead of an FST: it
+// takes more space per variant, but looking a query up is just a probe for each of its own
+// deletion variants, rather than a walk of the graph. Values are the same as the FST's (a word
+// ID, or an index into the ID list with the multi flag set).
+//
+// On disk, it's a little-endian u64 build ID, a u32 count of buckets (a power of two, more than
+// the number of variants), and a u32 count of variants; then for each bucket, the u32 offset of
+// its variant's entry from the start of the entries (or u32::MAX for none); then the entries, each
+// a u32 length, the variant's bytes, and its u64 value. Variants go in the bucket their xxh64 hash
+// picks, or the next free one after it. The table is mapped and probed in place, like the FST.
+pub struct VariantTable {
+    data: FileBytes,
+    build_id: u64,
+    buckets: usize,
+    entries_start: usize,
+}
+
+impl VariantTable {
+    #[cfg(test)]
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<VariantTable, IoError> {
+        VariantTable::new(FileBytes::Bytes(bytes))
+    }
+
+    // Map the table file at `path` into memory (see `FileBytes`). The file mustn't be modified
+    // while it's mapped.
+    pub unsafe fn from_path(path: &Path) -> Result<VariantTable, IoError> {
+        VariantTable::new(FileBytes::open(path)?)
+    }
+
+    fn new(data: FileBytes) -> Result<VariantTable, IoError> {
+        let invalid = || IoError::new(IoErrorKind::InvalidData, "Malformed fuzzy variant table");
+        let bytes = data.as_ref();
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid());
+        }
+        let build_id = LittleEndian::read_u64(&bytes[0..8]);
+        let buckets = LittleEndian::read_u32(&bytes[8..12]) as usize;
+        let count = LittleEndian::read_u32(&bytes[12..16]) as usize;
+        // there has to be an empty bucket for probes to stop at
+        if !buckets.is_power_of_two() || count >= buckets {
+            return Err(invalid());
+        }
+        let entries_start = HEADER_LEN + 4 * buckets;
+        if bytes.len() < entries_start {
+            return Err(invalid());
+        }
+
+        // check that every entry is where its bucket says, without reading any of them in
+        let mut occupied = 0;
+        for bucket in 0..buckets {
+            let offset = LittleEndian::read_u32(&bytes[(HEADER_LEN + 4 * bucket)..]);
+            if offset == EMPTY {
+                continue;
+            }
+            let entry = entries_start + offset as usize;
+            if bytes.len() < entry + 4 {
+                return Err(invalid());
+            }
+            let len = LittleEndian::read_u32(&bytes[entry..]) as usize;
+            if bytes.len() < entry + 4 + len + 8 {
+                return Err(invalid());
+            }
+            occupied += 1;
+        }
+        if occupied != count {
+            return Err(invalid());
+        }
+        Ok(VariantTable { data, build_id, buckets, entries_start })
+    }
+
+    #[inline(always)]
+    pub fn build_id(&self) -> u64 {
+        self.build_id
+    }
+
+    pub fn get(&self, variant: &str) -> Option<u64> {
+        let bytes = self.data.as_ref();
+        let mask = self.buckets - 1;
+        let mut bucket = xxh64(variant.as_bytes(), 0) as usize & mask;
+        loop {
+            let offset = LittleEndian::read_u32(&bytes[(HEADER_LEN + 4 * bucket)..]);
+            if offset == EMPTY {
+                return None;
+            }
+            let entry = self.entries_start + offset as usize;
+            let len = LittleEndian::read_u32(&bytes[entry..]) as usize;
+            if &bytes[(entry + 4)..(entry + 4 + len)] == variant.as_bytes() {
+                return Some(LittleEndian::read_u64(&bytes[(entry + 4 + len)..]));
+            }
+            bucket = (bucket + 1) & mask;
+        }
+    }
+}
+
+pub fn write_table<K: AsRef<str>, W: Write>(build_id: u64, variants: &[(K, u64)], mut writer: W) -> io::Result<()> {
+    // at most half full, so probes stay short
+    let buckets = (2 * variants.len()).max(1).next_power_of_two();
+    let mut offsets = vec![EMPTY; buckets];
+    let mut offset: usize = 0;
+    for (variant, _) in variants {
+        let variant = variant.as_ref();
+        let mut bucket = xxh64(variant.as_bytes(), 0) as usize & (buckets - 1);
+        while offsets[bucket] != EMPTY {
+            bucket = (bucket + 1) & (buckets - 1);
+        }
+        if offset >= EMPTY as usize {
+            return Err(IoError::new(IoErrorKind::InvalidInput, "Too many variants for a variant table"));
+        }
+        offsets[bucket] = offset as u32;
+        offset += 4 + variant.len() + 8;
+    }
+
+    writer.write_u64::<LittleEndian>(build_id)?;
+    writer.write_u32::<LittleEndian>(buckets as u32)?;
+    writer.write_u32::<LittleEndian>(variants.len() as u32)?;
+    for offset in offsets {
+        writer.write_u32::<LittleEndian>(offset)?;
+    }
+    for (variant, value) in variants {
+        let variant = variant.as_ref();
+        writer.write_u32::<LittleEndian>(variant.len() as u32)?;
+        writer.write_all(variant.as_bytes())?;
+        writer.write_u64::<LittleEndian>(*value)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_table_round_trip() {
+        let variants = vec![("".to_owned(), 3), ("mai".to_owned(), 1 << 63), ("москва".to_owned(), 7)];
+        let mut bytes: Vec<u8> = Vec::new();
+        write_table(42, &variants, &mut bytes).unwrap();
+        let table = VariantTable::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(table.build_id(), 42);
+        for (variant, value) in &variants {
+            assert_eq!(table.get(variant), Some(*value));
+        }
+        assert_eq!(table.get("main"), None);
+
+        assert!(VariantTable::from_bytes(bytes[..(bytes.len() - 1)].to_vec()).is_err());
+        assert!(VariantTable::from_bytes(Vec::new()).is_err());
+
+        let mut bytes: Vec<u8> = Vec::new();
+        write_table::<&str, _>(0, &[], &mut bytes).unwrap();
+        assert_eq!(VariantTable::from_bytes(bytes).unwrap().get(""), None);
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+use ::phrase::Combination;
+use ::phrase::query::QueryWord;
+
+/// The kinds of steps in a token-level edit script.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EditOp {
+    /// The query token is the phrase token (or, for the partial last word of an `AnyPrefix`
+    /// query, the start of it).
+    Match,
+    /// The query token was matched to a different phrase token. This includes word replacements
+    /// (like "st" for "street"), which have an edit distance of zero.
+    Substitute,
+    /// A phrase token with no counterpart in the query.
+    Insert,
+    /// A query token with no counterpart in the phrase (for instance, one dropped by the query
+    /// length limit).
+    Delete,
+}
+
+/// One step of the edit script between a query and a matched phrase. Positions and tokens are
+/// only set for the side(s) of the alignment the step involves.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TokenEdit {
+    pub op: EditOp,
+    pub query_position: Option<usize>,
+    pub query_token: Option<String>,
+    pub phrase_position: Option<usize>,
+    pub phrase_token: Option<String>,
+    /// The edit distance between the two tokens, as computed during matching (0 for anything
+    /// but substitutions).
+    pub edit_distance: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AlignedMatchResult {
+    pub result: FuzzyMatchResult,
+    /// The edit script turning the query into the phrase, in query order, followed by any
+    /// insertions at the end of the phrase.
+    pub edits: Vec<TokenEdit>,
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match`, but also returns the token-level edit script between the query and
+    /// each result, built from the per-word distances found while matching. Prefix results that
+    /// cover a single phrase are aligned against the whole phrase, so the words it has past the
+    /// end of the query show up as insertions; for ones covering several phrases, the rest of the
+    /// phrase isn't known, so there aren't any.
+    pub fn fuzzy_match_aligned<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<AlignedMatchResult>, Box<dyn Error>> {
+        let query = phrase;
+        let (phrase, offset, ending_type) = self.limit_query_length(query, ending_type)?;
+
+        let word_possibilities = match self.query_builder()
+            .max_word_dist(max_word_dist)
+            .ending_type(ending_type)
+            .build(phrase)? {
+            Some(word_possibilities) => word_possibilities,
+            None => return Ok(Vec::new()),
+        };
+
+        let phrase_matches = match ending_type {
+            EndingType::NonPrefix => {
+                self.phrase_set.match_combinations(&word_possibilities, max_phrase_dist)?
+            },
+            EndingType::WordBoundaryPrefix | EndingType::AnyPrefix => {
+                self.phrase_set.match_combinations_as_prefixes(&word_possibilities, max_phrase_dist)?
+            }
+        };
+
+        let mut results: Vec<AlignedMatchResult> = Vec::with_capacity(phrase_matches.len());
+        for combination in &phrase_matches {
+            let result = self.combination_to_result(phrase, combination, ending_type);
+            let edits = self.align_combination(query, offset, combination, &result)?;
+            results.push(AlignedMatchResult { result, edits });
+        }
+        Ok(results)
+    }
+
+    fn align_combination<T: AsRef<str>>(&self, query: &[T], offset: usize, combination: &Combination, result: &FuzzyMatchResult) -> Result<Vec<TokenEdit>, Box<dyn Error>> {
+        let delete = |position: usize| TokenEdit {
+            op: EditOp::Delete,
+            query_position: Some(position),
+            query_token: Some(query[position].as_ref().to_owned()),
+            phrase_position: None,
+            phrase_token: None,
+            edit_distance: 0,
+        };
+
+        let mut edits: Vec<TokenEdit> = (0..offset).map(&delete).collect();
+        for (i, (qw, phrase_token)) in combination.phrase.iter().zip(result.phrase.iter()).enumerate() {
+            let query_token = query[offset + i].as_ref();
+            let (op, edit_distance) = match *qw {
+                QueryWord::Full { edit_distance, .. } if phrase_token != query_token => (EditOp::Substitute, edit_distance),
+                _ => (EditOp::Match, 0),
+            };
+            edits.push(TokenEdit {
+                op,
+                query_position: Some(offset + i),
+                query_token: Some(query_token.to_owned()),
+                phrase_position: Some(i),
+                phrase_token: Some(phrase_token.to_owned()),
+                edit_distance,
+            });
+        }
+        edits.extend(((offset + combination.phrase.len())..query.len()).map(&delete));
+
+        // the rest of the phrase, if there's only one phrase it could be
+        let (first_id, last_id) = result.phrase_id_range;
+        if result.ending_type != EndingType::NonPrefix && first_id == last_id {
+            if let Some(full_phrase) = self.get_by_phrase_id(first_id)? {
+                for (position, phrase_token) in full_phrase.into_iter().enumerate().skip(result.phrase.len()) {
+                    edits.push(TokenEdit {
+                        op: EditOp::Insert,
+                        query_position: None,
+                        query_token: None,
+                        phrase_position: Some(position),
+                        phrase_token: Some(phrase_token),
+                        edit_distance: 0,
+                    });
+                }
+            }
+        }
+        Ok(edits)
+    }
+}
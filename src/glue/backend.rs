@@ -0,0 +1,237 @@
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+
+use ::phrase::{PhraseSet, Combination, CombinationWindow, PhraseId, PhraseIdRange};
+use ::phrase::matcher::PhraseMatcher;
+use ::phrase::louds::LoudsTrie;
+use ::phrase::query::QueryWord;
+use ::phrase::filter::PhraseIdFilter;
+use ::phrase::classes::TokenConstraints;
+use ::phrase::util::PhraseSetError;
+use ::instrument;
+use super::options::IndexOptions;
+use super::{FuzzyPhraseSet, FuzzyMatchResult, FuzzyWindowResult, EndingType};
+use super::dedup;
+
+pub(super) fn load_louds_trie(directory: &Path, options: &IndexOptions, build_id: u64) -> Result<Option<LoudsTrie>, Box<dyn Error>> {
+    if !options.louds_trie {
+        return Ok(None);
+    }
+    let louds_path = directory.join(Path::new("phrase.louds"));
+    if !louds_path.exists() {
+        return Err(Box::new(IoError::new(IoErrorKind::NotFound, "LOUDS trie does not exist")));
+    }
+    let louds_trie = LoudsTrie::from_path(&louds_path)?;
+    if louds_trie.build_id() != build_id {
+        return Err(Box::new(IoError::new(IoErrorKind::InvalidData, format!(
+            "LOUDS trie has build ID {}, but the index metadata has build ID {}", louds_trie.build_id(), build_id
+        ))));
+    }
+    Ok(Some(louds_trie))
+}
+
+// a query as it's matched: limited in length, with its (possibly changed) ending type, and the
+// possibilities for each of its words
+type QuerySlots<'a, T> = (&'a [T], EndingType, Vec<Vec<QueryWord>>);
+
+// The set's own phrase graph as a `PhraseMatcher`, matched the way `fuzzy_match` and
+// `fuzzy_match_windows` match it: pruned by the stored word depths, if there are any, and with a
+// filter or token constraints pushed down into the traversal where they're given.
+pub(super) struct OwnGraph<'a> {
+    set: &'a FuzzyPhraseSet,
+    filter: Option<&'a dyn PhraseIdFilter>,
+    constraints: Option<&'a TokenConstraints>,
+}
+
+impl<'a> OwnGraph<'a> {
+    pub(super) fn new(set: &'a FuzzyPhraseSet, filter: Option<&'a dyn PhraseIdFilter>, constraints: Option<&'a TokenConstraints>) -> OwnGraph<'a> {
+        OwnGraph { set, filter, constraints }
+    }
+}
+
+impl<'a> PhraseMatcher for OwnGraph<'a> {
+    fn phrase_id(&self, phrase: &[QueryWord]) -> Option<PhraseId> {
+        self.set.phrase_set.phrase_id(phrase)
+    }
+
+    fn prefix_range(&self, phrase: &[QueryWord]) -> Option<PhraseIdRange> {
+        PhraseMatcher::prefix_range(&self.set.phrase_set, phrase)
+    }
+
+    fn match_combinations(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        let set = self.set;
+        match (self.constraints, set.word_depths.as_ref()) {
+            (Some(c), _) => set.phrase_set.match_combinations_constrained(word_possibilities, max_phrase_dist, c, &set.word_classes),
+            (None, Some(word_depths)) => set.phrase_set.match_combinations_pruned(word_possibilities, max_phrase_dist, word_depths),
+            (None, None) => set.phrase_set.match_combinations(word_possibilities, max_phrase_dist),
+        }
+    }
+
+    fn match_combinations_as_prefixes(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        let set = self.set;
+        match (self.constraints, set.word_depths.as_ref()) {
+            (Some(c), _) => set.phrase_set.match_combinations_as_prefixes_constrained(word_possibilities, max_phrase_dist, c, &set.word_classes),
+            (None, Some(word_depths)) => set.phrase_set.match_combinations_as_prefixes_pruned(word_possibilities, max_phrase_dist, word_depths),
+            (None, None) => set.phrase_set.match_combinations_as_prefixes(word_possibilities, max_phrase_dist),
+        }
+    }
+
+    fn match_combinations_as_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        self.set.match_windows(word_possibilities, max_phrase_dist, ends_in_prefix, self.filter, self.constraints)
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// The set's own phrase graph, which is the `PhraseMatcher` its queries normally run against.
+    pub fn phrase_set(&self) -> &PhraseSet {
+        &self.phrase_set
+    }
+
+    /// The phrase graph as a `LoudsTrie`, if the index was built with the `louds_trie` option
+    /// (see `IndexOptions`). It's another `PhraseMatcher` for the same phrases, to be queried with
+    /// `fuzzy_match_with` and `fuzzy_match_windows_with`.
+    pub fn louds_trie(&self) -> Option<&LoudsTrie> {
+        self.louds_trie.as_ref()
+    }
+
+    /// Like `fuzzy_match`, but with the phrases matched by `matcher` instead of the set's own
+    /// phrase graph: the query is limited, and its words looked up, exactly as usual, and the
+    /// word slots that produces are handed to `matcher`. See `PhraseMatcher` for what it has to
+    /// agree with the set on. Rarest-first matching and the result cache only apply to the
+    /// set's own phrase graph.
+    pub fn fuzzy_match_with<M: PhraseMatcher + ?Sized, T: AsRef<str>>(&self, matcher: &M, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        self.match_pipeline(matcher, phrase, max_word_dist, max_phrase_dist, ending_type, None)
+    }
+
+    /// Like `fuzzy_match_windows`, but with the phrases matched by `matcher` instead of the
+    /// set's own phrase graph (see `fuzzy_match_with`). Windows are deduplicated if
+    /// `set_dedup_windows` is on.
+    pub fn fuzzy_match_windows_with<M: PhraseMatcher + ?Sized, T: AsRef<str>>(&self, matcher: &M, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyWindowResult>, Box<dyn Error>> {
+        self.windows_pipeline(matcher, phrase, max_word_dist, max_phrase_dist, ending_type)
+    }
+
+    // Limit the query's length, and look its words up to get the possibilities for each slot,
+    // as every match of a whole query starts out: `None` if some word has no possibilities, so
+    // nothing can match. Returns the (possibly shortened) query and ending type along with them.
+    pub(super) fn query_slots<'a, T: AsRef<str>>(&self, phrase: &'a [T], max_word_dist: u8, ending_type: EndingType) -> Result<Option<QuerySlots<'a, T>>, Box<dyn Error>> {
+        let (phrase, _offset, ending_type) = self.limit_query_length(phrase, ending_type)?;
+        let word_lookup = instrument::StageTimer::start("word_lookup");
+        let word_possibilities = self.query_builder()
+            .max_word_dist(max_word_dist)
+            .ending_type(ending_type)
+            .build(phrase)?;
+        word_lookup.stop();
+        Ok(word_possibilities.map(|word_possibilities| (phrase, ending_type, word_possibilities)))
+    }
+
+    // The whole-query pipeline, whatever the phrases are matched by.
+    //
+    // strategy: look up each word in the fuzzy graph, and also look up the last one in the prefix
+    // graph if the ending type allows for partial words (so, is AnyPrefix), and then construct a
+    // vector of vectors representing all the word variants that could reside in each slot in the
+    // phrase, and then have `matcher` recursively enumerate every combination of variants and
+    // look them each up in its phrase graph
+    pub(super) fn match_pipeline<M: PhraseMatcher + ?Sized, T: AsRef<str>>(&self, matcher: &M, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, filter: Option<&dyn PhraseIdFilter>) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        let (phrase, ending_type, word_possibilities) = match self.query_slots(phrase, max_word_dist, ending_type)? {
+            Some(slots) => slots,
+            None => return Ok(Vec::new()),
+        };
+
+        let phrase_traversal = instrument::StageTimer::start("phrase_traversal");
+        let phrase_matches = match ending_type {
+            EndingType::NonPrefix => matcher.match_combinations(&word_possibilities, max_phrase_dist)?,
+            EndingType::WordBoundaryPrefix | EndingType::AnyPrefix => matcher.match_combinations_as_prefixes(&word_possibilities, max_phrase_dist)?,
+        };
+        phrase_traversal.stop();
+
+        let mut results: Vec<FuzzyMatchResult> = Vec::new();
+        for combination in &phrase_matches {
+            if let Some(f) = filter {
+                if !f.allows_range((combination.output_range.0.value() as u32, combination.output_range.1.value() as u32)) {
+                    continue;
+                }
+            }
+            results.push(self.combination_to_result(phrase, combination, ending_type));
+        }
+        Ok(results)
+    }
+
+    // The windowed pipeline, whatever the phrases are matched by.
+    //
+    // this is a little different than the regular fuzzy match in that we're considering
+    // multiple possible substrings we'll start by trying to fuzzy-match all the words, but
+    // some of those will likely fail -- rather than early-returning like in regular fuzzy
+    // match, we'll keep going but those failed words will effectively wall off possible
+    // matching subphrases from eachother, so we'll end up with multiple candidate subphrases
+    // to explore.  (hence the extra nesting -- a list of word sequences, each sequence being a
+    // list of word slots, each slot being a list of fuzzy-match variants)
+    //
+    // The mechanics of this approach:
+    //
+    // We're iterating over the fuzzy matches of each word in the phrase to produce subqueries.
+    // This loop eliminates a lot of potential subqueries by looking at the word possibilities
+    // in each position. In any given position, we might have found zero possibilities. That
+    // means that we can ignore subqueries that include the original query word in this
+    // position. We can also isolate subqueries that include the previous words, and start in
+    // the next position when looking for more subqueries.  For example, if a query's word
+    // possibilities look like this (simplifying with letters instead of words):
+    //
+    //     [ [A, B], [C], [], [F, G], [H] ]
+    //
+    // Intuitively, we know we want to isolate "A C", "B C", "F H", and "G H" but. We'll
+    // also want to look at all possible start positions in those substrings, so "C" and "H" as
+    // well.
+    //
+    // What we want to ignore is whatever query word was in position 2 (since it didn't match
+    // anything in the FuzzyMap).  We also don't want to consider things like "A C F H" because
+    // that's not a continuous sequence of tokens in the query.
+    pub(super) fn windows_pipeline<M: PhraseMatcher + ?Sized, T: AsRef<str>>(&self, matcher: &M, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyWindowResult>, Box<dyn Error>> {
+        if phrase.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // if the query is too long, we'll only look at part of it, so keep track of where that
+        // part starts so that we can report window positions relative to the original query
+        let tokens_total = phrase.len();
+        let (phrase, offset, ending_type) = self.limit_query_length(phrase, ending_type)?;
+
+        let word_lookup = instrument::StageTimer::start("word_lookup");
+        let subqueries = self.window_subqueries(phrase, max_word_dist, ending_type)?;
+        word_lookup.stop();
+
+        // The things we're looking for will lie entirely within one of our identified chunks of
+        // contiguous matched words, but could start on any of said words (they'll end, at latest,
+        // and the end of the chunk), so, iterate over the chunks and then iterate over the
+        // possible start words.
+        //
+        // Continuing with the example above: by iterating over multiple start positions within
+        // each chunk, we'll end up considering "C" and "H" in addition to the combinations that
+        // start in the initial positions ("A C", "B C", "F H", "G H").
+        let mut windows: Vec<(usize, CombinationWindow)> = Vec::new();
+        let phrase_traversal = instrument::StageTimer::start("phrase_traversal");
+        for chunk in subqueries.iter() {
+            let ends_in_prefix = chunk.ending_type != EndingType::NonPrefix;
+            for i in self.window_starts(&chunk.word_possibilities) {
+                let phrase_matches = matcher.match_combinations_as_windows(&chunk.word_possibilities[i..], max_phrase_dist, ends_in_prefix)?;
+                windows.extend(phrase_matches.into_iter().map(|match_sq| (chunk.start_position + i, match_sq)));
+            }
+        }
+        phrase_traversal.stop();
+
+        // the phrase IDs are known now, before any of the results' strings have been put together
+        if self.dedup_windows && self.phrase_set.has_ids() {
+            dedup::dedup_by_phrase_id(&mut windows);
+        }
+
+        Ok(windows.iter().map(|&(start, ref match_sq)| {
+            self.window_to_result(phrase, start, match_sq, offset, tokens_total, ending_type)
+        }).collect())
+    }
+
+    // the positions in a run of word slots where a window could start: the ones where any of the
+    // possibilities begins a phrase (see `can_start_phrase`)
+    pub(super) fn window_starts<'a>(&'a self, word_possibilities: &'a [Vec<QueryWord>]) -> impl Iterator<Item = usize> + 'a {
+        (0..word_possibilities.len()).filter(move |&i| self.can_start_phrase(&word_possibilities[i]))
+    }
+}
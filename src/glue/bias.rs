@@ -0,0 +1,70 @@
+use std::cmp::Ordering;
+use std::error::Error;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+use super::util::distance_score;
+use ::phrase::filter::PhraseIdFilter;
+
+/// A set of phrases to favor in matching (e.g., those near the user's location), given as any
+/// `PhraseIdFilter` (a set or bitmap of phrase IDs, or a closure), and how much to favor them.
+pub struct ContextBias<F: PhraseIdFilter> {
+    pub phrases: F,
+    /// What the scores of favored results are multiplied by.
+    pub boost: f64,
+}
+
+impl<F: PhraseIdFilter> ContextBias<F> {
+    pub fn new(phrases: F, boost: f64) -> ContextBias<F> {
+        ContextBias { phrases, boost }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BiasedMatchResult {
+    pub score: f64,
+    /// Whether the result is in the bias set (for prefix matches, whether any of the phrases it
+    /// covers are).
+    pub biased: bool,
+    pub result: FuzzyMatchResult,
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match`, but returns at most `limit` results, favoring those in `bias`. Results
+    /// in the bias set are kept in preference to others when there are more than `limit` (see
+    /// `PhraseSet::match_combinations_biased`), and are scored higher: results are scored `1 -
+    /// edit_distance / (max_phrase_dist + 1)`, times `bias.boost` for favored ones, and returned
+    /// best first (favored results first among equals).
+    pub fn fuzzy_match_biased<T: AsRef<str>, F: PhraseIdFilter>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, bias: &ContextBias<F>, limit: usize) -> Result<Vec<BiasedMatchResult>, Box<dyn Error>> {
+        let (phrase, _offset, ending_type) = self.limit_query_length(phrase, ending_type)?;
+
+        let word_possibilities = match self.query_builder()
+            .max_word_dist(max_word_dist)
+            .ending_type(ending_type)
+            .build(phrase)? {
+            Some(word_possibilities) => word_possibilities,
+            None => return Ok(Vec::new()),
+        };
+
+        let phrase_matches = match ending_type {
+            EndingType::NonPrefix => {
+                self.phrase_set.match_combinations_biased(&word_possibilities, max_phrase_dist, &bias.phrases, limit)?
+            },
+            EndingType::WordBoundaryPrefix | EndingType::AnyPrefix => {
+                self.phrase_set.match_combinations_as_prefixes_biased(&word_possibilities, max_phrase_dist, &bias.phrases, limit)?
+            }
+        };
+
+        let mut results: Vec<BiasedMatchResult> = phrase_matches.iter().map(|combination| {
+            let result = self.combination_to_result(phrase, combination, ending_type);
+            let biased = bias.phrases.allows_range(result.phrase_id_range);
+            let score = distance_score(result.edit_distance, max_phrase_dist) * if biased { bias.boost } else { 1.0 };
+            BiasedMatchResult { score, biased, result }
+        }).collect();
+        results.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+                .then(b.biased.cmp(&a.biased))
+                .then_with(|| a.result.cmp(&b.result))
+        });
+        Ok(results)
+    }
+}
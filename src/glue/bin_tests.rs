@@ -1,6 +1,6 @@
 extern crate lazy_static;
 
-use fst::raw::Output;
+use fst_compat::raw::Output;
 use super::bins::PrefixBin;
 use super::{FuzzyPhraseSetBuilder, FuzzyPhraseSet};
 
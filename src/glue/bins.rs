@@ -1,5 +1,5 @@
 use itertools::Itertools;
-use fst::raw::{Fst, Node, Output};
+use fst_compat::raw::{Fst, Node, Output};
 
 use ::phrase::util::three_byte_decode;
 
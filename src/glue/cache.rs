@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rustc_hash::FxHashMap;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, FuzzyWindowResult, EndingType};
+use ::instrument;
+
+/// Settings for the cache turned on with `FuzzyPhraseSet::enable_result_cache`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ResultCacheConfig {
+    /// The most queries to keep results for; once it's full, the least recently used are evicted.
+    pub capacity: usize,
+    /// How long results stay valid, if they should expire at all.
+    pub ttl: Option<Duration>,
+}
+
+/// Counters for the result cache, since it was enabled or last cleared.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ResultCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for new ones (expired entries aren't counted).
+    pub evictions: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+enum QueryKind {
+    Match,
+    Windows,
+}
+
+// the query's tokens, plus everything else that affects its results, compared in full so that
+// different queries can never share an entry
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+struct CacheKey {
+    kind: QueryKind,
+    tokens: Vec<String>,
+    max_word_dist: u8,
+    max_phrase_dist: u8,
+    ending_type: EndingType,
+}
+
+impl CacheKey {
+    fn new<T: AsRef<str>>(kind: QueryKind, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> CacheKey {
+        let tokens = phrase.iter().map(|word| word.as_ref().to_owned()).collect();
+        CacheKey { kind, tokens, max_word_dist, max_phrase_dist, ending_type }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CachedResults {
+    Match(Vec<FuzzyMatchResult>),
+    Windows(Vec<FuzzyWindowResult>),
+}
+
+struct CacheEntry {
+    results: CachedResults,
+    inserted: Instant,
+    last_used: u64,
+}
+
+/// A least-recently-used map from queries to their results. Recency is tracked with a counter
+/// that's bumped on every access, and a second map from counter values back to keys gives the
+/// eviction order.
+pub(super) struct ResultCache {
+    config: ResultCacheConfig,
+    entries: FxHashMap<CacheKey, CacheEntry>,
+    recency: BTreeMap<u64, CacheKey>,
+    clock: u64,
+    stats: ResultCacheStats,
+}
+
+impl ResultCache {
+    fn new(config: ResultCacheConfig) -> ResultCache {
+        ResultCache {
+            config,
+            entries: FxHashMap::default(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            stats: ResultCacheStats::default(),
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<CachedResults> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => self.config.ttl.is_some_and(|ttl| entry.inserted.elapsed() > ttl),
+            None => {
+                self.stats.misses += 1;
+                instrument::record_cache_lookup(false);
+                return None;
+            },
+        };
+        if expired {
+            self.remove(key);
+            self.stats.misses += 1;
+            instrument::record_cache_lookup(false);
+            return None;
+        }
+
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(clock, key.clone());
+        entry.last_used = clock;
+        self.stats.hits += 1;
+        instrument::record_cache_lookup(true);
+        Some(entry.results.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, results: CachedResults) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.config.capacity {
+            let oldest = match self.recency.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            if let Some(evicted) = self.recency.remove(&oldest) {
+                self.entries.remove(&evicted);
+                self.stats.evictions += 1;
+            }
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, CacheEntry { results, inserted: Instant::now(), last_used: self.clock });
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.stats = ResultCacheStats::default();
+    }
+
+    fn stats(&self) -> ResultCacheStats {
+        ResultCacheStats { entries: self.entries.len(), ..self.stats }
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Keep the results of recent `fuzzy_match` and `fuzzy_match_windows` queries in memory, and
+    /// answer repeats of them from there. Queries are keyed by their tokens (so
+    /// `fuzzy_match_raw` and `fuzzy_match_str` queries that normalize to the same tokens share
+    /// entries) along with their distance and ending type settings; filtered and constrained
+    /// queries aren't cached. Enabling the cache again replaces it with an empty one.
+    pub fn enable_result_cache(&mut self, config: ResultCacheConfig) {
+        self.result_cache = Some(Mutex::new(ResultCache::new(config)));
+    }
+
+    pub fn disable_result_cache(&mut self) {
+        self.result_cache = None;
+    }
+
+    /// Drop all cached results and reset the counters.
+    pub fn clear_result_cache(&self) {
+        if let Some(ref cache) = self.result_cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.clear();
+            }
+        }
+    }
+
+    /// The cache's counters, if it's enabled.
+    pub fn result_cache_stats(&self) -> Option<ResultCacheStats> {
+        self.result_cache.as_ref()
+            .and_then(|cache| cache.lock().ok())
+            .map(|cache| cache.stats())
+    }
+
+    pub(super) fn cached_fuzzy_match<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        let cache = match self.result_cache {
+            Some(ref cache) => cache,
+            None => return self.fuzzy_match_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, None),
+        };
+        let key = CacheKey::new(QueryKind::Match, phrase, max_word_dist, max_phrase_dist, ending_type);
+        if let Some(CachedResults::Match(results)) = cache.lock().ok().and_then(|mut cache| cache.get(&key)) {
+            return Ok(results);
+        }
+        // the lock isn't held while matching, so concurrent misses on the same query may both
+        // do the work; whichever finishes last is what's kept
+        let results = self.fuzzy_match_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, None)?;
+        if let Ok(mut cache) = cache.lock() {
+            cache.insert(key, CachedResults::Match(results.clone()));
+        }
+        Ok(results)
+    }
+
+    pub(super) fn cached_fuzzy_match_windows<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyWindowResult>, Box<dyn Error>> {
+        let cache = match self.result_cache {
+            Some(ref cache) => cache,
+            None => return self.fuzzy_match_windows_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, None),
+        };
+        let key = CacheKey::new(QueryKind::Windows, phrase, max_word_dist, max_phrase_dist, ending_type);
+        if let Some(CachedResults::Windows(results)) = cache.lock().ok().and_then(|mut cache| cache.get(&key)) {
+            return Ok(results);
+        }
+        let results = self.fuzzy_match_windows_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, None)?;
+        if let Ok(mut cache) = cache.lock() {
+            cache.insert(key, CachedResults::Windows(results.clone()));
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::Deserialize;
+use rmps::Deserializer;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, FuzzyMatchResult, FuzzyWindowResult, EndingType};
+use ::phrase::classes::{TokenClass, TokenClassSet, TokenConstraints, WordClasses};
+
+impl FuzzyPhraseSetBuilder {
+    /// Tag `word` with `class`, so that queries can require it (or not) in particular positions;
+    /// see `FuzzyPhraseSet::fuzzy_match_constrained`. Words can have more than one class. Numbers
+    /// (including masked ones, like "1##") are tagged as `TokenClass::Number` automatically, and
+    /// tags for words that don't end up in any phrase are ignored.
+    pub fn set_word_class(&mut self, word: &str, class: TokenClass) {
+        self.word_classes.entry(word.to_owned()).or_default().insert(class);
+    }
+}
+
+// digits, plus the '#'s that stand in for masked digits
+fn is_number(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_digit() || c == '#')
+}
+
+/// Read the tags written by the builder, if any, and tag numbers.
+pub(super) fn load_word_classes(directory: &Path, word_list: &[String]) -> Result<WordClasses, Box<dyn Error>> {
+    let mut word_classes = WordClasses::new();
+    let word_class_path = directory.join(Path::new("word_classes.msg"));
+    if word_class_path.exists() {
+        let word_class_reader = BufReader::new(fs::File::open(&word_class_path)?);
+        let tags: Vec<(u32, u8)> = Deserialize::deserialize(&mut Deserializer::new(word_class_reader))?;
+        for (id, bits) in tags {
+            for class in &[TokenClass::Number, TokenClass::Direction, TokenClass::StreetType, TokenClass::Name] {
+                if TokenClassSet::from_bits(bits).contains(*class) {
+                    word_classes.insert(id, *class);
+                }
+            }
+        }
+    }
+    for (id, word) in word_list.iter().enumerate() {
+        if is_number(word) {
+            word_classes.insert(id as u32, TokenClass::Number);
+        }
+    }
+    Ok(word_classes)
+}
+
+impl FuzzyPhraseSet {
+    /// The classes of this set's words (see `FuzzyPhraseSetBuilder::set_word_class`), by word ID.
+    pub fn word_classes(&self) -> &WordClasses {
+        &self.word_classes
+    }
+
+    /// Like `fuzzy_match`, but only matches phrases whose words satisfy `constraints` in their
+    /// respective positions of the query. A partial word at the end of an `AnyPrefix` query only
+    /// satisfies constraints that explicitly allow prefixes.
+    pub fn fuzzy_match_constrained<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, constraints: &TokenConstraints) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        self.fuzzy_match_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, Some(constraints))
+    }
+
+    /// Like `fuzzy_match_windows`, but only returns windows whose words satisfy `constraints`,
+    /// with positions counted from the start of each window (so `TokenPosition::Last` refers to
+    /// the last token of the window).
+    pub fn fuzzy_match_windows_constrained<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, constraints: &TokenConstraints) -> Result<Vec<FuzzyWindowResult>, Box<dyn Error>> {
+        self.fuzzy_match_windows_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, Some(constraints))
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+use super::{FuzzyPhraseSet, EndingType};
+use ::phrase::util::PhraseSetError;
+
+/// A phrase that completes a query, found by `FuzzyPhraseSet::completions`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Completion {
+    pub phrase_id: u32,
+    pub phrase: Vec<String>,
+    /// The edit distance of the match the phrase completes.
+    pub edit_distance: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompletionPage {
+    pub completions: Vec<Completion>,
+    /// Where the next page starts, or `None` if this was the last one.
+    pub cursor: Option<CompletionCursor>,
+}
+
+/// Where to pick up enumerating completions from (see `FuzzyPhraseSet::completions_from`). It
+/// records the phrase ID ranges still to be enumerated, so resuming doesn't run the query again,
+/// along with the build ID of the index it came from, so it can't be used with a different one.
+/// It can be round-tripped through `to_bytes` and `from_bytes` to hand to an API client.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompletionCursor {
+    build_id: u64,
+    // disjoint ranges of phrase IDs, with the edit distance of the match each came from, in the
+    // order they're to be enumerated
+    ranges: VecDeque<(u32, u32, u8)>,
+}
+
+impl CompletionCursor {
+    // On the wire, a little-endian u64 build ID, then a u32 count of ranges, then each range's
+    // u32 start and end (inclusive) and its u8 edit distance.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(12 + 9 * self.ranges.len());
+        bytes.write_u64::<LittleEndian>(self.build_id).unwrap();
+        bytes.write_u32::<LittleEndian>(self.ranges.len() as u32).unwrap();
+        for &(start, end, edit_distance) in &self.ranges {
+            bytes.write_u32::<LittleEndian>(start).unwrap();
+            bytes.write_u32::<LittleEndian>(end).unwrap();
+            bytes.push(edit_distance);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<CompletionCursor, IoError> {
+        let invalid = || IoError::new(IoErrorKind::InvalidData, "Malformed completion cursor");
+        if bytes.len() < 12 {
+            return Err(invalid());
+        }
+        let build_id = LittleEndian::read_u64(&bytes[0..8]);
+        let count = LittleEndian::read_u32(&bytes[8..12]) as usize;
+        if bytes.len() - 12 != 9 * count {
+            return Err(invalid());
+        }
+        let ranges = bytes[12..].chunks(9).map(|range| {
+            (LittleEndian::read_u32(&range[0..4]), LittleEndian::read_u32(&range[4..8]), range[8])
+        }).collect::<VecDeque<_>>();
+        if ranges.iter().any(|&(start, end, _)| start > end) {
+            return Err(invalid());
+        }
+        Ok(CompletionCursor { build_id, ranges })
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Enumerate the phrases that complete `phrase` (so, every phrase that starts with one of its
+    /// matches, as `fuzzy_match` would find them), a page of at most `page_size` at a time. They
+    /// come in the order of the matches they complete (best first), and then by phrase ID, and
+    /// each phrase only once, even if it completes more than one match. The returned page has a
+    /// cursor for getting the next one from `completions_from`. `page_size` has to be at least
+    /// one, and the phrase graph has to store phrase IDs (see `PhraseSet::has_ids`), since the
+    /// completions are enumerated by them.
+    pub fn completions<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, page_size: usize) -> Result<CompletionPage, Box<dyn Error>> {
+        self.check_completion_paging(page_size)?;
+        let results = self.fuzzy_match(phrase, max_word_dist, max_phrase_dist, ending_type)?;
+
+        // a phrase prefix's range either contains another's or doesn't overlap it at all, but
+        // either way, only the parts not already covered by a better match are added
+        let mut covered: BTreeMap<u32, u32> = BTreeMap::new();
+        let mut ranges: VecDeque<(u32, u32, u8)> = VecDeque::new();
+        for result in &results {
+            let (start, end) = result.phrase_id_range;
+            for (start, end) in uncovered(&covered, start, end) {
+                covered.insert(start, end);
+                ranges.push_back((start, end, result.edit_distance));
+            }
+        }
+        let cursor = CompletionCursor { build_id: self.phrase_set.build_id(), ranges };
+        self.completions_from(&cursor, page_size)
+    }
+
+    /// Get the page of completions that starts at `cursor`, as returned with the previous page
+    /// by `completions` or this. Fails if the cursor came from a different index, or under the
+    /// same conditions as `completions`.
+    pub fn completions_from(&self, cursor: &CompletionCursor, page_size: usize) -> Result<CompletionPage, Box<dyn Error>> {
+        self.check_completion_paging(page_size)?;
+        if cursor.build_id != self.phrase_set.build_id() {
+            return Err(Box::new(PhraseSetError::new("Completion cursor is from a different index")));
+        }
+        let max_id = self.phrase_set.get_max_id().value() as u32;
+
+        let mut ranges = cursor.ranges.clone();
+        let mut completions: Vec<Completion> = Vec::new();
+        while completions.len() < page_size {
+            let (start, end, edit_distance) = match ranges.pop_front() {
+                Some(range) => range,
+                None => break,
+            };
+            if start > max_id {
+                continue;
+            }
+            let end = end.min(max_id);
+            let take = (page_size - completions.len()).min((end - start) as usize + 1);
+            for phrase_id in start..(start + take as u32) {
+                if let Some(phrase) = self.get_by_phrase_id(phrase_id)? {
+                    completions.push(Completion { phrase_id, phrase, edit_distance });
+                }
+            }
+            if (end - start) as usize >= take {
+                ranges.push_front((start + take as u32, end, edit_distance));
+            }
+        }
+
+        let cursor = if ranges.is_empty() { None } else { Some(CompletionCursor { build_id: cursor.build_id, ranges }) };
+        Ok(CompletionPage { completions, cursor })
+    }
+
+    fn check_completion_paging(&self, page_size: usize) -> Result<(), Box<dyn Error>> {
+        // an empty page would hand back the cursor it started from, forever
+        if page_size == 0 {
+            return Err(Box::new(PhraseSetError::new("Completion pages need a page size of at least 1")));
+        }
+        if !self.phrase_set.has_ids() {
+            return Err(Box::new(PhraseSetError::new("Paging through completions needs a phrase graph that stores phrase IDs")));
+        }
+        Ok(())
+    }
+}
+
+// the parts of `start..=end` that none of the disjoint ranges in `covered` take up
+fn uncovered(covered: &BTreeMap<u32, u32>, start: u32, end: u32) -> Vec<(u32, u32)> {
+    let mut pieces: Vec<(u32, u32)> = Vec::new();
+    let mut next = u64::from(start);
+    // a range that starts before this one might still run into it
+    let first = covered.range(..=start).next_back().map_or(start, |(&first, _)| first);
+    for (&covered_start, &covered_end) in covered.range(first..=end) {
+        if u64::from(covered_end) < next {
+            continue;
+        }
+        if u64::from(covered_start) > next {
+            pieces.push((next as u32, covered_start - 1));
+        }
+        next = u64::from(covered_end) + 1;
+    }
+    if next <= u64::from(end) {
+        pieces.push((next as u32, end));
+    }
+    pieces
+}
@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+use std::char;
+
+use super::FuzzyPhraseSet;
+
+/// How the final token of a prefix query is broken into characters to find the words it could
+/// be the beginning of. It's typed as the query is, so it may not be finished: an input method
+/// may still be composing its last character, or a client may have cut a multi-byte character in
+/// half. The default looks the token up as is.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PrefixSegmentation {
+    /// Look the token up byte for byte.
+    #[default]
+    Bytes,
+    /// Look the token up character by character, in normalized form: conjoining Hangul jamo are
+    /// composed into syllables (as NFC would), and anything at the end that can't be part of a
+    /// finished character (a U+FFFD left by decoding a truncated UTF-8 sequence, or a dangling
+    /// zero-width joiner or non-joiner) is dropped rather than looked up.
+    Chars,
+    /// As `Chars`, but also treat a trailing Hangul consonant or syllable that an input method
+    /// may still be composing as any of the characters it could become: a lone consonant as any
+    /// syllable that begins with it, a syllable without a final consonant as itself with any
+    /// final, and one with a final as itself, with a compound final built on that one, or, if
+    /// the next vowel typed takes the final away, as the syllable without it followed by any
+    /// syllable beginning with it. Compound vowels still being typed aren't anticipated.
+    Composing,
+}
+
+// a final token as it's looked up, and the word ID ranges it could still grow into
+type SegmentedToken<'a> = (Cow<'a, str>, Vec<(u32, u32)>);
+
+const S_BASE: u32 = 0xac00;
+const S_COUNT: u32 = 11172;
+const L_BASE: u32 = 0x1100;
+const L_COUNT: u32 = 19;
+const V_BASE: u32 = 0x1161;
+const V_COUNT: u32 = 21;
+const T_BASE: u32 = 0x11a7;
+const T_COUNT: u32 = 28;
+
+// each simple final consonant that can also lead a syllable, and the lead it becomes
+const FINAL_TO_LEAD: [(u32, u32); 16] = [
+    (1, 0), (2, 1), (4, 2), (7, 3), (8, 5), (16, 6), (17, 7), (19, 9),
+    (20, 10), (21, 11), (22, 12), (23, 14), (24, 15), (25, 16), (26, 17), (27, 18),
+];
+
+// each compound final consonant, the simple final it's built on, and the lead its second half
+// becomes when a vowel follows
+const COMPOUND_FINALS: [(u32, u32, u32); 11] = [
+    (3, 1, 9), (5, 4, 12), (6, 4, 18), (9, 8, 0), (10, 8, 6), (11, 8, 7),
+    (12, 8, 9), (13, 8, 16), (14, 8, 17), (15, 8, 18), (18, 17, 9),
+];
+
+// the compatibility jamo for the consonants that can lead a syllable (U+3131 onwards, with
+// gaps for the ones that can't), as lead indexes
+const COMPATIBILITY_LEADS: [Option<u32>; 30] = [
+    Some(0), Some(1), None, Some(2), None, None, Some(3), Some(4), Some(5), None,
+    None, None, None, None, None, None, Some(6), Some(7), Some(8), None,
+    Some(9), Some(10), Some(11), Some(12), Some(13), Some(14), Some(15), Some(16), Some(17), Some(18),
+];
+
+fn syllable(lead: u32, vowel: u32, tail: u32) -> char {
+    char::from_u32(S_BASE + (lead * V_COUNT + vowel) * T_COUNT + tail).expect("Hangul syllables are valid chars")
+}
+
+// a Hangul syllable's final consonant (0 for none), if it is one
+fn syllable_tail(c: char) -> Option<u32> {
+    let s = (c as u32).checked_sub(S_BASE).filter(|&s| s < S_COUNT)?;
+    Some(s % T_COUNT)
+}
+
+// the lead index of a lone consonant, conjoining or compatibility
+fn lone_lead(c: char) -> Option<u32> {
+    let c = c as u32;
+    if (L_BASE..(L_BASE + L_COUNT)).contains(&c) {
+        Some(c - L_BASE)
+    } else if (0x3131..(0x3131 + COMPATIBILITY_LEADS.len() as u32)).contains(&c) {
+        COMPATIBILITY_LEADS[(c - 0x3131) as usize]
+    } else {
+        None
+    }
+}
+
+fn is_incomplete(c: char) -> bool {
+    c == '\u{fffd}' || c == '\u{200c}' || c == '\u{200d}'
+}
+
+/// Normalize a final query token for prefix lookup, as `PrefixSegmentation::Chars` does:
+/// compose conjoining Hangul jamo into syllables, and drop anything incomplete from the end.
+pub fn normalize_prefix_token(token: &str) -> String {
+    let mut chars: Vec<char> = Vec::with_capacity(token.len());
+    for c in token.chars() {
+        let code = c as u32;
+        let composed = match chars.last().map(|&last| last as u32) {
+            Some(last) if (L_BASE..(L_BASE + L_COUNT)).contains(&last) && (V_BASE..(V_BASE + V_COUNT)).contains(&code) =>
+                Some(syllable(last - L_BASE, code - V_BASE, 0)),
+            Some(last) if syllable_tail(char::from_u32(last).unwrap_or('\0')) == Some(0) && ((T_BASE + 1)..(T_BASE + T_COUNT)).contains(&code) =>
+                char::from_u32(last + code - T_BASE),
+            _ => None,
+        };
+        match composed {
+            Some(composed) => *chars.last_mut().expect("composed with the last char") = composed,
+            None => chars.push(c),
+        }
+    }
+    while chars.last().is_some_and(|&c| is_incomplete(c)) {
+        chars.pop();
+    }
+    chars.into_iter().collect()
+}
+
+// the words a token still being composed could begin, beyond the ones it begins as typed: each
+// is a stable beginning and a range of characters that can follow it
+fn composing_ranges(token: &str) -> Vec<(String, char, char)> {
+    let last = match token.chars().next_back() {
+        Some(last) => last,
+        None => return Vec::new(),
+    };
+    let stable = &token[..(token.len() - last.len_utf8())];
+    let any_syllable = |lead: u32| (syllable(lead, 0, 0), syllable(lead, V_COUNT - 1, T_COUNT - 1));
+
+    let mut ranges: Vec<(String, char, char)> = Vec::new();
+    if let Some(lead) = lone_lead(last) {
+        let (lo, hi) = any_syllable(lead);
+        ranges.push((stable.to_string(), lo, hi));
+    } else if let Some(tail) = syllable_tail(last) {
+        let bare = char::from_u32(last as u32 - tail).expect("Hangul syllables are valid chars");
+        if tail == 0 {
+            ranges.push((stable.to_string(), last, syllable_with_tail(bare, T_COUNT - 1)));
+        } else {
+            for &(compound, _, _) in COMPOUND_FINALS.iter().filter(|&&(_, simple, _)| simple == tail) {
+                let compound = syllable_with_tail(bare, compound);
+                ranges.push((stable.to_string(), compound, compound));
+            }
+            let moved = FINAL_TO_LEAD.iter().find(|&&(simple, _)| simple == tail).map(|&(_, lead)| (bare, lead))
+                .or_else(|| COMPOUND_FINALS.iter().find(|&&(compound, _, _)| compound == tail)
+                    .map(|&(_, simple, lead)| (syllable_with_tail(bare, simple), lead)));
+            if let Some((kept, lead)) = moved {
+                let (lo, hi) = any_syllable(lead);
+                ranges.push((format!("{}{}", stable, kept), lo, hi));
+            }
+        }
+    }
+    ranges
+}
+
+fn syllable_with_tail(bare: char, tail: u32) -> char {
+    char::from_u32(bare as u32 + tail).expect("Hangul syllables are valid chars")
+}
+
+impl FuzzyPhraseSet {
+    /// Set how the final token of prefix queries is broken into characters (see
+    /// `PrefixSegmentation`). Other tokens are unaffected.
+    pub fn set_prefix_segmentation(&mut self, segmentation: PrefixSegmentation) {
+        self.prefix_segmentation = segmentation;
+        // cached results may have been produced under the other setting
+        self.clear_result_cache();
+    }
+
+    pub fn prefix_segmentation(&self) -> PrefixSegmentation {
+        self.prefix_segmentation
+    }
+
+    // the final token as it should be looked up, along with the ranges of any other words it
+    // could still become the beginning of; `None` if nothing's left of it once normalized, since
+    // looking up an empty prefix would match every word
+    pub(super) fn segment_prefix_token<'a>(&self, word: &'a str) -> Option<SegmentedToken<'a>> {
+        let word = match self.prefix_segmentation {
+            PrefixSegmentation::Bytes => return Some((Cow::Borrowed(word), Vec::new())),
+            PrefixSegmentation::Chars | PrefixSegmentation::Composing => normalize_prefix_token(word),
+        };
+        if word.is_empty() {
+            return None;
+        }
+        if self.prefix_segmentation != PrefixSegmentation::Composing {
+            return Some((Cow::Owned(word), Vec::new()));
+        }
+        let ranges = composing_ranges(&word).into_iter().filter_map(|(stable, lo, hi)| {
+            let start = format!("{}{}", stable, lo);
+            // Hangul syllables are never the last char, so there's always one after `hi`
+            let end = format!("{}{}", stable, char::from_u32(hi as u32 + 1)?);
+            self.prefix_set.range_between(start.as_bytes(), Some(end.as_bytes()))
+                .map(|(start, end)| (start.value() as u32, end.value() as u32))
+        }).collect();
+        Some((Cow::Owned(word), ranges))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_prefix_tokens() {
+        // conjoining jamo compose into the syllables they spell
+        assert_eq!(normalize_prefix_token("\u{1112}\u{1161}\u{11ab}"), "한");
+        assert_eq!(normalize_prefix_token("서\u{110b}\u{116e}\u{11af}"), "서울");
+        assert_eq!(normalize_prefix_token("mai\u{fffd}"), "mai");
+        assert_eq!(normalize_prefix_token("\u{fffd}\u{200d}"), "");
+        assert_eq!(normalize_prefix_token("main"), "main");
+    }
+
+    #[test]
+    fn composing_token_ranges() {
+        // a lone consonant could begin any syllable
+        assert_eq!(composing_ranges("서ㅇ"), vec![("서".to_string(), '아', '잏')]);
+        // a syllable without a final could still get one
+        assert_eq!(composing_ranges("서우"), vec![("서".to_string(), '우', '웋')]);
+        // one with a final could get a compound one, or lose it to the next syllable
+        assert_eq!(composing_ranges("갈"), vec![
+            ("".to_string(), '갉', '갉'), ("".to_string(), '갊', '갊'), ("".to_string(), '갋', '갋'),
+            ("".to_string(), '갌', '갌'), ("".to_string(), '갍', '갍'), ("".to_string(), '갎', '갎'),
+            ("".to_string(), '갏', '갏'), ("가".to_string(), '라', '맇'),
+        ]);
+        assert_eq!(composing_ranges("닭"), vec![("달".to_string(), '가', '깋')]);
+        assert!(composing_ranges("main").is_empty());
+        assert!(composing_ranges("").is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::error::Error;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+
+impl FuzzyPhraseSet {
+    // is this a word we know about? if it's the last word of a query that can end in a prefix,
+    // it's enough for it to be the start of a word we know about
+    #[inline(always)]
+    fn is_known_word(&self, word: &str, allow_prefix: bool) -> bool {
+        let lookup = self.prefix_set.lookup(word);
+        if allow_prefix { lookup.found() } else { lookup.found_final() }
+    }
+
+    /// Generate alternative tokenizations of `phrase` that correct for words that were run
+    /// together or split apart: each token that isn't in the vocabulary is split, at every
+    /// position where both halves are known words ("newyork" -> "new york"), and each pair of
+    /// adjacent tokens is merged if the result is a known word ("new york" -> "newyork"). Each
+    /// variant has at most one correction, and is returned along with `penalty`; the original
+    /// phrase comes first, with a penalty of zero.
+    pub fn split_merge_variants<T: AsRef<str>>(&self, phrase: &[T], ending_type: EndingType, penalty: u8) -> Vec<(Vec<String>, u8)> {
+        let phrase: Vec<&str> = phrase.iter().map(|word| word.as_ref()).collect();
+        let mut variants: Vec<(Vec<String>, u8)> = vec![(phrase.iter().map(|word| word.to_string()).collect(), 0)];
+        if phrase.is_empty() {
+            return variants;
+        }
+        let last_idx = phrase.len() - 1;
+        let last_is_prefix = ending_type == EndingType::AnyPrefix;
+
+        for (i, word) in phrase.iter().enumerate() {
+            let allow_prefix = last_is_prefix && i == last_idx;
+            if self.is_known_word(word, allow_prefix) {
+                continue;
+            }
+            for (split_at, _) in word.char_indices().skip(1) {
+                let (left, right) = word.split_at(split_at);
+                if self.is_known_word(left, false) && self.is_known_word(right, allow_prefix) {
+                    let mut variant: Vec<String> = Vec::with_capacity(phrase.len() + 1);
+                    variant.extend(phrase[..i].iter().map(|w| w.to_string()));
+                    variant.push(left.to_string());
+                    variant.push(right.to_string());
+                    variant.extend(phrase[(i + 1)..].iter().map(|w| w.to_string()));
+                    variants.push((variant, penalty));
+                }
+            }
+        }
+
+        for i in 0..last_idx {
+            let allow_prefix = last_is_prefix && i + 1 == last_idx;
+            let merged = format!("{}{}", phrase[i], phrase[i + 1]);
+            if self.is_known_word(&merged, allow_prefix) {
+                let mut variant: Vec<String> = Vec::with_capacity(phrase.len() - 1);
+                variant.extend(phrase[..i].iter().map(|w| w.to_string()));
+                variant.push(merged);
+                variant.extend(phrase[(i + 2)..].iter().map(|w| w.to_string()));
+                variants.push((variant, penalty));
+            }
+        }
+
+        variants
+    }
+
+    /// Like `fuzzy_match`, but also tries the split and merge corrections generated by
+    /// `split_merge_variants`. The penalty for a correction counts against `max_phrase_dist`,
+    /// and is included in the `edit_distance` of any results found with it. If the same phrase is
+    /// found with more than one variant, only the one with the lowest edit distance is kept.
+    pub fn fuzzy_match_split_merge<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, penalty: u8) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        let mut results: Vec<FuzzyMatchResult> = Vec::new();
+        for (variant, variant_penalty) in self.split_merge_variants(phrase, ending_type, penalty) {
+            if variant_penalty > max_phrase_dist {
+                continue;
+            }
+            for mut result in self.fuzzy_match(&variant, max_word_dist, max_phrase_dist - variant_penalty, ending_type)? {
+                result.edit_distance += variant_penalty;
+                keep_best_result(&mut results, result);
+            }
+        }
+        Ok(results)
+    }
+}
+
+// add a result found with one of several variants of a query, unless the same phrase has already
+// been found with a lower edit distance (in which case, replace it)
+pub(super) fn keep_best_result(results: &mut Vec<FuzzyMatchResult>, result: FuzzyMatchResult) {
+    match results.iter_mut().find(|r| r.phrase_id_range == result.phrase_id_range && r.ending_type == result.ending_type) {
+        Some(existing) => {
+            if result.edit_distance < existing.edit_distance {
+                *existing = result;
+            }
+        },
+        None => results.push(result),
+    }
+}
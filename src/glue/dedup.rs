@@ -0,0 +1,51 @@
+use std::cmp::Reverse;
+
+use rustc_hash::FxHashMap;
+
+use ::phrase::{CombinationWindow, PhraseIdRange};
+use super::FuzzyPhraseSet;
+
+impl FuzzyPhraseSet {
+    /// Have `fuzzy_match_windows` return at most one window per phrase ID range. Different
+    /// windows of a query often reach the same phrase, by way of different typo corrections or
+    /// overlapping start positions; with this on, only the best of them is kept: the one with
+    /// the fewest edits, then the one that covers the most tokens, then the one that starts
+    /// earliest. Off by default, and ignored if the phrase graph doesn't store phrase IDs (see
+    /// `PhraseSet::has_ids`), since every window would look like it reached the same phrase.
+    pub fn set_dedup_windows(&mut self, dedup: bool) {
+        self.dedup_windows = dedup;
+        // cached results may have been produced under the other setting
+        self.clear_result_cache();
+    }
+
+    pub fn dedup_windows(&self) -> bool {
+        self.dedup_windows
+    }
+}
+
+// lower is better
+fn window_score(start: usize, window: &CombinationWindow) -> (u32, Reverse<usize>, usize) {
+    let edit_distance = window.phrase.iter().map(|word| u32::from(word.edit_distance())).sum();
+    (edit_distance, Reverse(window.phrase.len()), start)
+}
+
+/// Keep only the best-scoring window (paired with its start position) for each phrase ID range,
+/// in the position where that range was first found.
+pub(super) fn dedup_by_phrase_id(windows: &mut Vec<(usize, CombinationWindow)>) {
+    let mut kept: Vec<(usize, CombinationWindow)> = Vec::with_capacity(windows.len());
+    let mut positions: FxHashMap<PhraseIdRange, usize> = FxHashMap::default();
+    for (start, window) in windows.drain(..) {
+        match positions.get(&window.phrase_id_range()) {
+            Some(&i) => {
+                if window_score(start, &window) < window_score(kept[i].0, &kept[i].1) {
+                    kept[i] = (start, window);
+                }
+            },
+            None => {
+                positions.insert(window.phrase_id_range(), kept.len());
+                kept.push((start, window));
+            },
+        }
+    }
+    *windows = kept;
+}
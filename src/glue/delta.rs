@@ -0,0 +1,140 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, BufReader, BufWriter};
+use std::path::Path;
+
+use fst_compat::Streamer;
+use serde::{Deserialize, Serialize};
+use rmps::{Deserializer, Serializer};
+
+use ::phrase::util::key_to_word_ids;
+use ::phrase::query::QueryWord;
+use ::phrase::util::PhraseSetError;
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, WordReplacement};
+
+/// The changes needed to turn one `FuzzyPhraseSet` into another, in a form that's small enough to
+/// ship to clients that already have the first one.
+///
+/// Removed phrases are recorded by their phrase ID in the base index ("tombstones"); added
+/// phrases are recorded as strings, since the word IDs of the new index depend on the full
+/// vocabulary and so can't be known ahead of time.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct FuzzyPhraseSetDelta {
+    index_type: String,
+    format_version: u32,
+    pub tombstoned_phrase_ids: Vec<u32>,
+    pub added_phrases: Vec<Vec<String>>,
+}
+
+// version 2 dropped the list of new words, which nothing used
+const DELTA_FORMAT_VERSION: u32 = 2;
+
+impl FuzzyPhraseSetDelta {
+    fn new() -> FuzzyPhraseSetDelta {
+        FuzzyPhraseSetDelta {
+            index_type: "fuzzy_phrase_set_delta".to_string(),
+            format_version: DELTA_FORMAT_VERSION,
+            tombstoned_phrase_ids: Vec::new(),
+            added_phrases: Vec::new(),
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let delta: FuzzyPhraseSetDelta = Deserialize::deserialize(&mut Deserializer::new(reader))?;
+        let default = FuzzyPhraseSetDelta::new();
+        if delta.index_type != default.index_type || delta.format_version != default.format_version {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidData, "Unexpected delta metadata")));
+        }
+        Ok(delta)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let writer = BufWriter::new(fs::File::create(path)?);
+        self.serialize(&mut Serializer::new(writer))?;
+        Ok(())
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Compute the delta that turns this set into `other`. This set's phrase graph has to store
+    /// phrase IDs (see `PhraseSet::has_ids`), since removed phrases are recorded by them.
+    pub fn delta(&self, other: &FuzzyPhraseSet) -> Result<FuzzyPhraseSetDelta, Box<dyn Error>> {
+        if !self.phrase_set.has_ids() {
+            return Err(Box::new(PhraseSetError::new("Computing a delta needs a phrase graph that stores phrase IDs")));
+        }
+        let diff = self.diff(other);
+        let mut delta = FuzzyPhraseSetDelta::new();
+
+        for phrase in &diff.removed_phrases {
+            let mut query: Vec<QueryWord> = Vec::with_capacity(phrase.len());
+            for word in phrase {
+                let id = self.prefix_set.lookup(word).id().ok_or("Removed phrase contains an unknown word")?;
+                query.push(QueryWord::new_full(id.value() as u32, 0));
+            }
+            let phrase_id = self.phrase_set.lookup(&query).id().ok_or("Removed phrase not found")?;
+            delta.tombstoned_phrase_ids.push(phrase_id.value() as u32);
+        }
+        delta.added_phrases = diff.added_phrases;
+
+        Ok(delta)
+    }
+
+    /// Apply a delta computed against this set, and write the resulting set to the directory at
+    /// `path`. This rebuilds the whole index (so, it's an offline compaction step); the result is
+    /// the same as building the new set from scratch with this one's options, word replacements,
+    /// word classes and build info, and with each remaining phrase's display forms, number
+    /// ranges and metadata, and can be loaded with `from_path`.
+    pub fn apply_delta<P: AsRef<Path>>(&self, delta: &FuzzyPhraseSetDelta, path: P) -> Result<(), Box<dyn Error>> {
+        let tombstones: BTreeSet<u32> = delta.tombstoned_phrase_ids.iter().cloned().collect();
+        if let Some(&max_id) = tombstones.iter().next_back() {
+            if max_id as u64 > self.phrase_set.get_max_id().value() {
+                return Err(Box::new(IoError::new(IoErrorKind::InvalidData, "Delta tombstones a phrase that isn't in this set")));
+            }
+        }
+
+        let mut builder = FuzzyPhraseSetBuilder::new(path)?;
+        builder.set_index_options(self.options.clone());
+        for (key, value) in &self.build_info {
+            // the library version is recorded afresh
+            if key != "fuzzy_phrase_version" {
+                builder.set_build_info(key.as_str(), value.as_str());
+            }
+        }
+        for (word_id, classes) in self.word_classes.iter() {
+            builder.word_classes.insert(self.word_list[word_id as usize].clone(), classes);
+        }
+        builder.load_word_replacements(self.word_replacement_map.iter().map(|(from, to)| WordReplacement {
+            from: self.word_list[*from as usize].clone(),
+            to: self.word_list[*to as usize].clone(),
+        }).collect())?;
+
+        let mut stream = self.phrase_set.as_fst().stream();
+        while let Some((key, output)) = stream.next() {
+            if tombstones.contains(&(output.value() as u32)) {
+                continue;
+            }
+            let word_ids = key_to_word_ids(key)?;
+            let phrase: Vec<&str> = word_ids.iter().map(|id| self.word_list[*id as usize].as_str()).collect();
+            // carry over any display forms along with the phrase
+            let display: Vec<&str> = word_ids.iter().map(|id| match self.display_forms.get(id) {
+                Some(display_word) => display_word.as_str(),
+                None => self.word_list[*id as usize].as_str(),
+            }).collect();
+            let phrase_id = builder.insert_with_display(&phrase, &display)?;
+            if let Some(ranges) = self.number_ranges.get(&(output.value() as u32)) {
+                builder.number_ranges.entry(phrase_id).or_default().extend_from_slice(ranges);
+            }
+            if let Some(metadata) = self.phrase_metadata.get(&(output.value() as u32)) {
+                builder.phrase_metadata.insert(phrase_id, metadata.clone());
+            }
+        }
+        for phrase in &delta.added_phrases {
+            builder.insert(phrase)?;
+        }
+
+        builder.finish()?;
+        Ok(())
+    }
+}
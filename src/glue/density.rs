@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::iter;
+
+use rustc_hash::FxHashMap;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder};
+use super::options::IndexOptions;
+use super::util;
+use ::fuzzy::{VariantBuffer, multi_modified_damlev_hint};
+
+// how many words are checked for neighbours; the rest of the vocabulary is only searched
+const DENSITY_SAMPLE_SIZE: usize = 2000;
+
+/// How crowded a vocabulary is: for a sample of the words that are indexed for fuzzy matching,
+/// how many other words are within edit distance 1 and 2 of each, by word length. In a dense
+/// vocabulary (short words, or lots of similar names), a typo is about as likely to land on some
+/// other real word as to be recoverable, so looking further out mostly adds noise, along with a
+/// great many variants to the fuzzy map. See `FuzzyPhraseSetBuilder::analyze_vocabulary`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct VocabularyDensity {
+    /// How many words are indexed for fuzzy matching.
+    pub words: usize,
+    /// The sampled words of each length (in characters).
+    pub by_length: BTreeMap<usize, LengthDensity>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+pub struct LengthDensity {
+    /// How many words of this length were sampled.
+    pub sampled: usize,
+    /// The total number of other words within edit distance 1 of them.
+    pub within_1: usize,
+    /// The total number of other words within edit distance 2 of them (including those within 1).
+    pub within_2: usize,
+}
+
+impl LengthDensity {
+    /// The mean number of other words within edit distance 2 of each sampled word.
+    pub fn mean_within_2(&self) -> f64 {
+        if self.sampled == 0 { 0.0 } else { self.within_2 as f64 / self.sampled as f64 }
+    }
+}
+
+/// The edit distance settings picked by `VocabularyDensity::recommend` (see
+/// `IndexOptions::max_edit_distance` and `IndexOptions::distance_min_lengths`).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EditDistanceRecommendation {
+    pub max_edit_distance: u8,
+    pub distance_min_lengths: Vec<u8>,
+}
+
+impl VocabularyDensity {
+    /// Recommend an index edit distance of 2 for words of the lengths where the sampled words
+    /// have at most `max_neighbors` other words within distance 2 on average, and 1 for the rest.
+    /// Since shorter words are denser, that's every word from some length up; if no length is
+    /// sparse enough, the whole index gets distance 1.
+    pub fn recommend(&self, max_neighbors: f64) -> EditDistanceRecommendation {
+        // the shortest length from which every sampled length is sparse enough
+        let mut shortest: Option<usize> = None;
+        for (&length, density) in self.by_length.iter().rev() {
+            if density.mean_within_2() > max_neighbors {
+                break;
+            }
+            shortest = Some(length);
+        }
+        match shortest {
+            None => EditDistanceRecommendation { max_edit_distance: 1, distance_min_lengths: Vec::new() },
+            Some(length) if self.by_length.keys().next() == Some(&length) => {
+                EditDistanceRecommendation { max_edit_distance: 2, distance_min_lengths: Vec::new() }
+            },
+            Some(length) => EditDistanceRecommendation {
+                max_edit_distance: 2,
+                distance_min_lengths: vec![0, length.min(u8::MAX as usize) as u8],
+            },
+        }
+    }
+}
+
+// measure the density of `words`, sorted, counting only the ones indexed for fuzzy matching
+pub(super) fn measure<'a, I: Iterator<Item = &'a str>>(words: I, options: &IndexOptions) -> Result<VocabularyDensity, Box<dyn Error>> {
+    let script_regex = options.script_regex()?;
+    let words: Vec<&str> = words.filter(|word| util::can_fuzzy_match(word, &script_regex)).collect();
+    // an even spread of the (sorted) vocabulary
+    let stride = words.len().div_ceil(DENSITY_SAMPLE_SIZE).max(1);
+    let sample: Vec<usize> = (0..words.len()).step_by(stride).collect();
+
+    // two words are within distance 2 only if they have a deletion variant within distance 2 in
+    // common, so index the sample's variants and look up every word's against them
+    let mut variants = VariantBuffer::new();
+    let mut sample_variants: FxHashMap<String, Vec<u32>> = FxHashMap::default();
+    for (i, &word_idx) in sample.iter().enumerate() {
+        let word = words[word_idx];
+        variants.fill(word, 2);
+        for variant in iter::once(word).chain(variants.iter()) {
+            sample_variants.entry(variant.to_owned()).or_default().push(i as u32);
+        }
+    }
+
+    let mut within: Vec<(usize, usize)> = vec![(0, 0); sample.len()];
+    let mut candidates: Vec<u32> = Vec::new();
+    for (word_idx, &word) in words.iter().enumerate() {
+        variants.fill(word, 2);
+        candidates.clear();
+        for variant in iter::once(word).chain(variants.iter()) {
+            if let Some(ids) = sample_variants.get(variant) {
+                candidates.extend_from_slice(ids);
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        for &i in &candidates {
+            let sampled_idx = sample[i as usize];
+            if sampled_idx == word_idx {
+                continue;
+            }
+            let distance = multi_modified_damlev_hint(words[sampled_idx], &[word], 2)[0];
+            let counts = &mut within[i as usize];
+            if distance <= 1 {
+                counts.0 += 1;
+            }
+            if distance <= 2 {
+                counts.1 += 1;
+            }
+        }
+    }
+
+    let mut density = VocabularyDensity { words: words.len(), by_length: BTreeMap::new() };
+    for (i, &word_idx) in sample.iter().enumerate() {
+        let length = density.by_length.entry(words[word_idx].chars().count()).or_default();
+        length.sampled += 1;
+        length.within_1 += within[i].0;
+        length.within_2 += within[i].1;
+    }
+    Ok(density)
+}
+
+impl FuzzyPhraseSetBuilder {
+    /// Measure the density of the vocabulary inserted so far (see `VocabularyDensity`), for
+    /// choosing an edit distance to build with. Large vocabularies are sampled.
+    pub fn analyze_vocabulary(&self) -> Result<VocabularyDensity, Box<dyn Error>> {
+        measure(self.words_to_tmp_word_ids.keys().map(|word| word.as_str()), &self.options)
+    }
+
+    /// Have `finish` choose the index's maximum edit distance and per-length limits itself,
+    /// replacing the configured ones, with `VocabularyDensity::recommend` and `max_neighbors`
+    /// (say, 5.0). The analysis is stored in the index's metadata alongside the settings it led
+    /// to (see `FuzzyPhraseSet::vocabulary_density`). Pass `None` to build with the configured
+    /// settings, as by default.
+    pub fn set_auto_edit_distance(&mut self, max_neighbors: Option<f64>) {
+        self.auto_edit_distance = max_neighbors;
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// The vocabulary analysis the set's edit distance was chosen by, if it was built with
+    /// `FuzzyPhraseSetBuilder::set_auto_edit_distance`.
+    pub fn vocabulary_density(&self) -> Option<&VocabularyDensity> {
+        self.vocabulary_density.as_ref()
+    }
+}
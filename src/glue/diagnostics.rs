@@ -0,0 +1,176 @@
+use std::error::Error;
+
+use ::phrase::query::QueryWord;
+use super::{FuzzyPhraseSet, EndingType};
+
+/// What happened to one token of a query, as reported by `FuzzyPhraseSet::why_no_match`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NoMatchReason {
+    /// The token has candidate words, and some phrase continues through it.
+    Matched,
+    /// Nothing in the vocabulary is close enough to the token to be a candidate at all, even at
+    /// the index's maximum edit distance.
+    UnknownWord,
+    /// There are words in the vocabulary near the token, but all of them are further away than
+    /// the word distance the query allowed; `nearest` is the distance to the closest.
+    OverWordDistance { nearest: u8 },
+    /// The token isn't in the vocabulary, and the fuzzy guards (see `set_fuzzy_guard_patterns`)
+    /// kept it from being fuzzy-matched.
+    FuzzyGuarded,
+    /// Some phrase continues through one of the token's candidates, but only by spending more
+    /// edits across the query than the phrase distance allowed.
+    OverPhraseDistance,
+    /// The token has candidate words, but no phrase continues from the tokens before it with any
+    /// of them. This is where every path through the phrase graph dead-ends.
+    DeadEnd,
+    /// The tokens up to and including this one (the last) are the beginning of some phrase, but
+    /// no phrase ends here, and the query didn't allow prefix matches.
+    Incomplete,
+    /// The token wasn't reached, because every path through the phrase graph ended before it.
+    Unreached,
+    /// The token was dropped by the query length limit (see `set_max_query_length`).
+    OutsideQueryLimit,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TokenDiagnosis {
+    /// The position of the token in the query.
+    pub position: usize,
+    pub token: String,
+    pub reason: NoMatchReason,
+}
+
+/// The results of `FuzzyPhraseSet::why_no_match`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NoMatchReport {
+    /// Set if the query does match something after all, in which case every token it was matched
+    /// on is `Matched`.
+    pub matched: bool,
+    pub tokens: Vec<TokenDiagnosis>,
+}
+
+impl NoMatchReport {
+    /// The position of the token at which every path through the phrase graph ended, if the
+    /// query got as far as the phrase graph.
+    pub fn dead_end(&self) -> Option<usize> {
+        self.tokens.iter()
+            .find(|token| matches!(token.reason, NoMatchReason::DeadEnd | NoMatchReason::OverPhraseDistance | NoMatchReason::Incomplete))
+            .map(|token| token.position)
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Explain why `fuzzy_match` (with the same arguments) finds nothing for a query, with a
+    /// reason for each token: whether it's in the vocabulary at all, whether its nearest words
+    /// are over the distance budget, and where the query's paths through the phrase graph run
+    /// out. The vocabulary is searched exactly as `fuzzy_match` searches it, and the phrase graph
+    /// is walked with the same matcher, instrumented to record how far it gets. This is meant for
+    /// debugging individual queries, and does considerably more work than a match.
+    pub fn why_no_match<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<NoMatchReport, Box<dyn Error>> {
+        let (limited, offset, limited_ending_type) = self.limit_query_length(phrase, ending_type)?;
+        let mut reasons: Vec<NoMatchReason> = phrase.iter().enumerate().map(|(i, _)| {
+            if i >= offset && i < offset + limited.len() {
+                NoMatchReason::Matched
+            } else {
+                NoMatchReason::OutsideQueryLimit
+            }
+        }).collect();
+        let diagnose = |reasons: Vec<NoMatchReason>, matched: bool| NoMatchReport {
+            matched,
+            tokens: phrase.iter().zip(reasons).enumerate().map(|(position, (token, reason))| {
+                TokenDiagnosis { position, token: token.as_ref().to_owned(), reason }
+            }).collect(),
+        };
+
+        if limited.is_empty() || !self.fuzzy_match_impl(phrase, max_word_dist, max_phrase_dist, ending_type, None, None)?.is_empty() {
+            let matched = !limited.is_empty();
+            return Ok(diagnose(reasons, matched));
+        }
+
+        let slots = self.query_builder()
+            .max_word_dist(max_word_dist)
+            .ending_type(limited_ending_type)
+            .build_each(limited)?;
+
+        // words with no candidates get the same reason wherever they are, so sort them out first
+        let first_missing = slots.iter().position(|slot| slot.is_none()).unwrap_or(slots.len());
+        for (i, slot) in slots.iter().enumerate() {
+            if slot.is_none() {
+                reasons[offset + i] = self.missing_word_reason(limited, i, max_word_dist, limited_ending_type)?;
+            }
+        }
+
+        // then see how far into the query the phrase graph goes, up to the first of them
+        let word_possibilities: Vec<Vec<QueryWord>> = slots.into_iter().take(first_missing).flatten().collect();
+        let ends_in_prefix = limited_ending_type != EndingType::NonPrefix;
+        let depth = self.matched_depth(&word_possibilities, max_phrase_dist, ends_in_prefix)?;
+        if depth < word_possibilities.len() || first_missing == limited.len() {
+            let reason = if depth == word_possibilities.len() {
+                // everything's there and in order, but the phrases continue past the end
+                NoMatchReason::Incomplete
+            } else if self.matched_depth(&word_possibilities, u8::MAX, ends_in_prefix)? > depth {
+                NoMatchReason::OverPhraseDistance
+            } else {
+                NoMatchReason::DeadEnd
+            };
+            let dead_end = depth.min(word_possibilities.len() - 1);
+            reasons[offset + dead_end] = reason;
+            for reason in reasons[(offset + dead_end + 1)..(offset + limited.len())].iter_mut() {
+                if *reason == NoMatchReason::Matched {
+                    *reason = NoMatchReason::Unreached;
+                }
+            }
+        } else {
+            // the graph got as far as the first missing word, so anything after it is unreached
+            for reason in reasons[(offset + first_missing + 1)..(offset + limited.len())].iter_mut() {
+                if *reason == NoMatchReason::Matched {
+                    *reason = NoMatchReason::Unreached;
+                }
+            }
+        }
+
+        Ok(diagnose(reasons, false))
+    }
+
+    // a word without any candidates is either over the word distance budget (if the index allows
+    // more edits than the query did, and there's something within them), or not known at all
+    fn missing_word_reason<T: AsRef<str>>(&self, phrase: &[T], position: usize, max_word_dist: u8, ending_type: EndingType) -> Result<NoMatchReason, Box<dyn Error>> {
+        let word = phrase[position].as_ref();
+        if self.can_fuzzy_match(word) && self.is_fuzzy_guarded(word) {
+            return Ok(NoMatchReason::FuzzyGuarded);
+        }
+        if max_word_dist < self.max_edit_distance {
+            let wider = if ending_type == EndingType::AnyPrefix && position == phrase.len() - 1 {
+                self.get_terminal_word_possibilities(word, self.max_edit_distance)?
+            } else {
+                self.get_nonterminal_word_possibilities(word, self.max_edit_distance)?
+            };
+            let nearest = wider.into_iter().flatten().map(|word| word.edit_distance()).min();
+            if let Some(nearest) = nearest {
+                return Ok(NoMatchReason::OverWordDistance { nearest });
+            }
+        }
+        Ok(NoMatchReason::UnknownWord)
+    }
+
+    /// Render the phrases beginning with `prefix` (after token replacement), down to `depth`
+    /// words past it, as a Graphviz DOT graph labeled with this set's words (see
+    /// `PhraseSet::to_dot`). Returns `None` if any word of `prefix` isn't in the vocabulary, or
+    /// no phrase begins with it.
+    pub fn to_dot<T: AsRef<str>>(&self, prefix: &[T], depth: usize) -> Option<String> {
+        let ids: Vec<u32> = self.exact_id_phrase(prefix)?.iter().filter_map(|word| match *word {
+            QueryWord::Full { id, .. } => Some(id),
+            QueryWord::Prefix { .. } => None,
+        }).collect();
+        self.phrase_set.to_dot(&ids, depth, |id| &self.word_list[id as usize])
+    }
+
+    // how many of the slots, from the first, lie along some path through the phrase graph
+    fn matched_depth(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<usize, Box<dyn Error>> {
+        if word_possibilities.is_empty() {
+            return Ok(0);
+        }
+        let windows = self.phrase_set.match_combinations_as_partial_windows(word_possibilities, max_phrase_dist, ends_in_prefix)?;
+        Ok(windows.first().map_or(0, |window| window.phrase.len()))
+    }
+}
@@ -0,0 +1,73 @@
+use std::cmp::Ordering;
+
+use fst_compat::Streamer;
+
+use ::prefix::PrefixSetDiff;
+use ::phrase::util::key_to_word_ids;
+use super::FuzzyPhraseSet;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FuzzyPhraseSetDiff {
+    pub words: PrefixSetDiff,
+    pub added_phrases: Vec<Vec<String>>,
+    pub removed_phrases: Vec<Vec<String>>,
+}
+
+// phrase graphs only ever hold keys made of whole words
+fn phrase_word_ids(key: &[u8]) -> Vec<u32> {
+    key_to_word_ids(key).expect("Phrase keys are made of whole words")
+}
+
+impl FuzzyPhraseSet {
+    /// Compare this set against another one, returning the words and phrases that were added or
+    /// removed, and the IDs of words that are in both sets but were renumbered.
+    ///
+    /// Unlike `PhraseSet::diff`, phrases are compared by their words rather than their word IDs,
+    /// so adding a single word to the vocabulary (which shifts the IDs of every word that sorts
+    /// after it) doesn't make every phrase containing those words look changed. Because word IDs
+    /// are assigned in lexicographic order, each phrase graph streams its phrases in word order,
+    /// so we can still walk the two of them in lockstep.
+    pub fn diff(&self, other: &FuzzyPhraseSet) -> FuzzyPhraseSetDiff {
+        let mut diff = FuzzyPhraseSetDiff {
+            words: self.prefix_set.diff(&other.prefix_set),
+            added_phrases: Vec::new(),
+            removed_phrases: Vec::new(),
+        };
+
+        let mut ours = self.phrase_set.as_fst().stream();
+        let mut theirs = other.phrase_set.as_fst().stream();
+        let mut a: Option<Vec<u32>> = ours.next().map(|(k, _)| phrase_word_ids(k));
+        let mut b: Option<Vec<u32>> = theirs.next().map(|(k, _)| phrase_word_ids(k));
+        loop {
+            let ord = match (&a, &b) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(x), Some(y)) => {
+                    let x_words = x.iter().map(|id| self.word_list[*id as usize].as_str());
+                    let y_words = y.iter().map(|id| other.word_list[*id as usize].as_str());
+                    x_words.cmp(y_words)
+                },
+            };
+            match ord {
+                Ordering::Less => {
+                    diff.removed_phrases.push(self.ids_to_words(a.as_ref().unwrap()));
+                    a = ours.next().map(|(k, _)| phrase_word_ids(k));
+                },
+                Ordering::Greater => {
+                    diff.added_phrases.push(other.ids_to_words(b.as_ref().unwrap()));
+                    b = theirs.next().map(|(k, _)| phrase_word_ids(k));
+                },
+                Ordering::Equal => {
+                    a = ours.next().map(|(k, _)| phrase_word_ids(k));
+                    b = theirs.next().map(|(k, _)| phrase_word_ids(k));
+                },
+            }
+        }
+        diff
+    }
+
+    fn ids_to_words(&self, ids: &[u32]) -> Vec<String> {
+        ids.iter().map(|id| self.word_list[*id as usize].clone()).collect()
+    }
+}
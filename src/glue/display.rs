@@ -0,0 +1,47 @@
+use std::fmt;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, FuzzyWindowResult};
+
+/// A result phrase as it should be shown to users: its words in the forms they were inserted for
+/// display with (see `FuzzyPhraseSet::display_phrase`), separated by spaces. Nothing is looked up
+/// until the phrase is formatted, so results that are never shown cost nothing extra.
+#[derive(Clone, Copy)]
+pub struct PhraseDisplay<'a> {
+    set: &'a FuzzyPhraseSet,
+    phrase: &'a [String],
+}
+
+impl<'a> fmt::Display for PhraseDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, word) in self.phrase.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            let display_word = self.set.exact_word_id(word).and_then(|id| self.set.display_forms.get(&id));
+            f.write_str(display_word.unwrap_or(word))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for PhraseDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl FuzzyMatchResult {
+    /// The matched phrase as it should be displayed, according to `set`, which must be the set
+    /// the result came from.
+    pub fn display<'a>(&'a self, set: &'a FuzzyPhraseSet) -> PhraseDisplay<'a> {
+        PhraseDisplay { set, phrase: &self.phrase }
+    }
+}
+
+impl FuzzyWindowResult {
+    /// The matched phrase as it should be displayed, according to `set`, which must be the set
+    /// the result came from.
+    pub fn display<'a>(&'a self, set: &'a FuzzyPhraseSet) -> PhraseDisplay<'a> {
+        PhraseDisplay { set, phrase: &self.phrase }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::error::Error;
+
+use fst_compat::Streamer;
+
+use super::FuzzyPhraseSet;
+use ::phrase::query::QueryWord;
+use ::phrase::util::{key_to_word_ids, PhraseSetError};
+
+/// Two phrases that are the same but for a single character edit to one of their words, as
+/// often happens when a source dataset has the same street twice, once with a typo.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NearDuplicate {
+    /// The IDs of the two phrases, lowest first.
+    pub phrase_ids: (u32, u32),
+    /// The words of the first phrase.
+    pub phrase: Vec<String>,
+    /// The position of the word the phrases differ in.
+    pub position: usize,
+    /// The second phrase's word at that position.
+    pub other_word: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct NearDuplicateReport {
+    pub phrases_checked: usize,
+    pub pairs: Vec<NearDuplicate>,
+}
+
+impl FuzzyPhraseSet {
+    /// Find the pairs of phrases in the set that differ only by a one-character edit (counting an
+    /// adjacent transposition as one, as the fuzzy map does) to one of their words, for cleaning
+    /// up the data the set was built from. Rather than comparing every phrase to every other, each
+    /// word of each phrase is swapped for each of its neighbors within edit distance 1 in the
+    /// fuzzy map, and the phrase graph checked for the result, so the cost grows with the number
+    /// of phrases times their length times how crowded the vocabulary is. Words that the fuzzy
+    /// map leaves out or only matches exactly (numbers in most configurations, and words in
+    /// exact-only scripts) are never the difference. Each pair is reported once, in order of the
+    /// first phrase's ID. The set has to have been built with a maximum edit distance of at least
+    /// one, and its phrase graph has to store phrase IDs (see `PhraseSet::has_ids`), since the
+    /// pairs are told apart by them.
+    pub fn near_duplicates(&self) -> Result<NearDuplicateReport, Box<dyn Error>> {
+        if self.max_edit_distance < 1 {
+            return Err(Box::new(PhraseSetError::new("Finding near-duplicate phrases needs an index built with an edit distance of at least 1")));
+        }
+        if !self.phrase_set.has_ids() {
+            return Err(Box::new(PhraseSetError::new("Finding near-duplicate phrases needs a phrase graph that stores phrase IDs")));
+        }
+
+        let mut report = NearDuplicateReport::default();
+        // the same words turn up in many phrases, so only look up each one's neighbors once
+        let mut neighbors: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut stream = self.phrase_set.as_fst().stream();
+        while let Some((key, output)) = stream.next() {
+            report.phrases_checked += 1;
+            let id = output.value() as u32;
+            let word_ids = key_to_word_ids(key)?;
+            let mut query: Vec<QueryWord> = word_ids.iter().map(|&word_id| QueryWord::new_full(word_id, 0)).collect();
+            for (position, &word_id) in word_ids.iter().enumerate() {
+                let word_neighbors = match neighbors.entry(word_id) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(self.word_neighbors(word_id)?),
+                };
+                for &neighbor in word_neighbors.iter() {
+                    query[position] = QueryWord::new_full(neighbor, 1);
+                    if let Some(other_id) = self.phrase_set.lookup(&query).id() {
+                        let other_id = other_id.value() as u32;
+                        if other_id > id {
+                            report.pairs.push(NearDuplicate {
+                                phrase_ids: (id, other_id),
+                                phrase: word_ids.iter().map(|&word_id| self.word_list[word_id as usize].clone()).collect(),
+                                position,
+                                other_word: self.word_list[neighbor as usize].clone(),
+                            });
+                        }
+                    }
+                }
+                query[position] = QueryWord::new_full(word_id, 0);
+            }
+        }
+        Ok(report)
+    }
+
+    // the other words within edit distance 1 of `word_id`, if it's fuzzy-matchable
+    fn word_neighbors(&self, word_id: u32) -> Result<Vec<u32>, Box<dyn Error>> {
+        let word = &self.word_list[word_id as usize];
+        if !self.can_fuzzy_match(word) || self.fuzzy_map.is_exact_only(word) {
+            return Ok(Vec::new());
+        }
+        Ok(self.fuzzy_map.lookup(word, 1, |id| &self.word_list[id as usize])?
+            .into_iter()
+            .filter(|result| result.edit_distance > 0 && result.id != word_id)
+            .map(|result| result.id)
+            .collect())
+    }
+}
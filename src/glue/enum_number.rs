@@ -2,7 +2,7 @@
 // with slight modifications
 macro_rules! enum_number {
     ($name:ident { $($variant:ident = $value:expr, )* }) => {
-        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
         pub enum $name {
             $($variant = $value,)*
         }
@@ -44,6 +44,16 @@ macro_rules! enum_number {
                                 stringify!($name), value))),
                         }
                     }
+
+                    // self-describing formats like MessagePack hand back
+                    // non-negative integers as unsigned, whatever they were
+                    // serialized as
+                    fn visit_u64<E>(self, value: u64) -> Result<$name, E>
+                    where
+                        E: ::serde::de::Error,
+                    {
+                        self.visit_i64(value as i64)
+                    }
                 }
 
                 // Deserialize the enum from a i64.
@@ -0,0 +1,32 @@
+use rustc_hash::FxHashMap;
+
+use super::FuzzyPhraseSet;
+
+impl FuzzyPhraseSet {
+    /// Build an in-memory hash table from words to word IDs, to be used instead of the prefix
+    /// graph for exact (distance-0) word lookups. Hashing is quite a bit faster than walking the
+    /// graph, at the cost of holding the whole vocabulary in memory a second time, so this is
+    /// worth it for hot sets with modestly-sized vocabularies.
+    pub fn enable_exact_lookup_cache(&mut self) {
+        let mut exact_word_ids: FxHashMap<String, u32> = FxHashMap::default();
+        exact_word_ids.reserve(self.word_list.len());
+        for (id, word) in self.word_list.iter().enumerate() {
+            exact_word_ids.insert(word.clone(), id as u32);
+        }
+        self.exact_word_ids = Some(exact_word_ids);
+    }
+
+    /// Drop the table built by `enable_exact_lookup_cache`, and go back to using the prefix graph.
+    pub fn disable_exact_lookup_cache(&mut self) {
+        self.exact_word_ids = None;
+    }
+
+    /// Look up the ID of a complete word, without applying word replacements.
+    #[inline(always)]
+    pub(super) fn exact_word_id(&self, word: &str) -> Option<u32> {
+        match self.exact_word_ids {
+            Some(ref exact_word_ids) => exact_word_ids.get(word).cloned(),
+            None => self.prefix_set.lookup(word).id().map(|id| id.value() as u32),
+        }
+    }
+}
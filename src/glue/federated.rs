@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, FuzzyWindowResult, EndingType};
+use super::language::LanguageDetector;
+use super::tokenize::{tokenize_with, TokenizerConfig};
+use super::util::distance_score;
+
+struct FederationMember {
+    name: String,
+    set: FuzzyPhraseSet,
+    weight: f64,
+    // the languages the set serves; empty if it serves all of them
+    languages: Vec<String>,
+}
+
+/// A match from one of the sets in a `FederatedFuzzyPhraseSet`. `index` is the position of the
+/// set the match came from (in the order the sets were added); phrase IDs in `result` are only
+/// meaningful within that set.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FederatedMatchResult {
+    pub index: usize,
+    pub score: f64,
+    pub result: FuzzyMatchResult,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FederatedWindowResult {
+    pub index: usize,
+    pub score: f64,
+    pub result: FuzzyWindowResult,
+}
+
+/// Queries several independently built sets (each with its own vocabulary and word IDs) as
+/// though they were one, and merges their results into a single ranking.
+///
+/// Edit distances from different sets are directly comparable, since they're all measured
+/// against the same query, so results are scored on a common scale: `1 - edit_distance /
+/// (max_phrase_dist + 1)`, times the weight of the set they came from (and, for windowed
+/// matches, times the fraction of the query the window covers). Results are returned best
+/// first; ties are broken by set order and then by phrase.
+///
+/// When the sets are for different languages, they can be tagged with the languages they serve
+/// and paired with a `LanguageDetector`; the `_raw` query methods then only query the sets for
+/// the language a query is detected to be in, tokenized the way that language needs.
+#[derive(Default)]
+pub struct FederatedFuzzyPhraseSet {
+    members: Vec<FederationMember>,
+    detector: Option<Box<dyn LanguageDetector>>,
+    // tokenization overrides by language; otherwise queries are tokenized with each set's own
+    // `normalize`
+    tokenizers: BTreeMap<String, TokenizerConfig>,
+}
+
+impl FederatedFuzzyPhraseSet {
+    pub fn new() -> FederatedFuzzyPhraseSet {
+        FederatedFuzzyPhraseSet::default()
+    }
+
+    /// Add a set with a weight of 1, and return its index.
+    pub fn add<S: Into<String>>(&mut self, name: S, set: FuzzyPhraseSet) -> usize {
+        self.add_weighted(name, set, 1.0)
+    }
+
+    /// Add a set whose scores will be multiplied by `weight`, and return its index.
+    pub fn add_weighted<S: Into<String>>(&mut self, name: S, set: FuzzyPhraseSet, weight: f64) -> usize {
+        self.members.push(FederationMember { name: name.into(), set, weight, languages: Vec::new() });
+        self.members.len() - 1
+    }
+
+    /// Load a set from disk (see `FuzzyPhraseSet::from_path`) and add it with a weight of 1.
+    pub fn add_path<S: Into<String>, P: AsRef<Path>>(&mut self, name: S, path: P) -> Result<usize, Box<dyn Error>> {
+        let set = FuzzyPhraseSet::from_path(path)?;
+        Ok(self.add(name, set))
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.members.get(index).map(|member| member.name.as_str())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&FuzzyPhraseSet> {
+        self.members.get(index).map(|member| &member.set)
+    }
+
+    /// Tag the set at `index` as serving queries in `languages` (in whatever codes the language
+    /// detector reports). Sets without tags serve every language. Panics if there's no set at
+    /// `index`.
+    pub fn set_languages<S: AsRef<str>>(&mut self, index: usize, languages: &[S]) {
+        self.members[index].languages = languages.iter().map(|language| language.as_ref().to_owned()).collect();
+    }
+
+    pub fn languages(&self, index: usize) -> Option<&[String]> {
+        self.members.get(index).map(|member| member.languages.as_slice())
+    }
+
+    /// Detect the language of queries to `fuzzy_match_raw` and `fuzzy_match_windows_raw` with
+    /// `detector`, and only query the sets tagged with that language (and the untagged ones). If
+    /// no language is detected, or none of the sets is tagged with it, every set is queried.
+    pub fn set_language_detector<D: LanguageDetector + 'static>(&mut self, detector: D) {
+        self.detector = Some(Box::new(detector));
+    }
+
+    pub fn clear_language_detector(&mut self) {
+        self.detector = None;
+    }
+
+    /// Tokenize raw queries detected to be in `language` with `config`, rather than with each
+    /// set's own configuration (see `FuzzyPhraseSet::normalize`).
+    pub fn set_language_tokenizer<S: Into<String>>(&mut self, language: S, config: TokenizerConfig) {
+        self.tokenizers.insert(language.into(), config);
+    }
+
+    /// The language the detector thinks `query` is in, if there's a detector and it can tell.
+    pub fn detect_language(&self, query: &str) -> Option<String> {
+        self.detector.as_ref().and_then(|detector| detector.detect(query))
+    }
+
+    // the sets to query for a language: those tagged with it, and the untagged ones, unless
+    // nothing is tagged with it at all
+    fn member_indexes(&self, language: Option<&str>) -> Vec<usize> {
+        let serves = |member: &FederationMember, language: &str| member.languages.iter().any(|l| l == language);
+        match language {
+            Some(language) if self.members.iter().any(|member| serves(member, language)) => {
+                self.members.iter().enumerate()
+                    .filter(|&(_, member)| member.languages.is_empty() || serves(member, language))
+                    .map(|(index, _)| index)
+                    .collect()
+            },
+            _ => (0..self.members.len()).collect(),
+        }
+    }
+
+    fn tokenize_for(&self, member: &FederationMember, language: Option<&str>, query: &str) -> Vec<String> {
+        match language.and_then(|language| self.tokenizers.get(language)) {
+            Some(config) => member.set.replace_words(member.set.segment(tokenize_with(query, config))),
+            None => member.set.normalize(query),
+        }
+    }
+
+    /// Same as `FuzzyPhraseSet::fuzzy_match`, across all of the sets.
+    pub fn fuzzy_match<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FederatedMatchResult>, Box<dyn Error>> {
+        let indexes: Vec<usize> = (0..self.members.len()).collect();
+        self.fuzzy_match_members(&indexes, |_| phrase.iter().map(|word| word.as_ref().to_owned()).collect(), max_word_dist, max_phrase_dist, ending_type)
+    }
+
+    /// Like `fuzzy_match`, but for an untokenized query: its language is detected (if there's a
+    /// language detector), and it's tokenized for and matched against only the sets for that
+    /// language.
+    pub fn fuzzy_match_raw(&self, query: &str, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FederatedMatchResult>, Box<dyn Error>> {
+        let language = self.detect_language(query);
+        let indexes = self.member_indexes(language.as_deref());
+        self.fuzzy_match_members(&indexes, |member| self.tokenize_for(member, language.as_deref(), query), max_word_dist, max_phrase_dist, ending_type)
+    }
+
+    fn fuzzy_match_members<F: Fn(&FederationMember) -> Vec<String>>(&self, indexes: &[usize], tokens: F, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FederatedMatchResult>, Box<dyn Error>> {
+        let mut out: Vec<FederatedMatchResult> = Vec::new();
+        for &index in indexes {
+            let member = &self.members[index];
+            for result in member.set.fuzzy_match(&tokens(member), max_word_dist, max_phrase_dist, ending_type)? {
+                let score = member.weight * distance_score(result.edit_distance, max_phrase_dist);
+                out.push(FederatedMatchResult { index, score, result });
+            }
+        }
+        out.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+                .then(a.index.cmp(&b.index))
+                .then_with(|| a.result.cmp(&b.result))
+        });
+        Ok(out)
+    }
+
+    /// Same as `FuzzyPhraseSet::fuzzy_match_windows`, across all of the sets.
+    pub fn fuzzy_match_windows<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FederatedWindowResult>, Box<dyn Error>> {
+        let indexes: Vec<usize> = (0..self.members.len()).collect();
+        self.fuzzy_match_windows_members(&indexes, |_| phrase.iter().map(|word| word.as_ref().to_owned()).collect(), max_word_dist, max_phrase_dist, ending_type)
+    }
+
+    /// Like `fuzzy_match_windows`, but for an untokenized query, as in `fuzzy_match_raw`.
+    pub fn fuzzy_match_windows_raw(&self, query: &str, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FederatedWindowResult>, Box<dyn Error>> {
+        let language = self.detect_language(query);
+        let indexes = self.member_indexes(language.as_deref());
+        self.fuzzy_match_windows_members(&indexes, |member| self.tokenize_for(member, language.as_deref(), query), max_word_dist, max_phrase_dist, ending_type)
+    }
+
+    fn fuzzy_match_windows_members<F: Fn(&FederationMember) -> Vec<String>>(&self, indexes: &[usize], tokens: F, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FederatedWindowResult>, Box<dyn Error>> {
+        let mut out: Vec<FederatedWindowResult> = Vec::new();
+        for &index in indexes {
+            let member = &self.members[index];
+            for result in member.set.fuzzy_match_windows(&tokens(member), max_word_dist, max_phrase_dist, ending_type)? {
+                let score = member.weight * result.coverage() * distance_score(result.edit_distance, max_phrase_dist);
+                out.push(FederatedWindowResult { index, score, result });
+            }
+        }
+        out.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+                .then(a.index.cmp(&b.index))
+                .then_with(|| a.result.cmp(&b.result))
+        });
+        Ok(out)
+    }
+}
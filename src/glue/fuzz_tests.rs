@@ -165,7 +165,9 @@ fn glue_fuzztest_windowed_multi_equivalent() {
                     edit_distance: result.edit_distance,
                     start_position: variant_starts[i],
                     ending_type: EndingType::NonPrefix,
-                    phrase_id_range: result.phrase_id_range
+                    phrase_id_range: result.phrase_id_range,
+                    tokens_consumed: result.phrase.len(),
+                    tokens_total: tokens.len()
                 });
             }
         }
@@ -236,7 +238,9 @@ fn glue_fuzztest_windowed_multi_equivalent_prefix() {
                     edit_distance: result.edit_distance,
                     start_position: variant_starts[i],
                     ending_type: result.ending_type,
-                    phrase_id_range: result.phrase_id_range
+                    phrase_id_range: result.phrase_id_range,
+                    tokens_consumed: result.phrase.len(),
+                    tokens_total: tokens.len()
                 });
             }
         }
@@ -246,4 +250,30 @@ fn glue_fuzztest_windowed_multi_equivalent_prefix() {
 
         assert_eq!(windowed_match_result, emulated_windowed_match_result);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn glue_fuzztest_generated_corpus() {
+    // unlike the tests above, this doesn't need any data downloaded
+    let config = datagen::CorpusConfig { phrase_count: 2_000, street_names: 300, ..Default::default() };
+    let phrases = datagen::generate(&config);
+    assert_eq!(phrases.len(), 2_000);
+    assert_eq!(phrases, datagen::generate(&config), "the corpus should be the same every time");
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+    for phrase in &phrases {
+        builder.insert_str(phrase).unwrap();
+    }
+    let tmp_to_final = builder.finish().unwrap();
+    let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+
+    for (query, phrase_idx) in datagen::damaged_queries(&phrases, 200, &config) {
+        let expected_id = tmp_to_final[phrase_idx];
+        let results = set.fuzzy_match_str(&query, 1, 1, EndingType::NonPrefix).unwrap();
+        assert!(
+            results.iter().any(|result| result.phrase_id_range == (expected_id, expected_id)),
+            "{:?} should have matched {:?}", query, phrases[phrase_idx]
+        );
+    }
+}
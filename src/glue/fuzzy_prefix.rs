@@ -0,0 +1,95 @@
+use ::phrase::query::{QueryWord, VariantSource};
+use super::FuzzyPhraseSet;
+
+impl FuzzyPhraseSet {
+    /// Have prefix queries also match words that begin with something within the query's word
+    /// edit distance of the final token, so that a typo in a partly typed word ("mian" for
+    /// "main...") doesn't lose every match. Candidates come from `PrefixSet::fuzzy_prefix_ranges`
+    /// and are added to the final token's prefix ranges, each charged what it's away from the
+    /// token against the phrase edit distance, as a whole word would be; words the typed prefix
+    /// (or a closer candidate) already covers keep its distance. Final tokens are only expanded
+    /// this way if they're eligible for fuzzy matching at all and longer than the edit distance
+    /// plus one, since otherwise nearly any word would do. Off by default.
+    pub fn set_fuzzy_prefixes(&mut self, fuzzy_prefixes: bool) {
+        self.fuzzy_prefixes = fuzzy_prefixes;
+        // cached results may have been produced under the other setting
+        self.clear_result_cache();
+    }
+
+    pub fn fuzzy_prefixes(&self) -> bool {
+        self.fuzzy_prefixes
+    }
+
+    // add the ranges of the words beginning with something close to `word` to `last_variants`
+    pub(super) fn add_fuzzy_prefix_variants(&self, word: &str, edit_distance: u8, last_variants: &mut Vec<QueryWord>) {
+        if word.chars().count() <= edit_distance as usize + 1 {
+            return;
+        }
+        let ranges = self.prefix_set.fuzzy_prefix_ranges(word, edit_distance).into_iter()
+            .map(|candidate| ((candidate.id_range.0 as u32, candidate.id_range.1 as u32), candidate.edit_distance))
+            .collect();
+        self.merge_prefix_ranges(ranges, last_variants);
+    }
+
+    // merge word ID ranges, each with the edit distance its words are at, into the prefix ranges
+    // already in `last_variants`: each word goes to the closest range or whole-word variant that
+    // covers it (a range, if they're equally close), leaving one prefix variant per disjoint run
+    // of words at the same distance, and dropping whole-word variants a range took over
+    pub(super) fn merge_prefix_ranges(&self, extra: Vec<((u32, u32), u8)>, last_variants: &mut Vec<QueryWord>) {
+        let mut claims: Vec<(u8, bool, (u32, u32))> = last_variants.iter().map(|variant| match *variant {
+            QueryWord::Prefix { id_range, edit_distance, .. } => (edit_distance, false, id_range),
+            QueryWord::Full { id, edit_distance, .. } => (edit_distance, true, (id, id)),
+        }).collect();
+        let mut replacements: Vec<(u32, u8)> = Vec::new();
+        for (range, distance) in extra {
+            claims.push((distance, false, range));
+            replacements.extend(self.word_replacement_map.range(range.0..=range.1).map(|(_, &target)| (target, distance)));
+        }
+        claims.sort_unstable();
+
+        // the parts of each claim nothing before it got, kept in order by word ID
+        let mut claimed: Vec<((u32, u32), u8, bool)> = Vec::with_capacity(claims.len());
+        for (distance, is_word, range) in claims {
+            let mut start = range.0;
+            let mut pieces: Vec<(u32, u32)> = Vec::new();
+            for &((lo, hi), _, _) in claimed.iter().filter(|&&((lo, hi), _, _)| hi >= range.0 && lo <= range.1) {
+                if lo > start {
+                    pieces.push((start, lo - 1));
+                }
+                start = start.max(hi + 1);
+            }
+            if start <= range.1 {
+                pieces.push((start, range.1));
+            }
+            claimed.extend(pieces.into_iter().map(|piece| (piece, distance, is_word)));
+            claimed.sort_unstable();
+        }
+
+        let mut merged: Vec<((u32, u32), u8)> = Vec::with_capacity(claimed.len());
+        for (range, distance, _) in claimed.into_iter().filter(|&(_, _, is_word)| !is_word) {
+            match merged.last_mut() {
+                Some(last) if last.1 == distance && range.0 == (last.0).1 + 1 => (last.0).1 = range.1,
+                _ => merged.push((range, distance)),
+            }
+        }
+
+        let covered = |id: u32| merged.iter().any(|&(range, _)| id >= range.0 && id <= range.1);
+        last_variants.retain(|variant| match *variant {
+            QueryWord::Full { id, .. } => !covered(id),
+            QueryWord::Prefix { .. } => false,
+        });
+        for (replacement, distance) in replacements {
+            let already = covered(replacement) || last_variants.iter().any(|&x| match x {
+                QueryWord::Full { id, .. } => id == replacement,
+                _ => false
+            });
+            if !already {
+                last_variants.insert(0, QueryWord::new_full_from(replacement, distance, VariantSource::Replacement));
+            }
+        }
+        // whole words stay sorted by edit distance, after the prefixes
+        last_variants.sort_by_key(|variant| variant.edit_distance());
+        let prefixes = merged.into_iter().map(|(range, distance)| QueryWord::new_fuzzy_prefix(range, distance));
+        last_variants.splice(0..0, prefixes);
+    }
+}
@@ -0,0 +1,64 @@
+use std::error::Error;
+
+use super::{FuzzyPhraseSet, FuzzyWindowResult, EndingType};
+use ::phrase::query::QueryWord;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GappedWindowResult {
+    /// The positions in the query of the tokens the window skipped over, in ascending order.
+    pub skipped: Vec<usize>,
+    /// The window, whose `tokens_consumed` counts the skipped tokens along with the matched ones,
+    /// so it still ends at `start_position + tokens_consumed`.
+    pub result: FuzzyWindowResult,
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match_windows`, but a window can skip over up to `max_skips` of the query's
+    /// tokens between the ones it matches, at a cost of `skip_penalty` each against
+    /// `max_phrase_dist`, so that something interjected into a phrase ("59 old apt 4 new milford
+    /// rd") doesn't split it up (see `PhraseSet::match_combinations_as_windows_with_gaps`).
+    /// Tokens that match nothing at all can be skipped like any other; they don't split the query
+    /// into separate runs the way they do for `fuzzy_match_windows`. Windows that don't skip
+    /// anything are the same as `fuzzy_match_windows` would find, though they aren't
+    /// deduplicated.
+    pub fn fuzzy_match_windows_with_gaps<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, max_skips: u8, skip_penalty: u8, ending_type: EndingType) -> Result<Vec<GappedWindowResult>, Box<dyn Error>> {
+        if phrase.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tokens_total = phrase.len();
+        let (phrase, offset, ending_type) = self.limit_query_length(phrase, ending_type)?;
+
+        // words with no possibilities get an empty slot, which can only be skipped
+        let word_possibilities: Vec<Vec<QueryWord>> = self.query_builder()
+            .max_word_dist(max_word_dist)
+            .ending_type(ending_type)
+            .build_each(phrase)?
+            .into_iter()
+            .map(|slot| slot.unwrap_or_default())
+            .collect();
+
+        let mut results: Vec<GappedWindowResult> = Vec::new();
+        for start in self.window_starts(&word_possibilities) {
+            let windows = self.phrase_set.match_combinations_as_windows_with_gaps(
+                &word_possibilities[start..],
+                max_phrase_dist,
+                ending_type != EndingType::NonPrefix,
+                max_skips,
+                skip_penalty
+            )?;
+            for gapped in windows {
+                // the tokens the window's words came from, so a prefix is echoed back as typed
+                let matched: Vec<&str> = (start..phrase.len())
+                    .filter(|&i| !gapped.skipped.contains(&(i - start)))
+                    .map(|i| phrase[i].as_ref())
+                    .collect();
+                let mut result = self.window_to_result(&matched, 0, &gapped.window, offset, tokens_total, ending_type);
+                result.start_position = offset + start;
+                result.tokens_consumed += gapped.skipped.len();
+                let skipped = gapped.skipped.iter().map(|&i| offset + start + i).collect();
+                results.push(GappedWindowResult { skipped, result });
+            }
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,66 @@
+use regex::{self, RegexSet};
+
+use super::FuzzyPhraseSet;
+
+/// Patterns for the unit designators that commonly appear in addresses ("apt", "ste", "#",
+/// etc.), for use with `FuzzyPhraseSet::set_fuzzy_guard_patterns`. House numbers and postcodes
+/// don't need guarding, since tokens with digits in them are never fuzzy-matched to begin with.
+pub const UNIT_DESIGNATOR_PATTERNS: &[&str] = &[
+    r"(?i)^(apt|apartment|unit|ste|suite|fl|floor|flr|rm|room|bldg|building|lot|spc|space|dept|trlr|ofc)$",
+    r"^#",
+];
+
+type FuzzyGuardClassifier = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+// the patterns and classifier that keep tokens from being fuzzy-matched; see
+// `set_fuzzy_guard_patterns` and `set_fuzzy_guard_classifier`
+#[derive(Default)]
+pub(super) struct FuzzyGuards {
+    patterns: Option<RegexSet>,
+    classifier: Option<FuzzyGuardClassifier>,
+}
+
+impl FuzzyGuards {
+    #[inline(always)]
+    fn guards(&self, word: &str) -> bool {
+        self.patterns.as_ref().is_some_and(|patterns| patterns.is_match(word)) ||
+            self.classifier.as_ref().is_some_and(|classifier| classifier(word))
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Never look for fuzzy variants of query tokens that match any of `patterns` (regular
+    /// expressions, as in the `regex` crate), whatever edit distance the query allows. They can
+    /// still match exactly, and as prefixes. This is for tokens whose near neighbors are almost
+    /// never what was meant, like unit designators (see `UNIT_DESIGNATOR_PATTERNS`), where a
+    /// typo correction is much more likely to produce a bad match than a good one. Replaces any
+    /// patterns set before; pass an empty list to remove them.
+    pub fn set_fuzzy_guard_patterns<S: AsRef<str>>(&mut self, patterns: &[S]) -> Result<(), regex::Error> {
+        self.fuzzy_guards.patterns = if patterns.is_empty() {
+            None
+        } else {
+            Some(RegexSet::new(patterns.iter().map(|pattern| pattern.as_ref()))?)
+        };
+        self.clear_result_cache();
+        Ok(())
+    }
+
+    /// Like `set_fuzzy_guard_patterns`, but with a function that decides which tokens to guard,
+    /// for rules that are awkward to express as regular expressions. Tokens are guarded if they
+    /// match a pattern or the classifier returns true for them.
+    pub fn set_fuzzy_guard_classifier<F: Fn(&str) -> bool + Send + Sync + 'static>(&mut self, classifier: F) {
+        self.fuzzy_guards.classifier = Some(Box::new(classifier));
+        self.clear_result_cache();
+    }
+
+    /// Remove all fuzzy guard patterns and the classifier.
+    pub fn clear_fuzzy_guards(&mut self) {
+        self.fuzzy_guards = FuzzyGuards::default();
+        self.clear_result_cache();
+    }
+
+    /// Whether the fuzzy guards keep `word` from being fuzzy-matched.
+    pub fn is_fuzzy_guarded(&self, word: &str) -> bool {
+        self.fuzzy_guards.guards(word)
+    }
+}
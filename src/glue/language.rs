@@ -0,0 +1,57 @@
+#[cfg(feature = "language-detection")]
+use whatlang;
+
+/// Guesses the language of a query, so that a `FederatedFuzzyPhraseSet` can tokenize it the way
+/// that language needs and only query the sets for that language (see
+/// `FederatedFuzzyPhraseSet::set_language_detector`). Closures from a string to an optional
+/// language code work as detectors too.
+pub trait LanguageDetector: Send + Sync {
+    /// The language `text` is most likely in, as one of the codes the federation's sets are
+    /// tagged with, or `None` if it can't tell (in which case every set is queried).
+    fn detect(&self, text: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String> + Send + Sync> LanguageDetector for F {
+    fn detect(&self, text: &str) -> Option<String> {
+        self(text)
+    }
+}
+
+/// A `LanguageDetector` backed by the `whatlang` crate, which reports ISO 639-3 codes ("eng",
+/// "deu", "jpn", etc.). Short queries often don't carry enough signal to tell languages apart,
+/// so guesses below a minimum confidence are discarded.
+#[cfg(feature = "language-detection")]
+#[derive(Debug, Clone, Copy)]
+pub struct WhatlangDetector {
+    min_confidence: f64,
+}
+
+#[cfg(feature = "language-detection")]
+impl WhatlangDetector {
+    /// A detector that only reports the guesses `whatlang` itself considers reliable.
+    pub fn new() -> WhatlangDetector {
+        WhatlangDetector { min_confidence: 1.0 }
+    }
+
+    /// A detector that reports any guess `whatlang` is at least `min_confidence` (between 0 and
+    /// 1) sure of, or considers reliable.
+    pub fn with_min_confidence(min_confidence: f64) -> WhatlangDetector {
+        WhatlangDetector { min_confidence }
+    }
+}
+
+#[cfg(feature = "language-detection")]
+impl Default for WhatlangDetector {
+    fn default() -> WhatlangDetector {
+        WhatlangDetector::new()
+    }
+}
+
+#[cfg(feature = "language-detection")]
+impl LanguageDetector for WhatlangDetector {
+    fn detect(&self, text: &str) -> Option<String> {
+        whatlang::detect(text)
+            .filter(|info| info.is_reliable() || info.confidence() >= self.min_confidence)
+            .map(|info| info.lang().code().to_owned())
+    }
+}
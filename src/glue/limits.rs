@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+use super::{FuzzyPhraseSet, EndingType};
+use ::instrument;
+
+/// What to do with a query that has more tokens than the configured maximum.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QueryLengthPolicy {
+    /// Refuse to run the query.
+    Error,
+    /// Drop tokens from the end of the query.
+    TruncateTail,
+    /// Keep the run of tokens of the maximum length that has the most tokens in the vocabulary
+    /// (or the earliest such run, if there's a tie).
+    BestWindow,
+}
+
+impl FuzzyPhraseSet {
+    /// Limit the number of tokens the matchers will consider in a single query. Very long queries
+    /// (a pasted full address with unit, city, region, postcode and country, say) make for huge
+    /// possibility matrices and deep recursion, so this lets callers cap them, with `policy`
+    /// deciding what happens to queries over the limit. Pass `None` to remove the limit.
+    pub fn set_max_query_length(&mut self, max_tokens: Option<usize>, policy: QueryLengthPolicy) {
+        self.max_query_length = max_tokens.map(|max_tokens| (max_tokens, policy));
+        // cached results may have been cut down under the old limit
+        self.clear_result_cache();
+    }
+
+    /// Apply the configured query length limit to a query, returning the part of it that should
+    /// be matched, the position in the original query that part starts at, and the ending type
+    /// it should be matched with (if the tail of the query was cut off, its new last token is
+    /// known to be a whole word).
+    pub(super) fn limit_query_length<'a, T: AsRef<str>>(&self, phrase: &'a [T], ending_type: EndingType) -> Result<(&'a [T], usize, EndingType), Box<dyn Error>> {
+        let (max_tokens, policy) = match self.max_query_length {
+            Some(limit) if phrase.len() > limit.0 => limit,
+            _ => return Ok((phrase, 0, ending_type)),
+        };
+        instrument::record_limit_exceeded(policy);
+
+        let start = match policy {
+            QueryLengthPolicy::Error => {
+                return Err(Box::new(IoError::new(IoErrorKind::InvalidInput, format!(
+                    "Query has {} tokens; the maximum is {}", phrase.len(), max_tokens
+                ))));
+            },
+            QueryLengthPolicy::TruncateTail => 0,
+            QueryLengthPolicy::BestWindow => {
+                let known: Vec<usize> = phrase.iter().map(|word| {
+                    if self.prefix_set.lookup(word.as_ref()).found_final() { 1 } else { 0 }
+                }).collect();
+                // slide a window across the query, keeping a running count of known words
+                let mut count: usize = known[..max_tokens].iter().sum();
+                let (mut best_start, mut best_count) = (0, count);
+                for start in 1..=(phrase.len() - max_tokens) {
+                    count = count + known[start + max_tokens - 1] - known[start - 1];
+                    if count > best_count {
+                        best_start = start;
+                        best_count = count;
+                    }
+                }
+                best_start
+            },
+        };
+
+        let end = start + max_tokens;
+        let ending_type = if end < phrase.len() && ending_type == EndingType::AnyPrefix {
+            EndingType::WordBoundaryPrefix
+        } else {
+            ending_type
+        };
+        Ok((&phrase[start..end], start, ending_type))
+    }
+}
@@ -1,23 +1,36 @@
 use std::collections::{BTreeMap, hash_map};
+use std::hash::Hash;
 use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind, BufReader, BufWriter};
 use std::fs;
 use std::iter;
+use std::mem;
 use std::cmp::Ord;
 use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde_json;
-use fst::Streamer;
-use fst::raw::Output;
+use serde::{Deserialize, Serialize};
+use rmps::{Deserializer, Serializer};
+use fst_compat::Streamer;
+use fst_compat::raw::Output;
 use regex;
 use rustc_hash::FxHashMap;
+use xxhash_rust::xxh64::Xxh64;
 
 use ::prefix::{PrefixSet, PrefixSetBuilder};
-use ::phrase::{PhraseSet, PhraseSetBuilder};
+use ::phrase::{PhraseSet, PhraseSetBuilder, PhraseMatchKind, Combination, CombinationWindow, WordDepths, WordBitset};
 use ::phrase::util::PhraseSetError;
-use ::phrase::query::QueryWord;
+use ::phrase::louds::{LoudsTrie, LoudsTrieBuilder};
+use ::phrase::query::{QueryWord, VariantSource};
+use ::phrase::filter::PhraseIdFilter;
+use ::phrase::classes::{TokenClassSet, TokenConstraints, WordClasses};
 use ::fuzzy::{FuzzyMap, FuzzyMapBuilder};
+use ::progress::{ProgressReporter, ProgressTracker, check_cancelled};
+use ::instrument;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
 
 use std::{str, fmt};
 #[macro_use] mod enum_number;
@@ -25,6 +38,80 @@ use std::{str, fmt};
 pub mod unicode_ranges;
 mod util;
 mod bins;
+mod query;
+mod diff;
+mod delta;
+mod correction;
+mod limits;
+mod numbers;
+mod pruning;
+mod tokenize;
+mod federated;
+mod exact;
+mod classes;
+mod bias;
+mod partial;
+mod spellcheck;
+mod alignment;
+mod verify;
+mod records;
+mod order;
+mod options;
+mod cache;
+mod diagnostics;
+mod dedup;
+mod guards;
+mod fuzzy_prefix;
+mod rerank;
+mod language;
+mod display;
+mod segment;
+mod warmup;
+mod backend;
+mod reorder;
+mod gaps;
+mod duplicates;
+mod completion;
+mod overlay;
+mod weights;
+mod composition;
+mod density;
+#[cfg(feature = "query-log")]
+mod querylog;
+
+pub use self::query::QueryBuilder;
+pub use self::limits::QueryLengthPolicy;
+pub use self::numbers::mask_number;
+pub use self::diff::FuzzyPhraseSetDiff;
+pub use self::delta::FuzzyPhraseSetDelta;
+pub use self::pruning::{RareWordPolicy, PRUNED_PHRASE_ID};
+pub use self::tokenize::{tokenize, tokenize_with, punctuation_variants, TokenizerConfig, JoinRule};
+pub use self::federated::{FederatedFuzzyPhraseSet, FederatedMatchResult, FederatedWindowResult};
+pub use self::bias::{ContextBias, BiasedMatchResult};
+pub use self::partial::BestEffortWindows;
+pub use self::alignment::{AlignedMatchResult, TokenEdit, EditOp};
+pub use self::verify::{VerificationReport, VerificationIssue, VerificationFailure};
+pub use self::records::{CorpusRecord, PhraseMetadata};
+pub use self::cache::{ResultCacheConfig, ResultCacheStats};
+pub use self::options::IndexOptions;
+pub use self::diagnostics::{NoMatchReport, NoMatchReason, TokenDiagnosis};
+pub use self::guards::UNIT_DESIGNATOR_PATTERNS;
+pub use self::rerank::{RerankConfig, RerankedMatchResult};
+pub use self::language::LanguageDetector;
+pub use self::display::PhraseDisplay;
+pub use self::segment::{Segmentation, Segmenter};
+pub use self::warmup::{WarmUpStrategy, WarmUpReport};
+pub use self::reorder::ReorderedMatchResult;
+pub use self::gaps::GappedWindowResult;
+pub use self::duplicates::{NearDuplicate, NearDuplicateReport};
+pub use self::completion::{Completion, CompletionPage, CompletionCursor};
+pub use self::overlay::WeightOverlay;
+pub use self::composition::{PrefixSegmentation, normalize_prefix_token};
+pub use self::density::{VocabularyDensity, LengthDensity, EditDistanceRecommendation};
+#[cfg(feature = "query-log")]
+pub use self::querylog::{read_query_log, RecordedQuery, RecordedQueryKind, RecordedResult, ReplayDifference};
+#[cfg(feature = "language-detection")]
+pub use self::language::WhatlangDetector;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct WordReplacement {
@@ -40,26 +127,78 @@ pub struct FuzzyPhraseSetBuilder {
     words_to_tmp_word_ids: BTreeMap<String, u32>,
     word_replacements: Vec<WordReplacement>,
     word_replacement_map: FxHashMap<u32, u32>,
+    // normalized word -> how it should be displayed, for words where those differ
+    display_forms: BTreeMap<String, String>,
+    // tmp phrase ID -> the numeric ranges covered by a masked number in that phrase
+    number_ranges: FxHashMap<u32, Vec<(u32, u32)>>,
+    // tmp phrase ID -> what was inserted with the phrase, for phrases inserted from records
+    phrase_metadata: FxHashMap<u32, PhraseMetadata>,
+    build_info: BTreeMap<String, String>,
+    // whether to add the build time to build_info
+    record_build_timestamp: bool,
+    // tmp word ID -> how many times it's been inserted, for pruning rare words
+    word_counts: FxHashMap<u32, u32>,
+    min_word_frequency: Option<(u32, RareWordPolicy)>,
+    // normalized word -> the classes it's been tagged with
+    word_classes: BTreeMap<String, TokenClassSet>,
+    options: IndexOptions,
+    // the caller's segmenter, for `Segmentation::Custom`
+    segmenter: segment::CustomSegmenter,
+    // the max_neighbors to choose the edit distance with; see set_auto_edit_distance
+    auto_edit_distance: Option<f64>,
+    // the zstd level to compress the FSTs with, if they should be
+    #[cfg(feature = "compression")]
+    compression_level: Option<i32>,
+    // vocabularies smaller than this get a fuzzy variant table instead of an FST
+    small_index_threshold: usize,
+    progress: Option<ProgressTracker>,
+    cancel: Option<Arc<AtomicBool>>,
     directory: PathBuf,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+// everything `FuzzyPhraseSetBuilder::finish` might write
+static OUTPUT_FILES: &[&str] = &[
+    "prefix.fst", "fuzzy.fst", "fuzzy.tbl", "fuzzy.ids", "fuzzy.flt", "display.msg", "phrase.fst", "phrase.louds", "number_ranges.msg", "word_classes.msg", "word_frequencies.msg", "word_depths.msg", "phrase_metadata.msg", "metadata.json"
+];
+
+// version 3 replaced the msgpack-encoded fuzzy map ID list (fuzzy.msg) with a flat one
+// (fuzzy.ids); indexes from version 2 on can still be read
+const FORMAT_VERSION: u32 = 3;
+const OLDEST_FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct FuzzyPhraseSetMetadata {
     index_type: String,
     format_version: u32,
-    fuzzy_enabled_scripts: Vec<String>,
-    max_edit_distance: u8,
-    word_replacements: Vec<WordReplacement>
+    word_replacements: Vec<WordReplacement>,
+    // free-form provenance info (corpus version, build time, etc.); older indexes won't have it
+    #[serde(default)]
+    build_info: BTreeMap<String, String>,
+    // random identifier shared by all the components of a single build; 0 for older indexes
+    #[serde(default)]
+    build_id: u64,
+    // older indexes won't have all of these, and take the defaults for the ones they're missing
+    #[serde(flatten)]
+    options: IndexOptions,
+    // what the edit distance was chosen by, if the builder chose it
+    #[serde(default)]
+    vocabulary_density: Option<VocabularyDensity>,
+    // the hash of each of the other files, as written; see `FuzzyPhraseSet::verify_checksums`
+    #[serde(default)]
+    checksums: BTreeMap<String, String>,
 }
 
 impl Default for FuzzyPhraseSetMetadata {
     fn default() -> FuzzyPhraseSetMetadata {
         FuzzyPhraseSetMetadata {
             index_type: "fuzzy_phrase_set".to_string(),
-            format_version: 2,
-            fuzzy_enabled_scripts: vec!["Latin".to_string(), "Greek".to_string(), "Cyrillic".to_string()],
-            max_edit_distance: 1,
-            word_replacements: vec![]
+            format_version: FORMAT_VERSION,
+            word_replacements: vec![],
+            build_info: BTreeMap::new(),
+            build_id: 0,
+            options: IndexOptions::default(),
+            vocabulary_density: None,
+            checksums: BTreeMap::new(),
         }
     }
 }
@@ -119,9 +258,93 @@ impl FuzzyPhraseSetBuilder {
             tmp_word_id_phrase.push(word_id.to_owned());
         }
 
-        let current_phrase_len = self.phrases.len();
-        let phrase_id = self.phrases.entry(tmp_word_id_phrase).or_insert(current_phrase_len as u32);
-        Ok(*phrase_id)
+        let current_phrase_len = self.phrases.len() as u32;
+        match self.phrases.entry(tmp_word_id_phrase) {
+            hash_map::Entry::Occupied(entry) => Ok(*entry.get()),
+            hash_map::Entry::Vacant(entry) => {
+                // a phrase only counts towards its words' frequencies the first time it's inserted
+                for word_id in entry.key() {
+                    *self.word_counts.entry(*word_id).or_insert(0) += 1;
+                }
+                entry.insert(current_phrase_len);
+                Ok(current_phrase_len)
+            },
+        }
+    }
+
+    /// Insert a phrase along with the form each of its words should be displayed in, for callers
+    /// that normalize (lowercase, strip accents, etc.) words before indexing them. `display` must
+    /// be the same length as `phrase`. If a word is inserted with more than one display form, the
+    /// first one wins.
+    pub fn insert_with_display<T: AsRef<str>, U: AsRef<str>>(&mut self, phrase: &[T], display: &[U]) -> Result<u32, Box<dyn Error>> {
+        if phrase.len() != display.len() {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidInput, "Phrase and display form have different lengths")));
+        }
+        for (word, display_word) in phrase.iter().zip(display.iter()) {
+            let (word, display_word) = (word.as_ref(), display_word.as_ref());
+            if word != display_word && !self.display_forms.contains_key(word) {
+                self.display_forms.insert(word.to_owned(), display_word.to_owned());
+            }
+        }
+        self.insert(phrase)
+    }
+
+    /// Insert a phrase containing a masked number (like "1##" for the 100 block of a street),
+    /// along with the (inclusive) range of actual numbers it stands for. A phrase can be inserted
+    /// with more than one range. See `FuzzyPhraseSet::fuzzy_match_number`.
+    pub fn insert_with_number_range<T: AsRef<str>>(&mut self, phrase: &[T], range: (u32, u32)) -> Result<u32, Box<dyn Error>> {
+        if range.0 > range.1 {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidInput, "Number range start is after its end")));
+        }
+        let phrase_id = self.insert(phrase)?;
+        self.number_ranges.entry(phrase_id).or_default().push(range);
+        Ok(phrase_id)
+    }
+
+    /// Record a piece of provenance information (corpus version, data source, builder options,
+    /// etc.) in the index metadata, where it can be read back with `FuzzyPhraseSet::build_info`.
+    /// The library version is recorded automatically, unless it's set here. The files written by
+    /// `finish` depend only on what was inserted (not the order it was inserted in), so builds
+    /// are byte-for-byte reproducible unless the build time is recorded too (see
+    /// `set_record_build_timestamp`).
+    pub fn set_build_info<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.build_info.insert(key.into(), value.into());
+    }
+
+    /// Record the time `finish` was called (in seconds since the Unix epoch) as the
+    /// "build_timestamp" build info, unless it's been set with `set_build_info`. Off by default,
+    /// since it makes otherwise identical builds differ.
+    pub fn set_record_build_timestamp(&mut self, record: bool) {
+        self.record_build_timestamp = record;
+    }
+
+    /// Call `reporter` every `interval` items processed while the set is being built (that is,
+    /// during `finish`); it's handed down to the builders for the fuzzy map and the phrase graph,
+    /// which between them account for nearly all of the build time.
+    pub fn set_progress_reporter<R: ProgressReporter + Send + 'static>(&mut self, reporter: R, interval: u64) {
+        self.progress = Some(ProgressTracker::new(reporter, interval));
+    }
+
+    /// Abort `finish` (with an `Interrupted` error) if `cancel` gets set while it's running. Any
+    /// files the build had already written are removed.
+    pub fn set_cancellation_flag(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+
+    /// Have `finish` zstd-compress the prefix set, phrase graph and fuzzy map at `level` (see
+    /// `compress::DEFAULT_LEVEL`) once they're written, for indexes that are shipped around more
+    /// than they're loaded. `FuzzyPhraseSet::from_path` decompresses them transparently. Pass
+    /// `None` to write them uncompressed, as by default.
+    #[cfg(feature = "compression")]
+    pub fn set_compression_level(&mut self, level: Option<i32>) {
+        self.compression_level = level;
+    }
+
+    /// Write the fuzzy map as a hashed variant table rather than an FST if the vocabulary has
+    /// fewer than `max_words` words (see `FuzzyMapBuilder::set_small_index_threshold`), trading
+    /// space for faster fuzzy lookups. Off (0) by default.
+    pub fn set_small_index_threshold(&mut self, max_words: usize) {
+        self.small_index_threshold = max_words;
     }
 
     // convenience method that splits the input string on the space character
@@ -133,37 +356,111 @@ impl FuzzyPhraseSetBuilder {
     }
 
     pub fn finish(self) -> Result<Vec<u32>, Box<Error>> {
-        // in the future we could make some of this setable from the outside
-        let mut metadata = FuzzyPhraseSetMetadata::default();
+        let directory = self.directory.clone();
+        let cancel = self.cancel.clone();
+        let result = self.write();
+        if result.is_err() {
+            if let Err(e) = check_cancelled(&cancel) {
+                for file in OUTPUT_FILES {
+                    let _ = fs::remove_file(directory.join(Path::new(file)));
+                }
+                // report the cancellation itself, rather than however it surfaced from the
+                // component that noticed it (not every FST release's errors keep the message)
+                return Err(Box::new(e));
+            }
+        }
+        result
+    }
+
+    fn write(mut self) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut metadata = FuzzyPhraseSetMetadata { options: self.options.clone(), ..Default::default() };
+
+        // pruning removes phrases, so grab the number of temp phrase IDs handed out first; it
+        // can also remove words, so temp word IDs might not be contiguous anymore afterwards
+        let tmp_phrase_count = self.phrases.len();
+        let pruned_phrases = self.prune_rare_words();
+        let tmp_word_count = self.words_to_tmp_word_ids.values().max().map_or(0, |id| *id as usize + 1);
 
         // we can go from name -> tmp_word_id
         // we need to go from tmp_word_id -> id
         // so build a mapping that does that
-        let mut tmp_word_ids_to_ids: Vec<u32> = vec![0; self.words_to_tmp_word_ids.len()];
+        // (words_to_tmp_word_ids is a btreemap over word keys, so when we iterate over it, we'll get
+        // back words sorted, and lex ids are just their positions in that list)
+        let mut tmp_word_ids_to_ids: Vec<u32> = vec![0; tmp_word_count];
+        for (id, tmp_word_id) in self.words_to_tmp_word_ids.values().enumerate() {
+            tmp_word_ids_to_ids[*tmp_word_id as usize] = id as u32;
+        }
+
+        let mut final_phrases: Vec<(Vec<u32>, u32)> = Vec::new();
+        // next, renumber all of the current phrases with real rather than temp IDs
+        for (mut phrase, tmp_phrase_id) in mem::take(&mut self.phrases).into_iter() {
+            for word_idx in (*phrase).iter_mut() {
+                *word_idx = tmp_word_ids_to_ids[*word_idx as usize];
+            }
+            final_phrases.push((phrase, tmp_phrase_id));
+        }
+
+        final_phrases.sort();
+
+        // phrase IDs are positions in the sorted list
+        let mut tmp_phrase_ids_to_ids: Vec<u32> = vec![PRUNED_PHRASE_ID; tmp_phrase_count];
+        for (id, phrase) in final_phrases.iter().enumerate() {
+            tmp_phrase_ids_to_ids[phrase.1 as usize] = id as u32;
+        }
+        // phrases that got merged into others during pruning share their IDs
+        for (tmp_phrase_id, merged_into) in pruned_phrases {
+            if let Some(merged_into) = merged_into {
+                tmp_phrase_ids_to_ids[tmp_phrase_id as usize] = tmp_phrase_ids_to_ids[merged_into as usize];
+            }
+        }
+
+        // pick the edit distance to suit the vocabulary, if asked to, now that it's final
+        if let Some(max_neighbors) = self.auto_edit_distance {
+            let density = density::measure(self.words_to_tmp_word_ids.keys().map(|word| word.as_str()), &metadata.options)?;
+            let recommendation = density.recommend(max_neighbors);
+            metadata.options.max_edit_distance = recommendation.max_edit_distance;
+            metadata.options.distance_min_lengths = recommendation.distance_min_lengths;
+            metadata.vocabulary_density = Some(density);
+        }
+
+        // the optional files are serialized up front, since the build ID covers them
+        let sidecars = self.serialize_sidecars(&tmp_word_ids_to_ids, &tmp_phrase_ids_to_ids, &final_phrases)?;
+        metadata.word_replacements = mem::take(&mut self.word_replacements);
+
+        // stamp every component with the same ID so that we can tell on load if they've gotten
+        // mixed up with the components of some other build; it's derived from the contents so
+        // that building the same corpus twice produces identical files
+        metadata.build_id = content_build_id(&self.words_to_tmp_word_ids, &final_phrases, &metadata, &sidecars)?;
 
         let prefix_writer = BufWriter::new(fs::File::create(self.directory.join(Path::new("prefix.fst")))?);
-        let mut prefix_set_builder = PrefixSetBuilder::new(prefix_writer)?;
 
-        let mut fuzzy_map_builder = FuzzyMapBuilder::new(
+        let mut prefix_set_builder = PrefixSetBuilder::new_with_build_id(prefix_writer, metadata.build_id)?;
+
+        let mut fuzzy_map_builder = FuzzyMapBuilder::new_with_build_id(
             self.directory.join(Path::new("fuzzy")),
-            metadata.max_edit_distance
+            metadata.options.max_edit_distance,
+            metadata.build_id
         )?;
+        fuzzy_map_builder.set_progress_tracker(self.progress.clone());
+        fuzzy_map_builder.set_distance_min_lengths(metadata.options.distance_min_lengths.clone());
+        fuzzy_map_builder.set_small_index_threshold(self.small_index_threshold);
+        fuzzy_map_builder.set_script_strategies(metadata.options.script_strategies()?);
+        if metadata.options.membership_filter {
+            // about a 1% false positive rate
+            fuzzy_map_builder.set_membership_filter(10);
+        }
+        if let Some(ref cancel) = self.cancel {
+            fuzzy_map_builder.set_cancellation_flag(cancel.clone());
+        }
 
         // this is a regex set to decide whether to index somehing for fuzzy matching
-        let allowed_scripts = &metadata.fuzzy_enabled_scripts.iter().map(
-            |s| unicode_ranges::get_script_by_name(s)
-        ).collect::<Option<Vec<_>>>().ok_or("unknown script")?;
-        let script_regex = regex::Regex::new(
-            &unicode_ranges::get_pattern_for_scripts(&allowed_scripts),
-        )?;
+        let script_regex = metadata.options.script_regex()?;
 
-        // words_to_tmp_word_ids is a btreemap over word keys,
-        // so when we iterate over it, we'll get back words sorted
-        // we'll do three things with that:
+        // we'll do two things with the sorted words:
         // - build up our prefix set
-        // - map from temporary IDs to lex ids (which we can get just be enumerating our sorted list)
         // - build up our fuzzy set (this one doesn't require the sorted words, but it doesn't hurt)
-        for (id, (word, tmp_word_id)) in self.words_to_tmp_word_ids.iter().enumerate() {
+        for (id, word) in self.words_to_tmp_word_ids.keys().enumerate() {
+            check_cancelled(&self.cancel)?;
             let id = id as u32;
 
             prefix_set_builder.insert(word)?;
@@ -173,39 +470,68 @@ impl FuzzyPhraseSetBuilder {
             if allowed {
                 fuzzy_map_builder.insert(word, id);
             }
-
-            tmp_word_ids_to_ids[*tmp_word_id as usize] = id;
         }
 
         prefix_set_builder.finish()?;
         fuzzy_map_builder.finish()?;
 
-        let mut final_phrases: Vec<(Vec<u32>, u32)> = Vec::new();
-        // next, renumber all of the current phrases with real rather than temp IDs
-        for (mut phrase, tmp_phrase_id) in self.phrases.into_iter() {
-            for word_idx in (*phrase).iter_mut() {
-                *word_idx = tmp_word_ids_to_ids[*word_idx as usize];
+        let phrase_writer = BufWriter::new(fs::File::create(self.directory.join(Path::new("phrase.fst")))?);
+        let mut phrase_set_builder = PhraseSetBuilder::new_with_build_id(phrase_writer, metadata.build_id)?;
+        phrase_set_builder.set_progress_tracker(self.progress.clone());
+        if let Some(ref cancel) = self.cancel {
+            phrase_set_builder.set_cancellation_flag(cancel.clone());
+        }
+
+        let mut louds_trie_builder = if metadata.options.louds_trie {
+            Some(LoudsTrieBuilder::new_with_build_id(metadata.build_id))
+        } else {
+            None
+        };
+
+        for (phrase, _tmp_phrase_id) in final_phrases.iter() {
+            phrase_set_builder.insert(phrase)?;
+            if let Some(ref mut louds_trie_builder) = louds_trie_builder {
+                louds_trie_builder.insert(phrase)?;
             }
-            final_phrases.push((phrase, tmp_phrase_id));
         }
 
-        final_phrases.sort();
+        phrase_set_builder.finish()?;
 
-        let phrase_writer = BufWriter::new(fs::File::create(self.directory.join(Path::new("phrase.fst")))?);
-        let mut phrase_set_builder = PhraseSetBuilder::new(phrase_writer)?;
+        if let Some(louds_trie_builder) = louds_trie_builder {
+            let louds_writer = BufWriter::new(fs::File::create(self.directory.join(Path::new("phrase.louds")))?);
+            louds_trie_builder.finish().write(louds_writer)?;
+        }
 
-        let mut tmp_phrase_ids_to_ids: Vec<u32> = vec![0; final_phrases.len()];
-        for (id, phrase) in final_phrases.into_iter().enumerate() {
-            phrase_set_builder.insert(&phrase.0)?;
-            tmp_phrase_ids_to_ids[phrase.1 as usize] = id as u32;
+        for (name, bytes) in &sidecars {
+            if let Some(ref bytes) = *bytes {
+                fs::write(self.directory.join(Path::new(name)), bytes)?;
+            }
         }
 
-        phrase_set_builder.finish()?;
+        metadata.build_info = self.build_info;
+        metadata.build_info.entry("fuzzy_phrase_version".to_string())
+            .or_insert_with(|| env!("CARGO_PKG_VERSION").to_string());
+        if self.record_build_timestamp {
+            metadata.build_info.entry("build_timestamp".to_string())
+                .or_insert_with(|| format!("{}", SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)));
+        }
 
-        for word_replacement in self.word_replacements {
-            metadata.word_replacements.push(word_replacement);
+        #[cfg(feature = "compression")]
+        {
+            if let Some(level) = self.compression_level {
+                for name in &["prefix.fst", "phrase.fst", "fuzzy.fst", "fuzzy.tbl"] {
+                    let path = self.directory.join(name);
+                    if path.exists() {
+                        let compressed_path = path.with_extension("zst");
+                        ::compress::compress_file(&path, &compressed_path, level)?;
+                        fs::rename(&compressed_path, &path)?;
+                    }
+                }
+            }
         }
 
+        metadata.checksums = verify::file_checksums(&self.directory)?;
+
         let metadata_writer = BufWriter::new(fs::File::create(self.directory.join(Path::new("metadata.json")))?);
         serde_json::to_writer_pretty(metadata_writer, &metadata)?;
 
@@ -213,14 +539,171 @@ impl FuzzyPhraseSetBuilder {
     }
 }
 
+// the optional files a build writes, as (file name, contents), with no contents for the ones
+// there's nothing to write to
+type Sidecars = Vec<(&'static str, Option<Vec<u8>>)>;
+
+impl FuzzyPhraseSetBuilder {
+    // msgpack-encode each of the optional files, in final IDs
+    fn serialize_sidecars(&mut self, tmp_word_ids_to_ids: &[u32], tmp_phrase_ids_to_ids: &[u32], final_phrases: &[(Vec<u32>, u32)]) -> Result<Sidecars, Box<dyn Error>> {
+        fn encode<T: Serialize>(value: &T) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+            let mut bytes: Vec<u8> = Vec::new();
+            value.serialize(&mut Serializer::new(&mut bytes))?;
+            Ok(Some(bytes))
+        }
+        let words_to_tmp_word_ids = &self.words_to_tmp_word_ids;
+        let final_word_id = |word: &str| words_to_tmp_word_ids.get(word).map(|tmp_word_id| tmp_word_ids_to_ids[*tmp_word_id as usize]);
+
+        // display forms are optional, so only write them out if there are any (skipping any
+        // words that were pruned)
+        let display_forms = if self.display_forms.is_empty() { None } else {
+            let display_forms: Vec<(u32, &String)> = self.display_forms.iter().filter_map(|(word, display_word)| {
+                final_word_id(word).map(|id| (id, display_word))
+            }).collect();
+            encode(&display_forms)?
+        };
+
+        // so are word classes
+        let word_classes = if self.word_classes.is_empty() { None } else {
+            let word_classes: Vec<(u32, u8)> = self.word_classes.iter().filter_map(|(word, classes)| {
+                final_word_id(word).map(|id| (id, classes.bits()))
+            }).collect();
+            encode(&word_classes)?
+        };
+
+        // word frequencies are opt-in; they're in word ID order, which is the order of the words
+        let word_frequencies = if !self.options.word_frequencies { None } else {
+            let frequencies: Vec<u32> = self.words_to_tmp_word_ids.values().map(|tmp_word_id| {
+                self.word_counts.get(tmp_word_id).cloned().unwrap_or(0)
+            }).collect();
+            encode(&frequencies)?
+        };
+
+        // as are word depths, which are in final word IDs
+        let word_depths = if !self.options.word_depths { None } else {
+            encode(&WordDepths::from_phrases(final_phrases.iter().map(|(phrase, _)| &phrase[..])))?
+        };
+
+        // number ranges are optional too, so likewise only write them if there are any
+        let number_ranges = if self.number_ranges.is_empty() { None } else {
+            let mut number_ranges: Vec<(u32, u32, u32)> = Vec::new();
+            for (tmp_phrase_id, ranges) in self.number_ranges.iter() {
+                let id = tmp_phrase_ids_to_ids[*tmp_phrase_id as usize];
+                number_ranges.extend(ranges.iter().map(|range| (id, range.0, range.1)));
+            }
+            number_ranges.sort();
+            encode(&number_ranges)?
+        };
+
+        // as is phrase metadata
+        let phrase_metadata = if self.phrase_metadata.is_empty() { None } else {
+            let phrase_metadata = mem::take(&mut self.phrase_metadata);
+            encode(&records::finalize_phrase_metadata(phrase_metadata, tmp_phrase_ids_to_ids, PRUNED_PHRASE_ID))?
+        };
+
+        Ok(vec![
+            ("display.msg", display_forms),
+            ("word_classes.msg", word_classes),
+            ("word_frequencies.msg", word_frequencies),
+            ("word_depths.msg", word_depths),
+            ("number_ranges.msg", number_ranges),
+            ("phrase_metadata.msg", phrase_metadata),
+        ])
+    }
+}
+
+// The build ID is the xxh64 (seed 0) of everything the build writes: the words, in order; the
+// phrases, in order, as little-endian u32 word IDs; the JSON-serialized index options and word
+// replacements; and the contents of each optional file (empty if there isn't one). Each of these
+// goes in as a little-endian u64 byte length followed by the bytes, and lists are preceded by
+// their lengths, so no two different builds hash the same input.
+fn content_build_id(
+    words: &BTreeMap<String, u32>,
+    phrases: &[(Vec<u32>, u32)],
+    metadata: &FuzzyPhraseSetMetadata,
+    sidecars: &Sidecars
+) -> Result<u64, Box<dyn Error>> {
+    let mut hasher = Xxh64::new(0);
+    let mut write = |bytes: &[u8]| {
+        hasher.update(&(bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    write(&(words.len() as u64).to_le_bytes());
+    for word in words.keys() {
+        write(word.as_bytes());
+    }
+    write(&(phrases.len() as u64).to_le_bytes());
+    let mut phrase_bytes: Vec<u8> = Vec::new();
+    for (phrase, _tmp_phrase_id) in phrases {
+        phrase_bytes.clear();
+        for word_id in phrase {
+            phrase_bytes.extend_from_slice(&word_id.to_le_bytes());
+        }
+        write(&phrase_bytes);
+    }
+    write(&serde_json::to_vec(&metadata.options)?);
+    write(&serde_json::to_vec(&metadata.word_replacements)?);
+    for (_name, bytes) in sidecars {
+        write(bytes.as_ref().map_or(&[][..], |bytes| &bytes[..]));
+    }
+    // 0 means "unstamped," so don't use it
+    Ok(hasher.digest().max(1))
+}
+
+// a run of consecutive query words that all have possibilities, starting at `start_position`
+#[derive(Debug)]
+struct WindowSubquery {
+    start_position: usize,
+    ending_type: EndingType,
+    word_possibilities: Vec<Vec<QueryWord>>
+}
+
 pub struct FuzzyPhraseSet {
     prefix_set: PrefixSet,
     phrase_set: PhraseSet,
     fuzzy_map: FuzzyMap,
     word_list: Vec<String>,
     word_replacement_map: BTreeMap<u32, u32>,
+    display_forms: BTreeMap<u32, String>,
+    number_ranges: BTreeMap<u32, Vec<(u32, u32)>>,
+    phrase_metadata: BTreeMap<u32, PhraseMetadata>,
+    // the phrases' built weights, indexed for rerank's popularity
+    built_weights: weights::BuiltWeights,
+    word_classes: WordClasses,
+    // word ID -> insertion count; empty unless the builder was asked to store them
+    word_frequencies: Vec<u32>,
     script_regex: regex::Regex,
     max_edit_distance: u8,
+    max_query_length: Option<(usize, QueryLengthPolicy)>,
+    // the words at each phrase position, if the builder stored them
+    word_depths: Option<WordDepths>,
+    // the words that can begin a phrase, for skipping window start positions
+    first_words: WordBitset,
+    build_info: BTreeMap<String, String>,
+    options: IndexOptions,
+    // optional hash table for exact word lookups; see enable_exact_lookup_cache
+    exact_word_ids: Option<FxHashMap<String, u32>>,
+    // optional cache of recent query results; see enable_result_cache
+    result_cache: Option<Mutex<cache::ResultCache>>,
+    // whether to keep only the best window per phrase; see set_dedup_windows
+    dedup_windows: bool,
+    // tokens never to look for fuzzy variants of; see set_fuzzy_guard_patterns
+    fuzzy_guards: guards::FuzzyGuards,
+    // whether prefix queries also match near misses of the final token; see set_fuzzy_prefixes
+    fuzzy_prefixes: bool,
+    // how the final token of prefix queries is broken into characters; see set_prefix_segmentation
+    prefix_segmentation: composition::PrefixSegmentation,
+    // the caller's segmenter, for `Segmentation::Custom`; see set_segmenter
+    segmenter: segment::CustomSegmenter,
+    // the phrase graph as a succinct trie, if the builder was asked to write one
+    louds_trie: Option<LoudsTrie>,
+    // phrase weights that can change after the build; see set_weight_overlay
+    weight_overlay: Option<overlay::WeightOverlay>,
+    // what the edit distance was chosen by; see vocabulary_density
+    vocabulary_density: Option<VocabularyDensity>,
+    // where to record queries, if anywhere; see set_query_recorder
+    #[cfg(feature = "query-log")]
+    query_recorder: Option<querylog::QueryRecorder>,
 }
 
 enum_number! {
@@ -246,6 +729,20 @@ pub struct FuzzyWindowResult {
     pub start_position: usize,
     pub ending_type: EndingType,
     pub phrase_id_range: (u32, u32),
+    // how many of the query's tokens this window matched, out of how many there were in total
+    pub tokens_consumed: usize,
+    pub tokens_total: usize,
+}
+
+impl FuzzyWindowResult {
+    /// The fraction of the query's tokens covered by this window.
+    pub fn coverage(&self) -> f64 {
+        if self.tokens_total == 0 {
+            0.0
+        } else {
+            self.tokens_consumed as f64 / self.tokens_total as f64
+        }
+    }
 }
 
 impl<'a, 'b> PartialEq<FuzzyMatchResult> for FuzzyWindowResult {
@@ -271,18 +768,20 @@ impl FuzzyPhraseSet {
         let metadata_reader = BufReader::new(fs::File::open(directory.join(Path::new("metadata.json")))?);
         let metadata: FuzzyPhraseSetMetadata = serde_json::from_reader(metadata_reader)?;
         let default = FuzzyPhraseSetMetadata::default();
-        if metadata.index_type != default.index_type || metadata.format_version != default.format_version {
+        if metadata.index_type != default.index_type {
             return Err(Box::new(IoError::new(IoErrorKind::InvalidData, "Unexpected structure metadata")));
         }
+        if metadata.format_version < OLDEST_FORMAT_VERSION || metadata.format_version > FORMAT_VERSION {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidData, format!(
+                "Index has format version {}, but only versions {} to {} can be read",
+                metadata.format_version, OLDEST_FORMAT_VERSION, FORMAT_VERSION
+            ))));
+        }
+        metadata.options.validate()?;
 
-        let allowed_scripts = &metadata.fuzzy_enabled_scripts.iter().map(
-            |s| unicode_ranges::get_script_by_name(s)
-        ).collect::<Option<Vec<_>>>().ok_or("unknown script")?;
-        let script_regex = regex::Regex::new(
-            &unicode_ranges::get_pattern_for_scripts(&allowed_scripts),
-        )?;
+        let script_regex = metadata.options.script_regex()?;
 
-        let max_edit_distance = metadata.max_edit_distance;
+        let max_edit_distance = metadata.options.max_edit_distance;
 
         let prefix_path = directory.join(Path::new("prefix.fst"));
         if !prefix_path.exists() {
@@ -308,9 +807,14 @@ impl FuzzyPhraseSet {
             return Err(Box::new(IoError::new(IoErrorKind::NotFound, "Phrase FST does not exist")));
         }
         let phrase_set = unsafe { PhraseSet::from_path(&phrase_path) }?;
+        let first_words = phrase_set.first_words();
 
         let fuzzy_path = directory.join(Path::new("fuzzy"));
-        let fuzzy_map = unsafe { FuzzyMap::from_path(&fuzzy_path) }?;
+        let mut fuzzy_map = unsafe { FuzzyMap::from_path(&fuzzy_path) }?;
+        fuzzy_map.set_script_strategies(metadata.options.script_strategies()?);
+        // the map's files don't record how far it was built to look; the metadata does, and is
+        // checked against the map's build ID below
+        fuzzy_map.set_edit_distance_limits(metadata.options.max_edit_distance, metadata.options.distance_min_lengths.clone());
 
         // the word replacements in the metadata are string to string, but we want ID to ID for
         // the sake of speed, so use the prefix map to go from the former to the latter and put
@@ -326,11 +830,104 @@ impl FuzzyPhraseSet {
             word_replacement_map.insert(from, to);
         }
 
+        let display_path = directory.join(Path::new("display.msg"));
+        let display_forms: BTreeMap<u32, String> = if display_path.exists() {
+            let display_reader = BufReader::new(fs::File::open(&display_path)?);
+            let display_forms: Vec<(u32, String)> = Deserialize::deserialize(&mut Deserializer::new(display_reader))?;
+            display_forms.into_iter().collect()
+        } else {
+            BTreeMap::new()
+        };
+
+        // make sure all the components came from the same build, since if they didn't, their word
+        // IDs won't agree and any results we return would be garbage
+        for (name, build_id) in &[("Prefix", prefix_set.build_id()), ("Phrase", phrase_set.build_id()), ("Fuzzy", fuzzy_map.build_id())] {
+            if *build_id != metadata.build_id {
+                return Err(Box::new(IoError::new(IoErrorKind::InvalidData, format!(
+                    "{} FST has build ID {}, but the index metadata has build ID {}", name, build_id, metadata.build_id
+                ))));
+            }
+        }
+
+        let number_range_path = directory.join(Path::new("number_ranges.msg"));
+        let mut number_ranges: BTreeMap<u32, Vec<(u32, u32)>> = BTreeMap::new();
+        if number_range_path.exists() {
+            let number_range_reader = BufReader::new(fs::File::open(&number_range_path)?);
+            let flat_ranges: Vec<(u32, u32, u32)> = Deserialize::deserialize(&mut Deserializer::new(number_range_reader))?;
+            for (id, start, end) in flat_ranges {
+                number_ranges.entry(id).or_default().push((start, end));
+            }
+        }
+
+        let word_classes = classes::load_word_classes(directory, &word_list)?;
+        let word_frequencies = spellcheck::load_word_frequencies(directory, word_list.len())?;
+        let phrase_metadata = records::load_phrase_metadata(directory)?;
+        let built_weights = weights::BuiltWeights::new(&phrase_metadata);
+        let word_depths = order::load_word_depths(directory)?;
+        let louds_trie = backend::load_louds_trie(directory, &metadata.options, metadata.build_id)?;
+
         Ok(FuzzyPhraseSet {
-            prefix_set, phrase_set, fuzzy_map, word_list, word_replacement_map, script_regex, max_edit_distance
+            prefix_set, phrase_set, fuzzy_map, word_list, word_replacement_map, display_forms, number_ranges, phrase_metadata, built_weights, word_classes, word_frequencies, script_regex, max_edit_distance,
+            max_query_length: None,
+            word_depths,
+            first_words,
+            build_info: metadata.build_info,
+            options: metadata.options,
+            exact_word_ids: None,
+            result_cache: None,
+            dedup_windows: false,
+            fuzzy_guards: guards::FuzzyGuards::default(),
+            fuzzy_prefixes: false,
+            prefix_segmentation: composition::PrefixSegmentation::default(),
+            segmenter: segment::CustomSegmenter::default(),
+            louds_trie,
+            weight_overlay: None,
+            vocabulary_density: metadata.vocabulary_density,
+            #[cfg(feature = "query-log")]
+            query_recorder: None,
         })
     }
 
+    /// The provenance information recorded when this set was built (see
+    /// `FuzzyPhraseSetBuilder::set_build_info`).
+    pub fn build_info(&self) -> &BTreeMap<String, String> {
+        &self.build_info
+    }
+
+    /// The number of distinct words in the set's vocabulary.
+    pub fn word_count(&self) -> usize {
+        self.word_list.len()
+    }
+
+    /// The number of phrases in the set.
+    pub fn phrase_count(&self) -> usize {
+        self.phrase_set.as_fst().len()
+    }
+
+    /// The maximum edit distance the set's fuzzy word index was built to support.
+    pub fn max_edit_distance(&self) -> u8 {
+        self.max_edit_distance
+    }
+
+    /// Get a `QueryBuilder` for turning tokenized queries into word slots that can be passed to
+    /// this set's `PhraseSet` matchers.
+    pub fn query_builder<'a>(&'a self) -> QueryBuilder<'a> {
+        QueryBuilder::new(self)
+    }
+
+    /// Map the words of a result phrase back to the forms they were inserted for display with
+    /// (see `FuzzyPhraseSetBuilder::insert_with_display`). Words without a stored display form,
+    /// including partial words at the end of prefix matches, are returned as they are.
+    pub fn display_phrase<T: AsRef<str>>(&self, phrase: &[T]) -> Vec<String> {
+        phrase.iter().map(|word| {
+            let word = word.as_ref();
+            self.exact_word_id(word)
+                .and_then(|id| self.display_forms.get(&id))
+                .map(|display_word| display_word.to_owned())
+                .unwrap_or_else(|| word.to_owned())
+        }).collect()
+    }
+
     pub fn can_fuzzy_match(&self, word: &str) -> bool {
         util::can_fuzzy_match(word, &self.script_regex)
     }
@@ -340,17 +937,10 @@ impl FuzzyPhraseSet {
             EndingType::NonPrefix | EndingType::WordBoundaryPrefix => {
                 // strategy: get each word's ID from the prefix graph (or return false if any are missing)
                 // and then look up that ID sequence in the phrase graph
-                let mut id_phrase: Vec<QueryWord> = Vec::with_capacity(phrase.len());
-                for word in phrase {
-                    match self.prefix_set.lookup(word.as_ref()).id() {
-                        Some(word_id) => {
-                            let id = word_id.value() as u32;
-                            let maybe_replaced = *self.word_replacement_map.get(&id).unwrap_or(&id);
-                            id_phrase.push(QueryWord::new_full(maybe_replaced, 0))
-                        },
-                        None => { return Ok(false) }
-                    }
-                }
+                let id_phrase = match self.exact_id_phrase(phrase) {
+                    Some(id_phrase) => id_phrase,
+                    None => { return Ok(false) }
+                };
                 Ok(match ending_type {
                     EndingType::NonPrefix => self.phrase_set.lookup(&id_phrase).found_final(),
                     _ => self.phrase_set.lookup(&id_phrase).found()
@@ -369,9 +959,8 @@ impl FuzzyPhraseSet {
 
                 let last_idx = phrase.len() - 1;
                 for word in phrase[..last_idx].iter() {
-                    match self.prefix_set.lookup(word.as_ref()).id() {
-                        Some(word_id) => {
-                            let id = word_id.value() as u32;
+                    match self.exact_word_id(word.as_ref()) {
+                        Some(id) => {
                             let maybe_replaced = *self.word_replacement_map.get(&id).unwrap_or(&id);
                             word_possibilities.push(vec![QueryWord::new_full(maybe_replaced, 0)])
                         },
@@ -383,8 +972,8 @@ impl FuzzyPhraseSet {
                     None => return Ok(false),
                 }
 
-                let phrase_matches = self.phrase_set.match_combinations_as_prefixes(&word_possibilities, 0)?;
-                Ok(phrase_matches.len() > 0)
+                // we only care whether there's at least one match, so stop looking after the first
+                Ok(self.phrase_set.has_prefix_match(&word_possibilities, 0)?)
             }
         }
     }
@@ -397,11 +986,45 @@ impl FuzzyPhraseSet {
         self.contains(&phrase_v, ending_type)
     }
 
+    /// Whether `phrase`, taken as whole words, is a complete phrase in the set, the beginning of
+    /// longer ones, or both, or `None` if it's neither. This is what autocomplete needs to decide
+    /// whether to offer the query itself, its continuations, or both, and saves asking `contains`
+    /// the question twice with different ending types.
+    pub fn match_kind<T: AsRef<str>>(&self, phrase: &[T]) -> Option<PhraseMatchKind> {
+        if phrase.is_empty() {
+            return None;
+        }
+        self.exact_id_phrase(phrase).and_then(|id_phrase| self.phrase_set.match_kind(&id_phrase))
+    }
+
+    // each word of `phrase` as a full query word, after token replacement, or `None` if any of
+    // them isn't in the prefix graph
+    fn exact_id_phrase<T: AsRef<str>>(&self, phrase: &[T]) -> Option<Vec<QueryWord>> {
+        phrase.iter().map(|word| {
+            self.exact_word_id(word.as_ref()).map(|id| self.full_variant(id, 0))
+        }).collect()
+    }
+
+    // `id` as a full query word, after token replacement, tagged with where it came from; a typo
+    // is a typo whether or not the word it corrects to then gets replaced
+    fn full_variant(&self, id: u32, edit_distance: u8) -> QueryWord {
+        let source = if edit_distance > 0 {
+            VariantSource::Typo
+        } else if self.word_replacement_map.contains_key(&id) {
+            VariantSource::Replacement
+        } else {
+            VariantSource::Exact
+        };
+        let maybe_replaced = *self.word_replacement_map.get(&id).unwrap_or(&id);
+        QueryWord::new_full_from(maybe_replaced, edit_distance, source)
+    }
+
     #[inline(always)]
     fn get_nonterminal_word_possibilities(&self, word: &str, edit_distance: u8) -> Result<Option<Vec<QueryWord>>, Box<Error>> {
         // check if we actually want to fuzzy-match, if the word is made of the right kind of characters
-        // and if it's more than one char long
-        if edit_distance > 0 && self.can_fuzzy_match(word) && word.chars().nth(1).is_some() {
+        // (and not in a script that only matches exactly), if it's more than one char long, and
+        // if it isn't guarded against fuzzy matching
+        if edit_distance > 0 && self.can_fuzzy_match(word) && !self.fuzzy_map.is_exact_only(word) && word.chars().nth(1).is_some() && !self.is_fuzzy_guarded(word) {
             let fuzzy_results = self.fuzzy_map.lookup(&word, edit_distance, |id| &self.word_list[id as usize])?;
             if fuzzy_results.len() == 0 {
                 Ok(None)
@@ -414,17 +1037,19 @@ impl FuzzyPhraseSet {
                         _ => false
                     });
                     if !already {
-                        variants.push(QueryWord::new_full(maybe_replaced, result.edit_distance));
+                        variants.push(self.full_variant(result.id, result.edit_distance));
                     }
                 }
                 Ok(Some(variants))
             }
         } else {
-            match self.prefix_set.lookup(&word).id() {
-                Some(word_id) => {
-                    let id = word_id.value() as u32;
-                    let maybe_replaced = *self.word_replacement_map.get(&id).unwrap_or(&id);
-                    Ok(Some(vec![QueryWord::new_full(maybe_replaced, 0)])) },
+            // words in fuzzy-matchable scripts all went into the fuzzy map, so if its filter says
+            // this one isn't there, it's not in the vocabulary at all
+            if self.can_fuzzy_match(word) && !self.fuzzy_map.may_contain(word) {
+                return Ok(None);
+            }
+            match self.exact_word_id(word) {
+                Some(id) => Ok(Some(vec![self.full_variant(id, 0)])),
                 None => { Ok(None) }
             }
         }
@@ -434,6 +1059,11 @@ impl FuzzyPhraseSet {
     fn get_terminal_word_possibilities(&self, word: &str, edit_distance: u8) -> Result<Option<Vec<QueryWord>>, Box<Error>> {
         // last word: try both prefix and, if eligible, fuzzy lookup, and return nothing if both fail
         let mut last_variants: Vec<QueryWord> = Vec::new();
+        let (word, composing) = match self.segment_prefix_token(word) {
+            Some(segmented) => segmented,
+            None => return Ok(None),
+        };
+        let word: &str = &word;
 
         let lookup = self.prefix_set.lookup(word);
         if let Some((word_id_start, word_id_end)) = lookup.range() {
@@ -465,14 +1095,22 @@ impl FuzzyPhraseSet {
                     _ => false
                 });
                 if !already {
-                    last_variants.push(QueryWord::new_full(replacement, 0));
+                    last_variants.push(QueryWord::new_full_from(replacement, 0, VariantSource::Replacement));
                 }
             }
         }
 
-        // check if we actually want to fuzzy-match, if the word is made of the right kind of characters
-        // and if it's more than one char long
-        if edit_distance > 0 && self.can_fuzzy_match(word) && word.chars().nth(1).is_some() {
+        if !composing.is_empty() {
+            self.merge_prefix_ranges(composing.into_iter().map(|range| (range, 0)).collect(), &mut last_variants);
+        }
+
+        // check if we actually want to fuzzy-match, if the word is made of the right kind of characters,
+        // if it's more than one char long and if it isn't guarded against fuzzy matching; words
+        // that only match exactly were already found by the prefix lookup
+        if edit_distance > 0 && self.can_fuzzy_match(word) && !self.fuzzy_map.is_exact_only(word) && word.chars().nth(1).is_some() && !self.is_fuzzy_guarded(word) {
+            if self.fuzzy_prefixes {
+                self.add_fuzzy_prefix_variants(word, edit_distance, &mut last_variants);
+            }
             let last_fuzzy_results = self.fuzzy_map.lookup(word, edit_distance, |id| &self.word_list[id as usize])?;
             for result in last_fuzzy_results {
                 let maybe_replaced = *self.word_replacement_map.get(&result.id).unwrap_or(&result.id);
@@ -483,7 +1121,7 @@ impl FuzzyPhraseSet {
                     QueryWord::Prefix { id_range, .. } => maybe_replaced >= id_range.0 && maybe_replaced <= id_range.1
                 });
                 if !already {
-                    last_variants.push(QueryWord::new_full(maybe_replaced, result.edit_distance));
+                    last_variants.push(self.full_variant(result.id, result.edit_distance));
                 }
             }
         }
@@ -494,79 +1132,125 @@ impl FuzzyPhraseSet {
         }
     }
 
-    pub fn fuzzy_match<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<Error>> {
-        // strategy: look up each word in the fuzzy graph, and also look up the last one in the prefix graph
-        // if the ending type allows for partial words (so, is AnyPrefix), and then construct a vector of
-        // vectors representing all the word variants that could reside in each slot in the phrase, and
-        // then recursively enumerate every combination of variants and look them each up in the phrase graph
+    // only go through the filtered phrase graph traversal if we actually have a filter, since it
+    // has to do some extra work at every step to figure out which phrases are still reachable
+    #[inline(always)]
+    fn match_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool, filter: Option<&dyn PhraseIdFilter>, constraints: Option<&TokenConstraints>) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        // with word depths, a window can't reach past the first slot none of its words can be at
+        let pruned: Vec<Vec<QueryWord>>;
+        let (word_possibilities, ends_in_prefix) = match self.word_depths {
+            Some(ref word_depths) => {
+                pruned = word_depths.prune_window(word_possibilities);
+                if pruned.is_empty() {
+                    return Ok(Vec::new());
+                }
+                (&pruned[..], ends_in_prefix && pruned.len() == word_possibilities.len())
+            },
+            None => (word_possibilities, ends_in_prefix),
+        };
+        match (filter, constraints) {
+            (_, Some(c)) => self.phrase_set.match_combinations_as_windows_constrained(word_possibilities, max_phrase_dist, ends_in_prefix, c, &self.word_classes, filter),
+            (Some(f), None) => self.phrase_set.match_combinations_as_windows_filtered(word_possibilities, max_phrase_dist, ends_in_prefix, f),
+            (None, None) => self.phrase_set.match_combinations_as_windows(word_possibilities, max_phrase_dist, ends_in_prefix),
+        }
+    }
 
-        let mut word_possibilities: Vec<Vec<QueryWord>> = Vec::with_capacity(phrase.len());
+    pub fn fuzzy_match<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<Error>> {
+        let results = self.cached_fuzzy_match(phrase, max_word_dist, max_phrase_dist, ending_type)?;
+        instrument::record_results("match", results.len());
+        #[cfg(feature = "query-log")]
+        self.record_match(phrase, max_word_dist, max_phrase_dist, ending_type, &results);
+        Ok(results)
+    }
 
-        if phrase.len() == 0 {
-            return Ok(Vec::new());
-        }
+    /// Like `fuzzy_match`, but only returns results whose phrase ID range is allowed by `filter`
+    /// (a closure over the range, or a set of allowed phrase IDs). The filter is applied before
+    /// the result's strings are assembled, so it's a cheap way for callers to restrict matches to
+    /// some subset of phrases (for example, those within a particular geographic area).
+    pub fn fuzzy_match_filtered<T: AsRef<str>, F: PhraseIdFilter>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, filter: F) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        self.fuzzy_match_impl(phrase, max_word_dist, max_phrase_dist, ending_type, Some(&filter), None)
+    }
 
-        let edit_distance = if max_word_dist > self.max_edit_distance {
-            return Err(Box::new(PhraseSetError::new(format!(
-                "The maximum configured edit distance for this index is {}; {} requested",
-                self.max_edit_distance,
-                max_word_dist
-            ).as_str())));
-        } else {
-            max_word_dist
-        };
+    fn fuzzy_match_impl<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, filter: Option<&dyn PhraseIdFilter>, constraints: Option<&TokenConstraints>) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        let matcher = backend::OwnGraph::new(self, None, constraints);
+        self.match_pipeline(&matcher, phrase, max_word_dist, max_phrase_dist, ending_type, filter)
+    }
 
-        // all words but the last one: fuzzy-lookup if eligible, or exact-match if not,
-        // and return nothing if those fail
-        match ending_type {
-            EndingType::NonPrefix | EndingType::WordBoundaryPrefix => {
-                for matches in phrase.iter().map(|word| self.get_nonterminal_word_possibilities(word.as_ref(), edit_distance)) {
-                    match matches? {
-                        Some(possibilities) => word_possibilities.push(possibilities),
-                        None => return Ok(Vec::new()),
+    fn combination_to_result<T: AsRef<str>>(&self, phrase: &[T], combination: &Combination, ending_type: EndingType) -> FuzzyMatchResult {
+        FuzzyMatchResult {
+            phrase: combination.phrase.iter().enumerate().map(|(i, qw)| match qw {
+                QueryWord::Full { id, .. } => self.word_list[*id as usize].clone(),
+                QueryWord::Prefix { .. } => phrase[i].as_ref().to_owned(),
+            }).collect::<Vec<String>>(),
+            edit_distance: combination.phrase.iter().map(|qw| qw.edit_distance()).sum(),
+            ending_type: match ending_type {
+                EndingType::NonPrefix | EndingType::WordBoundaryPrefix => ending_type,
+                EndingType::AnyPrefix => {
+                    match combination.phrase.last() {
+                        None => EndingType::NonPrefix,
+                        Some(qw) => match qw {
+                            QueryWord::Full { .. } => EndingType::WordBoundaryPrefix,
+                            QueryWord::Prefix { .. } => EndingType::AnyPrefix,
+                        }
                     }
                 }
             },
-            EndingType::AnyPrefix => {
-                let last_idx = phrase.len() - 1;
-                for matches in phrase[..last_idx].iter().map(|word| self.get_nonterminal_word_possibilities(word.as_ref(), edit_distance)) {
-                    match matches? {
-                        Some(possibilities) => word_possibilities.push(possibilities),
-                        None => return Ok(Vec::new()),
-                    }
-                }
-                match self.get_terminal_word_possibilities(phrase[last_idx].as_ref(), edit_distance)? {
-                    Some(possibilities) => word_possibilities.push(possibilities),
-                    None => return Ok(Vec::new()),
-                }
-            }
+            phrase_id_range: (combination.output_range.0.value() as u32, combination.output_range.1.value() as u32),
         }
+    }
 
+    pub fn fuzzy_match_str(&self, phrase: &str, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<Error>> {
+        let phrase_v: Vec<&str> = phrase.split(' ').collect();
+        self.fuzzy_match(&phrase_v, max_word_dist, max_phrase_dist, ending_type)
+    }
 
-        let phrase_matches = match ending_type {
-            EndingType::NonPrefix => {
-                self.phrase_set.match_combinations(&word_possibilities, max_phrase_dist)?
-            },
-            EndingType::WordBoundaryPrefix | EndingType::AnyPrefix => {
-                self.phrase_set.match_combinations_as_prefixes(&word_possibilities, max_phrase_dist)?
-            }
-        };
+    pub fn fuzzy_match_windows<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyWindowResult>, Box<Error>> {
+        let results = self.cached_fuzzy_match_windows(phrase, max_word_dist, max_phrase_dist, ending_type)?;
+        instrument::record_results("windows", results.len());
+        #[cfg(feature = "query-log")]
+        self.record_windows(phrase, max_word_dist, max_phrase_dist, ending_type, &results);
+        Ok(results)
+    }
+
+    /// Like `fuzzy_match_windows`, but only returns windows whose phrase ID range is allowed by
+    /// `filter`. The filter is pushed down into the phrase graph traversal, so branches that
+    /// can't lead to an allowed phrase aren't explored at all.
+    pub fn fuzzy_match_windows_filtered<T: AsRef<str>, F: PhraseIdFilter>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, filter: F) -> Result<Vec<FuzzyWindowResult>, Box<dyn Error>> {
+        self.fuzzy_match_windows_impl(phrase, max_word_dist, max_phrase_dist, ending_type, Some(&filter), None)
+    }
+
+    fn fuzzy_match_windows_impl<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, filter: Option<&dyn PhraseIdFilter>, constraints: Option<&TokenConstraints>) -> Result<Vec<FuzzyWindowResult>, Box<dyn Error>> {
+        // the filter is pushed down into the phrase graph traversal, so branches that can't lead
+        // to an allowed phrase aren't explored at all
+        let matcher = backend::OwnGraph::new(self, filter, constraints);
+        self.windows_pipeline(&matcher, phrase, max_word_dist, max_phrase_dist, ending_type)
+    }
 
-        let mut results: Vec<FuzzyMatchResult> = Vec::new();
-        for combination in &phrase_matches {
-            results.push(FuzzyMatchResult {
-                phrase: combination.phrase.iter().enumerate().map(|(i, qw)| match qw {
-                    QueryWord::Full { id, .. } => self.word_list[*id as usize].clone(),
-                    QueryWord::Prefix { .. } => phrase[i].as_ref().to_owned(),
-                }).collect::<Vec<String>>(),
-                edit_distance: combination.phrase.iter().map(|qw| match qw {
-                    QueryWord::Full { edit_distance, .. } => *edit_distance,
-                    QueryWord::Prefix { .. } => 0u8,
-                }).sum(),
-                ending_type: match ending_type {
+    // whether any of the possibilities for a window's first slot begins a phrase; windows that
+    // start anywhere else can't match anything, so there's no need to explore the graph for them
+    pub(super) fn can_start_phrase(&self, possibilities: &[QueryWord]) -> bool {
+        possibilities.iter().any(|word| match *word {
+            QueryWord::Full { id, .. } => self.first_words.contains(id),
+            QueryWord::Prefix { id_range, .. } => self.first_words.contains_range(id_range),
+        })
+    }
+
+    // turn a window found starting at position `start` of the (possibly length-limited) query into
+    // a result, with positions relative to the original query
+    fn window_to_result<T: AsRef<str>>(&self, phrase: &[T], start: usize, match_sq: &CombinationWindow, offset: usize, tokens_total: usize, ending_type: EndingType) -> FuzzyWindowResult {
+        FuzzyWindowResult {
+            phrase: match_sq.phrase.iter().enumerate().map(|(j, qw)| match qw {
+                QueryWord::Full { id, .. } => self.word_list[*id as usize].clone(),
+                QueryWord::Prefix { .. } => phrase[start + j].as_ref().to_owned(),
+            }).collect::<Vec<String>>(),
+            edit_distance: match_sq.phrase.iter().map(|qw| qw.edit_distance()).sum(),
+            start_position: offset + start,
+            ending_type: match match_sq.ends_in_prefix {
+                false => EndingType::NonPrefix,
+                true => match ending_type {
                     EndingType::NonPrefix | EndingType::WordBoundaryPrefix => ending_type,
                     EndingType::AnyPrefix => {
-                        match combination.phrase.last() {
+                        match match_sq.phrase.last() {
                             None => EndingType::NonPrefix,
                             Some(qw) => match qw {
                                 QueryWord::Full { .. } => EndingType::WordBoundaryPrefix,
@@ -574,59 +1258,18 @@ impl FuzzyPhraseSet {
                             }
                         }
                     }
-                },
-                phrase_id_range: (combination.output_range.0.value() as u32, combination.output_range.1.value() as u32)
-            })
+                }
+            },
+            phrase_id_range: (match_sq.output_range.0.value() as u32, match_sq.output_range.1.value() as u32),
+            tokens_consumed: match_sq.phrase.len(),
+            tokens_total,
         }
-
-        Ok(results)
     }
 
-    pub fn fuzzy_match_str(&self, phrase: &str, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<Error>> {
-        let phrase_v: Vec<&str> = phrase.split(' ').collect();
-        self.fuzzy_match(&phrase_v, max_word_dist, max_phrase_dist, ending_type)
-    }
-
-    pub fn fuzzy_match_windows<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyWindowResult>, Box<Error>> {
-        // this is a little different than the regular fuzzy match in that we're considering
-        // multiple possible substrings we'll start by trying to fuzzy-match all the words, but
-        // some of those will likely fail -- rather than early-returning like in regular fuzzy
-        // match, we'll keep going but those failed words will effectively wall off possible
-        // matching subphrases from eachother, so we'll end up with multiple candidate subphrases
-        // to explore.  (hence the extra nesting -- a list of word sequences, each sequence being a
-        // list of word slots, each slot being a list of fuzzy-match variants)
-        //
-        // The mechanics of this approach:
-        //
-        // We're iterating over the fuzzy matches of each word in the phrase to produce subqueries.
-        // This loop eliminates a lot of potential subqueries by looking at the word possibilities
-        // in each position. In any given position, we might have found zero possibilities. That
-        // means that we can ignore subqueries that include the original query word in this
-        // position. We can also isolate subqueries that include the previous words, and start in
-        // the next position when looking for more subqueries.  For example, if a query's word
-        // possibilities look like this (simplifying with letters instead of words):
-        //
-        //     [ [A, B], [C], [], [F, G], [H] ]
-        //
-        // Intuitively, we know we want to isolate "A C", "B C", "F H", and "G H" but. We'll
-        // also want to look at all possible start positions in those substrings, so "C" and "H" as
-        // well.
-        //
-        // What we want to ignore is whatever query word was in position 2 (since it didn't match
-        // anything in the FuzzyMap).  We also don't want to consider things like "A C F H" because
-        // that's not a continuous sequence of tokens in the query.
-        //
-        if phrase.len() == 0 {
-            return Ok(Vec::new());
-        }
-
-        #[derive(Debug)]
-        struct Subquery {
-            start_position: usize,
-            ending_type: EndingType,
-            word_possibilities: Vec<Vec<QueryWord>>
-        }
-        let mut subqueries: Vec<Subquery> = Vec::new();
+    // split a query into runs of consecutive words that each have at least one possibility (see
+    // `fuzzy_match_windows_impl` for the details)
+    fn window_subqueries<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, ending_type: EndingType) -> Result<Vec<WindowSubquery>, Box<dyn Error>> {
+        let mut subqueries: Vec<WindowSubquery> = Vec::new();
 
         let edit_distance = if max_word_dist > self.max_edit_distance {
             return Err(Box::new(PhraseSetError::new(format!(
@@ -660,7 +1303,7 @@ impl FuzzyPhraseSet {
         };
 
         // the sq variable starts off set to default variables.
-        let mut sq: Subquery = Subquery { start_position: 0, ending_type: EndingType::NonPrefix, word_possibilities: Vec::new() };
+        let mut sq: WindowSubquery = WindowSubquery { start_position: 0, ending_type: EndingType::NonPrefix, word_possibilities: Vec::new() };
 
         // Continuing with the example from above:
         //
@@ -676,7 +1319,7 @@ impl FuzzyPhraseSet {
         for (i, matches) in seq.chain(iter::once(Ok(None))).enumerate() {
             match matches? {
                 Some(p) => {
-                    sq.word_possibilities.push(p);
+                    sq.word_possibilities.push(p.into_iter().map(|qw| qw.with_source_position(i as u16)).collect());
                     if sq.word_possibilities.len() == 1 {
                         // this was the first thing to be added to this list
                         sq.start_position = i;
@@ -696,67 +1339,26 @@ impl FuzzyPhraseSet {
                         // push this subquery into the result array.
                         subqueries.push(sq);
                         // if reset the sq variable to the same default values after each loop.
-                        sq = Subquery { start_position: 0, ending_type: EndingType::NonPrefix, word_possibilities: Vec::new() };
+                        sq = WindowSubquery { start_position: 0, ending_type: EndingType::NonPrefix, word_possibilities: Vec::new() };
                     }
                 },
             }
         }
 
-        // The things we're looking for will lie entirely within one of our identified chunks of
-        // contiguous matched words, but could start on any of said words (they'll end, at latest,
-        // and the end of the chunk), so, iterate over the chunks and then iterate over the
-        // possible start words.
-        //
-        // Continuing with the example above: by iterating over multiple start positions within
-        // each chunk, we'll end up considering "C" and "H" in addition to the combinations that
-        // start in the initial positions ("A C", "B C", "F H", "G H").
-        let mut results: Vec<FuzzyWindowResult> = Vec::new();
-        for chunk in subqueries.iter() {
-            for i in 0..chunk.word_possibilities.len() {
-                let phrase_matches = self.phrase_set.match_combinations_as_windows(
-                    &chunk.word_possibilities[i..],
-                    max_phrase_dist,
-                    match chunk.ending_type {
-                        EndingType::NonPrefix => false,
-                        _ => true
-                    }
-                )?;
-                for match_sq in &phrase_matches {
-                    results.push(FuzzyWindowResult {
-                        phrase: match_sq.phrase.iter().enumerate().map(|(j, qw)| match qw {
-                            QueryWord::Full { id, .. } => self.word_list[*id as usize].clone(),
-                            QueryWord::Prefix { .. } => phrase[chunk.start_position + i + j].as_ref().to_owned(),
-                        }).collect::<Vec<String>>(),
-                        edit_distance: match_sq.phrase.iter().map(|qw| match qw {
-                            QueryWord::Full { edit_distance, .. } => *edit_distance,
-                            QueryWord::Prefix { .. } => 0u8,
-                        }).sum(),
-                        start_position: chunk.start_position + i,
-                        ending_type: match match_sq.ends_in_prefix {
-                            false => EndingType::NonPrefix,
-                            true => match ending_type {
-                                EndingType::NonPrefix | EndingType::WordBoundaryPrefix => ending_type,
-                                EndingType::AnyPrefix => {
-                                    match match_sq.phrase.last() {
-                                        None => EndingType::NonPrefix,
-                                        Some(qw) => match qw {
-                                            QueryWord::Full { .. } => EndingType::WordBoundaryPrefix,
-                                            QueryWord::Prefix { .. } => EndingType::AnyPrefix,
-                                        }
-                                    }
-                                }
-                            }
-                        },
-                        phrase_id_range: (match_sq.output_range.0.value() as u32, match_sq.output_range.1.value() as u32)
-                    })
-                }
-            }
-        }
+        Ok(subqueries)
+    }
 
-        Ok(results)
+    pub fn fuzzy_match_multi<T: AsRef<str> + Ord + Debug, U: AsRef<[T]>>(&self, phrases: &[(U, EndingType)], max_word_dist: u8, max_phrase_dist: u8) -> Result<Vec<Vec<FuzzyMatchResult>>, Box<Error>> {
+        self.fuzzy_match_multi_impl(phrases, max_word_dist, max_phrase_dist, None)
     }
 
-    pub fn fuzzy_match_multi<T: AsRef<str> + Ord + Debug, U: AsRef<[T]>>(&self, phrases: &[(U, EndingType)], max_word_dist: u8, max_phrase_dist: u8) -> Result<Vec<Vec<FuzzyMatchResult>>, Box<Error>> {
+    /// Like `fuzzy_match_multi`, but only returns results whose phrase ID range is allowed by
+    /// `filter`.
+    pub fn fuzzy_match_multi_filtered<T: AsRef<str> + Ord + Debug, U: AsRef<[T]>, F: PhraseIdFilter>(&self, phrases: &[(U, EndingType)], max_word_dist: u8, max_phrase_dist: u8, filter: F) -> Result<Vec<Vec<FuzzyMatchResult>>, Box<dyn Error>> {
+        self.fuzzy_match_multi_impl(phrases, max_word_dist, max_phrase_dist, Some(&filter))
+    }
+
+    fn fuzzy_match_multi_impl<T: AsRef<str> + Ord + Debug, U: AsRef<[T]>>(&self, phrases: &[(U, EndingType)], max_word_dist: u8, max_phrase_dist: u8, filter: Option<&dyn PhraseIdFilter>) -> Result<Vec<Vec<FuzzyMatchResult>>, Box<dyn Error>> {
 
         // This is roughly equivalent to `fuzzy_match_windows` in purpose, but operating under
         // the assumption that the caller will have wanted to make some changes to some of the
@@ -894,21 +1496,26 @@ impl FuzzyPhraseSet {
                 EndingType::NonPrefix | EndingType::WordBoundaryPrefix => false,
                 _ => true
             };
-            for word in longest_phrase[..(longest_phrase.len() - 1)].iter() {
+            let last_idx = longest_phrase.len() - 1;
+            for (j, word) in longest_phrase[..last_idx].iter().enumerate() {
                 word_possibilities.push(
                     all_words.get(&(word.as_ref(), false))
-                        .ok_or("Can't find corrected word")?.clone()
+                        .ok_or("Can't find corrected word")?
+                        .iter().map(|qw| qw.with_source_position(j as u16)).collect()
                 );
             }
             word_possibilities.push(
-                all_words.get(&(longest_phrase[longest_phrase.len() - 1].as_ref(), final_word_ends_in_prefix))
-                    .ok_or("Can't find corrected word")?.clone()
+                all_words.get(&(longest_phrase[last_idx].as_ref(), final_word_ends_in_prefix))
+                    .ok_or("Can't find corrected word")?
+                    .iter().map(|qw| qw.with_source_position(last_idx as u16)).collect()
             );
 
-            let phrase_matches = self.phrase_set.match_combinations_as_windows(
+            let phrase_matches = self.match_windows(
                 &word_possibilities,
                 max_phrase_dist,
-                phrase_ends_in_prefix
+                phrase_ends_in_prefix,
+                filter,
+                None
             )?;
 
             // Within this prefix cluster we have different things of different lengths and
@@ -926,16 +1533,14 @@ impl FuzzyPhraseSet {
                 // We might have found results in our phrase graph traversal that we weren't
                 // actually look for -- we'll ignore those and only add results if they match
                 if let Some(&input_idx) = length_map.get(&(match_sq.phrase.len(), match_sq.ends_in_prefix)) {
+                    let phrase_id_range = (match_sq.output_range.0.value() as u32, match_sq.output_range.1.value() as u32);
                     let input_phrase = phrases[input_idx].0.as_ref();
                     results[input_idx].push(FuzzyMatchResult {
                         phrase: match_sq.phrase.iter().enumerate().map(|(i, qw)| match qw {
                             QueryWord::Full { id, .. } => self.word_list[*id as usize].clone(),
                             QueryWord::Prefix { .. } => input_phrase[i].as_ref().to_owned(),
                         }).collect::<Vec<String>>(),
-                        edit_distance: match_sq.phrase.iter().map(|qw| match qw {
-                            QueryWord::Full { edit_distance, .. } => *edit_distance,
-                            QueryWord::Prefix { .. } => 0u8,
-                        }).sum(),
+                        edit_distance: match_sq.phrase.iter().map(|qw| qw.edit_distance()).sum(),
                         ending_type: match match_sq.ends_in_prefix {
                             false => EndingType::NonPrefix,
                             true => match ending_type {
@@ -951,7 +1556,7 @@ impl FuzzyPhraseSet {
                                 }
                             }
                         },
-                        phrase_id_range: (match_sq.output_range.0.value() as u32, match_sq.output_range.1.value() as u32)
+                        phrase_id_range
                     });
                 }
             }
@@ -986,6 +1591,7 @@ mod basic_tests {
     extern crate lazy_static;
 
     use super::*;
+    use std::collections::BTreeSet;
 
     lazy_static! {
         static ref DIR: tempfile::TempDir = tempfile::tempdir().unwrap();
@@ -1011,6 +1617,26 @@ mod basic_tests {
         };
     }
 
+    // build a set in a new directory from `phrases`; the directory has to be kept for as long as
+    // the set is used
+    fn build_set(phrases: &[&str]) -> (tempfile::TempDir, FuzzyPhraseSet) {
+        build_set_with(|builder| {
+            for phrase in phrases {
+                builder.insert_str(phrase).unwrap();
+            }
+        })
+    }
+
+    // as `build_set`, but `build` configures the builder and inserts the phrases itself
+    fn build_set_with<F: FnOnce(&mut FuzzyPhraseSetBuilder)>(build: F) -> (tempfile::TempDir, FuzzyPhraseSet) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        build(&mut builder);
+        builder.finish().unwrap();
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        (dir, set)
+    }
+
     #[test]
     fn glue_build() -> () {
         lazy_static::initialize(&SET);
@@ -1021,8 +1647,11 @@ mod basic_tests {
         contents.sort();
         assert_eq!(
             contents,
-            vec!["fuzzy.fst", "fuzzy.msg", "metadata.json", "phrase.fst", "prefix.fst"]
+            vec!["fuzzy.fst", "fuzzy.ids", "metadata.json", "phrase.fst", "prefix.fst"]
         );
+        assert_eq!(SET.word_count(), 8);
+        assert_eq!(SET.phrase_count(), 4);
+        assert_eq!(SET.max_edit_distance(), 1);
     }
 
     // TODO:  test fpsb.insert <05-07-18, boblannon> //
@@ -1173,41 +1802,44 @@ mod basic_tests {
 
     #[test]
     fn glue_fuzzy_match_windows() -> () {
+        let windows = SET.fuzzy_match_windows(&["100", "main", "street", "washington"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(windows[0].coverage(), 0.75);
+
         assert_eq!(
             SET.fuzzy_match_windows(&["100", "main", "street", "washington", "30"], 1, 1, EndingType::AnyPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1) },
-                FuzzyWindowResult { phrase: vec!["30".to_string()], edit_distance: 0, start_position: 4, ending_type: EndingType::AnyPrefix, phrase_id_range: (3, 3) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1), tokens_consumed: 3, tokens_total: 5 },
+                FuzzyWindowResult { phrase: vec!["30".to_string()], edit_distance: 0, start_position: 4, ending_type: EndingType::AnyPrefix, phrase_id_range: (3, 3), tokens_consumed: 1, tokens_total: 5 }
             ]
         );
 
         assert_eq!(
             SET.fuzzy_match_windows(&["100", "main", "street", "washington", "300"], 1, 1, EndingType::AnyPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1) },
-                FuzzyWindowResult { phrase: vec!["300".to_string()], edit_distance: 0, start_position: 4, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (3, 3) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1), tokens_consumed: 3, tokens_total: 5 },
+                FuzzyWindowResult { phrase: vec!["300".to_string()], edit_distance: 0, start_position: 4, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (3, 3), tokens_consumed: 1, tokens_total: 5 }
             ]
         );
 
         assert_eq!(
             SET.fuzzy_match_windows(&["100", "main", "street", "washington", "30"], 1, 1, EndingType::WordBoundaryPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1) },
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1), tokens_consumed: 3, tokens_total: 5 },
             ]
         );
 
         assert_eq!(
             SET.fuzzy_match_windows(&["100", "main", "street", "washington", "300"], 1, 1, EndingType::WordBoundaryPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1) },
-                FuzzyWindowResult { phrase: vec!["300".to_string()], edit_distance: 0, start_position: 4, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (3, 3) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1), tokens_consumed: 3, tokens_total: 5 },
+                FuzzyWindowResult { phrase: vec!["300".to_string()], edit_distance: 0, start_position: 4, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (3, 3), tokens_consumed: 1, tokens_total: 5 }
             ]
         );
 
         assert_eq!(
             SET.fuzzy_match_windows(&["100", "main", "street", "washington", "300"], 1, 1, EndingType::NonPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1) },
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1), tokens_consumed: 3, tokens_total: 5 },
             ]
         );
     }
@@ -1240,6 +1872,1789 @@ mod basic_tests {
         );
     }
 
+    #[test]
+    fn glue_diff() -> () {
+        let (_dir, other) = build_set_with(|builder| {
+            // "150" sorts between "100" and "200", so it shifts the IDs of every word after it
+            builder.insert_str("100 main street").unwrap();
+            builder.insert_str("150 main street").unwrap();
+            builder.insert_str("200 main street").unwrap();
+            builder.insert_str("300 mlk blvd").unwrap();
+        });
+
+        let diff = SET.diff(&other);
+        assert_eq!(diff.words.added, vec![("150".to_string(), 1)]);
+        assert_eq!(diff.words.removed, vec![("ave".to_string(), 3)]);
+        // "200" and "300" shift up one to make room for "150", and everything after "ave" stays put
+        assert_eq!(diff.words.remapped, vec![(1, 2), (2, 3)]);
+        assert_eq!(diff.added_phrases, vec![vec!["150".to_string(), "main".to_string(), "street".to_string()]]);
+        assert_eq!(diff.removed_phrases, vec![vec!["100".to_string(), "main".to_string(), "ave".to_string()]]);
+
+        let same = SET.diff(&SET);
+        assert!(same.words.added.is_empty() && same.words.removed.is_empty() && same.words.remapped.is_empty());
+        assert!(same.added_phrases.is_empty() && same.removed_phrases.is_empty());
+    }
+
+    #[test]
+    fn glue_delta() -> () {
+        let (dir, other) = build_set(&["100 main street", "150 main street", "200 main street", "300 mlk blvd"]);
+
+        let delta = SET.delta(&other).unwrap();
+        // "100 main ave" is phrase 0 in SET
+        assert_eq!(delta.tombstoned_phrase_ids, vec![0]);
+
+        // round-trip through a file, the way it'd be shipped to a client
+        let delta_file = dir.path().join("delta.msg");
+        delta.write(&delta_file).unwrap();
+        let delta = FuzzyPhraseSetDelta::from_path(&delta_file).unwrap();
+
+        let applied_dir = tempfile::tempdir().unwrap();
+        SET.apply_delta(&delta, &applied_dir.path()).unwrap();
+        let applied = FuzzyPhraseSet::from_path(&applied_dir.path()).unwrap();
+        let diff = applied.diff(&other);
+        assert!(diff.words.added.is_empty() && diff.words.removed.is_empty() && diff.words.remapped.is_empty());
+        assert!(diff.added_phrases.is_empty() && diff.removed_phrases.is_empty());
+        assert!(applied.contains_str("150 main street", EndingType::NonPrefix).unwrap());
+        assert!(!applied.contains_str("100 main ave", EndingType::NonPrefix).unwrap());
+    }
+
+    #[test]
+    fn glue_delta_keeps_options_and_metadata() -> () {
+        let options = IndexOptions {
+            max_edit_distance: 2,
+            tokenizer_config: TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join },
+            word_frequencies: true,
+            membership_filter: true,
+            louds_trie: true,
+            word_depths: true,
+            ..Default::default()
+        };
+        let (_dir, base) = build_set_with(|builder| {
+            builder.set_index_options(options.clone());
+            builder.set_build_info("corpus_version", "2018-09-01");
+            builder.set_word_class("main", ::phrase::classes::TokenClass::StreetType);
+            builder.insert_record(&CorpusRecord { phrase: "100 main street".to_string(), weight: Some(2.0), language: Some("en".to_string()), payload: None }).unwrap();
+            builder.insert_with_number_range(&["1##", "main", "street"], (150, 199)).unwrap();
+            builder.insert_str("300 mlk blvd").unwrap();
+        });
+        let (_other_dir, other) = build_set(&["100 main street", "1## main street", "400 elm street"]);
+
+        let applied_dir = tempfile::tempdir().unwrap();
+        base.apply_delta(&base.delta(&other).unwrap(), &applied_dir.path()).unwrap();
+        let applied = FuzzyPhraseSet::from_path(&applied_dir.path()).unwrap();
+        assert_eq!(applied.index_options(), &options);
+        assert!(applied.louds_trie().is_some());
+        assert_eq!(applied.build_info()["corpus_version"], "2018-09-01");
+        let main_id = applied.exact_word_id("main").unwrap();
+        assert!(applied.word_classes().get(main_id).contains(::phrase::classes::TokenClass::StreetType));
+        let main_street = |set: &FuzzyPhraseSet| set.fuzzy_match_str("100 main street", 0, 0, EndingType::NonPrefix).unwrap()[0].phrase_id_range.0;
+        assert!(base.phrase_metadata(main_street(&base)).is_some());
+        assert_eq!(applied.phrase_metadata(main_street(&applied)), base.phrase_metadata(main_street(&base)));
+        assert_eq!(applied.word_frequency("main"), Some(2));
+        assert_eq!(applied.fuzzy_match_number(&["1##", "main", "street"], 175, 0, 0, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(applied.fuzzy_match_number(&["1##", "main", "street"], 142, 0, 0, EndingType::NonPrefix).unwrap(), vec![]);
+        // the edit distance is still the one the base was built with
+        assert_eq!(applied.fuzzy_match_str("100 mian stret", 2, 2, EndingType::NonPrefix).unwrap().len(), 1);
+        assert!(!applied.contains_str("300 mlk blvd", EndingType::NonPrefix).unwrap());
+    }
+
+    #[test]
+    fn glue_display_forms() -> () {
+        let (_dir, set) = build_set_with(|builder| {
+            builder.insert_with_display(&["100", "main", "st"], &["100", "Main", "St"]).unwrap();
+            builder.insert_with_display(&["200", "main", "st"], &["200", "MAIN", "St."]).unwrap();
+            builder.insert_str("300 mlk blvd").unwrap();
+            assert!(builder.insert_with_display(&["1", "2"], &["1"]).is_err());
+        });
+
+        let results = set.fuzzy_match(&["200", "mian", "st"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results[0].phrase, vec!["200", "main", "st"]);
+        // the first display form seen for each word wins
+        assert_eq!(set.display_phrase(&results[0].phrase), vec!["200", "Main", "St"]);
+
+        let results = set.fuzzy_match(&["300", "ml"], 0, 0, EndingType::AnyPrefix).unwrap();
+        assert_eq!(set.display_phrase(&results[0].phrase), vec!["300", "ml"]);
+
+        // sets built without display forms just echo their input
+        assert_eq!(SET.display_phrase(&["100", "main"]), vec!["100", "main"]);
+
+        // results can resolve their display forms directly
+        let results = set.fuzzy_match(&["200", "mian", "st"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results[0].display(&set).to_string(), "200 Main St");
+        let results = set.fuzzy_match_windows(&["at", "300", "mlk", "blvd"], 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(results[0].display(&set).to_string(), "300 mlk blvd");
+    }
+
+    #[test]
+    fn glue_split_merge() -> () {
+        let variants = SET.split_merge_variants(&["100", "mainstreet"], EndingType::NonPrefix, 1);
+        assert_eq!(variants, vec![
+            (vec!["100".to_string(), "mainstreet".to_string()], 0),
+            (vec!["100".to_string(), "main".to_string(), "street".to_string()], 1),
+        ]);
+
+        // run-together words
+        assert_eq!(SET.fuzzy_match(&["100", "mainstreet"], 1, 1, EndingType::NonPrefix).unwrap(), vec![]);
+        let results = SET.fuzzy_match_split_merge(&["100", "mainstreet"], 1, 1, EndingType::NonPrefix, 1).unwrap();
+        assert_eq!(results, vec![
+            FuzzyMatchResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 1, ending_type: EndingType::NonPrefix, phrase_id_range: (1, 1) }
+        ]);
+        // but only if the phrase budget can cover the penalty
+        assert_eq!(SET.fuzzy_match_split_merge(&["100", "mainstreet"], 1, 0, EndingType::NonPrefix, 1).unwrap(), vec![]);
+
+        // split-apart words, and a split where the second half is a prefix
+        let results = SET.fuzzy_match_split_merge(&["100", "ma", "in", "street"], 0, 1, EndingType::NonPrefix, 1).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].phrase_id_range, (1, 1));
+        let results = SET.fuzzy_match_split_merge(&["100", "mainstr"], 0, 1, EndingType::AnyPrefix, 1).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].phrase, vec!["100".to_string(), "main".to_string(), "str".to_string()]);
+    }
+
+    #[test]
+    fn glue_max_query_length() -> () {
+        let (_dir, mut set) = build_set(&["100 main street", "300 mlk blvd"]);
+
+        let query = ["100", "main", "street", "apt", "3", "springfield"];
+        let window_query = ["apt", "3", "300", "mlk", "blvd"];
+        assert_eq!(set.fuzzy_match(&query, 0, 0, EndingType::AnyPrefix).unwrap(), vec![]);
+
+        set.set_max_query_length(Some(3), QueryLengthPolicy::Error);
+        assert!(set.fuzzy_match(&query, 0, 0, EndingType::AnyPrefix).is_err());
+        assert!(set.fuzzy_match(&query[..3], 0, 0, EndingType::AnyPrefix).is_ok());
+
+        set.set_max_query_length(Some(3), QueryLengthPolicy::TruncateTail);
+        let results = set.fuzzy_match(&query, 0, 0, EndingType::AnyPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        // the last token we kept is followed by more query, so it must be a whole word
+        assert_eq!(results[0].ending_type, EndingType::WordBoundaryPrefix);
+
+        set.set_max_query_length(Some(3), QueryLengthPolicy::BestWindow);
+        let results = set.fuzzy_match_windows(&window_query, 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].start_position, 2);
+
+        set.set_max_query_length(None, QueryLengthPolicy::Error);
+        assert!(set.fuzzy_match(&query, 0, 0, EndingType::AnyPrefix).is_ok());
+    }
+
+    #[test]
+    fn glue_fuzzy_match_number() -> () {
+        assert_eq!(mask_number("142", 1), "1##");
+        assert_eq!(mask_number("12b", 0), "##b");
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.insert_with_number_range(&["1##", "main", "street"], (100, 130)).unwrap();
+            builder.insert_with_number_range(&["1##", "main", "street"], (170, 199)).unwrap();
+            builder.insert_with_number_range(&["1##", "main", "ave"], (100, 199)).unwrap();
+            builder.insert_str("1## main blvd").unwrap();
+            assert!(builder.insert_with_number_range(&["2##", "main", "ave"], (299, 200)).is_err());
+        });
+
+        let query = [mask_number("142", 1)];
+        let query = [query[0].as_str(), "main"];
+        let phrases = |results: Vec<FuzzyMatchResult>| results.into_iter().map(|r| r.phrase).collect::<Vec<_>>();
+
+        // 142 isn't in either of "1## main street"'s ranges, and "1## main blvd" has no ranges
+        let results = set.fuzzy_match_number(&[query[0], "main", "street"], 142, 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(results, vec![]);
+        let results = set.fuzzy_match_number(&[query[0], "main", "street"], 175, 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        let results = set.fuzzy_match_number(&[query[0], "main", "blvd"], 142, 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+
+        // prefix matches are kept as long as some phrase they cover is valid
+        let results = phrases(set.fuzzy_match_number(&[query[0], "main", "s"], 142, 0, 0, EndingType::AnyPrefix).unwrap());
+        assert_eq!(results.len(), 0);
+        let results = phrases(set.fuzzy_match_number(&query, 142, 0, 0, EndingType::WordBoundaryPrefix).unwrap());
+        assert_eq!(results, vec![vec!["1##".to_string(), "main".to_string()]]);
+    }
+
+    #[test]
+    fn glue_build_info() -> () {
+        assert_eq!(SET.build_info().get("fuzzy_phrase_version").map(|v| v.as_str()), Some(env!("CARGO_PKG_VERSION")));
+        assert!(!SET.build_info().contains_key("build_timestamp"));
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_build_info("corpus_version", "2018-09-01");
+            builder.set_record_build_timestamp(true);
+            builder.insert_str("100 main street").unwrap();
+        });
+        assert_eq!(set.build_info()["corpus_version"], "2018-09-01");
+        assert!(set.build_info()["build_timestamp"].parse::<u64>().unwrap() > 0);
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_build_info("build_timestamp", "0");
+            builder.set_record_build_timestamp(true);
+            builder.insert_str("100 main street").unwrap();
+        });
+        assert_eq!(set.build_info()["build_timestamp"], "0");
+    }
+
+    #[test]
+    fn glue_progress_reporter() -> () {
+        use std::sync::{Arc, Mutex};
+        use ::progress::{BuildPhase, BuildProgress};
+
+        let reports: Arc<Mutex<Vec<BuildProgress>>> = Arc::new(Mutex::new(Vec::new()));
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        let sink = reports.clone();
+        builder.set_progress_reporter(move |progress: &BuildProgress| sink.lock().unwrap().push(*progress), 2);
+        for phrase in &["100 main street", "200 main street", "100 main ave", "300 mlk blvd"] {
+            builder.insert_str(phrase).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let reports = reports.lock().unwrap();
+        let of_phase = |phase: BuildPhase| reports.iter().filter(|r| r.phase == phase).map(|r| r.items_processed).collect::<Vec<_>>();
+        // five fuzzy-matchable words (the numbers aren't), four phrases; the end of each of the
+        // last two phases is always reported
+        assert_eq!(of_phase(BuildPhase::FuzzyVariants), vec![2, 4]);
+        assert_eq!(of_phase(BuildPhase::Phrases), vec![2, 4, 4]);
+        assert!(of_phase(BuildPhase::FuzzyMap).len() > 1);
+        let last = reports.last().unwrap();
+        assert_eq!(last.phase, BuildPhase::Phrases);
+        // (the FST's root and footer only get written after the last report)
+        assert!(last.bytes_written > 0);
+        assert!(last.bytes_written < fs::metadata(dir.path().join("phrase.fst")).unwrap().len());
+    }
+
+    #[test]
+    fn glue_cancel_build() -> () {
+        use std::sync::atomic::Ordering;
+        use ::progress::{BuildPhase, BuildProgress};
+
+        let dir = tempfile::tempdir().unwrap();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        builder.set_cancellation_flag(cancel.clone());
+        // pull the plug partway through writing the phrase graph
+        let trigger = cancel.clone();
+        builder.set_progress_reporter(move |progress: &BuildProgress| {
+            if progress.phase == BuildPhase::Phrases {
+                trigger.store(true, Ordering::Relaxed);
+            }
+        }, 1);
+        for phrase in &["100 main street", "200 main street", "100 main ave", "300 mlk blvd"] {
+            builder.insert_str(phrase).unwrap();
+        }
+        let err = builder.finish().err().unwrap();
+        assert_eq!(err.to_string(), "Build cancelled");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn glue_reproducible_build() -> () {
+        let phrases = ["100 main street", "200 main street", "100 main ave", "300 mlk blvd", "1## main street"];
+        let build = |order: &[usize]| {
+            let dir = tempfile::tempdir().unwrap();
+            let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+            for i in order {
+                let phrase: Vec<&str> = phrases[*i].split(' ').collect();
+                if *i == 4 {
+                    builder.insert_with_number_range(&phrase, (150, 199)).unwrap();
+                    builder.insert_with_number_range(&phrase, (100, 120)).unwrap();
+                } else {
+                    builder.insert(&phrase).unwrap();
+                }
+            }
+            builder.finish().unwrap();
+            dir
+        };
+
+        let a = build(&[0, 1, 2, 3, 4]);
+        let b = build(&[4, 3, 2, 1, 0]);
+        let mut files: Vec<_> = fs::read_dir(a.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        files.sort();
+        assert_eq!(files.len(), 6);
+        for file in &files {
+            assert_eq!(fs::read(a.path().join(file)).unwrap(), fs::read(b.path().join(file)).unwrap(), "{:?} differs", file);
+        }
+
+        // ...but a different corpus gets a different build ID
+        let other = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&other.path(), &phrases[..4]).unwrap();
+        let a_set = FuzzyPhraseSet::from_path(&a.path()).unwrap();
+        let other_set = FuzzyPhraseSet::from_path(&other.path()).unwrap();
+        assert!(a_set.phrase_set.build_id() != other_set.phrase_set.build_id());
+
+        // as does the same corpus with different options or optional files
+        let build_id = |build: &dyn Fn(&mut FuzzyPhraseSetBuilder)| build_set_with(|builder| {
+            builder.insert_with_number_range(&["1##", "main", "street"], (100, 120)).unwrap();
+            build(builder);
+        }).1.phrase_set.build_id();
+        let plain = build_id(&|_| ());
+        assert_eq!(build_id(&|_| ()), plain);
+        assert!(build_id(&|builder| builder.set_index_options(IndexOptions { membership_filter: true, ..Default::default() })) != plain);
+        assert!(build_id(&|builder| { builder.insert_with_number_range(&["1##", "main", "street"], (150, 199)).unwrap(); }) != plain);
+        assert!(build_id(&|builder| { builder.insert_with_display(&["1##", "main", "street"], &["1##", "Main", "St"]).unwrap(); }) != plain);
+        assert!(build_id(&|builder| builder.set_word_class("main", ::phrase::classes::TokenClass::Name)) != plain);
+    }
+
+    #[test]
+    fn glue_punctuation_variants() -> () {
+        let joined = TokenizerConfig { hyphens: JoinRule::Join, apostrophes: JoinRule::Join };
+        assert_eq!(tokenize("O\u{2019}Fallon"), vec!["o", "fallon"]);
+        assert_eq!(tokenize_with("O\u{2019}Fallon", &joined), vec!["ofallon"]);
+        assert_eq!(tokenize_with("Martin-Luther-King Jr.", &joined), vec!["martinlutherking", "jr"]);
+        assert_eq!(punctuation_variants("main street"), vec![vec!["main", "street"]]);
+        assert_eq!(punctuation_variants("o'fallon-ave"), vec![
+            vec!["o", "fallon", "ave"],
+            vec!["ofallon", "ave"],
+            vec!["o", "fallonave"],
+            vec!["ofallonave"],
+        ]);
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_tokenizer_config(TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join });
+            builder.insert_raw("100 O'Fallon Rd").unwrap();
+            builder.insert_raw("300 Martin-Luther-King Blvd").unwrap();
+        });
+        assert!(set.contains_str("100 ofallon rd", EndingType::NonPrefix).unwrap());
+
+        let phrases = |results: Vec<FuzzyMatchResult>| results.into_iter().map(|r| (r.phrase.join(" "), r.edit_distance)).collect::<Vec<_>>();
+        assert_eq!(phrases(set.fuzzy_match_raw("100 o'fallon rd", 0, 0, EndingType::NonPrefix).unwrap()), vec![("100 ofallon rd".to_string(), 0)]);
+        assert_eq!(phrases(set.fuzzy_match_raw("300 martin luther-king blvd", 0, 0, EndingType::NonPrefix).unwrap()), vec![("300 martin luther king blvd".to_string(), 0)]);
+        assert_eq!(phrases(set.fuzzy_match_raw("100 O\u{2019}Falon Rd", 1, 1, EndingType::NonPrefix).unwrap()), vec![("100 ofallon rd".to_string(), 1)]);
+    }
+
+    #[test]
+    fn glue_normalize() -> () {
+        // sets normalize with the tokenizer configuration they were built with
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_tokenizer_config(TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join });
+            builder.insert_raw("100 O'Fallon Rd").unwrap();
+        });
+        assert_eq!(set.normalize("100 O\u{2019}Fallon-Rd."), vec!["100", "ofallon", "rd"]);
+        assert_eq!(set.normalize(""), Vec::<String>::new());
+
+        assert_eq!(SET.normalize("100 O'Fallon-Rd."), vec!["100", "o", "fallon", "rd"]);
+
+        // and with the word replacements they were built with
+        let (_dir, set) = build_set_with(|builder| {
+            builder.load_word_replacements(vec![WordReplacement { from: "street".to_string(), to: "st".to_string() }]).unwrap();
+            builder.insert_raw("100 Main Street").unwrap();
+        });
+        assert_eq!(set.normalize("100 Main Street"), vec!["100", "main", "st"]);
+        assert_eq!(set.normalize("Streets"), vec!["streets"]);
+    }
+
+    #[test]
+    fn glue_segmentation() -> () {
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_segmentation(Segmentation::Characters);
+            builder.insert_raw("東京駅").unwrap();
+            builder.insert_raw("Tokyo タワー").unwrap();
+        });
+        assert_eq!(set.index_options().segmentation, Segmentation::Characters);
+        // mixed-script words are split where the script changes
+        assert_eq!(set.normalize("東京tower"), vec!["東", "京", "tower"]);
+        assert_eq!(set.fuzzy_match_raw("東京駅", 0, 0, EndingType::NonPrefix).unwrap()[0].phrase, vec!["東", "京", "駅"]);
+        assert_eq!(set.fuzzy_match_raw("東京", 0, 0, EndingType::AnyPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match_raw("tokyo タワー", 0, 0, EndingType::NonPrefix).unwrap()[0].phrase, vec!["tokyo", "タ", "ワ", "ー"]);
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_segmentation(Segmentation::NGrams(2));
+            builder.insert_raw("東京駅").unwrap();
+        });
+        assert_eq!(set.normalize("東京駅"), vec!["東京", "京駅"]);
+        assert_eq!(set.normalize("東"), vec!["東"]);
+        assert_eq!(set.fuzzy_match_raw("東京駅", 0, 0, EndingType::NonPrefix).unwrap().len(), 1);
+
+        // custom segmenters are recorded by name, and have to be registered again after loading
+        let split_eki = |run: &str| -> Vec<String> {
+            match run.find('駅') {
+                Some(i) if i > 0 => vec![run[..i].to_owned(), run[i..].to_owned()],
+                _ => vec![run.to_owned()],
+            }
+        };
+        let (_dir, mut set) = build_set_with(|builder| {
+            builder.set_segmenter("eki", split_eki);
+            builder.insert_raw("東京駅").unwrap();
+        });
+        assert_eq!(set.index_options().segmentation, Segmentation::Custom("eki".to_string()));
+        assert_eq!(set.normalize("東京駅"), vec!["東京駅"]);
+        assert!(set.set_segmenter("other", split_eki).is_err());
+        set.set_segmenter("eki", split_eki).unwrap();
+        assert_eq!(set.normalize("東京駅"), vec!["東京", "駅"]);
+        assert_eq!(set.fuzzy_match_raw("東京駅", 0, 0, EndingType::NonPrefix).unwrap()[0].phrase, vec!["東京", "駅"]);
+
+        // sets built without segmentation leave unspaced words whole
+        assert_eq!(SET.normalize("東京駅"), vec!["東京駅"]);
+    }
+
+    #[test]
+    fn glue_verify_corpus() -> () {
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_tokenizer_config(TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join });
+            for phrase in &["100 Main Street", "100 O'Fallon Rd", "300 Martin-Luther-King Blvd"] {
+                builder.insert_raw(phrase).unwrap();
+            }
+        });
+
+        let report = set.verify_corpus(&["100 Main Street", "300 Martin-Luther-King Blvd", "100 O'Fallon Rd"]).unwrap();
+        assert!(report.is_ok());
+        assert_eq!((report.phrases_checked, report.phrases_found), (3, 3));
+
+        let report = set.verify_corpus(vec!["100 main street", "--", "200 Main Street", "100 Main", "Street 100 Main"]).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!((report.phrases_checked, report.phrases_found), (5, 1));
+        assert_eq!(
+            report.issues.iter().map(|issue| (issue.index, issue.failure.clone())).collect::<Vec<_>>(),
+            vec![
+                (1, VerificationFailure::Empty),
+                (2, VerificationFailure::UnknownWords(vec!["200".to_string()])),
+                (3, VerificationFailure::NotFinal),
+                (4, VerificationFailure::Missing),
+            ]
+        );
+        assert_eq!(report.issues[3].tokens, vec!["street", "100", "main"]);
+
+        // phrases tokenized upstream with different rules than the set's
+        let (_drift_dir, drifted) = build_set_with(|builder| {
+            builder.insert(&["100", "ofallon", "rd"]).unwrap();
+        });
+        let report = drifted.verify_corpus(&["100 O'Fallon Rd", "100 OFallon Rd"]).unwrap();
+        assert_eq!(report.phrases_found, 1);
+        assert_eq!(report.issues[0].tokens, vec!["100", "o", "fallon", "rd"]);
+        assert_eq!(report.issues[0].failure, VerificationFailure::TokenizationDrift(vec!["100".to_string(), "ofallon".to_string(), "rd".to_string()]));
+    }
+
+    #[test]
+    fn glue_verify_checksums() -> () {
+        let (dir, _set) = build_set(&["100 main street"]);
+        assert_eq!(
+            FuzzyPhraseSet::verify_checksums(dir.path()).unwrap(),
+            vec!["fuzzy.fst", "fuzzy.ids", "phrase.fst", "prefix.fst"]
+        );
+
+        // a file that's changed since the build, even if it still loads, doesn't match
+        let mut phrase_fst = fs::read(dir.path().join("phrase.fst")).unwrap();
+        let last = phrase_fst.len() - 1;
+        phrase_fst[last] ^= 1;
+        fs::write(dir.path().join("phrase.fst"), &phrase_fst).unwrap();
+        fs::remove_file(dir.path().join("fuzzy.ids")).unwrap();
+        let error = FuzzyPhraseSet::verify_checksums(dir.path()).err().unwrap();
+        assert_eq!(error.to_string(), "Index files are missing or don't match their checksums: fuzzy.ids, phrase.fst");
+    }
+
+    #[test]
+    fn glue_token_constraints() -> () {
+        use ::phrase::classes::{TokenClass, TokenConstraints, TokenPosition};
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.insert_str("100 main street").unwrap();
+            builder.insert_str("100 main").unwrap();
+            builder.insert_str("main street").unwrap();
+            builder.insert_str("100 n main ave").unwrap();
+            builder.set_word_class("street", TokenClass::StreetType);
+            builder.set_word_class("ave", TokenClass::StreetType);
+            builder.set_word_class("n", TokenClass::Direction);
+            builder.set_word_class("main", TokenClass::Name);
+            builder.set_word_class("nonexistent", TokenClass::Name);
+        });
+        // three explicitly tagged words, plus "100"
+        assert_eq!(set.word_classes().len(), 5);
+
+        let phrases = |results: Vec<FuzzyMatchResult>| results.into_iter().map(|r| r.phrase.join(" ")).collect::<Vec<_>>();
+        let numbered = TokenConstraints::new().require(TokenPosition::Index(0), TokenClass::Number);
+        assert_eq!(phrases(set.fuzzy_match_constrained(&["100", "main", "street"], 0, 0, EndingType::NonPrefix, &numbered).unwrap()), vec!["100 main street"]);
+        assert_eq!(phrases(set.fuzzy_match_constrained(&["main", "street"], 0, 0, EndingType::NonPrefix, &numbered).unwrap()), Vec::<String>::new());
+
+        let typed = TokenConstraints::new().require(TokenPosition::Last, TokenClass::StreetType);
+        assert_eq!(phrases(set.fuzzy_match(&["100", "main"], 0, 0, EndingType::NonPrefix).unwrap()), vec!["100 main"]);
+        assert_eq!(phrases(set.fuzzy_match_constrained(&["100", "main"], 0, 0, EndingType::NonPrefix, &typed).unwrap()), Vec::<String>::new());
+
+        // partial words only satisfy constraints that allow them
+        assert_eq!(phrases(set.fuzzy_match_constrained(&["100", "n", "main", "av"], 0, 0, EndingType::AnyPrefix, &typed).unwrap()), Vec::<String>::new());
+        let typed_or_prefix = TokenConstraints::new().require_or_prefix(TokenPosition::Last, TokenClass::StreetType);
+        assert_eq!(phrases(set.fuzzy_match_constrained(&["100", "n", "main", "av"], 0, 0, EndingType::AnyPrefix, &typed_or_prefix).unwrap()), vec!["100 n main av"]);
+
+        let windows = |results: Vec<FuzzyWindowResult>| {
+            let mut windows = results.into_iter().map(|r| (r.start_position, r.phrase.join(" "))).collect::<Vec<_>>();
+            windows.sort();
+            windows
+        };
+        assert_eq!(
+            windows(set.fuzzy_match_windows(&["100", "main", "street"], 0, 0, EndingType::NonPrefix).unwrap()),
+            vec![(0, "100 main".to_string()), (0, "100 main street".to_string()), (1, "main street".to_string())]
+        );
+        assert_eq!(
+            windows(set.fuzzy_match_windows_constrained(&["100", "main", "street"], 0, 0, EndingType::NonPrefix, &typed).unwrap()),
+            vec![(0, "100 main street".to_string()), (1, "main street".to_string())]
+        );
+        assert_eq!(
+            windows(set.fuzzy_match_windows_constrained(&["100", "main", "street"], 0, 0, EndingType::NonPrefix, &typed.clone().require(TokenPosition::Index(0), TokenClass::Name)).unwrap()),
+            vec![(1, "main street".to_string())]
+        );
+    }
+
+    #[test]
+    fn glue_context_bias() -> () {
+        let (_dir, set) = build_set_with(|builder| {
+            // phrase IDs: main street = 0, main streets = 1, maine street = 2, maine streets = 3
+            for phrase in &["main street", "main streets", "maine street", "maine streets"] {
+                builder.insert_str(phrase).unwrap();
+            }
+        });
+
+        let summarize = |results: Vec<BiasedMatchResult>| results.into_iter()
+            .map(|r| (r.result.phrase.join(" "), r.biased, r.score))
+            .collect::<Vec<_>>();
+
+        let bias = ContextBias::new([3u32].iter().cloned().collect::<BTreeSet<u32>>(), 3.0);
+        assert_eq!(summarize(set.fuzzy_match_biased(&["main", "street"], 1, 2, EndingType::NonPrefix, &bias, 10).unwrap()), vec![
+            ("maine streets".to_string(), true, 1.0),
+            ("main street".to_string(), false, 1.0),
+            ("main streets".to_string(), false, 1.0 - 1.0 / 3.0),
+            ("maine street".to_string(), false, 1.0 - 1.0 / 3.0),
+        ]);
+        // the favored phrase is the farthest from the query, but it's kept under a tight limit
+        assert_eq!(
+            summarize(set.fuzzy_match_biased(&["main", "street"], 1, 2, EndingType::NonPrefix, &bias, 2).unwrap()),
+            vec![("maine streets".to_string(), true, 1.0), ("main street".to_string(), false, 1.0)]
+        );
+        assert_eq!(set.fuzzy_match_biased(&["main", "street"], 1, 2, EndingType::NonPrefix, &bias, 0).unwrap(), vec![]);
+
+        let bias = ContextBias::new(|(start, end): (u32, u32)| start <= 2 && end >= 2, 2.0);
+        assert_eq!(
+            summarize(set.fuzzy_match_biased(&["maine", "st"], 0, 0, EndingType::AnyPrefix, &bias, 1).unwrap()),
+            vec![("maine st".to_string(), true, 2.0)]
+        );
+    }
+
+    #[test]
+    fn glue_best_effort_windows() -> () {
+        let complete = SET.fuzzy_match_windows_best_effort(&["100", "main", "street"], 0, 0, EndingType::NonPrefix).unwrap();
+        assert!(!complete.partial);
+        assert_eq!(complete.results, SET.fuzzy_match_windows(&["100", "main", "street"], 0, 0, EndingType::NonPrefix).unwrap());
+
+        // no complete phrase in here, but "100 main" starts two of them
+        assert!(SET.fuzzy_match_windows(&["x", "100", "mian", "blvd"], 1, 1, EndingType::NonPrefix).unwrap().is_empty());
+        let partial = SET.fuzzy_match_windows_best_effort(&["x", "100", "mian", "blvd"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert!(partial.partial);
+        assert_eq!(partial.results, vec![FuzzyWindowResult {
+            edit_distance: 1,
+            phrase: vec!["100".to_string(), "main".to_string()],
+            start_position: 1,
+            ending_type: EndingType::WordBoundaryPrefix,
+            phrase_id_range: (0, 1),
+            tokens_consumed: 2,
+            tokens_total: 4,
+        }]);
+
+        // ties for longest are all kept
+        let partial = SET.fuzzy_match_windows_best_effort(&["mlk", "main"], 0, 0, EndingType::NonPrefix).unwrap();
+        assert!(partial.partial);
+        assert!(partial.results.is_empty());
+        let partial = SET.fuzzy_match_windows_best_effort(&["100", "x", "200"], 0, 0, EndingType::NonPrefix).unwrap();
+        assert!(partial.partial);
+        assert_eq!(
+            partial.results.iter().map(|r| (r.phrase.join(" "), r.start_position, r.phrase_id_range)).collect::<Vec<_>>(),
+            vec![("100".to_string(), 0, (0, 1)), ("200".to_string(), 2, (2, 2))]
+        );
+    }
+
+    #[test]
+    fn glue_correct() -> () {
+        let phrases = ["main st", "maine st", "maine ave", "maine rd"];
+
+        let (_plain_dir, plain) = build_set(&phrases);
+
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_store_word_frequencies(true);
+            for phrase in &phrases {
+                builder.insert_str(phrase).unwrap();
+            }
+            // inserting a phrase again doesn't count its words again
+            builder.insert_str("maine ave").unwrap();
+        });
+
+        assert_eq!(plain.word_frequency("maine"), None);
+        assert_eq!(set.word_frequency("maine"), Some(3));
+        assert_eq!(set.word_frequency("mains"), None);
+
+        assert_eq!(set.correct("Main St"), Some(("main st".to_string(), 0)));
+        assert_eq!(set.correct("mane avw"), Some(("maine ave".to_string(), 2)));
+        assert_eq!(set.correct("elm st"), None);
+        assert_eq!(set.correct(""), None);
+
+        // "mains" is one edit away from both "main" and "maine"; without frequencies, the tie goes
+        // to the first phrase, and with them, to the more common word
+        assert_eq!(plain.correct("mains st"), Some(("main st".to_string(), 1)));
+        assert_eq!(set.correct("mains st"), Some(("maine st".to_string(), 1)));
+    }
+
+    #[test]
+    fn glue_fuzzy_match_aligned() -> () {
+        fn edit(op: EditOp, query: Option<(usize, &str)>, phrase: Option<(usize, &str)>, edit_distance: u8) -> TokenEdit {
+            TokenEdit {
+                op,
+                query_position: query.map(|q| q.0),
+                query_token: query.map(|q| q.1.to_string()),
+                phrase_position: phrase.map(|p| p.0),
+                phrase_token: phrase.map(|p| p.1.to_string()),
+                edit_distance,
+            }
+        }
+
+        let results = SET.fuzzy_match_aligned(&["100", "man", "street"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].result, SET.fuzzy_match(&["100", "man", "street"], 1, 1, EndingType::NonPrefix).unwrap()[0]);
+        assert_eq!(results[0].edits, vec![
+            edit(EditOp::Match, Some((0, "100")), Some((0, "100")), 0),
+            edit(EditOp::Substitute, Some((1, "man")), Some((1, "main")), 1),
+            edit(EditOp::Match, Some((2, "street")), Some((2, "street")), 0),
+        ]);
+
+        // a prefix of a single phrase gets aligned against all of it
+        let results = SET.fuzzy_match_aligned(&["300", "mlk"], 1, 1, EndingType::WordBoundaryPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].edits, vec![
+            edit(EditOp::Match, Some((0, "300")), Some((0, "300")), 0),
+            edit(EditOp::Match, Some((1, "mlk")), Some((1, "mlk")), 0),
+            edit(EditOp::Insert, None, Some((2, "blvd")), 0),
+        ]);
+        // ... but not a prefix of several
+        let results = SET.fuzzy_match_aligned(&["100", "main"], 0, 0, EndingType::WordBoundaryPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].edits.iter().all(|e| e.op == EditOp::Match));
+
+        // tokens dropped by the query length limit are deletions
+        lazy_static::initialize(&TMP_TO_FINAL);
+        let mut set = FuzzyPhraseSet::from_path(&DIR.path()).unwrap();
+        set.set_max_query_length(Some(3), QueryLengthPolicy::TruncateTail);
+        let results = set.fuzzy_match_aligned(&["200", "main", "stret", "apt"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].edits, vec![
+            edit(EditOp::Match, Some((0, "200")), Some((0, "200")), 0),
+            edit(EditOp::Match, Some((1, "main")), Some((1, "main")), 0),
+            edit(EditOp::Substitute, Some((2, "stret")), Some((2, "street")), 1),
+            edit(EditOp::Delete, Some((3, "apt")), None, 0),
+        ]);
+    }
+
+    #[test]
+    fn glue_jsonl_records() -> () {
+        let dir = tempfile::tempdir().unwrap();
+        let corpus = concat!(
+            "{\"phrase\": \"100 Main Street\", \"weight\": 2.5, \"language\": \"en\", \"payload\": {\"id\": 7}}\n",
+            "\n",
+            "{\"phrase\": \"300 Mlk Blvd\"}\n",
+            "{\"phrase\": \"100 main street\", \"weight\": 1.0, \"language\": \"fr\"}\n",
+        );
+        let ids = FuzzyPhraseSetBuilder::build_from_jsonl(&dir.path(), corpus.as_bytes()).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], ids[2]);
+
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert_eq!(
+            set.phrase_metadata(ids[0]),
+            Some(&PhraseMetadata { weight: Some(3.5), language: Some("en".to_string()), payload: Some("{\"id\":7}".to_string()) })
+        );
+        assert_eq!(set.phrase_metadata(ids[1]), None);
+        assert_eq!(set.contains_str("300 mlk blvd", EndingType::NonPrefix).unwrap(), true);
+
+        let bad_dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&bad_dir.path()).unwrap();
+        let error = builder.insert_jsonl("{\"phrase\": \"100 main street\"}\n{\"weight\": 1}\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("Bad corpus record on line 2"));
+    }
+
+    #[test]
+    fn glue_word_depths() -> () {
+        let phrases = ["100 main street", "200 main street", "100 main ave", "300 mlk blvd"];
+        let (_dir, mut set) = build_set(&phrases);
+        assert!(set.word_depths().is_none());
+        let (_depths_dir, depths_set) = build_set_with(|builder| {
+            builder.set_store_word_depths(true);
+            for phrase in &phrases {
+                builder.insert_str(phrase).unwrap();
+            }
+        });
+        let word_depths = depths_set.word_depths().unwrap();
+        assert_eq!(word_depths.len(), 3);
+        assert!(depths_set.index_options().word_depths);
+
+        let queries = [("100 man stret", EndingType::NonPrefix), ("100 main av", EndingType::AnyPrefix), ("main 100 street", EndingType::NonPrefix)];
+        let expected: Vec<_> = queries.iter().map(|&(q, ending)| set.fuzzy_match_str(q, 1, 2, ending).unwrap()).collect();
+        for (&(q, ending), expected) in queries.iter().zip(expected.iter()) {
+            assert_eq!(&depths_set.fuzzy_match_str(q, 1, 2, ending).unwrap(), expected);
+        }
+        assert_eq!(expected.iter().map(|results| results.len()).collect::<Vec<_>>(), vec![1, 1, 0]);
+
+        // windows stop short of words that can't be where they are, but find the same matches
+        let window_queries: [(&[&str], EndingType); 3] = [
+            (&["the", "100", "main", "st"], EndingType::AnyPrefix),
+            (&["100", "main", "ave", "mlk"], EndingType::NonPrefix),
+            (&["300", "mlk", "100", "main", "av"], EndingType::AnyPrefix),
+        ];
+        for &(q, ending) in window_queries.iter() {
+            let expected_windows = set.fuzzy_match_windows(q, 1, 1, ending).unwrap();
+            assert!(!expected_windows.is_empty());
+            assert_eq!(depths_set.fuzzy_match_windows(q, 1, 1, ending).unwrap(), expected_windows);
+        }
+        let window_query = ["the", "100", "main", "st"];
+        let expected_windows = set.fuzzy_match_windows(&window_query, 1, 1, EndingType::AnyPrefix).unwrap();
+
+        set.preload_phrase_graph(2);
+        for (&(q, ending), expected) in queries.iter().zip(expected.iter()) {
+            assert_eq!(&set.fuzzy_match_str(q, 1, 2, ending).unwrap(), expected);
+        }
+        assert_eq!(set.fuzzy_match_windows(&window_query, 1, 1, EndingType::AnyPrefix).unwrap(), expected_windows);
+        assert!(set.contains_str("100 main ave", EndingType::NonPrefix).unwrap());
+    }
+
+    #[test]
+    fn glue_index_options() -> () {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        let options = IndexOptions { max_edit_distance: 2, word_frequencies: true, ..Default::default() };
+        builder.set_index_options(options.clone());
+        builder.set_tokenizer_config(TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join });
+        let options = IndexOptions { tokenizer_config: TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join }, ..options };
+        assert_eq!(builder.index_options(), &options);
+        builder.insert_raw("100 Main Street").unwrap();
+        builder.finish().unwrap();
+
+        let set = FuzzyPhraseSet::from_path_with_options(&dir.path(), &options).unwrap();
+        assert_eq!(set.index_options(), &options);
+        assert_eq!(set.max_edit_distance(), 2);
+        assert_eq!(set.fuzzy_match_str("100 mian street", 2, 2, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.word_frequency("main"), Some(1));
+
+        let error = FuzzyPhraseSet::from_path_with_options(&dir.path(), &IndexOptions::default()).err().unwrap();
+        assert!(error.to_string().ends_with("max_edit_distance, tokenizer_config, word_frequencies"));
+
+        // the fuzzy map looks as far as the metadata says it was built to
+        assert_eq!(set.fuzzy_map.edit_distance(), Some(2));
+
+        // options from newer versions are kept, but unsupported key widths aren't loadable
+        let metadata_path = dir.path().join("metadata.json");
+        let mut metadata: serde_json::Value = serde_json::from_reader(fs::File::open(&metadata_path).unwrap()).unwrap();
+        metadata["phrase_weights"] = serde_json::Value::Bool(true);
+        serde_json::to_writer(fs::File::create(&metadata_path).unwrap(), &metadata).unwrap();
+        let set = FuzzyPhraseSet::from_path_with_options(&dir.path(), &options).unwrap();
+        assert_eq!(set.index_options().unknown.keys().collect::<Vec<_>>(), vec!["phrase_weights"]);
+        assert_eq!(set.index_options().unknown["phrase_weights"], serde_json::Value::Bool(true));
+
+        metadata["word_key_width"] = serde_json::Value::from(4);
+        serde_json::to_writer(fs::File::create(&metadata_path).unwrap(), &metadata).unwrap();
+        assert!(FuzzyPhraseSet::from_path(&dir.path()).is_err());
+
+        // and neither are indexes in formats this library doesn't know
+        metadata["word_key_width"] = serde_json::Value::from(3);
+        metadata["format_version"] = serde_json::Value::from(4);
+        serde_json::to_writer(fs::File::create(&metadata_path).unwrap(), &metadata).unwrap();
+        let error = FuzzyPhraseSet::from_path(&dir.path()).err().unwrap();
+        assert_eq!(error.to_string(), "Index has format version 4, but only versions 2 to 3 can be read");
+    }
+
+    #[test]
+    fn glue_membership_filter() -> () {
+        let (dir, set) = build_set_with(|builder| {
+            builder.set_index_options(IndexOptions { membership_filter: true, ..Default::default() });
+            builder.insert_str("100 main street").unwrap();
+            builder.insert_str("200 main avenue").unwrap();
+        });
+        assert!(dir.path().join("fuzzy.flt").exists());
+
+        assert!(set.fuzzy_map.may_contain("main"));
+        assert!(set.fuzzy_map.may_contain("avenue"));
+        assert_eq!(set.fuzzy_match_str("100 main street", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match_str("200 main ave", 1, 1, EndingType::AnyPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match_str("100 broadway", 0, 0, EndingType::NonPrefix).unwrap().len(), 0);
+
+        // rebuilding without the filter removes it
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        builder.insert_str("100 main street").unwrap();
+        builder.finish().unwrap();
+        assert!(!dir.path().join("fuzzy.flt").exists());
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert!(set.fuzzy_map.may_contain("broadway"));
+    }
+
+    #[test]
+    fn glue_small_index_threshold() -> () {
+        let (dir, set) = build_set_with(|builder| {
+            builder.set_small_index_threshold(100);
+            builder.insert_str("100 main street").unwrap();
+        });
+        assert!(dir.path().join("fuzzy.tbl").exists() && !dir.path().join("fuzzy.fst").exists());
+        assert!(set.fuzzy_map.as_fst().is_none());
+        assert_eq!(set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match_str("100 main st", 1, 1, EndingType::AnyPrefix).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn glue_script_strategies() -> () {
+        let mut strategies = ::fuzzy::ScriptStrategies::new();
+        strategies.set(unicode_ranges::Script::Latin, ::fuzzy::VariantStrategy::Exact);
+        let (_dir, set) = build_set_with(|builder| {
+            builder.set_script_strategies(&strategies);
+            builder.insert_str("100 main street").unwrap();
+        });
+        // the strategies are recorded, and queried with after loading
+        assert_eq!(set.index_options().script_strategies.get("Latin"), Some(&::fuzzy::VariantStrategy::Exact));
+        assert_eq!(set.fuzzy_match_str("100 main street", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap().len(), 0);
+
+        // without any, every script is matched by deletion
+        let (_dir, set) = build_set(&["100 main street"]);
+        assert!(set.index_options().script_strategies.is_empty());
+        assert_eq!(set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn glue_result_cache() -> () {
+        let dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&dir.path(), vec!["100 main street", "200 main avenue"]).unwrap();
+        let mut set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert_eq!(set.result_cache_stats(), None);
+
+        set.enable_result_cache(ResultCacheConfig { capacity: 2, ttl: None });
+        let uncached = set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap(), uncached);
+        assert_eq!(set.fuzzy_match_raw("100 Mian Street", 1, 1, EndingType::NonPrefix).unwrap(), uncached);
+        assert_eq!(set.result_cache_stats(), Some(ResultCacheStats { hits: 2, misses: 1, evictions: 0, entries: 1 }));
+
+        // different settings and windowed queries get entries of their own
+        assert_eq!(set.fuzzy_match_str("100 mian street", 0, 0, EndingType::NonPrefix).unwrap(), vec![]);
+        assert_eq!(set.fuzzy_match_windows(&["100", "mian", "street"], 1, 1, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.result_cache_stats(), Some(ResultCacheStats { hits: 2, misses: 3, evictions: 1, entries: 2 }));
+        // the least recently used entry was the one evicted
+        set.fuzzy_match_str("100 mian street", 0, 0, EndingType::NonPrefix).unwrap();
+        set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(set.result_cache_stats().unwrap().hits, 3);
+
+        set.clear_result_cache();
+        assert_eq!(set.result_cache_stats(), Some(ResultCacheStats::default()));
+
+        // tokens are compared whole, so however they're spelled, different queries don't collide
+        assert_eq!(set.fuzzy_match(&["100", "mian", "street"], 1, 1, EndingType::NonPrefix).unwrap(), uncached);
+        assert_eq!(set.fuzzy_match(&["100\u{1f}mian", "street"], 1, 1, EndingType::NonPrefix).unwrap(), vec![]);
+        assert_eq!(set.result_cache_stats(), Some(ResultCacheStats { hits: 0, misses: 2, evictions: 0, entries: 2 }));
+
+        set.enable_result_cache(ResultCacheConfig { capacity: 10, ttl: Some(::std::time::Duration::from_millis(0)) });
+        set.fuzzy_match_str("200 main", 1, 1, EndingType::AnyPrefix).unwrap();
+        ::std::thread::sleep(::std::time::Duration::from_millis(2));
+        assert_eq!(set.fuzzy_match_str("200 main", 1, 1, EndingType::AnyPrefix).unwrap().len(), 1);
+        assert_eq!(set.result_cache_stats(), Some(ResultCacheStats { hits: 0, misses: 2, evictions: 0, entries: 1 }));
+
+        set.disable_result_cache();
+        assert_eq!(set.result_cache_stats(), None);
+    }
+
+    #[test]
+    fn glue_why_no_match() -> () {
+        let reasons = |phrase: &[&str], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType| {
+            let report = SET.why_no_match(phrase, max_word_dist, max_phrase_dist, ending_type).unwrap();
+            (report.matched, report.tokens.into_iter().map(|token| token.reason).collect::<Vec<_>>())
+        };
+
+        assert_eq!(
+            reasons(&["100", "main", "street"], 1, 1, EndingType::NonPrefix),
+            (true, vec![NoMatchReason::Matched, NoMatchReason::Matched, NoMatchReason::Matched])
+        );
+        assert_eq!(
+            reasons(&["100", "mian", "street"], 0, 0, EndingType::NonPrefix),
+            (false, vec![NoMatchReason::Matched, NoMatchReason::OverWordDistance { nearest: 1 }, NoMatchReason::Unreached])
+        );
+        assert_eq!(
+            reasons(&["100", "xyzzy", "street"], 1, 1, EndingType::NonPrefix),
+            (false, vec![NoMatchReason::Matched, NoMatchReason::UnknownWord, NoMatchReason::Unreached])
+        );
+        assert_eq!(
+            reasons(&["100", "mian", "stret"], 1, 1, EndingType::NonPrefix),
+            (false, vec![NoMatchReason::Matched, NoMatchReason::Matched, NoMatchReason::OverPhraseDistance])
+        );
+        assert_eq!(
+            reasons(&["100", "main"], 0, 0, EndingType::NonPrefix),
+            (false, vec![NoMatchReason::Matched, NoMatchReason::Incomplete])
+        );
+        assert_eq!(
+            reasons(&["100", "main"], 0, 0, EndingType::WordBoundaryPrefix),
+            (true, vec![NoMatchReason::Matched, NoMatchReason::Matched])
+        );
+
+        let report = SET.why_no_match(&["300", "main", "street"], 0, 0, EndingType::NonPrefix).unwrap();
+        assert!(!report.matched);
+        assert_eq!(report.dead_end(), Some(1));
+        assert_eq!(report.tokens[1], TokenDiagnosis { position: 1, token: "main".to_string(), reason: NoMatchReason::DeadEnd });
+        assert_eq!(report.tokens[2].reason, NoMatchReason::Unreached);
+    }
+
+    #[test]
+    fn glue_dedup_windows() -> () {
+        let (_dir, mut set) = build_set(&PHRASES);
+        assert!(!set.dedup_windows());
+
+        let windows = |set: &FuzzyPhraseSet| {
+            set.fuzzy_match_windows(&["100", "mian", "street", "100", "main", "street", "100", "main", "street"], 1, 1, EndingType::NonPrefix).unwrap()
+                .into_iter().map(|r| (r.start_position, r.edit_distance, r.phrase.join(" "))).collect::<Vec<_>>()
+        };
+        assert_eq!(windows(&set), vec![
+            (0, 1, "100 main street".to_string()),
+            (3, 0, "100 main street".to_string()),
+            (6, 0, "100 main street".to_string()),
+        ]);
+
+        // the exact match is better than the one with the typo, and the earlier of the two exact
+        // matches wins the tie
+        set.set_dedup_windows(true);
+        assert!(set.dedup_windows());
+        assert_eq!(windows(&set), vec![(3, 0, "100 main street".to_string())]);
+
+        // windows reaching different phrases are all kept
+        let results = set.fuzzy_match_windows(&["100", "main", "street", "300", "mlk", "blvd"], 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn glue_fuzzy_guards() -> () {
+        let (_dir, mut set) = build_set_with(|builder| {
+            for phrase in PHRASES.iter() {
+                builder.insert_str(phrase).unwrap();
+            }
+            builder.insert_str("100 main st ste 4").unwrap();
+        });
+
+        let matches = |set: &FuzzyPhraseSet, query: &str, ending_type: EndingType| {
+            set.fuzzy_match_str(query, 1, 1, ending_type).unwrap().into_iter().map(|r| r.phrase.join(" ")).collect::<Vec<_>>()
+        };
+        assert_eq!(matches(&set, "100 mian street", EndingType::NonPrefix), vec!["100 main street"]);
+        assert_eq!(matches(&set, "100 main st sta 4", EndingType::NonPrefix), vec!["100 main st ste 4"]);
+
+        // guarded tokens don't get fuzzy variants, but still match exactly and as prefixes
+        set.set_fuzzy_guard_patterns(UNIT_DESIGNATOR_PATTERNS).unwrap();
+        assert!(set.is_fuzzy_guarded("Ste"));
+        assert!(set.is_fuzzy_guarded("#4"));
+        assert!(!set.is_fuzzy_guarded("main"));
+        assert!(!set.is_fuzzy_guarded("sta"));
+        set.set_fuzzy_guard_patterns(&["^sta$"]).unwrap();
+        assert_eq!(matches(&set, "100 main st sta 4", EndingType::NonPrefix), Vec::<String>::new());
+        assert_eq!(matches(&set, "100 main st ste 4", EndingType::NonPrefix), vec!["100 main st ste 4"]);
+        assert_eq!(
+            set.why_no_match(&["100", "main", "st", "sta", "4"], 1, 1, EndingType::NonPrefix).unwrap().tokens[3].reason,
+            NoMatchReason::FuzzyGuarded
+        );
+
+        set.set_fuzzy_guard_classifier(|word: &str| word.starts_with("mi"));
+        assert_eq!(matches(&set, "100 mian street", EndingType::NonPrefix), Vec::<String>::new());
+        assert_eq!(matches(&set, "100 main stree", EndingType::AnyPrefix), vec!["100 main stree"]);
+
+        set.clear_fuzzy_guards();
+        assert_eq!(matches(&set, "100 mian street", EndingType::NonPrefix), vec!["100 main street"]);
+        assert_eq!(matches(&set, "100 main st sta 4", EndingType::NonPrefix), vec!["100 main st ste 4"]);
+    }
+
+    #[test]
+    fn glue_rerank() -> () {
+        let (_dir, set) = build_set(&["main street", "mean street"]);
+
+        // both are one edit away, so by edit distance alone they come back in lexical order
+        let results = set.fuzzy_match(&["maen", "street"], 1, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.iter().map(|r| r.phrase.join(" ")).collect::<Vec<_>>(), vec!["main street", "mean street"]);
+
+        // but the transposition leaves "mean" looking much more like "maen"
+        let reranked = set.fuzzy_match_reranked(&["maen", "street"], 1, 1, EndingType::NonPrefix, &RerankConfig::default()).unwrap();
+        assert_eq!(reranked.iter().map(|r| r.result.phrase.join(" ")).collect::<Vec<_>>(), vec!["mean street", "main street"]);
+        assert!(reranked[0].similarity > reranked[1].similarity);
+        assert!(reranked[0].score > reranked[1].score);
+
+        // exact matches still come first, and with no weight on similarity, it's edit distance
+        // then natural order
+        let reranked = set.fuzzy_match_reranked(&["main", "street"], 1, 1, EndingType::NonPrefix, &RerankConfig::default()).unwrap();
+        assert_eq!(reranked[0].result.phrase.join(" "), "main street");
+        assert_eq!(reranked[0].similarity, 1.0);
+        let config = RerankConfig { similarity_weight: 0.0, ..RerankConfig::default() };
+        let reranked = set.rerank(&["maen", "street"], results.clone(), 1, &config).unwrap();
+        assert_eq!(reranked.into_iter().map(|r| r.result).collect::<Vec<_>>(), results);
+
+        // past a prefix scale of 0.25, similarities can come out above 1
+        let config = RerankConfig { prefix_scale: 0.3, ..RerankConfig::default() };
+        assert!(set.rerank(&["maen", "street"], results.clone(), 1, &config).is_err());
+        assert!(set.fuzzy_match_reranked(&["maen", "street"], 1, 1, EndingType::NonPrefix, &config).is_err());
+    }
+
+    #[test]
+    fn glue_weight_overlay() -> () {
+        let (dir, mut set) = build_set_with(|builder| {
+            builder.insert_record(&CorpusRecord { phrase: "main street".to_string(), weight: Some(2.0), language: None, payload: None }).unwrap();
+            builder.insert_str("mean street").unwrap();
+        });
+        let (main_id, mean_id) = (0, 1);
+        assert_eq!(set.phrase_weight(main_id), 2.0);
+        assert_eq!(set.phrase_weight(mean_id), 0.0);
+
+        let config = RerankConfig { similarity_weight: 0.0, popularity_weight: 0.5, ..RerankConfig::default() };
+        let ranked = |set: &FuzzyPhraseSet| set.fuzzy_match_reranked(&["maen", "street"], 1, 1, EndingType::NonPrefix, &config).unwrap()
+            .into_iter().map(|r| r.result.phrase.join(" ")).collect::<Vec<_>>();
+        assert_eq!(ranked(&set), vec!["main street", "mean street"]);
+
+        // feedback in the overlay can outweigh the build-time weight
+        let overlay_path = dir.path().join("weights.bin");
+        let mut overlay = unsafe { WeightOverlay::create(&overlay_path, &set).unwrap() };
+        assert_eq!(overlay.len(), 2);
+        assert_eq!(overlay.get(mean_id), 0.0);
+        overlay.add(mean_id, 3.0).unwrap();
+        overlay.add(mean_id, 2.0).unwrap();
+        assert!(overlay.set(2, 1.0).is_err());
+        assert_eq!(overlay.get(2), 0.0);
+        set.set_weight_overlay(overlay).unwrap();
+        assert_eq!(set.phrase_weight(mean_id), 5.0);
+        assert_eq!(ranked(&set), vec!["mean street", "main street"]);
+
+        // until it decays
+        set.weight_overlay_mut().unwrap().decay(0.25);
+        assert_eq!(set.phrase_weight(mean_id), 1.25);
+        assert_eq!(ranked(&set), vec!["main street", "mean street"]);
+
+        // changes survive being flushed and reopened
+        let overlay = set.take_weight_overlay().unwrap();
+        overlay.flush().unwrap();
+        drop(overlay);
+        assert_eq!(set.phrase_weight(mean_id), 0.0);
+        let overlay = unsafe { WeightOverlay::open(&overlay_path).unwrap() };
+        assert_eq!(overlay.get(mean_id), 1.25);
+
+        // but can't be used with another index
+        lazy_static::initialize(&SET);
+        let mut other = FuzzyPhraseSet::from_path(&DIR.path()).unwrap();
+        assert!(other.set_weight_overlay(overlay).is_err());
+        fs::write(&overlay_path, [0u8; 5]).unwrap();
+        assert!(unsafe { WeightOverlay::open(&overlay_path) }.is_err());
+    }
+
+    #[test]
+    fn glue_warm_up() -> () {
+        let sequential = SET.warm_up(&WarmUpStrategy::Sequential).unwrap();
+        let size = SET.prefix_set.as_fst().as_bytes().len() + SET.phrase_set.as_fst().as_bytes().len() +
+            SET.fuzzy_map.as_fst().map_or(0, |fst| fst.as_bytes().len());
+        assert_eq!(sequential, WarmUpReport { bytes_read: size as u64, ..Default::default() });
+
+        let shallow = SET.warm_up(&WarmUpStrategy::HotPrefixes { depth: 1 }).unwrap();
+        let deep = SET.warm_up(&WarmUpStrategy::HotPrefixes { depth: 4 }).unwrap();
+        assert!(shallow.nodes_visited > 2 && deep.nodes_visited > shallow.nodes_visited);
+        assert_eq!(SET.warm_up(&WarmUpStrategy::HotPrefixes { depth: 0 }).unwrap().nodes_visited, SET.mapped_fsts().len() as u64);
+
+        let log = vec![vec!["100".to_string(), "main".to_string()], vec![], vec!["main".to_string()]];
+        assert_eq!(SET.warm_up(&WarmUpStrategy::QueryLog(log)).unwrap().queries_replayed, 2);
+    }
+
+    #[test]
+    fn glue_phrase_matcher_backend() -> () {
+        use std::cell::Cell;
+        use ::phrase::{Combination, CombinationWindow, PhraseId, PhraseIdRange, PhraseMatcher};
+
+        // an engine that hands everything off to a phrase set, counting what it's asked to do
+        struct CountingMatcher<'a> {
+            inner: &'a PhraseSet,
+            calls: Cell<usize>,
+        }
+        impl<'a> PhraseMatcher for CountingMatcher<'a> {
+            fn phrase_id(&self, phrase: &[QueryWord]) -> Option<PhraseId> {
+                self.inner.phrase_id(phrase)
+            }
+            fn prefix_range(&self, phrase: &[QueryWord]) -> Option<PhraseIdRange> {
+                self.inner.prefix_range(phrase)
+            }
+            fn match_combinations(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+                self.calls.set(self.calls.get() + 1);
+                PhraseMatcher::match_combinations(self.inner, word_possibilities, max_phrase_dist)
+            }
+            fn match_combinations_as_prefixes(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+                self.calls.set(self.calls.get() + 1);
+                PhraseMatcher::match_combinations_as_prefixes(self.inner, word_possibilities, max_phrase_dist)
+            }
+            fn match_combinations_as_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+                self.calls.set(self.calls.get() + 1);
+                PhraseMatcher::match_combinations_as_windows(self.inner, word_possibilities, max_phrase_dist, ends_in_prefix)
+            }
+        }
+
+        let matcher = CountingMatcher { inner: SET.phrase_set(), calls: Cell::new(0) };
+        for &(query, ending_type) in &[
+            (&["100", "main", "stret"][..], EndingType::NonPrefix),
+            (&["100", "main", "st"][..], EndingType::WordBoundaryPrefix),
+            (&["100", "man"][..], EndingType::AnyPrefix),
+        ] {
+            assert_eq!(
+                SET.fuzzy_match_with(&matcher, query, 1, 1, ending_type).unwrap(),
+                SET.fuzzy_match(query, 1, 1, ending_type).unwrap()
+            );
+        }
+        // queries with words that aren't found never get as far as the matcher
+        let calls = matcher.calls.get();
+        assert!(calls > 0);
+        assert_eq!(SET.fuzzy_match_with(&matcher, &["zzzz", "main"], 1, 1, EndingType::NonPrefix).unwrap(), vec![]);
+        assert_eq!(matcher.calls.get(), calls);
+
+        let query = ["at", "100", "main", "street", "in", "town"];
+        let dyn_matcher: &dyn PhraseMatcher = SET.phrase_set();
+        assert_eq!(
+            SET.fuzzy_match_windows_with(dyn_matcher, &query, 1, 1, EndingType::AnyPrefix).unwrap(),
+            SET.fuzzy_match_windows(&query, 1, 1, EndingType::AnyPrefix).unwrap()
+        );
+
+        let words: Vec<QueryWord> = ["100", "main", "street"].iter().map(|word| QueryWord::new_full(SET.exact_word_id(word).unwrap(), 0)).collect();
+        assert!(dyn_matcher.phrase_id(&words).is_some());
+        assert_eq!(dyn_matcher.phrase_id(&words), SET.phrase_set().lookup(&words).phrase_id());
+        assert_eq!(dyn_matcher.phrase_id(&words[..2]), None);
+        assert!(dyn_matcher.prefix_range(&words[..2]).unwrap().contains(dyn_matcher.phrase_id(&words).unwrap()));
+    }
+
+    #[test]
+    fn glue_louds_trie() -> () {
+        let (dir, set) = build_set_with(|builder| {
+            builder.set_index_options(IndexOptions { louds_trie: true, ..Default::default() });
+            for phrase in &["100 main street", "100 main avenue", "200 main street", "main street station", "100 maple street"] {
+                builder.insert_str(phrase).unwrap();
+            }
+        });
+        assert!(dir.path().join("phrase.louds").exists());
+
+        let trie = set.louds_trie().unwrap();
+        assert_eq!(trie.len(), set.phrase_count());
+        for &(query, ending_type) in &[
+            ("100 main stret", EndingType::NonPrefix),
+            ("100 ma", EndingType::AnyPrefix),
+            ("100 main", EndingType::WordBoundaryPrefix),
+            ("200 mian street", EndingType::NonPrefix),
+        ] {
+            let query: Vec<&str> = query.split(' ').collect();
+            assert_eq!(
+                set.fuzzy_match_with(trie, &query, 1, 1, ending_type).unwrap(),
+                set.fuzzy_match(&query, 1, 1, ending_type).unwrap()
+            );
+        }
+        let query = ["at", "100", "main", "street", "station", "ma"];
+        assert_eq!(
+            set.fuzzy_match_windows_with(trie, &query, 1, 1, EndingType::AnyPrefix).unwrap(),
+            set.fuzzy_match_windows(&query, 1, 1, EndingType::AnyPrefix).unwrap()
+        );
+
+        // a trie from another build is refused
+        let other = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&other.path()).unwrap();
+        builder.set_index_options(IndexOptions { louds_trie: true, ..Default::default() });
+        builder.insert_str("100 main street").unwrap();
+        builder.finish().unwrap();
+        fs::copy(other.path().join("phrase.louds"), dir.path().join("phrase.louds")).unwrap();
+        assert!(FuzzyPhraseSet::from_path(&dir.path()).is_err());
+
+        // and sets built without the option don't have one
+        assert!(SET.louds_trie().is_none());
+        assert!(!DIR.path().join("phrase.louds").exists());
+    }
+
+    #[test]
+    fn glue_match_kind() -> () {
+        let (_dir, set) = build_set(&["main street", "main street north", "main avenue"]);
+
+        assert_eq!(set.match_kind(&["main", "avenue"]), Some(PhraseMatchKind::ExactOnly));
+        assert_eq!(set.match_kind(&["main", "street"]), Some(PhraseMatchKind::ExactWithContinuations));
+        assert_eq!(set.match_kind(&["main"]), Some(PhraseMatchKind::PrefixOnly));
+        assert_eq!(set.match_kind(&["main", "str"]), None);
+        assert_eq!(set.match_kind(&["north"]), None);
+        assert_eq!(set.match_kind::<&str>(&[]), None);
+    }
+
+    #[test]
+    fn glue_fuzzy_prefixes() -> () {
+        let (_dir, mut set) = build_set(&["100 main street", "100 main stop", "100 main avenue"]);
+        // prefix results echo the query, so tell them apart by the phrases they cover
+        let phrase_ids = |set: &FuzzyPhraseSet, query: &str| -> Vec<(u32, u32)> {
+            let query: Vec<&str> = query.split(' ').collect();
+            set.fuzzy_match(&query, 1, 1, EndingType::AnyPrefix).unwrap().iter().map(|r| r.phrase_id_range).collect()
+        };
+        let (avenue, stop, street) = (0, 1, 2);
+
+        // "stere" is two edits away from "street", but only one from "stree"
+        assert!(!set.fuzzy_prefixes());
+        assert_eq!(phrase_ids(&set, "100 main stere"), vec![]);
+        set.set_fuzzy_prefixes(true);
+        assert_eq!(phrase_ids(&set, "100 main stere"), vec![(street, street)]);
+        assert_eq!(phrase_ids(&set, "100 main stro"), vec![(stop, street)]);
+        assert_eq!(phrase_ids(&set, "100 main avneue"), vec![(avenue, avenue)]);
+        // too short to expand
+        assert_eq!(phrase_ids(&set, "100 main sx"), vec![]);
+
+        // a near miss costs its distance, like a misspelled whole word, but the typed prefix's own
+        // words don't
+        let results = set.fuzzy_match(&["100", "main", "stere"], 1, 1, EndingType::AnyPrefix).unwrap();
+        assert_eq!(results.iter().map(|r| r.edit_distance).collect::<Vec<u8>>(), vec![1]);
+        assert!(set.fuzzy_match(&["100", "main", "stere"], 1, 0, EndingType::AnyPrefix).unwrap().is_empty());
+        assert!(set.fuzzy_match(&["100", "mian", "stere"], 1, 1, EndingType::AnyPrefix).unwrap().is_empty());
+        assert_eq!(set.fuzzy_match(&["100", "mian", "stere"], 1, 2, EndingType::AnyPrefix).unwrap().len(), 1);
+        let results = set.fuzzy_match(&["100", "main", "stre"], 1, 0, EndingType::AnyPrefix).unwrap();
+        assert_eq!(results.iter().map(|r| (r.phrase_id_range, r.edit_distance)).collect::<Vec<_>>(), vec![((street, street), 0)]);
+    }
+
+    #[test]
+    fn glue_auto_edit_distance() -> () {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        // the short words are all within one edit of each other, and the long ones nowhere near
+        for phrase in &["cat", "bat", "hat", "mat", "rat", "sat", "pat", "fat", "springfield", "riverside", "lakewood", "greenville"] {
+            builder.insert_raw(phrase).unwrap();
+        }
+        let density = builder.analyze_vocabulary().unwrap();
+        assert_eq!(density.words, 12);
+        assert_eq!(density.by_length[&3], LengthDensity { sampled: 8, within_1: 56, within_2: 56 });
+        assert_eq!(density.by_length[&8], LengthDensity { sampled: 1, within_1: 0, within_2: 0 });
+        assert_eq!(density.recommend(2.0), EditDistanceRecommendation { max_edit_distance: 2, distance_min_lengths: vec![0, 8] });
+        assert_eq!(density.recommend(10.0), EditDistanceRecommendation { max_edit_distance: 2, distance_min_lengths: vec![] });
+        builder.set_auto_edit_distance(Some(2.0));
+        builder.finish().unwrap();
+
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert_eq!(set.max_edit_distance(), 2);
+        assert_eq!(set.index_options().distance_min_lengths, vec![0, 8]);
+        assert_eq!(set.vocabulary_density(), Some(&density));
+        // long words are matched two edits out, and short ones only one
+        assert_eq!(set.fuzzy_match(&["sprngfeld"], 2, 2, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match(&["ct"], 2, 2, EndingType::NonPrefix).unwrap().len(), 1);
+        assert_eq!(set.fuzzy_match(&["kab"], 2, 2, EndingType::NonPrefix).unwrap().len(), 0);
+
+        // without it, the configured distance is used, and nothing's recorded
+        assert_eq!(SET.vocabulary_density(), None);
+        assert!(SET.index_options().distance_min_lengths.is_empty());
+    }
+
+    #[test]
+    fn glue_prefix_segmentation() -> () {
+        let dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(dir.path(), &["서울 강남구", "서울 가나다로", "서울 강북구", "main street"]).unwrap();
+        let mut set = FuzzyPhraseSet::from_path(dir.path()).unwrap();
+        let phrases = |set: &FuzzyPhraseSet, query: &[&str]| -> Vec<Vec<String>> {
+            let mut phrases: Vec<Vec<String>> = Vec::new();
+            for result in set.fuzzy_match(query, 0, 0, EndingType::AnyPrefix).unwrap() {
+                for id in result.phrase_id_range.0..=result.phrase_id_range.1 {
+                    phrases.push(set.get_by_phrase_id(id).unwrap().unwrap());
+                }
+            }
+            phrases.sort();
+            phrases
+        };
+        let gangnam = vec!["서울".to_string(), "강남구".to_string()];
+        let ganada = vec!["서울".to_string(), "가나다로".to_string()];
+        let gangbuk = vec!["서울".to_string(), "강북구".to_string()];
+
+        // as typed, a half-composed syllable or a truncated character only matches itself
+        assert_eq!(set.prefix_segmentation(), PrefixSegmentation::Bytes);
+        assert_eq!(phrases(&set, &["서울", "ㄱ"]), Vec::<Vec<String>>::new());
+        assert_eq!(phrases(&set, &["서울", "\u{1100}\u{1161}\u{11bc}"]), Vec::<Vec<String>>::new());
+        assert_eq!(phrases(&set, &["main", "str\u{fffd}"]), Vec::<Vec<String>>::new());
+
+        set.set_prefix_segmentation(PrefixSegmentation::Chars);
+        assert_eq!(phrases(&set, &["서울", "\u{1100}\u{1161}\u{11bc}"]), vec![gangnam.clone(), gangbuk.clone()]);
+        assert_eq!(phrases(&set, &["main", "str\u{fffd}"]), vec![vec!["main".to_string(), "street".to_string()]]);
+        assert_eq!(phrases(&set, &["서울", "ㄱ"]), Vec::<Vec<String>>::new());
+        // a token with nothing complete in it doesn't match every word
+        assert_eq!(phrases(&set, &["main", "\u{fffd}"]), Vec::<Vec<String>>::new());
+
+        set.set_prefix_segmentation(PrefixSegmentation::Composing);
+        assert_eq!(phrases(&set, &["서울", "\u{200d}"]), Vec::<Vec<String>>::new());
+        // a lone consonant begins any syllable that starts with it
+        assert_eq!(phrases(&set, &["서울", "ㄱ"]), vec![ganada.clone(), gangnam.clone(), gangbuk.clone()]);
+        // "간" may be on its way to "가나", with the final consonant moving on to the next syllable
+        assert_eq!(phrases(&set, &["서울", "간"]), vec![ganada.clone()]);
+        // and "가" may yet get one
+        assert_eq!(phrases(&set, &["서울", "가"]), vec![ganada, gangnam, gangbuk]);
+        assert_eq!(phrases(&set, &["서울", "ㅎ"]), Vec::<Vec<String>>::new());
+    }
+
+    #[test]
+    fn glue_query_alternates() -> () {
+        // "st" isn't a word, so its alternates are all it can match
+        let word_possibilities = SET.query_builder()
+            .alternates(2, &["street", "ave", "boulevard"])
+            .build(&["100", "main", "st"])
+            .unwrap()
+            .unwrap();
+        let alternate = |word: &str| QueryWord::new_full(SET.exact_word_id(word).unwrap(), 0)
+            .with_variant_source(VariantSource::Alternate)
+            .with_source_position(2);
+        assert_eq!(word_possibilities[2], vec![alternate("street"), alternate("ave")]);
+        assert_eq!(SET.query_builder().build(&["100", "main", "st"]).unwrap(), None);
+
+        let combinations = SET.phrase_set.match_combinations(&word_possibilities, 0).unwrap();
+        assert_eq!(combinations.len(), 2);
+        for combination in &combinations {
+            assert_eq!(combination.variant_count(VariantSource::Exact), 2);
+            assert_eq!(combination.variant_count(VariantSource::Alternate), 1);
+        }
+
+        // alternates go ahead of typos, and aren't repeated if they're already possibilities
+        let word_possibilities = SET.query_builder()
+            .max_word_dist(1)
+            .alternates(1, &["mlk", "main"])
+            .build(&["300", "mian", "blvd"])
+            .unwrap()
+            .unwrap();
+        let sources: Vec<(u8, VariantSource)> = word_possibilities[1].iter().map(|word| match *word {
+            QueryWord::Full { edit_distance, source, .. } => (edit_distance, source),
+            QueryWord::Prefix { .. } => panic!("expected only full words"),
+        }).collect();
+        assert_eq!(sources, vec![(0, VariantSource::Alternate), (1, VariantSource::Typo)]);
+
+        let combinations = SET.phrase_set.match_combinations(&word_possibilities, 1).unwrap();
+        assert_eq!(combinations.len(), 1);
+        assert_eq!(combinations[0].variant_count(VariantSource::Alternate), 1);
+        assert_eq!(combinations[0].variant_count(VariantSource::Typo), 0);
+    }
+
+    #[test]
+    fn glue_fuzzy_match_reordered() -> () {
+        assert_eq!(SET.fuzzy_match_str("main 100 street", 0, 0, EndingType::NonPrefix).unwrap(), vec![]);
+        assert_eq!(SET.fuzzy_match_reordered(&["main", "100", "street"], 0, 0, 0, EndingType::NonPrefix).unwrap(), vec![]);
+
+        let results = SET.fuzzy_match_reordered(&["main", "100", "street"], 0, 0, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].swaps, 1);
+        assert_eq!(results[0].positions, vec![1, 0, 2]);
+        assert_eq!(results[0].result.phrase, vec!["100", "main", "street"]);
+        assert_eq!(results[0].result.edit_distance, 0);
+
+        // a typo and a swap are both within budget, and the prefix stays at the end
+        let results = SET.fuzzy_match_reordered(&["mian", "100", "str"], 1, 1, 1, EndingType::AnyPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].result.phrase, vec!["100", "main", "str"]);
+        assert_eq!((results[0].result.edit_distance, results[0].swaps), (1, 1));
+
+        // results in order are ranked ahead of reordered ones
+        let results = SET.fuzzy_match_reordered(&["100", "main", "street"], 0, 0, 2, EndingType::NonPrefix).unwrap();
+        assert_eq!(results[0].swaps, 0);
+        assert_eq!(results[0].result, SET.fuzzy_match_str("100 main street", 0, 0, EndingType::NonPrefix).unwrap()[0]);
+    }
+
+    #[test]
+    fn glue_fuzzy_match_windows_with_gaps() -> () {
+        let query = ["100", "main", "apt", "4", "street"];
+        assert!(SET.fuzzy_match_windows(&query, 0, 2, EndingType::NonPrefix).unwrap().is_empty());
+        assert_eq!(SET.fuzzy_match_windows_with_gaps(&query, 0, 2, 1, 1, EndingType::NonPrefix).unwrap(), vec![]);
+
+        let results = SET.fuzzy_match_windows_with_gaps(&query, 0, 2, 2, 1, EndingType::NonPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].skipped, vec![2, 3]);
+        assert_eq!(results[0].result.phrase, vec!["100", "main", "street"]);
+        assert_eq!((results[0].result.start_position, results[0].result.tokens_consumed), (0, 5));
+        // the skips cost more than this budget allows
+        assert_eq!(SET.fuzzy_match_windows_with_gaps(&query, 0, 1, 2, 1, EndingType::NonPrefix).unwrap(), vec![]);
+
+        // a prefix at the end is echoed back as typed
+        let results = SET.fuzzy_match_windows_with_gaps(&["x", "100", "main", "apt", "av"], 0, 1, 1, 1, EndingType::AnyPrefix).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].skipped, vec![3]);
+        assert_eq!(results[0].result.phrase, vec!["100", "main", "av"]);
+        assert_eq!((results[0].result.start_position, results[0].result.tokens_consumed), (1, 4));
+
+        // without skips, the same windows as fuzzy_match_windows
+        let query = ["100", "main", "street", "300", "mlk", "blvd"];
+        let mut plain = SET.fuzzy_match_windows(&query, 1, 1, EndingType::AnyPrefix).unwrap();
+        let mut gapped: Vec<FuzzyWindowResult> = SET.fuzzy_match_windows_with_gaps(&query, 1, 1, 0, 1, EndingType::AnyPrefix).unwrap()
+            .into_iter()
+            .map(|gapped| gapped.result)
+            .collect();
+        plain.sort();
+        gapped.sort();
+        assert_eq!(gapped, plain);
+    }
+
+    #[test]
+    fn glue_near_duplicates() -> () {
+        let (_dir, set) = build_set(&["100 main street", "100 mian street", "100 maine street", "100 main st", "200 main street", "100 mian"]);
+
+        let report = set.near_duplicates().unwrap();
+        assert_eq!(report.phrases_checked, 6);
+        let mut pairs: Vec<(String, String)> = report.pairs.iter().map(|pair| {
+            let mut other = pair.phrase.clone();
+            other[pair.position] = pair.other_word.clone();
+            let phrase = |id: u32| set.get_by_phrase_id(id).unwrap().unwrap().join(" ");
+            let (first, second) = (phrase(pair.phrase_ids.0), phrase(pair.phrase_ids.1));
+            assert_eq!((&first, &second), (&pair.phrase.join(" "), &other.join(" ")));
+            (first, second)
+        }).collect();
+        pairs.sort();
+        // "mian" and "maine" are two edits apart, and "st" and "street" are more than one
+        assert_eq!(pairs, vec![
+            ("100 main street".to_string(), "100 maine street".to_string()),
+            ("100 main street".to_string(), "100 mian street".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn glue_to_dot() -> () {
+        let dot = SET.to_dot(&["100", "main"], 1).unwrap();
+        assert!(dot.starts_with("digraph phrases {"));
+        assert!(dot.contains("label=\"100 main\\n0..1\""));
+        assert!(dot.contains("label=\"ave ("));
+        assert!(dot.contains("label=\"street ("));
+        assert_eq!(dot.matches(" -> ").count(), 2);
+
+        assert_eq!(SET.to_dot(&["100", "mlk"], 1), None);
+        assert_eq!(SET.to_dot(&["100", "nowhere"], 1), None);
+    }
+
+    #[test]
+    fn glue_completions_paged() -> () {
+        let (_dir, set) = build_set(&["main st", "main st north", "main st south", "main street", "maine st", "mains ave", "oak st"]);
+
+        let all = set.completions(&["main", "st"], 1, 1, EndingType::AnyPrefix, 100).unwrap();
+        assert_eq!(all.cursor, None);
+        assert_eq!(
+            all.completions.iter().map(|c| (c.phrase.join(" "), c.edit_distance)).collect::<Vec<_>>(),
+            vec![
+                ("main st".to_string(), 0),
+                ("main st north".to_string(), 0),
+                ("main st south".to_string(), 0),
+                ("main street".to_string(), 0),
+                ("maine st".to_string(), 1),
+            ]
+        );
+
+        // the same completions, two at a time, with the cursor passed around as bytes
+        let mut page = set.completions(&["main", "st"], 1, 1, EndingType::AnyPrefix, 2).unwrap();
+        let mut paged: Vec<Completion> = page.completions.clone();
+        let mut page_count = 1;
+        while let Some(cursor) = page.cursor {
+            let cursor = CompletionCursor::from_bytes(&cursor.to_bytes()).unwrap();
+            page = set.completions_from(&cursor, 2).unwrap();
+            assert!(page.completions.len() <= 2);
+            paged.extend(page.completions.iter().cloned());
+            page_count += 1;
+        }
+        assert_eq!(paged, all.completions);
+        assert_eq!(page_count, 3);
+
+        // cursors can't be used with other indexes, or if they've been mangled
+        let cursor = set.completions(&["main", "st"], 1, 1, EndingType::AnyPrefix, 2).unwrap().cursor.unwrap();
+        assert!(SET.completions_from(&cursor, 2).is_err());
+        assert!(set.completions_from(&cursor, 0).is_err());
+        assert!(set.completions(&["main", "st"], 1, 1, EndingType::AnyPrefix, 0).is_err());
+        let bytes = cursor.to_bytes();
+        assert!(CompletionCursor::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let none = set.completions(&["elm"], 1, 1, EndingType::AnyPrefix, 2).unwrap();
+        assert_eq!(none, CompletionPage { completions: vec![], cursor: None });
+    }
+
+    #[test]
+    fn glue_federated() -> () {
+        let us_dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&us_dir.path(), vec!["100 main street", "100 main ave"]).unwrap();
+        let ca_dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&ca_dir.path(), vec!["100 maine street", "200 main st"]).unwrap();
+
+        let mut federated = FederatedFuzzyPhraseSet::new();
+        assert!(federated.is_empty());
+        assert_eq!(federated.add_path("us", &us_dir.path()).unwrap(), 0);
+        assert_eq!(federated.add_path("ca", &ca_dir.path()).unwrap(), 1);
+        assert_eq!(federated.name(1), Some("ca"));
+
+        let results = federated.fuzzy_match(&["100", "maine", "street"], 1, 1, EndingType::NonPrefix).unwrap();
+        let summary: Vec<(usize, String, u8)> = results.iter()
+            .map(|r| (r.index, r.result.phrase.join(" "), r.result.edit_distance))
+            .collect();
+        // the exact match from the second set outranks the fuzzy one from the first
+        assert_eq!(summary, vec![
+            (1, "100 maine street".to_string(), 0),
+            (0, "100 main street".to_string(), 1),
+        ]);
+        assert_eq!(results[0].score, 1.0);
+        assert_eq!(results[1].score, 0.5);
+
+        // weights and window coverage both scale scores
+        let mut federated = FederatedFuzzyPhraseSet::new();
+        federated.add_weighted("us", FuzzyPhraseSet::from_path(&us_dir.path()).unwrap(), 0.5);
+        federated.add_path("ca", &ca_dir.path()).unwrap();
+        let results = federated.fuzzy_match_windows(&["100", "main", "street", "apt", "1"], 0, 0, EndingType::NonPrefix).unwrap();
+        let summary: Vec<(usize, String)> = results.iter().map(|r| (r.index, r.result.phrase.join(" "))).collect();
+        assert_eq!(summary, vec![(0, "100 main street".to_string())]);
+        assert_eq!(results[0].score, 0.5 * 3.0 / 5.0);
+    }
+
+    #[test]
+    fn glue_federated_languages() -> () {
+        let en_dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&en_dir.path(), vec!["main street", "o'brien street"]).unwrap();
+        let de_dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&de_dir.path(), vec!["main street", "hauptstrasse"]).unwrap();
+        let any_dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(&any_dir.path(), vec!["main street"]).unwrap();
+
+        let mut federated = FederatedFuzzyPhraseSet::new();
+        federated.add_path("en", &en_dir.path()).unwrap();
+        federated.add_path("de", &de_dir.path()).unwrap();
+        federated.add_path("any", &any_dir.path()).unwrap();
+        federated.set_languages(0, &["eng"]);
+        federated.set_languages(1, &["deu"]);
+        assert_eq!(federated.languages(1), Some(&["deu".to_string()][..]));
+        assert_eq!(federated.languages(2), Some(&[][..]));
+
+        let indexes = |results: Vec<FederatedMatchResult>| results.into_iter().map(|r| r.index).collect::<Vec<_>>();
+        // without a detector, everything is queried
+        assert_eq!(indexes(federated.fuzzy_match_raw("Main Street", 0, 0, EndingType::NonPrefix).unwrap()), vec![0, 1, 2]);
+
+        federated.set_language_detector(|query: &str| {
+            if query.contains("rue") {
+                Some("fra".to_string())
+            } else if query.contains("strasse") {
+                Some("deu".to_string())
+            } else if query.contains("street") {
+                Some("eng".to_string())
+            } else {
+                None
+            }
+        });
+        assert_eq!(federated.detect_language("main street"), Some("eng".to_string()));
+        assert_eq!(indexes(federated.fuzzy_match_raw("main street", 0, 0, EndingType::NonPrefix).unwrap()), vec![0, 2]);
+        assert_eq!(indexes(federated.fuzzy_match_raw("hauptstrasse", 0, 0, EndingType::NonPrefix).unwrap()), vec![1]);
+        // nothing's tagged for this one, so everything is queried again
+        let windows = federated.fuzzy_match_windows_raw("main street rue", 0, 0, EndingType::NonPrefix).unwrap();
+        assert_eq!(windows.into_iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        // per-language tokenization
+        assert_eq!(indexes(federated.fuzzy_match_raw("o'brien street", 0, 0, EndingType::NonPrefix).unwrap()), vec![0]);
+        federated.set_language_tokenizer("eng", TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Join });
+        assert!(federated.fuzzy_match_raw("o'brien street", 0, 0, EndingType::NonPrefix).unwrap().is_empty());
+
+        federated.clear_language_detector();
+        assert_eq!(federated.detect_language("main street"), None);
+    }
+
+    #[cfg(feature = "query-log")]
+    #[test]
+    fn glue_query_log() -> () {
+        let build = |phrases: &[&str]| {
+            let dir = tempfile::tempdir().unwrap();
+            let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+            for phrase in phrases {
+                builder.insert_str(phrase).unwrap();
+            }
+            builder.finish().unwrap();
+            (FuzzyPhraseSet::from_path(&dir.path()).unwrap(), dir)
+        };
+        let (mut set, _dir) = build(&["100 main st", "200 main st", "300 mlk blvd"]);
+
+        let log_dir = tempfile::tempdir().unwrap();
+        let log_path = log_dir.path().join("queries.log");
+        set.set_query_recorder(fs::File::create(&log_path).unwrap());
+        set.fuzzy_match_raw("200 Mian St", 1, 1, EndingType::NonPrefix).unwrap();
+        set.fuzzy_match_windows(&["at", "300", "mlk"], 0, 0, EndingType::AnyPrefix).unwrap();
+        set.fuzzy_match(&["400"], 0, 0, EndingType::NonPrefix).unwrap();
+        set.stop_query_recorder().unwrap();
+        set.fuzzy_match(&["100"], 0, 0, EndingType::AnyPrefix).unwrap();
+
+        let log = read_query_log(fs::File::open(&log_path).unwrap()).unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[0].kind, RecordedQueryKind::Match);
+        // queries are recorded as the matcher saw them
+        assert_eq!(log[0].phrase, vec!["200", "mian", "st"]);
+        assert_eq!(log[0].results, vec![RecordedResult { phrase_id_range: (1, 1), edit_distance: 1, start_position: 0 }]);
+        assert_eq!((log[1].kind, log[1].ending_type), (RecordedQueryKind::Windows, EndingType::AnyPrefix));
+        assert_eq!(log[1].results[0].start_position, 1);
+        assert!(log[2].results.is_empty());
+
+        // the same build replays cleanly; a build with different phrases doesn't
+        assert_eq!(set.replay_query_log(&log).unwrap(), vec![]);
+        let (rebuilt, _dir) = build(&["100 main st", "150 main st", "200 main st", "300 mlk blvd", "400"]);
+        let differences = rebuilt.replay_query_log(&log).unwrap();
+        assert_eq!(differences.len(), 3);
+        assert_eq!(differences[0].results[0].phrase_id_range, (2, 2));
+        assert_eq!(differences[2].results.len(), 1);
+    }
+
+    #[cfg(feature = "language-detection")]
+    #[test]
+    fn glue_whatlang_detector() -> () {
+        let detector = WhatlangDetector::with_min_confidence(0.3);
+        assert_eq!(detector.detect("Der schnelle braune Fuchs springt über den faulen Hund"), Some("deu".to_string()));
+        assert_eq!(detector.detect("The quick brown fox jumps over the lazy dog and runs away"), Some("eng".to_string()));
+        assert_eq!(WhatlangDetector::new().detect("zz"), None);
+    }
+
+    #[test]
+    fn glue_exact_lookup_cache() -> () {
+        lazy_static::initialize(&TMP_TO_FINAL);
+        let mut set = FuzzyPhraseSet::from_path(&DIR.path()).unwrap();
+        let before = (
+            set.fuzzy_match_str("100 main street", 0, 0, EndingType::NonPrefix).unwrap(),
+            set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap(),
+            set.fuzzy_match_windows(&["100", "main", "street", "apt"], 0, 0, EndingType::NonPrefix).unwrap(),
+        );
+        set.enable_exact_lookup_cache();
+        assert_eq!(set.exact_word_id("main"), Some(5));
+        assert_eq!(set.exact_word_id("mai"), None);
+        assert!(set.contains_str("100 main street", EndingType::NonPrefix).unwrap());
+        assert!(!set.contains_str("100 main str", EndingType::NonPrefix).unwrap());
+        assert!(set.contains_str("100 main str", EndingType::AnyPrefix).unwrap());
+        let after = (
+            set.fuzzy_match_str("100 main street", 0, 0, EndingType::NonPrefix).unwrap(),
+            set.fuzzy_match_str("100 mian street", 1, 1, EndingType::NonPrefix).unwrap(),
+            set.fuzzy_match_windows(&["100", "main", "street", "apt"], 0, 0, EndingType::NonPrefix).unwrap(),
+        );
+        assert_eq!(before, after);
+        assert_eq!(after.0.len(), 1);
+
+        set.disable_exact_lookup_cache();
+        assert_eq!(set.exact_word_id("main"), Some(5));
+    }
+
+    #[test]
+    fn glue_build_from_raw() -> () {
+        assert_eq!(tokenize("  100 Main St., Apt #4 "), vec!["100", "main", "st", "apt", "#4"]);
+        assert_eq!(tokenize(" ,. "), Vec::<String>::new());
+
+        let dir = tempfile::tempdir().unwrap();
+        let ids = FuzzyPhraseSetBuilder::build_from_raw(&dir.path(), vec!["300 MLK Blvd", "100 Main Street", "1## main-street"]).unwrap();
+        assert_eq!(ids, vec![2, 1, 0]);
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert!(set.contains_str("300 mlk blvd", EndingType::NonPrefix).unwrap());
+        assert!(set.contains_str("1## main street", EndingType::NonPrefix).unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(FuzzyPhraseSetBuilder::build_from_raw(&dir.path(), vec!["100 main street", "--"]).is_err());
+    }
+
+    #[test]
+    fn glue_prune_rare_words() -> () {
+        let phrases = ["100 main street", "200 main street", "100 main ave", "300 mlk blvd", "400 main street"];
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        builder.set_min_word_frequency(Some(2), RareWordPolicy::DropPhrases);
+        for phrase in &phrases {
+            builder.insert_str(phrase).unwrap();
+        }
+        let ids = builder.finish().unwrap();
+        assert_eq!(ids, vec![0, PRUNED_PHRASE_ID, PRUNED_PHRASE_ID, PRUNED_PHRASE_ID, PRUNED_PHRASE_ID]);
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert!(set.contains_str("100 main street", EndingType::NonPrefix).unwrap());
+        assert!(!set.contains_str("200 main street", EndingType::NonPrefix).unwrap());
+        assert!(!set.contains_str("100 main ave", EndingType::NonPrefix).unwrap());
+        assert_eq!(set.fuzzy_match_str("ave", 1, 1, EndingType::AnyPrefix).unwrap(), vec![]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+        builder.set_min_word_frequency(Some(2), RareWordPolicy::MapTo("<unk>".to_string()));
+        for phrase in &phrases {
+            builder.insert_str(phrase).unwrap();
+        }
+        let ids = builder.finish().unwrap();
+        let set = FuzzyPhraseSet::from_path(&dir.path()).unwrap();
+        assert!(set.contains_str("<unk> main street", EndingType::NonPrefix).unwrap());
+        assert!(set.contains_str("<unk> <unk> <unk>", EndingType::NonPrefix).unwrap());
+        assert!(set.contains_str("100 main <unk>", EndingType::NonPrefix).unwrap());
+        assert!(!set.contains_str("200 main street", EndingType::NonPrefix).unwrap());
+        // "200 main street" and "400 main street" both become "<unk> main street"
+        assert_eq!(ids[1], ids[4]);
+        assert!(ids.iter().all(|id| *id != PRUNED_PHRASE_ID));
+    }
+
+    #[test]
+    fn glue_mismatched_components() -> () {
+        let dir_a = tempfile::tempdir().unwrap();
+        let dir_b = tempfile::tempdir().unwrap();
+        for (dir, phrase) in &[(&dir_a, "100 main street"), (&dir_b, "200 main street")] {
+            let mut builder = FuzzyPhraseSetBuilder::new(&dir.path()).unwrap();
+            builder.insert_str(phrase).unwrap();
+            builder.finish().unwrap();
+        }
+        assert!(FuzzyPhraseSet::from_path(&dir_a.path()).is_ok());
+
+        // swap in a phrase graph from a different build
+        let phrase_fst = fs::read(dir_a.path().join("phrase.fst")).unwrap();
+        fs::copy(dir_b.path().join("phrase.fst"), dir_a.path().join("phrase.fst")).unwrap();
+        let err = FuzzyPhraseSet::from_path(&dir_a.path()).err().unwrap();
+        assert!(err.to_string().starts_with("Phrase FST has build ID"));
+        fs::write(dir_a.path().join("phrase.fst"), phrase_fst).unwrap();
+
+        // or any of the files written alongside the fuzzy map
+        fs::copy(dir_b.path().join("fuzzy.ids"), dir_a.path().join("fuzzy.ids")).unwrap();
+        let err = FuzzyPhraseSet::from_path(&dir_a.path()).err().unwrap();
+        // the FST error wraps the underlying I/O error
+        let err = err.source().map(|source| source.to_string()).unwrap_or_else(|| err.to_string());
+        assert!(err.starts_with("Fuzzy map ID list has build ID"), "{}", err);
+    }
+
+    #[test]
+    fn glue_query_builder() -> () {
+        let word_possibilities = SET.query_builder()
+            .ending_type(EndingType::AnyPrefix)
+            .build(&["100", "ma"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(word_possibilities.len(), 2);
+        assert_eq!(word_possibilities[0].len(), 1);
+        assert_eq!(word_possibilities[0][0].source_position(), Some(0));
+        assert_eq!(word_possibilities[1][0].source_position(), Some(1));
+        match word_possibilities[1][0] {
+            QueryWord::Prefix { .. } => {},
+            _ => panic!("expected the final word to be a prefix"),
+        }
+
+        // without a prefix ending, "ma" isn't a word
+        assert_eq!(SET.query_builder().build(&["100", "ma"]).unwrap(), None);
+
+        // a per-word policy can turn fuzzy matching off for some words
+        let fuzzy = SET.query_builder().max_word_dist(1).build(&["100", "man"]).unwrap();
+        assert!(fuzzy.is_some());
+        let not_fuzzy = SET.query_builder()
+            .max_word_dist(1)
+            .word_dist_policy(|_i, word| if word.len() <= 3 { 0 } else { 1 })
+            .build(&["100", "man"])
+            .unwrap();
+        assert_eq!(not_fuzzy, None);
+
+        // the index's configured maximum still applies
+        assert!(SET.query_builder().max_word_dist(5).build(&["100"]).is_err());
+
+        // hand-made words are checked against the vocabulary
+        let builder = SET.query_builder();
+        let word_count = SET.word_count() as u32;
+        assert_eq!(builder.checked_full(word_count - 1, 1).unwrap(), QueryWord::new_full(word_count - 1, 1));
+        assert!(builder.checked_full(word_count, 0).is_err());
+        assert_eq!(builder.checked_prefix((0, word_count - 1)).unwrap(), QueryWord::new_prefix((0, word_count - 1)));
+        assert!(builder.checked_prefix((1, 0)).is_err());
+        assert!(builder.checked_prefix((0, word_count)).is_err());
+    }
+
+    #[test]
+    fn glue_fuzzy_match_filtered() -> () {
+        // "100 main street" is phrase 1 and "200 main street" is phrase 2
+        let unfiltered = SET.fuzzy_match(&["100", "main"], 0, 0, EndingType::AnyPrefix).unwrap();
+        assert_eq!(unfiltered[0].phrase_id_range, (0, 1));
+
+        // a filter that admits any part of the range keeps the result
+        let filtered = SET.fuzzy_match_filtered(&["100", "main"], 0, 0, EndingType::AnyPrefix, |(start, end)| start <= 1 && end >= 1).unwrap();
+        assert_eq!(filtered, unfiltered);
+
+        // one that excludes the whole range drops it
+        let filtered = SET.fuzzy_match_filtered(&["100", "main"], 0, 0, EndingType::AnyPrefix, |(start, _end)| start >= 2).unwrap();
+        assert_eq!(filtered, vec![]);
+
+        let windows = SET.fuzzy_match_windows(&["100", "main", "street", "300"], 0, 0, EndingType::AnyPrefix).unwrap();
+        assert_eq!(windows.len(), 2);
+        let filtered = SET.fuzzy_match_windows_filtered(&["100", "main", "street", "300"], 0, 0, EndingType::AnyPrefix, |(start, _end)| start == 3).unwrap();
+        assert_eq!(filtered, vec![windows[1].clone()]);
+
+        let allowed: BTreeSet<u32> = [3u32].iter().cloned().collect();
+        let filtered = SET.fuzzy_match_windows_filtered(&["100", "main", "street", "300"], 0, 0, EndingType::AnyPrefix, allowed).unwrap();
+        assert_eq!(filtered, vec![windows[1].clone()]);
+
+        let multi = SET.fuzzy_match_multi_filtered(&[
+            (vec!["100", "main", "street"], EndingType::NonPrefix),
+            (vec!["300", "mlk", "blvd"], EndingType::NonPrefix),
+        ], 0, 0, |(start, _end)| start != 1).unwrap();
+        assert_eq!(multi[0], vec![]);
+        assert_eq!(multi[1].len(), 1);
+        assert_eq!(multi[1][0].phrase_id_range, (3, 3));
+    }
+
     #[test]
     fn get_by_id() {
         let mut phrases = PHRASES.clone();
@@ -1275,7 +3690,7 @@ mod basic_tests {
         // emits a word boundary prefix because there's exactly one termination and we matched it
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "main", "street"], 1, 1, EndingType::AnyPrefix).unwrap(),
-            vec![FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (3, 3) }]
+            vec![FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "street".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (3, 3), tokens_consumed: 3, tokens_total: 3 }]
         );
         //address not present in the data, hence should not match
         assert_eq!(
@@ -1286,28 +3701,28 @@ mod basic_tests {
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "main", "st"], 1, 1, EndingType::NonPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "st".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (2, 2) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "st".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (2, 2), tokens_consumed: 3, tokens_total: 3 }
             ]
         );
         //address contains words in another address
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "st", "washington"], 1, 1, EndingType::NonPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "st".to_string(), "washington".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (4, 4) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "st".to_string(), "washington".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::NonPrefix, phrase_id_range: (4, 4), tokens_consumed: 3, tokens_total: 3 }
             ]
         );
         //autocomplete is applied only to the last term
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "main", "st"], 1, 1, EndingType::AnyPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "st".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::AnyPrefix, phrase_id_range: (2, 3) },
-                FuzzyWindowResult { phrase: vec!["St".to_string()], edit_distance: 1, start_position: 2, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (5, 5) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "st".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::AnyPrefix, phrase_id_range: (2, 3), tokens_consumed: 3, tokens_total: 3 },
+                FuzzyWindowResult { phrase: vec!["St".to_string()], edit_distance: 1, start_position: 2, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (5, 5), tokens_consumed: 1, tokens_total: 3 }
             ]
         );
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "main", "s"], 1, 1, EndingType::AnyPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "s".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::AnyPrefix, phrase_id_range: (2, 3) },
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "main".to_string(), "s".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::AnyPrefix, phrase_id_range: (2, 3), tokens_consumed: 3, tokens_total: 3 },
             ]
         );
         assert_eq!(
@@ -1348,8 +3763,8 @@ mod basic_tests {
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "d", "st"], 1, 1, EndingType::AnyPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "d".to_string(), "st".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::AnyPrefix, phrase_id_range: (0, 0) },
-                FuzzyWindowResult { phrase: vec!["St".to_string()], edit_distance: 1, start_position: 2, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (5, 5) }
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "d".to_string(), "st".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::AnyPrefix, phrase_id_range: (0, 0), tokens_consumed: 3, tokens_total: 3 },
+                FuzzyWindowResult { phrase: vec!["St".to_string()], edit_distance: 1, start_position: 2, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (5, 5), tokens_consumed: 1, tokens_total: 3 }
             ]
         );
 
@@ -1357,7 +3772,7 @@ mod basic_tests {
         assert_eq!(
             TEST_SET.fuzzy_match_windows(&["100", "e"], 1, 1, EndingType::AnyPrefix).unwrap(),
             vec![
-                FuzzyWindowResult { phrase: vec!["100".to_string(), "e".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (1, 1) },
+                FuzzyWindowResult { phrase: vec!["100".to_string(), "e".to_string()], edit_distance: 0, start_position: 0, ending_type: EndingType::WordBoundaryPrefix, phrase_id_range: (1, 1), tokens_consumed: 2, tokens_total: 2 },
             ]
         );
 
@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use ::phrase::filter::PhraseIdFilter;
+use super::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+
+/// Mask all but the first `keep_digits` digits of a number with '#', the way house numbers are
+/// often masked at index time (so "142" becomes "1##"). Non-digit characters are left alone.
+pub fn mask_number(number: &str, keep_digits: usize) -> String {
+    let mut seen_digits = 0;
+    number.chars().map(|c| {
+        if c.is_ascii_digit() {
+            seen_digits += 1;
+            if seen_digits > keep_digits { '#' } else { c }
+        } else {
+            c
+        }
+    }).collect()
+}
+
+// a phrase is allowed if one of its number ranges contains the query number, or if it doesn't
+// have any number ranges at all (there's nothing to check)
+struct NumberRangeFilter<'a> {
+    number_ranges: &'a BTreeMap<u32, Vec<(u32, u32)>>,
+    number: u32,
+}
+
+impl<'a> PhraseIdFilter for NumberRangeFilter<'a> {
+    fn allows_range(&self, range: (u32, u32)) -> bool {
+        let width = (range.1 - range.0) as usize + 1;
+        let mut constrained = 0;
+        for (_id, ranges) in self.number_ranges.range(range.0..=range.1) {
+            if ranges.iter().any(|&(start, end)| self.number >= start && self.number <= end) {
+                return true;
+            }
+            constrained += 1;
+        }
+        constrained < width
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match`, for a query containing a masked number (see `mask_number`), but only
+    /// returns phrases whose number ranges (as inserted with
+    /// `FuzzyPhraseSetBuilder::insert_with_number_range`) contain the actual, unmasked `number`.
+    /// Phrases that were inserted without any number ranges aren't filtered out.
+    pub fn fuzzy_match_number<T: AsRef<str>>(&self, phrase: &[T], number: u32, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        if self.number_ranges.is_empty() {
+            return self.fuzzy_match(phrase, max_word_dist, max_phrase_dist, ending_type);
+        }
+        let filter = NumberRangeFilter { number_ranges: &self.number_ranges, number };
+        self.fuzzy_match_filtered(phrase, max_word_dist, max_phrase_dist, ending_type, filter)
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+
+use regex;
+use serde_json;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder};
+use super::tokenize::TokenizerConfig;
+use super::segment::Segmentation;
+use super::unicode_ranges;
+use ::fuzzy::{ScriptStrategies, VariantStrategy};
+
+// every word ID in the phrase graph is a three-byte key
+const WORD_KEY_WIDTH: u8 = 3;
+
+/// The settings an index is built with that matter when it's queried, kept together. The builder
+/// writes them into the index's metadata, and they're checked again when it's loaded (see
+/// `FuzzyPhraseSet::from_path_with_options`). Settings missing from older indexes take their
+/// defaults; settings from newer versions of this library that this one doesn't know about are
+/// kept in `unknown`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct IndexOptions {
+    /// The largest per-word edit distance the fuzzy map supports.
+    pub max_edit_distance: u8,
+    /// The shortest words (in characters) indexed at each edit distance, starting with 1; words
+    /// shorter than that are only matched as far as the distances before it (see
+    /// `FuzzyMapBuilder::set_distance_min_lengths`). Empty for no limits.
+    pub distance_min_lengths: Vec<u8>,
+    /// The scripts (by Unicode script name) whose words are indexed for fuzzy matching.
+    pub fuzzy_enabled_scripts: Vec<String>,
+    /// The width in bytes of the word keys in the phrase graph. Only 3 is supported.
+    pub word_key_width: u8,
+    /// How phrases inserted with `insert_raw` were tokenized.
+    pub tokenizer_config: TokenizerConfig,
+    /// How words from scripts without word spacing were segmented (see `Segmentation`).
+    pub segmentation: Segmentation,
+    /// Whether per-word frequencies are stored (see `FuzzyPhraseSetBuilder::set_store_word_frequencies`).
+    pub word_frequencies: bool,
+    /// Whether the fuzzy map has a membership filter, which lets queries skip looking up words
+    /// that definitely aren't in the vocabulary (see `FuzzyMapBuilder::set_membership_filter`).
+    pub membership_filter: bool,
+    /// Whether a `LoudsTrie` of the phrase graph is written alongside the phrase FST, for running
+    /// queries against with `FuzzyPhraseSet::fuzzy_match_with` (see `FuzzyPhraseSet::louds_trie`).
+    pub louds_trie: bool,
+    /// The scripts (by Unicode script name) whose words are indexed for fuzzy matching with a
+    /// strategy other than `VariantStrategy::Deletion`, and the strategy each uses (see
+    /// `ScriptStrategies`). Empty for deletion everywhere.
+    pub script_strategies: BTreeMap<String, VariantStrategy>,
+    /// Whether the words at each phrase position are stored, for pruning query possibilities by
+    /// (see `FuzzyPhraseSetBuilder::set_store_word_depths`).
+    pub word_depths: bool,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for IndexOptions {
+    fn default() -> IndexOptions {
+        IndexOptions {
+            max_edit_distance: 1,
+            distance_min_lengths: Vec::new(),
+            fuzzy_enabled_scripts: vec!["Latin".to_string(), "Greek".to_string(), "Cyrillic".to_string()],
+            word_key_width: WORD_KEY_WIDTH,
+            tokenizer_config: TokenizerConfig::default(),
+            segmentation: Segmentation::default(),
+            word_frequencies: false,
+            membership_filter: false,
+            louds_trie: false,
+            script_strategies: BTreeMap::new(),
+            word_depths: false,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl IndexOptions {
+    /// Check that these options describe an index this version of the library can read.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.word_key_width != WORD_KEY_WIDTH {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidData, format!(
+                "Index uses {}-byte word keys; only {}-byte keys are supported", self.word_key_width, WORD_KEY_WIDTH
+            ))));
+        }
+        Ok(())
+    }
+
+    // matches the words in the scripts that are indexed for fuzzy matching
+    pub(super) fn script_regex(&self) -> Result<regex::Regex, Box<dyn Error>> {
+        let allowed_scripts = self.fuzzy_enabled_scripts.iter().map(
+            |s| unicode_ranges::get_script_by_name(s)
+        ).collect::<Option<Vec<_>>>().ok_or("unknown script")?;
+        Ok(regex::Regex::new(&unicode_ranges::get_pattern_for_scripts(&allowed_scripts))?)
+    }
+
+    pub(super) fn script_strategies(&self) -> Result<ScriptStrategies, Box<dyn Error>> {
+        let mut strategies = ScriptStrategies::new();
+        for (name, strategy) in self.script_strategies.iter() {
+            let script = unicode_ranges::get_script_by_name(name).ok_or("unknown script")?;
+            strategies.set(script, *strategy);
+        }
+        Ok(strategies)
+    }
+
+    /// The names of the known options that differ between `self` and `other`.
+    pub fn differences(&self, other: &IndexOptions) -> Vec<&'static str> {
+        let mut differences: Vec<&'static str> = Vec::new();
+        if self.max_edit_distance != other.max_edit_distance {
+            differences.push("max_edit_distance");
+        }
+        if self.distance_min_lengths != other.distance_min_lengths {
+            differences.push("distance_min_lengths");
+        }
+        if self.fuzzy_enabled_scripts != other.fuzzy_enabled_scripts {
+            differences.push("fuzzy_enabled_scripts");
+        }
+        if self.word_key_width != other.word_key_width {
+            differences.push("word_key_width");
+        }
+        if self.tokenizer_config != other.tokenizer_config {
+            differences.push("tokenizer_config");
+        }
+        if self.segmentation != other.segmentation {
+            differences.push("segmentation");
+        }
+        if self.word_frequencies != other.word_frequencies {
+            differences.push("word_frequencies");
+        }
+        if self.membership_filter != other.membership_filter {
+            differences.push("membership_filter");
+        }
+        if self.louds_trie != other.louds_trie {
+            differences.push("louds_trie");
+        }
+        if self.script_strategies != other.script_strategies {
+            differences.push("script_strategies");
+        }
+        if self.word_depths != other.word_depths {
+            differences.push("word_depths");
+        }
+        differences
+    }
+}
+
+impl FuzzyPhraseSetBuilder {
+    /// Set all of the index's options at once. The individual setters
+    /// (`set_tokenizer_config`, `set_segmentation`, `set_store_word_frequencies`,
+    /// `set_script_strategies`, `set_store_word_depths`) set the corresponding fields.
+    pub fn set_index_options(&mut self, options: IndexOptions) {
+        self.options = options;
+    }
+
+    pub fn index_options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    /// Index words for fuzzy matching using `strategies` for each script (see
+    /// `ScriptStrategies`), rather than deletion everywhere. They're recorded in the index
+    /// options, and `FuzzyPhraseSet::from_path` queries with the same ones.
+    pub fn set_script_strategies(&mut self, strategies: &ScriptStrategies) {
+        self.options.script_strategies = strategies.iter()
+            .map(|(script, strategy)| (unicode_ranges::get_script_name(&script), strategy))
+            .collect();
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Same as `from_path`, but fails if the index was built with options other than `expected`
+    /// (for example, with a smaller maximum edit distance, or different tokenization, than the
+    /// caller is going to query it with). Unknown options aren't compared.
+    pub fn from_path_with_options<P: AsRef<Path>>(path: P, expected: &IndexOptions) -> Result<Self, Box<dyn Error>> {
+        let set = FuzzyPhraseSet::from_path(path)?;
+        let differences = expected.differences(&set.options);
+        if !differences.is_empty() {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidData, format!(
+                "Index was built with different options than expected: {}", differences.join(", ")
+            ))));
+        }
+        Ok(set)
+    }
+
+    /// The options the index was built with.
+    pub fn index_options(&self) -> &IndexOptions {
+        &self.options
+    }
+}
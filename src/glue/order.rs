@@ -0,0 +1,39 @@
+use std::error::Error;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::Deserialize;
+use rmps::Deserializer;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder};
+use ::phrase::WordDepths;
+
+impl FuzzyPhraseSetBuilder {
+    /// Record which words occur at each position of the phrases, so that `fuzzy_match` and
+    /// `fuzzy_match_windows` can drop the possibilities that can't appear where they are in the
+    /// query before exploring the phrase graph (see `PhraseSet::match_combinations_pruned`). This
+    /// takes a set of word IDs per position in memory when the index is loaded. Off by default.
+    pub fn set_store_word_depths(&mut self, store: bool) {
+        self.options.word_depths = store;
+    }
+}
+
+/// Read the word depths written by the builder, if any.
+pub(super) fn load_word_depths(directory: &Path) -> Result<Option<WordDepths>, Box<dyn Error>> {
+    let depths_path = directory.join(Path::new("word_depths.msg"));
+    if !depths_path.exists() {
+        return Ok(None);
+    }
+    let depths_reader = BufReader::new(fs::File::open(&depths_path)?);
+    Ok(Some(Deserialize::deserialize(&mut Deserializer::new(depths_reader))?))
+}
+
+impl FuzzyPhraseSet {
+    /// The words at each phrase position, if the set was built with
+    /// `FuzzyPhraseSetBuilder::set_store_word_depths`; the matchers prune by them when they're
+    /// there, and windowed matching drops the words each window can't have where they are.
+    pub fn word_depths(&self) -> Option<&WordDepths> {
+        self.word_depths.as_ref()
+    }
+}
@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Error as IoError, ErrorKind as IoErrorKind, Write};
+use std::path::Path;
+#[cfg(not(feature = "mmap"))]
+use std::path::PathBuf;
+
+use byteorder::{LittleEndian, ByteOrder, WriteBytesExt};
+
+use super::FuzzyPhraseSet;
+use super::weights::MaxTree;
+use ::phrase::util::PhraseSetError;
+
+const HEADER_LEN: usize = 12;
+
+// the overlay's bytes: mapped from its file where possible, and otherwise read in and written
+// back out on `flush`
+enum OverlayData {
+    #[cfg(feature = "mmap")]
+    Mmap(::memmap::MmapMut),
+    #[cfg(not(feature = "mmap"))]
+    Bytes(Vec<u8>, PathBuf),
+}
+
+/// Weights for a set's phrases that can be changed while it's in use, to adjust its ranking
+/// between rebuilds (with click-through feedback, say) without touching the immutable index. It's
+/// a small file of its own, next to the index or anywhere else, holding a weight for each phrase
+/// ID; `FuzzyPhraseSet::rerank` adds these to the weights phrases were built with (see
+/// `RerankConfig::popularity_weight`). Changes are written to the file by `flush`.
+///
+/// On disk, it's a little-endian u64 build ID (that of the set it was created for), then a u32
+/// count of phrases, then an f32 weight for each.
+pub struct WeightOverlay {
+    data: OverlayData,
+    // the weights again, for finding the heaviest in a range of phrases
+    weights: MaxTree<f32>,
+}
+
+impl WeightOverlay {
+    /// Write a new overlay for `set` to `path`, with every weight at zero, and open it.
+    ///
+    /// # Safety
+    ///
+    /// As for `open`.
+    pub unsafe fn create<P: AsRef<Path>>(path: P, set: &FuzzyPhraseSet) -> Result<WeightOverlay, Box<dyn Error>> {
+        let phrase_count = set.phrase_count() as u32;
+        let mut writer = io::BufWriter::new(fs::File::create(path.as_ref())?);
+        writer.write_u64::<LittleEndian>(set.phrase_set.build_id())?;
+        writer.write_u32::<LittleEndian>(phrase_count)?;
+        writer.write_all(&vec![0u8; 4 * phrase_count as usize])?;
+        writer.flush()?;
+        drop(writer);
+        Ok(WeightOverlay::open(path)?)
+    }
+
+    /// Open the overlay at `path` for reading and writing. With the `mmap` feature, it's mapped
+    /// into memory; otherwise, it's read in.
+    ///
+    /// # Safety
+    ///
+    /// The file mustn't be modified by anything else while it's mapped.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<WeightOverlay, IoError> {
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        WeightOverlay::from_data(OverlayData::Mmap(::memmap::MmapMut::map_mut(&file)?))
+    }
+
+    /// Without the `mmap` feature, read the overlay at `path` in, to be written back by `flush`.
+    ///
+    /// # Safety
+    ///
+    /// None needed; this is only `unsafe` to match the memory-mapped version.
+    #[cfg(not(feature = "mmap"))]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<WeightOverlay, IoError> {
+        WeightOverlay::from_data(OverlayData::Bytes(fs::read(path.as_ref())?, path.as_ref().to_owned()))
+    }
+
+    fn from_data(data: OverlayData) -> Result<WeightOverlay, IoError> {
+        let mut overlay = WeightOverlay { data, weights: MaxTree::new(&[], 0.0) };
+        let bytes = overlay.bytes();
+        if bytes.len() < HEADER_LEN || bytes.len() != HEADER_LEN + 4 * LittleEndian::read_u32(&bytes[8..12]) as usize {
+            return Err(IoError::new(IoErrorKind::InvalidData, "Malformed weight overlay"));
+        }
+        overlay.index_weights();
+        Ok(overlay)
+    }
+
+    fn index_weights(&mut self) {
+        let weights: Vec<f32> = self.bytes()[HEADER_LEN..].chunks(4).map(LittleEndian::read_f32).collect();
+        self.weights = MaxTree::new(&weights, 0.0);
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.data {
+            #[cfg(feature = "mmap")]
+            OverlayData::Mmap(ref mmap) => mmap,
+            #[cfg(not(feature = "mmap"))]
+            OverlayData::Bytes(ref bytes, _) => bytes,
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match self.data {
+            #[cfg(feature = "mmap")]
+            OverlayData::Mmap(ref mut mmap) => mmap,
+            #[cfg(not(feature = "mmap"))]
+            OverlayData::Bytes(ref mut bytes, _) => bytes,
+        }
+    }
+
+    /// The build ID of the set the overlay was created for.
+    pub fn build_id(&self) -> u64 {
+        LittleEndian::read_u64(&self.bytes()[0..8])
+    }
+
+    /// How many phrases the overlay has weights for.
+    pub fn len(&self) -> usize {
+        LittleEndian::read_u32(&self.bytes()[8..12]) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The weight of phrase `phrase_id`, or zero if there's no such phrase.
+    pub fn get(&self, phrase_id: u32) -> f32 {
+        if phrase_id as usize >= self.len() {
+            return 0.0;
+        }
+        let offset = HEADER_LEN + 4 * phrase_id as usize;
+        LittleEndian::read_f32(&self.bytes()[offset..(offset + 4)])
+    }
+
+    /// Set the weight of phrase `phrase_id`. Fails if there's no such phrase.
+    pub fn set(&mut self, phrase_id: u32, weight: f32) -> Result<(), PhraseSetError> {
+        if phrase_id as usize >= self.len() {
+            return Err(PhraseSetError::new("Phrase ID is out of range for the weight overlay"));
+        }
+        let offset = HEADER_LEN + 4 * phrase_id as usize;
+        LittleEndian::write_f32(&mut self.bytes_mut()[offset..(offset + 4)], weight);
+        self.weights.set(phrase_id as usize, weight);
+        Ok(())
+    }
+
+    /// Add `delta` to the weight of phrase `phrase_id` (once for each click on it, say).
+    pub fn add(&mut self, phrase_id: u32, delta: f32) -> Result<(), PhraseSetError> {
+        let weight = self.get(phrase_id);
+        self.set(phrase_id, weight + delta)
+    }
+
+    /// Multiply every weight by `factor`, so that older feedback counts for less than newer;
+    /// calling this periodically with a factor a little under 1 gives weights that decay
+    /// exponentially with age.
+    pub fn decay(&mut self, factor: f32) {
+        for weight in self.bytes_mut()[HEADER_LEN..].chunks_mut(4) {
+            let decayed = LittleEndian::read_f32(weight) * factor;
+            LittleEndian::write_f32(weight, decayed);
+        }
+        self.index_weights();
+    }
+
+    // the heaviest weight among phrases `start` to `end` (inclusive), with negative weights
+    // counting as zero
+    pub(super) fn heaviest(&self, start: u32, end: u32) -> f32 {
+        self.weights.heaviest(start as usize, end as usize + 1)
+    }
+
+    /// Write any changes out to the overlay's file.
+    pub fn flush(&self) -> Result<(), IoError> {
+        match self.data {
+            #[cfg(feature = "mmap")]
+            OverlayData::Mmap(ref mmap) => mmap.flush(),
+            #[cfg(not(feature = "mmap"))]
+            OverlayData::Bytes(ref bytes, ref path) => fs::write(path, bytes),
+        }
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Consult `overlay` for phrase weights in addition to the ones they were built with (see
+    /// `WeightOverlay`). Fails if the overlay was created for a different index.
+    pub fn set_weight_overlay(&mut self, overlay: WeightOverlay) -> Result<(), Box<dyn Error>> {
+        if overlay.build_id() != self.phrase_set.build_id() || overlay.len() != self.phrase_count() {
+            return Err(Box::new(PhraseSetError::new("Weight overlay was created for a different index")));
+        }
+        self.weight_overlay = Some(overlay);
+        Ok(())
+    }
+
+    /// Stop consulting the set's weight overlay, and hand it back.
+    pub fn take_weight_overlay(&mut self) -> Option<WeightOverlay> {
+        self.weight_overlay.take()
+    }
+
+    /// The set's weight overlay, for updating its weights.
+    pub fn weight_overlay_mut(&mut self) -> Option<&mut WeightOverlay> {
+        self.weight_overlay.as_mut()
+    }
+
+    /// The weight of phrase `phrase_id`: the sum of the one it was built with (see
+    /// `PhraseMetadata::weight`) and the one in the set's weight overlay, each zero if absent.
+    pub fn phrase_weight(&self, phrase_id: u32) -> f64 {
+        let built = self.phrase_metadata.get(&phrase_id).and_then(|metadata| metadata.weight).unwrap_or(0.0);
+        let overlaid = self.weight_overlay.as_ref().map_or(0.0, |overlay| f64::from(overlay.get(phrase_id)));
+        built + overlaid
+    }
+}
@@ -0,0 +1,50 @@
+use std::error::Error;
+
+use super::{FuzzyPhraseSet, FuzzyWindowResult, EndingType};
+
+/// The results of `FuzzyPhraseSet::fuzzy_match_windows_best_effort`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BestEffortWindows {
+    pub results: Vec<FuzzyWindowResult>,
+    /// Set if nothing in the query matched a complete phrase, and `results` holds the longest
+    /// partial matches instead.
+    pub partial: bool,
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match_windows`, but if that finds nothing, return the longest windows of the
+    /// query that match the beginning of some phrase instead of an empty result, so that callers
+    /// can offer "did you mean" suggestions. Partial results are marked with the `partial` flag;
+    /// each one has an ending type of `WordBoundaryPrefix`, since the phrases it covers continue
+    /// past the end of the window, and a phrase ID range covering all of those phrases.
+    pub fn fuzzy_match_windows_best_effort<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<BestEffortWindows, Box<dyn Error>> {
+        let results = self.fuzzy_match_windows(phrase, max_word_dist, max_phrase_dist, ending_type)?;
+        if !results.is_empty() || phrase.is_empty() {
+            return Ok(BestEffortWindows { results, partial: false });
+        }
+
+        let tokens_total = phrase.len();
+        let (phrase, offset, ending_type) = self.limit_query_length(phrase, ending_type)?;
+
+        let mut partials: Vec<FuzzyWindowResult> = Vec::new();
+        for chunk in self.window_subqueries(phrase, max_word_dist, ending_type)? {
+            let ends_in_prefix = !matches!(chunk.ending_type, EndingType::NonPrefix);
+            for i in self.window_starts(&chunk.word_possibilities) {
+                let windows = self.phrase_set.match_combinations_as_partial_windows(&chunk.word_possibilities[i..], max_phrase_dist, ends_in_prefix)?;
+                for window in &windows {
+                    let mut result = self.window_to_result(phrase, chunk.start_position + i, window, offset, tokens_total, ending_type);
+                    result.ending_type = EndingType::WordBoundaryPrefix;
+                    let longest = partials.first().map_or(0, |partial| partial.tokens_consumed);
+                    if result.tokens_consumed > longest {
+                        partials.clear();
+                    }
+                    if result.tokens_consumed >= longest {
+                        partials.push(result);
+                    }
+                }
+            }
+        }
+
+        Ok(BestEffortWindows { results: partials, partial: true })
+    }
+}
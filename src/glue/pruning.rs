@@ -0,0 +1,101 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::FuzzyPhraseSetBuilder;
+
+/// The phrase ID `FuzzyPhraseSetBuilder::finish` reports for phrases that were dropped because
+/// they contained a rare word.
+pub const PRUNED_PHRASE_ID: u32 = u32::MAX;
+
+/// What to do with words that occur less often than the minimum frequency set with
+/// `FuzzyPhraseSetBuilder::set_min_word_frequency`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RareWordPolicy {
+    /// Leave out any phrase containing a rare word.
+    DropPhrases,
+    /// Replace rare words with the given out-of-vocabulary placeholder word. Phrases that become
+    /// identical as a result are merged.
+    MapTo(String),
+}
+
+impl FuzzyPhraseSetBuilder {
+    /// Prune words that occur fewer than `min_count` times across all inserted phrases (counting
+    /// repeat insertions) when the set is built, to keep noisy corpora from bloating the index.
+    /// Words involved in word replacements are never pruned. Pass `None` to turn pruning off.
+    pub fn set_min_word_frequency(&mut self, min_count: Option<u32>, policy: RareWordPolicy) {
+        self.min_word_frequency = min_count.map(|min_count| (min_count, policy));
+    }
+
+    /// Remove rare words from the vocabulary, and drop or rewrite the phrases that contain them,
+    /// according to the configured policy. Returns the temporary IDs of the phrases that were
+    /// removed from the phrase map, along with the temporary ID of the phrase each one was
+    /// merged into, if it was merged rather than dropped.
+    pub(super) fn prune_rare_words(&mut self) -> Vec<(u32, Option<u32>)> {
+        let (min_count, policy) = match self.min_word_frequency.take() {
+            Some(setting) => setting,
+            None => return Vec::new(),
+        };
+
+        let oov_id = match policy {
+            RareWordPolicy::MapTo(ref oov) => Some(self.get_or_create_tmp_word_id(oov)),
+            RareWordPolicy::DropPhrases => None,
+        };
+        let rare: FxHashSet<u32> = self.word_counts.iter()
+            .filter(|&(id, count)| {
+                *count < min_count &&
+                Some(*id) != oov_id &&
+                !self.word_replacement_map.contains_key(id) &&
+                !self.word_replacement_map.values().any(|to| to == id)
+            })
+            .map(|(id, _count)| *id)
+            .collect();
+        if rare.is_empty() {
+            return Vec::new();
+        }
+
+        self.words_to_tmp_word_ids.retain(|_word, id| !rare.contains(id));
+
+        // process phrases in insertion order, so that when phrases get merged, the earliest one
+        // is the one that survives
+        let mut phrases: Vec<(Vec<u32>, u32)> = self.phrases.drain().collect();
+        phrases.sort_by_key(|&(_, tmp_phrase_id)| tmp_phrase_id);
+
+        let mut kept: FxHashMap<Vec<u32>, u32> = FxHashMap::default();
+        let mut pruned: Vec<(u32, Option<u32>)> = Vec::new();
+        for (mut phrase, tmp_phrase_id) in phrases {
+            if phrase.iter().any(|id| rare.contains(id)) {
+                match oov_id {
+                    Some(oov_id) => {
+                        for id in phrase.iter_mut() {
+                            if rare.contains(id) {
+                                *id = oov_id;
+                            }
+                        }
+                    },
+                    None => {
+                        self.number_ranges.remove(&tmp_phrase_id);
+                        self.phrase_metadata.remove(&tmp_phrase_id);
+                        pruned.push((tmp_phrase_id, None));
+                        continue;
+                    },
+                }
+            }
+            match kept.get(&phrase) {
+                Some(&canonical_id) => {
+                    if let Some(ranges) = self.number_ranges.remove(&tmp_phrase_id) {
+                        self.number_ranges.entry(canonical_id).or_default().extend(ranges);
+                    }
+                    if let Some(metadata) = self.phrase_metadata.remove(&tmp_phrase_id) {
+                        self.phrase_metadata.entry(canonical_id).or_default().merge(metadata);
+                    }
+                    pruned.push((tmp_phrase_id, Some(canonical_id)));
+                },
+                None => {
+                    kept.insert(phrase, tmp_phrase_id);
+                },
+            }
+        }
+        self.phrases = kept;
+
+        pruned
+    }
+}
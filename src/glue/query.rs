@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use ::phrase::query::{QueryWord, VariantSource, validate_word_possibilities};
+use ::phrase::util::PhraseSetError;
+use super::{FuzzyPhraseSet, EndingType};
+
+type WordDistPolicy<'a> = Box<dyn Fn(usize, &str) -> u8 + 'a>;
+
+/// Turns a tokenized query into the list of word slots (`Vec<Vec<QueryWord>>`) that the
+/// `PhraseSet` matchers consume.
+///
+/// Each word is looked up in the prefix set (and, if it's eligible and the edit distance for its
+/// position is nonzero, the fuzzy map), token replacements are applied, and if the ending type
+/// allows it, the final word is expanded into a prefix range. The edit distance for each word
+/// defaults to `max_word_dist`, but can be lowered per-word with `word_dist_policy` (for example,
+/// to skip fuzzy matching for very short words). Any position can also be given `alternates`,
+/// other words that are just as good a match there as the one typed.
+pub struct QueryBuilder<'a> {
+    set: &'a FuzzyPhraseSet,
+    max_word_dist: u8,
+    ending_type: EndingType,
+    word_dist_policy: Option<WordDistPolicy<'a>>,
+    alternates: BTreeMap<usize, Vec<String>>,
+}
+
+impl<'a> QueryBuilder<'a> {
+    pub fn new(set: &'a FuzzyPhraseSet) -> QueryBuilder<'a> {
+        QueryBuilder { set, max_word_dist: 0, ending_type: EndingType::NonPrefix, word_dist_policy: None, alternates: BTreeMap::new() }
+    }
+
+    pub fn max_word_dist(mut self, max_word_dist: u8) -> Self {
+        self.max_word_dist = max_word_dist;
+        self
+    }
+
+    pub fn ending_type(mut self, ending_type: EndingType) -> Self {
+        self.ending_type = ending_type;
+        self
+    }
+
+    /// Set a function that picks the edit distance for each word, given its position in the
+    /// query and its text. Whatever it returns is capped at `max_word_dist`.
+    pub fn word_dist_policy<F: Fn(usize, &str) -> u8 + 'a>(mut self, policy: F) -> Self {
+        self.word_dist_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Let the word at `position` also match any of `words` (say, "saint" and "street" for "st"),
+    /// as though it had been typed that way: each is looked up exactly, token replacements are
+    /// applied, and the result goes in the word's slot at zero edit distance, tagged
+    /// `VariantSource::Alternate` so ranking can tell it apart from the word as typed. Alternates
+    /// that aren't in the vocabulary are ignored, and if the word as typed isn't either, its
+    /// alternates are all that position can match. Calling this again for the same position adds
+    /// to its alternates.
+    pub fn alternates<T: AsRef<str>>(mut self, position: usize, words: &[T]) -> Self {
+        self.alternates.entry(position).or_default().extend(words.iter().map(|word| word.as_ref().to_string()));
+        self
+    }
+
+    /// Make a `QueryWord::Full` for a word ID, checking that it belongs to this set's vocabulary
+    /// (IDs from a different index's ID space are otherwise accepted, and match the wrong words).
+    pub fn checked_full(&self, id: u32, edit_distance: u8) -> Result<QueryWord, Box<dyn Error>> {
+        if id as usize >= self.set.word_list.len() {
+            return Err(Box::new(PhraseSetError::new(&format!("Word ID {} is not in this set's vocabulary of {} words", id, self.set.word_list.len()))));
+        }
+        Ok(QueryWord::try_new_full(id, edit_distance)?)
+    }
+
+    /// Make a `QueryWord::Prefix` for an inclusive range of word IDs, checking that the range is
+    /// non-empty and lies within this set's vocabulary.
+    pub fn checked_prefix(&self, id_range: (u32, u32)) -> Result<QueryWord, Box<dyn Error>> {
+        let word = QueryWord::try_new_prefix(id_range)?;
+        if id_range.1 as usize >= self.set.word_list.len() {
+            return Err(Box::new(PhraseSetError::new(&format!("Prefix range {}..={} is not within this set's vocabulary of {} words", id_range.0, id_range.1, self.set.word_list.len()))));
+        }
+        Ok(word)
+    }
+
+    /// Build the word slots for `phrase`. Returns `None` if the phrase is empty or any of its
+    /// words has no possible matches, since in either case no phrase in the set can match it.
+    pub fn build<T: AsRef<str>>(&self, phrase: &[T]) -> Result<Option<Vec<Vec<QueryWord>>>, Box<dyn Error>> {
+        self.check_max_word_dist()?;
+
+        if phrase.is_empty() {
+            return Ok(None);
+        }
+
+        let mut word_possibilities: Vec<Vec<QueryWord>> = Vec::with_capacity(phrase.len());
+        for (i, word) in phrase.iter().enumerate() {
+            match self.slot(phrase.len(), i, word.as_ref())? {
+                Some(possibilities) => word_possibilities.push(possibilities),
+                None => return Ok(None),
+            }
+        }
+
+        // the slots are valid by construction, so they're only checked in debug builds, to keep
+        // the check out of the query path
+        if cfg!(debug_assertions) {
+            validate_word_possibilities(&word_possibilities, self.ending_type == EndingType::AnyPrefix)?;
+        }
+        Ok(Some(word_possibilities))
+    }
+
+    /// Like `build`, but look up every word even if some have no possible matches, and return
+    /// the slot for each (`None` for those that have none) rather than giving up.
+    pub fn build_each<T: AsRef<str>>(&self, phrase: &[T]) -> Result<Vec<Option<Vec<QueryWord>>>, Box<dyn Error>> {
+        self.check_max_word_dist()?;
+        phrase.iter().enumerate().map(|(i, word)| self.slot(phrase.len(), i, word.as_ref())).collect()
+    }
+
+    fn check_max_word_dist(&self) -> Result<(), Box<dyn Error>> {
+        if self.max_word_dist > self.set.max_edit_distance {
+            return Err(Box::new(PhraseSetError::new(format!(
+                "The maximum configured edit distance for this index is {}; {} requested",
+                self.set.max_edit_distance,
+                self.max_word_dist
+            ).as_str())));
+        }
+        Ok(())
+    }
+
+    fn slot(&self, len: usize, i: usize, word: &str) -> Result<Option<Vec<QueryWord>>, Box<dyn Error>> {
+        let edit_distance = match self.word_dist_policy {
+            Some(ref policy) => policy(i, word).min(self.max_word_dist),
+            None => self.max_word_dist,
+        };
+        let possibilities = if self.ending_type == EndingType::AnyPrefix && i == len - 1 {
+            self.set.get_terminal_word_possibilities(word, edit_distance)?
+        } else {
+            self.set.get_nonterminal_word_possibilities(word, edit_distance)?
+        };
+        let possibilities = match self.alternates.get(&i) {
+            Some(alternates) => self.add_alternates(possibilities, alternates),
+            None => possibilities,
+        };
+        Ok(possibilities.map(|possibilities| {
+            possibilities.into_iter().map(|qw| qw.with_source_position(i as u16)).collect()
+        }))
+    }
+
+    // add each alternate that isn't already a possibility after the zero-distance possibilities,
+    // so the slot stays sorted by edit distance
+    fn add_alternates(&self, possibilities: Option<Vec<QueryWord>>, alternates: &[String]) -> Option<Vec<QueryWord>> {
+        let mut possibilities = possibilities.unwrap_or_default();
+        for alternate in alternates {
+            let id = match self.set.exact_word_id(alternate) {
+                Some(id) => *self.set.word_replacement_map.get(&id).unwrap_or(&id),
+                None => continue,
+            };
+            let already = possibilities.iter().any(|word| match *word {
+                QueryWord::Full { id: other, .. } => other == id,
+                QueryWord::Prefix { id_range, .. } => id >= id_range.0 && id <= id_range.1,
+            });
+            if !already {
+                let at = possibilities.iter().position(|word| match *word {
+                    QueryWord::Full { edit_distance, .. } => edit_distance > 0,
+                    QueryWord::Prefix { .. } => false,
+                }).unwrap_or(possibilities.len());
+                possibilities.insert(at, QueryWord::new_full_from(id, 0, VariantSource::Alternate));
+            }
+        }
+        if possibilities.is_empty() {
+            None
+        } else {
+            Some(possibilities)
+        }
+    }
+}
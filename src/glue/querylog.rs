@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use rmps::{Deserializer, Serializer};
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, FuzzyWindowResult, EndingType};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecordedQueryKind {
+    Match,
+    Windows,
+}
+
+/// One result of a recorded query: which phrases it matched, and how.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RecordedResult {
+    pub phrase_id_range: (u32, u32),
+    pub edit_distance: u8,
+    /// Where in the query the window started; always 0 for `RecordedQueryKind::Match` queries.
+    pub start_position: usize,
+}
+
+/// A query as the matcher received it (after any tokenization and normalization), along with
+/// its settings and results.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RecordedQuery {
+    pub kind: RecordedQueryKind,
+    pub phrase: Vec<String>,
+    pub max_word_dist: u8,
+    pub max_phrase_dist: u8,
+    pub ending_type: EndingType,
+    pub results: Vec<RecordedResult>,
+}
+
+/// A replayed query whose results differ from the recorded ones (see
+/// `FuzzyPhraseSet::replay_query_log`).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReplayDifference {
+    /// The query as it was recorded, with the results it got then.
+    pub recorded: RecordedQuery,
+    /// The results it gets now.
+    pub results: Vec<RecordedResult>,
+}
+
+// where recorded queries go; the first write error is kept, to be reported when recording stops
+pub(super) struct QueryRecorder {
+    writer: Mutex<(Box<dyn Write + Send>, Option<io::Error>)>,
+}
+
+impl QueryRecorder {
+    fn record(&self, query: &RecordedQuery) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let (ref mut writer, ref mut error) = *writer;
+            if error.is_none() {
+                if let Err(e) = query.serialize(&mut Serializer::new(writer)) {
+                    *error = Some(io::Error::other(e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+fn match_results(results: &[FuzzyMatchResult]) -> Vec<RecordedResult> {
+    results.iter().map(|result| RecordedResult {
+        phrase_id_range: result.phrase_id_range,
+        edit_distance: result.edit_distance,
+        start_position: 0,
+    }).collect()
+}
+
+fn window_results(results: &[FuzzyWindowResult]) -> Vec<RecordedResult> {
+    results.iter().map(|result| RecordedResult {
+        phrase_id_range: result.phrase_id_range,
+        edit_distance: result.edit_distance,
+        start_position: result.start_position,
+    }).collect()
+}
+
+/// Read back a log written by a query recorder (see `FuzzyPhraseSet::set_query_recorder`).
+pub fn read_query_log<R: Read>(reader: R) -> Result<Vec<RecordedQuery>, Box<dyn Error>> {
+    let mut reader = BufReader::new(reader);
+    let mut queries: Vec<RecordedQuery> = Vec::new();
+    while !reader.fill_buf()?.is_empty() {
+        queries.push(Deserialize::deserialize(&mut Deserializer::new(&mut reader))?);
+    }
+    Ok(queries)
+}
+
+impl FuzzyPhraseSet {
+    /// Record every `fuzzy_match` and `fuzzy_match_windows` query (including those made by way
+    /// of `fuzzy_match_raw` and the like), with its settings and the IDs of its results, to
+    /// `writer` as a stream of MessagePack records. The log can be read back with
+    /// `read_query_log` and replayed against another build of the index with
+    /// `replay_query_log`. Queries are recorded as the matcher receives them, after any
+    /// normalization, so replaying them exercises exactly the same matcher inputs. Replaces any
+    /// recorder set before, without flushing it.
+    pub fn set_query_recorder<W: Write + Send + 'static>(&mut self, writer: W) {
+        self.query_recorder = Some(QueryRecorder { writer: Mutex::new((Box::new(writer), None)) });
+    }
+
+    /// Stop recording queries, flush the log, and report the first error there was writing it, if
+    /// any. Recording doesn't make queries fail, so this is where write errors surface.
+    pub fn stop_query_recorder(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(recorder) = self.query_recorder.take() {
+            let (mut writer, error) = recorder.writer.into_inner().map_err(|_| "Query recorder lock was poisoned")?;
+            if let Some(error) = error {
+                return Err(Box::new(error));
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Run each query in `log` again, with the settings it was recorded with, and return the ones
+    /// whose results have changed, in log order. This is for checking a new build of an index
+    /// against the one the log was recorded with: phrase IDs are compared, so it's most useful
+    /// between builds of the same (or a lightly edited) corpus. Replayed queries go around the
+    /// result cache and aren't recorded.
+    pub fn replay_query_log(&self, log: &[RecordedQuery]) -> Result<Vec<ReplayDifference>, Box<dyn Error>> {
+        let mut differences: Vec<ReplayDifference> = Vec::new();
+        for recorded in log {
+            let results = match recorded.kind {
+                RecordedQueryKind::Match => match_results(&self.fuzzy_match_impl(&recorded.phrase, recorded.max_word_dist, recorded.max_phrase_dist, recorded.ending_type, None, None)?),
+                RecordedQueryKind::Windows => window_results(&self.fuzzy_match_windows_impl(&recorded.phrase, recorded.max_word_dist, recorded.max_phrase_dist, recorded.ending_type, None, None)?),
+            };
+            if results != recorded.results {
+                differences.push(ReplayDifference { recorded: recorded.clone(), results });
+            }
+        }
+        Ok(differences)
+    }
+
+    pub(super) fn record_match<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, results: &[FuzzyMatchResult]) {
+        if let Some(ref recorder) = self.query_recorder {
+            recorder.record(&RecordedQuery {
+                kind: RecordedQueryKind::Match,
+                phrase: phrase.iter().map(|word| word.as_ref().to_owned()).collect(),
+                max_word_dist, max_phrase_dist, ending_type,
+                results: match_results(results),
+            });
+        }
+    }
+
+    pub(super) fn record_windows<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, results: &[FuzzyWindowResult]) {
+        if let Some(ref recorder) = self.query_recorder {
+            recorder.record(&RecordedQuery {
+                kind: RecordedQueryKind::Windows,
+                phrase: phrase.iter().map(|word| word.as_ref().to_owned()).collect(),
+                max_word_dist, max_phrase_dist, ending_type,
+                results: window_results(results),
+            });
+        }
+    }
+}
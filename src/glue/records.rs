@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use serde_json;
+use rmps::Deserializer;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder};
+
+/// Information about a phrase carried through from the structured records it was inserted with
+/// (see `FuzzyPhraseSetBuilder::insert_record`).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct PhraseMetadata {
+    /// The sum of the weights of the records the phrase was inserted with.
+    pub weight: Option<f64>,
+    pub language: Option<String>,
+    /// Arbitrary JSON, serialized.
+    pub payload: Option<String>,
+}
+
+impl PhraseMetadata {
+    // fold in the metadata from another insertion of the same phrase: weights add up, and the
+    // first language and payload win
+    pub(super) fn merge(&mut self, other: PhraseMetadata) {
+        if let Some(weight) = other.weight {
+            self.weight = Some(self.weight.unwrap_or(0.0) + weight);
+        }
+        if self.language.is_none() {
+            self.language = other.language;
+        }
+        if self.payload.is_none() {
+            self.payload = other.payload;
+        }
+    }
+}
+
+/// One record of a JSONL corpus, like `{"phrase": "100 Main St", "weight": 2.5, "language":
+/// "en", "payload": {"id": 123}}`. Only `phrase`, which is tokenized as by `insert_raw`, is
+/// required.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct CorpusRecord {
+    pub phrase: String,
+    #[serde(default)]
+    pub weight: Option<f64>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub payload: Option<serde_json::Value>,
+}
+
+impl FuzzyPhraseSetBuilder {
+    /// Insert a structured corpus record, keeping its weight, language, and payload with the
+    /// phrase (see `FuzzyPhraseSet::phrase_metadata`). If a phrase is inserted more than once,
+    /// its weights are added up, and the first language and payload it was given are kept.
+    pub fn insert_record(&mut self, record: &CorpusRecord) -> Result<u32, Box<dyn Error>> {
+        let phrase_id = self.insert_raw(&record.phrase)?;
+        if record.weight.is_some() || record.language.is_some() || record.payload.is_some() {
+            let metadata = PhraseMetadata {
+                weight: record.weight,
+                language: record.language.clone(),
+                payload: record.payload.as_ref().map(|payload| payload.to_string()),
+            };
+            self.phrase_metadata.entry(phrase_id).or_default().merge(metadata);
+        }
+        Ok(phrase_id)
+    }
+
+    /// Insert every record of a JSONL corpus (one `CorpusRecord` per line; blank lines are
+    /// skipped), returning the temporary phrase ID of each record in order.
+    pub fn insert_jsonl<R: BufRead>(&mut self, reader: R) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut phrase_ids: Vec<u32> = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: CorpusRecord = serde_json::from_str(&line)
+                .map_err(|e| format!("Bad corpus record on line {}: {}", i + 1, e))?;
+            phrase_ids.push(self.insert_record(&record)?);
+        }
+        Ok(phrase_ids)
+    }
+
+    /// Like `build_from_raw`, but from a JSONL corpus (see `insert_jsonl`). Returns the final
+    /// phrase ID of each record, in record order.
+    pub fn build_from_jsonl<P: AsRef<Path>, R: BufRead>(path: P, reader: R) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut builder = FuzzyPhraseSetBuilder::new(path)?;
+        let tmp_phrase_ids = builder.insert_jsonl(reader)?;
+        let tmp_phrase_ids_to_ids = builder.finish()?;
+        Ok(tmp_phrase_ids.into_iter().map(|tmp_phrase_id| tmp_phrase_ids_to_ids[tmp_phrase_id as usize]).collect())
+    }
+}
+
+/// Read the phrase metadata written by the builder, if any.
+pub(super) fn load_phrase_metadata(directory: &Path) -> Result<BTreeMap<u32, PhraseMetadata>, Box<dyn Error>> {
+    let metadata_path = directory.join(Path::new("phrase_metadata.msg"));
+    if !metadata_path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let metadata_reader = BufReader::new(fs::File::open(&metadata_path)?);
+    let metadata: Vec<(u32, PhraseMetadata)> = Deserialize::deserialize(&mut Deserializer::new(metadata_reader))?;
+    Ok(metadata.into_iter().collect())
+}
+
+// renumber metadata from temporary to final phrase IDs, leaving out pruned phrases
+pub(super) fn finalize_phrase_metadata(phrase_metadata: FxHashMap<u32, PhraseMetadata>, tmp_phrase_ids_to_ids: &[u32], pruned_id: u32) -> Vec<(u32, PhraseMetadata)> {
+    let mut finalized: Vec<(u32, PhraseMetadata)> = phrase_metadata.into_iter()
+        .map(|(tmp_phrase_id, metadata)| (tmp_phrase_ids_to_ids[tmp_phrase_id as usize], metadata))
+        .filter(|&(id, _)| id != pruned_id)
+        .collect();
+    finalized.sort_by_key(|&(id, _)| id);
+    finalized
+}
+
+impl FuzzyPhraseSet {
+    /// The weight, language, and payload a phrase was inserted with, if it was inserted from a
+    /// structured record that had any (see `FuzzyPhraseSetBuilder::insert_record`).
+    pub fn phrase_metadata(&self, phrase_id: u32) -> Option<&PhraseMetadata> {
+        self.phrase_metadata.get(&phrase_id)
+    }
+}
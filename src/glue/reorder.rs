@@ -0,0 +1,50 @@
+use std::error::Error;
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReorderedMatchResult {
+    /// How many swaps of adjacent query words it took to match the phrase.
+    pub swaps: u8,
+    /// For each word of the matched phrase, the position of the query word it matched.
+    pub positions: Vec<usize>,
+    pub result: FuzzyMatchResult,
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match`, but also matches phrases that have the query's words in a different
+    /// order ("main 100 st" for "100 main st"), as long as it takes no more than `max_swaps` swaps
+    /// of adjacent words to put them in the phrase's order (see
+    /// `PhraseSet::match_combinations_reordered`). Swaps don't count against `max_phrase_dist`,
+    /// but each one is penalized like an edit in the order results are returned in: by edit
+    /// distance plus swaps, and then by swaps. If the query ends in a prefix, that stays the last
+    /// word, and only the ones before it are reordered.
+    pub fn fuzzy_match_reordered<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, max_swaps: u8, ending_type: EndingType) -> Result<Vec<ReorderedMatchResult>, Box<dyn Error>> {
+        let (phrase, ending_type, word_possibilities) = match self.query_slots(phrase, max_word_dist, ending_type)? {
+            Some(slots) => slots,
+            None => return Ok(Vec::new()),
+        };
+
+        let phrase_matches = match ending_type {
+            EndingType::NonPrefix => {
+                self.phrase_set.match_combinations_reordered(&word_possibilities, max_phrase_dist, max_swaps)?
+            },
+            EndingType::WordBoundaryPrefix | EndingType::AnyPrefix => {
+                self.phrase_set.match_combinations_as_prefixes_reordered(&word_possibilities, max_phrase_dist, max_swaps)?
+            }
+        };
+
+        let mut results: Vec<ReorderedMatchResult> = phrase_matches.into_iter().map(|reordered| {
+            // the query's words in the phrase's order, so that a prefix is echoed back as typed
+            let reordered_phrase: Vec<&str> = reordered.slots.iter().map(|&slot| phrase[slot].as_ref()).collect();
+            let result = self.combination_to_result(&reordered_phrase, &reordered.combination, ending_type);
+            ReorderedMatchResult { swaps: reordered.swaps, positions: reordered.slots, result }
+        }).collect();
+        results.sort_by(|a, b| {
+            (u32::from(a.result.edit_distance) + u32::from(a.swaps)).cmp(&(u32::from(b.result.edit_distance) + u32::from(b.swaps)))
+                .then(a.swaps.cmp(&b.swaps))
+                .then_with(|| a.result.cmp(&b.result))
+        });
+        Ok(results)
+    }
+}
@@ -187,6 +187,24 @@ fn get_terminal_word_possibilities() -> () {
     );
 }
 
+#[test]
+fn word_possibility_sources() -> () {
+    // equality doesn't look at where a word came from, so check that separately
+    let sources = |word: &str, terminal: bool| -> Vec<VariantSource> {
+        let possibilities = if terminal {
+            TEST_SET.get_terminal_word_possibilities(word, 1)
+        } else {
+            TEST_SET.get_nonterminal_word_possibilities(word, 1)
+        };
+        possibilities.unwrap().unwrap().iter().map(|word| word.variant_source()).collect()
+    };
+    assert_eq!(sources("main", false), vec![VariantSource::Exact, VariantSource::Typo]);
+    assert_eq!(sources("street", false), vec![VariantSource::Replacement]);
+    assert_eq!(sources("stret", false), vec![VariantSource::Typo]);
+    assert_eq!(sources("street", true), vec![VariantSource::Replacement]);
+    assert_eq!(sources("st", true), vec![VariantSource::Exact, VariantSource::Typo]);
+}
+
 #[test]
 fn contains() {
     assert_eq!(
@@ -495,7 +513,9 @@ fn fuzzy_match_windows() -> () {
                     phrase: vec!["100".to_string(), "ft".to_string(), "wayne".to_string(), "rd".to_string()],
                     start_position: 0,
                     ending_type: EndingType::WordBoundaryPrefix,
-                    phrase_id_range: (1, 1)
+                    phrase_id_range: (1, 1),
+                    tokens_consumed: 4,
+                    tokens_total: 4
                 }
             } else {
                 FuzzyWindowResult {
@@ -503,7 +523,9 @@ fn fuzzy_match_windows() -> () {
                     phrase: vec!["100".to_string(), "ft".to_string(), "wayne".to_string(), "r".to_string()],
                     start_position: 0,
                     ending_type: EndingType::AnyPrefix,
-                    phrase_id_range: (1, 1)
+                    phrase_id_range: (1, 1),
+                    tokens_consumed: 4,
+                    tokens_total: 4
                 }
             }]
         );
@@ -516,7 +538,9 @@ fn fuzzy_match_windows() -> () {
                         phrase: vec!["100".to_string(), "ft".to_string(), "wayne".to_string(), "rd".to_string()],
                         start_position: 0,
                         ending_type: EndingType::WordBoundaryPrefix,
-                        phrase_id_range: (1, 1)
+                        phrase_id_range: (1, 1),
+                        tokens_consumed: 4,
+                        tokens_total: 4
                     }
                 ]
             } else {
@@ -539,7 +563,9 @@ fn fuzzy_match_windows() -> () {
                     phrase: vec!["100".to_string(), "ft".to_string(), "wayne".to_string(), "rd".to_string()],
                     start_position: 0,
                     ending_type: EndingType::NonPrefix,
-                    phrase_id_range: (1, 1)
+                    phrase_id_range: (1, 1),
+                    tokens_consumed: 4,
+                    tokens_total: 6
                 }
             ]
         )
@@ -559,7 +585,9 @@ fn fuzzy_match_windows() -> () {
                     phrase: vec!["100".to_string(), "ft".to_string(), "wayne".to_string(), "rd".to_string()],
                     start_position: 2,
                     ending_type: EndingType::WordBoundaryPrefix,
-                    phrase_id_range: (1, 1)
+                    phrase_id_range: (1, 1),
+                    tokens_consumed: 4,
+                    tokens_total: 6
                 }
             ]
         )
@@ -574,14 +602,18 @@ fn fuzzy_match_windows() -> () {
                 phrase: vec!["100".to_string(), "fo".to_string()],
                 start_position: 2,
                 ending_type: EndingType::AnyPrefix,
-                phrase_id_range: (0, 0)
+                phrase_id_range: (0, 0),
+                tokens_consumed: 2,
+                tokens_total: 4
             },
             FuzzyWindowResult {
                 edit_distance: 0,
                 phrase: vec!["100".to_string(), "ft".to_string()],
                 start_position: 2,
                 ending_type: EndingType::WordBoundaryPrefix,
-                phrase_id_range: (1, 1)
+                phrase_id_range: (1, 1),
+                tokens_consumed: 2,
+                tokens_total: 4
             }
         ]
     );
@@ -595,7 +627,9 @@ fn fuzzy_match_windows() -> () {
                 phrase: vec!["100".to_string(), "ft".to_string()],
                 start_position: 2,
                 ending_type: EndingType::WordBoundaryPrefix,
-                phrase_id_range: (1, 1)
+                phrase_id_range: (1, 1),
+                tokens_consumed: 2,
+                tokens_total: 4
             }
         ]
     );
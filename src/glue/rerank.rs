@@ -0,0 +1,180 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+use super::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+use super::util::distance_score;
+
+/// Settings for `FuzzyPhraseSet::rerank`. More may be added, so start from the default and set
+/// the ones to change.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub struct RerankConfig {
+    /// How much of a result's score comes from its character-level similarity to the query,
+    /// from 0 (the edit distance alone, as in `fuzzy_match_biased`) to 1 (similarity alone).
+    pub similarity_weight: f64,
+    /// The Jaro-Winkler prefix scale: how much words that begin the same way are favored
+    /// (between 0 and 0.25; `FuzzyPhraseSet::rerank` fails otherwise).
+    pub prefix_scale: f64,
+    /// How much of a result's score comes from the weights of the phrases it matched (see
+    /// `FuzzyPhraseSet::phrase_weight`), from 0 (not at all) to 1 (weight alone).
+    pub popularity_weight: f64,
+}
+
+impl Default for RerankConfig {
+    fn default() -> RerankConfig {
+        RerankConfig { similarity_weight: 0.5, prefix_scale: 0.1, popularity_weight: 0.0 }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RerankedMatchResult {
+    pub score: f64,
+    /// The result's character-level similarity to the query, from 0 to 1.
+    pub similarity: f64,
+    /// The weight of the result's phrase (for a prefix result, the heaviest weight any of the
+    /// phrases it covers was built with, plus the heaviest any of them has in the overlay),
+    /// squashed to between 0 and 1.
+    pub popularity: f64,
+    pub result: FuzzyMatchResult,
+}
+
+impl FuzzyPhraseSet {
+    /// Like `fuzzy_match`, but with the results re-ranked by `rerank`, best first.
+    pub fn fuzzy_match_reranked<T: AsRef<str>>(&self, phrase: &[T], max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType, config: &RerankConfig) -> Result<Vec<RerankedMatchResult>, Box<dyn Error>> {
+        let results = self.fuzzy_match(phrase, max_word_dist, max_phrase_dist, ending_type)?;
+        self.rerank(phrase, results, max_phrase_dist, config)
+    }
+
+    /// Score and sort match results for `phrase`, so that results with the same edit distance
+    /// (which `fuzzy_match` can't tell apart) come back in a sensible order. Each result is
+    /// compared to the query word by word with a soft TF-IDF measure: every query word is paired
+    /// with the most similar word of the result by Jaro-Winkler similarity, and the pairs are
+    /// averaged, weighted by how rare the result's words are if the set stores word frequencies
+    /// (see `FuzzyPhraseSetBuilder::set_store_word_frequencies`), or evenly otherwise. That's
+    /// blended with the edit distance score used by `fuzzy_match_biased` according to
+    /// `config.similarity_weight`, and that with the result's popularity, its phrase weight
+    /// (counting both the weight it was built with and any from the set's weight overlay, and
+    /// for a prefix result, the heaviest of each among the phrases it covers) mapped from 0 and
+    /// up to between 0 and 1, according to `config.popularity_weight`. Ties are broken by the
+    /// results' natural order. Fails if `config.prefix_scale` is outside 0 to 0.25, past which the similarity of
+    /// words that begin the same way can come out above 1.
+    pub fn rerank<T: AsRef<str>>(&self, phrase: &[T], results: Vec<FuzzyMatchResult>, max_phrase_dist: u8, config: &RerankConfig) -> Result<Vec<RerankedMatchResult>, Box<dyn Error>> {
+        if !(0.0..=0.25).contains(&config.prefix_scale) {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidInput, format!(
+                "Jaro-Winkler prefix scale {} is outside 0 to 0.25", config.prefix_scale
+            ))));
+        }
+        let mut reranked: Vec<RerankedMatchResult> = results.into_iter().map(|result| {
+            let similarity = self.soft_similarity(phrase, &result.phrase, config.prefix_scale);
+            let popularity = if config.popularity_weight > 0.0 { self.popularity(result.phrase_id_range) } else { 0.0 };
+            let score = config.similarity_weight * similarity +
+                (1.0 - config.similarity_weight) * distance_score(result.edit_distance, max_phrase_dist);
+            let score = config.popularity_weight * popularity + (1.0 - config.popularity_weight) * score;
+            RerankedMatchResult { score, similarity, popularity, result }
+        }).collect();
+        reranked.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+                .then_with(|| a.result.cmp(&b.result))
+        });
+        Ok(reranked)
+    }
+
+    // the heaviest built and overlaid phrase weights in the range, with negative weights counting
+    // as zero, squashed so that it approaches 1 as the weight grows
+    fn popularity(&self, phrase_id_range: (u32, u32)) -> f64 {
+        let (start, end) = phrase_id_range;
+        let built = self.built_weights.heaviest(start, end);
+        let overlaid = self.weight_overlay.as_ref().map_or(0.0, |overlay| f64::from(overlay.heaviest(start, end)));
+        let weight = built + overlaid;
+        weight / (1.0 + weight)
+    }
+
+    fn soft_similarity<T: AsRef<str>>(&self, query: &[T], phrase: &[String], prefix_scale: f64) -> f64 {
+        if query.is_empty() || phrase.is_empty() {
+            return 0.0;
+        }
+        let phrase_count = self.phrase_count().max(1) as f64;
+        let (mut total, mut total_weight) = (0.0, 0.0);
+        for word in query {
+            let (best, similarity) = phrase.iter()
+                .map(|candidate| (candidate, jaro_winkler(word.as_ref(), candidate, prefix_scale)))
+                .fold((&phrase[0], -1.0), |best, pair| if pair.1 > best.1 { pair } else { best });
+            let weight = if self.word_frequencies.is_empty() {
+                1.0
+            } else {
+                let frequency = self.word_frequency(best).unwrap_or(0).max(1) as f64;
+                (1.0 + phrase_count / frequency).ln()
+            };
+            total += weight * similarity;
+            total_weight += weight;
+        }
+        total / total_weight
+    }
+}
+
+/// The Jaro-Winkler similarity of two strings (by character), from 0 (nothing in common) to 1
+/// (identical).
+fn jaro_winkler(a: &str, b: &str, prefix_scale: f64) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    } else if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    // characters only count as matching if they're no further apart than this
+    let window = (a.len().max(b.len()) / 2).saturating_sub(1);
+    let mut a_matched = vec![false; a.len()];
+    let mut b_matched = vec![false; b.len()];
+    let mut matches = 0usize;
+    for (i, ca) in a.iter().enumerate() {
+        let start = i.saturating_sub(window);
+        let end = (i + window + 1).min(b.len());
+        for j in start..end {
+            if !b_matched[j] && b[j] == *ca {
+                a_matched[i] = true;
+                b_matched[j] = true;
+                matches += 1;
+                break;
+            }
+        }
+    }
+    if matches == 0 {
+        return 0.0;
+    }
+
+    // matched characters that appear in a different order in each string
+    let mut transpositions = 0usize;
+    let mut b_matches = b.iter().zip(b_matched.iter()).filter(|&(_, matched)| *matched).map(|(c, _)| c);
+    for (ca, _) in a.iter().zip(a_matched.iter()).filter(|&(_, matched)| *matched) {
+        if b_matches.next() != Some(ca) {
+            transpositions += 1;
+        }
+    }
+
+    let m = matches as f64;
+    let jaro = (m / a.len() as f64 + m / b.len() as f64 + (m - transpositions as f64 / 2.0) / m) / 3.0;
+    let prefix = a.iter().zip(b.iter()).take(4).take_while(|&(ca, cb)| ca == cb).count() as f64;
+    jaro + prefix * prefix_scale * (1.0 - jaro)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jaro_winkler_test() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.001;
+        assert!(close(jaro_winkler("martha", "marhta", 0.1), 0.961));
+        assert!(close(jaro_winkler("dwayne", "duane", 0.1), 0.840));
+        assert!(close(jaro_winkler("dixon", "dicksonx", 0.1), 0.813));
+        assert!(close(jaro_winkler("maen", "mean", 0.1), 0.925));
+        assert!(close(jaro_winkler("maen", "main", 0.1), 0.867));
+        assert!(close(jaro_winkler("straße", "straße", 0.1), 1.0));
+        assert!(close(jaro_winkler("", "", 0.1), 1.0));
+        assert!(close(jaro_winkler("abc", "", 0.1), 0.0));
+        assert!(close(jaro_winkler("abc", "xyz", 0.1), 0.0));
+    }
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder};
+use super::unicode_ranges::{get_script, Script};
+
+/// How words from scripts that aren't written with spaces between words (Han, Hiragana,
+/// Katakana, Thai, Lao, Khmer and Myanmar) are broken up after tokenization, so that phrases in
+/// them can be matched word by word like any other. Words in other scripts are left alone.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub enum Segmentation {
+    /// Leave them whole, as the tokenizer found them.
+    #[default]
+    None,
+    /// Treat every character as a word ("東京駅" -> "東", "京", "駅").
+    Characters,
+    /// Treat every run of this many characters as a word, overlapping ("東京駅" -> "東京",
+    /// "京駅" for 2). Runs shorter than that are left whole.
+    NGrams(u8),
+    /// Break them up with the caller's own `Segmenter`, registered under this name (see
+    /// `FuzzyPhraseSetBuilder::set_segmenter` and `FuzzyPhraseSet::set_segmenter`).
+    Custom(String),
+}
+
+/// Breaks a run of characters from a script without word spacing into words, for
+/// `Segmentation::Custom` (with a dictionary, say). Closures from a string to a list of words
+/// work as segmenters too.
+pub trait Segmenter: Send + Sync {
+    fn segment(&self, run: &str) -> Vec<String>;
+}
+
+impl<F: Fn(&str) -> Vec<String> + Send + Sync> Segmenter for F {
+    fn segment(&self, run: &str) -> Vec<String> {
+        self(run)
+    }
+}
+
+// the caller's segmenter, if there is one; shared between a builder and anything else it's
+// registered with
+#[derive(Clone, Default)]
+pub(super) struct CustomSegmenter(Option<Arc<dyn Segmenter>>);
+
+impl fmt::Debug for CustomSegmenter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "CustomSegmenter(Some(..))" } else { "CustomSegmenter(None)" })
+    }
+}
+
+#[inline(always)]
+fn is_unspaced(c: char) -> bool {
+    matches!(get_script(c), Script::Han | Script::Hiragana | Script::Katakana | Script::Thai | Script::Lao | Script::Khmer | Script::Myanmar)
+}
+
+fn segment_run(run: &str, segmentation: &Segmentation, custom: &CustomSegmenter, words: &mut Vec<String>) {
+    match *segmentation {
+        Segmentation::None => words.push(run.to_owned()),
+        Segmentation::Characters => words.extend(run.chars().map(|c| c.to_string())),
+        Segmentation::NGrams(n) => {
+            let chars: Vec<char> = run.chars().collect();
+            let n = (n as usize).max(1);
+            if chars.len() <= n {
+                words.push(run.to_owned());
+            } else {
+                words.extend(chars.windows(n).map(|gram| gram.iter().collect::<String>()));
+            }
+        },
+        Segmentation::Custom(_) => match custom.0 {
+            Some(ref segmenter) => words.extend(segmenter.segment(run).into_iter().filter(|word| !word.is_empty())),
+            None => words.push(run.to_owned()),
+        },
+    }
+}
+
+/// Break up the words of a tokenized phrase according to `segmentation`. Words that mix scripts
+/// are split where the script changes, and only the unspaced parts are segmented further.
+pub(super) fn segment_words(words: Vec<String>, segmentation: &Segmentation, custom: &CustomSegmenter) -> Vec<String> {
+    if *segmentation == Segmentation::None {
+        return words;
+    }
+    let mut segmented: Vec<String> = Vec::with_capacity(words.len());
+    for word in words {
+        if !word.chars().any(is_unspaced) {
+            segmented.push(word);
+            continue;
+        }
+        let mut start = 0;
+        let mut unspaced = None;
+        for (i, c) in word.char_indices() {
+            let this_unspaced = is_unspaced(c);
+            if unspaced.is_some_and(|unspaced| unspaced != this_unspaced) {
+                if unspaced == Some(true) {
+                    segment_run(&word[start..i], segmentation, custom, &mut segmented);
+                } else {
+                    segmented.push(word[start..i].to_owned());
+                }
+                start = i;
+            }
+            unspaced = Some(this_unspaced);
+        }
+        if unspaced == Some(true) {
+            segment_run(&word[start..], segmentation, custom, &mut segmented);
+        } else {
+            segmented.push(word[start..].to_owned());
+        }
+    }
+    segmented
+}
+
+impl FuzzyPhraseSetBuilder {
+    /// Set how `insert_raw` (and `build_from_raw`) segment words from scripts without word
+    /// spacing. The mode is stored with the set, so that raw queries are segmented the same way.
+    pub fn set_segmentation(&mut self, segmentation: Segmentation) {
+        self.options.segmentation = segmentation;
+    }
+
+    /// Segment words from scripts without word spacing with `segmenter`, recorded as
+    /// `Segmentation::Custom(name)`. The same segmenter has to be registered with the set under
+    /// the same name when it's loaded (see `FuzzyPhraseSet::set_segmenter`).
+    pub fn set_segmenter<S: Into<String>, G: Segmenter + 'static>(&mut self, name: S, segmenter: G) {
+        self.options.segmentation = Segmentation::Custom(name.into());
+        self.segmenter = CustomSegmenter(Some(Arc::new(segmenter)));
+    }
+
+    pub(super) fn segment(&self, words: Vec<String>) -> Vec<String> {
+        segment_words(words, &self.options.segmentation, &self.segmenter)
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Register the segmenter the set was built with (see `FuzzyPhraseSetBuilder::set_segmenter`),
+    /// so that raw queries are segmented the same way its phrases were. Fails if the set was
+    /// built with a different segmentation mode, or a segmenter with another name. Until it's
+    /// registered, unspaced words in raw queries are left whole.
+    pub fn set_segmenter<S: AsRef<str>, G: Segmenter + 'static>(&mut self, name: S, segmenter: G) -> Result<(), Box<dyn Error>> {
+        if self.options.segmentation != Segmentation::Custom(name.as_ref().to_owned()) {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidInput, format!(
+                "Set was built with segmentation {:?}, not a segmenter named {:?}", self.options.segmentation, name.as_ref()
+            ))));
+        }
+        self.segmenter = CustomSegmenter(Some(Arc::new(segmenter)));
+        self.clear_result_cache();
+        Ok(())
+    }
+
+    pub(super) fn segment(&self, words: Vec<String>) -> Vec<String> {
+        segment_words(words, &self.options.segmentation, &self.segmenter)
+    }
+}
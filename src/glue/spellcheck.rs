@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, BufReader};
+use std::path::Path;
+
+use serde::Deserialize;
+use rmps::Deserializer;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, EndingType};
+use super::tokenize::tokenize;
+
+impl FuzzyPhraseSetBuilder {
+    /// Record how many times each word was inserted (counting repeat insertions), so that
+    /// `FuzzyPhraseSet::correct` can prefer corrections made of common words over ones made of
+    /// rare words. Off by default.
+    pub fn set_store_word_frequencies(&mut self, store: bool) {
+        self.options.word_frequencies = store;
+    }
+}
+
+/// Read the word counts written by the builder, if any; they're indexed by word ID.
+pub(super) fn load_word_frequencies(directory: &Path, word_count: usize) -> Result<Vec<u32>, Box<dyn Error>> {
+    let frequency_path = directory.join(Path::new("word_frequencies.msg"));
+    if !frequency_path.exists() {
+        return Ok(Vec::new());
+    }
+    let frequency_reader = BufReader::new(fs::File::open(&frequency_path)?);
+    let frequencies: Vec<u32> = Deserialize::deserialize(&mut Deserializer::new(frequency_reader))?;
+    if frequencies.len() != word_count {
+        return Err(Box::new(IoError::new(IoErrorKind::InvalidData, "Word frequencies don't match the word list")));
+    }
+    Ok(frequencies)
+}
+
+impl FuzzyPhraseSet {
+    /// How many times `word` was inserted, if the set was built with
+    /// `FuzzyPhraseSetBuilder::set_store_word_frequencies`.
+    pub fn word_frequency(&self, word: &str) -> Option<u32> {
+        self.exact_word_id(word).and_then(|id| self.word_frequencies.get(id as usize).cloned())
+    }
+
+    // the average log-frequency of the words in a phrase; 0 if frequencies weren't stored
+    fn frequency_weight(&self, phrase: &[String]) -> f64 {
+        if phrase.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = phrase.iter()
+            .map(|word| (1.0 + f64::from(self.word_frequency(word).unwrap_or(0))).ln())
+            .sum();
+        total / phrase.len() as f64
+    }
+
+    /// Spellcheck a raw phrase: tokenize it (see `tokenize`) and return the single most likely
+    /// complete phrase in the set it could be a misspelling of, joined with spaces, along with its
+    /// total edit distance. Every word may be misspelled by up to the set's maximum edit distance.
+    /// The closest candidate wins; among equally close ones, the one whose words are most frequent
+    /// wins if the set has word frequencies, and the first in phrase order otherwise. Returns
+    /// `None` if nothing is close enough, including if the query is empty or too long to search.
+    pub fn correct(&self, phrase: &str) -> Option<(String, u8)> {
+        let tokens = tokenize(phrase);
+        if tokens.is_empty() {
+            return None;
+        }
+        let max_phrase_dist = (tokens.len().min(u8::MAX as usize) as u8).saturating_mul(self.max_edit_distance);
+        let results = self.fuzzy_match(&tokens, self.max_edit_distance, max_phrase_dist, EndingType::NonPrefix).ok()?;
+
+        let mut best: Option<(u8, f64, Vec<String>)> = None;
+        for result in results {
+            let weight = self.frequency_weight(&result.phrase);
+            let better = match best {
+                None => true,
+                Some((edit_distance, best_weight, _)) => {
+                    result.edit_distance < edit_distance || (result.edit_distance == edit_distance && weight > best_weight)
+                },
+            };
+            if better {
+                best = Some((result.edit_distance, weight, result.phrase));
+            }
+        }
+        best.map(|(edit_distance, _weight, phrase)| (phrase.join(" "), edit_distance))
+    }
+}
@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::path::Path;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, FuzzyMatchResult, EndingType};
+use super::correction::keep_best_result;
+
+/// What the tokenizer should do with a kind of word-internal punctuation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum JoinRule {
+    /// Treat the punctuation as a word boundary ("martin-luther-king" -> "martin luther king").
+    Split,
+    /// Drop the punctuation and keep the pieces together as one word ("o'fallon" -> "ofallon").
+    Join,
+}
+
+/// How the tokenizer handles hyphens and apostrophes (including the typographic variants of
+/// each). Both are split by default.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct TokenizerConfig {
+    pub hyphens: JoinRule,
+    pub apostrophes: JoinRule,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> TokenizerConfig {
+        TokenizerConfig { hyphens: JoinRule::Split, apostrophes: JoinRule::Split }
+    }
+}
+
+#[inline(always)]
+fn is_hyphen(c: char) -> bool {
+    c == '-' || c == '\u{2010}' || c == '\u{2011}'
+}
+
+#[inline(always)]
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}' || c == '\u{02bc}'
+}
+
+/// A basic tokenizer for raw phrases: lowercases the input and splits it into words on anything
+/// that isn't a letter, digit, or '#' (which is kept so that masked numbers like "1##" survive).
+/// Callers with more sophisticated needs (language-specific normalization, abbreviation handling,
+/// etc.) should tokenize on their own and call `insert` directly.
+pub fn tokenize(phrase: &str) -> Vec<String> {
+    tokenize_with(phrase, &TokenizerConfig::default())
+}
+
+/// Same as `tokenize`, but with hyphens and apostrophes handled according to `config`.
+pub fn tokenize_with(phrase: &str, config: &TokenizerConfig) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for c in phrase.chars() {
+        if c.is_alphanumeric() || c == '#' {
+            current.extend(c.to_lowercase());
+        } else if (is_hyphen(c) && config.hyphens == JoinRule::Join) ||
+            (is_apostrophe(c) && config.apostrophes == JoinRule::Join) {
+            continue;
+        } else if !current.is_empty() {
+            words.push(current);
+            current = String::new();
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// Tokenize a raw phrase every way that `JoinRule`s could apply to it: with hyphens and with
+/// apostrophes each split and joined. Only distinct tokenizations are returned, the all-split
+/// one first, so a phrase without any hyphens or apostrophes yields just one.
+pub fn punctuation_variants(phrase: &str) -> Vec<Vec<String>> {
+    let mut variants: Vec<Vec<String>> = Vec::with_capacity(4);
+    for hyphens in &[JoinRule::Split, JoinRule::Join] {
+        for apostrophes in &[JoinRule::Split, JoinRule::Join] {
+            let variant = tokenize_with(phrase, &TokenizerConfig { hyphens: *hyphens, apostrophes: *apostrophes });
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+    }
+    variants
+}
+
+impl FuzzyPhraseSetBuilder {
+    /// Set how `insert_raw` (and `build_from_raw`) tokenize phrases. The configuration is stored
+    /// with the set, so that `FuzzyPhraseSet::normalize` tokenizes the same way.
+    pub fn set_tokenizer_config(&mut self, config: TokenizerConfig) {
+        self.options.tokenizer_config = config;
+    }
+
+    /// Tokenize a raw phrase with `tokenize_with` and the builder's tokenizer configuration,
+    /// segment it according to the builder's segmentation mode (see `set_segmentation`), and
+    /// insert it. Phrases that don't contain any words are rejected.
+    pub fn insert_raw(&mut self, phrase: &str) -> Result<u32, Box<dyn Error>> {
+        let words = self.segment(tokenize_with(phrase, &self.options.tokenizer_config));
+        if words.is_empty() {
+            return Err(format!("Phrase {:?} contains no words", phrase).into());
+        }
+        self.insert(&words)
+    }
+
+    /// Build a complete set in the directory at `path` from an iterator of raw phrases, handling
+    /// tokenization, word and phrase ID assignment, and writing out all of the components in one
+    /// go. Returns the final phrase ID of each input phrase, in input order, as `finish` does.
+    pub fn build_from_raw<P, I, S>(path: P, phrases: I) -> Result<Vec<u32>, Box<dyn Error>>
+        where P: AsRef<Path>, I: IntoIterator<Item=S>, S: AsRef<str> {
+        let mut builder = FuzzyPhraseSetBuilder::new(path)?;
+        for phrase in phrases {
+            builder.insert_raw(phrase.as_ref())?;
+        }
+        builder.finish()
+    }
+}
+
+impl FuzzyPhraseSet {
+    /// Tokenize and normalize a raw string exactly the way `FuzzyPhraseSetBuilder::insert_raw`
+    /// did when this set was built (with `tokenize_with` and the builder's tokenizer
+    /// configuration and segmentation mode, and then the set's word replacements), so that other
+    /// systems (caches, logs, analytics) can canonicalize strings the same way the index does.
+    pub fn normalize(&self, phrase: &str) -> Vec<String> {
+        self.replace_words(self.segment(tokenize_with(phrase, &self.options.tokenizer_config)))
+    }
+
+    // swap each word that the set has a replacement for (see
+    // `FuzzyPhraseSetBuilder::load_word_replacements`) for its replacement, as the builder did
+    pub(super) fn replace_words(&self, mut tokens: Vec<String>) -> Vec<String> {
+        if self.word_replacement_map.is_empty() {
+            return tokens;
+        }
+        for token in tokens.iter_mut() {
+            let replacement = self.prefix_set.lookup(token.as_str()).id()
+                .and_then(|id| self.word_replacement_map.get(&(id.value() as u32)));
+            if let Some(&to) = replacement {
+                *token = self.word_list[to as usize].clone();
+            }
+        }
+        tokens
+    }
+
+    /// Like `fuzzy_match`, but for an untokenized query. The query is tokenized with hyphens and
+    /// apostrophes both split and joined (see `punctuation_variants`), so that it matches however
+    /// the set's phrases were tokenized (and then segmented the way they were); if the same
+    /// phrase is found with more than one tokenization, only the one with the lowest edit
+    /// distance is kept.
+    pub fn fuzzy_match_raw(&self, phrase: &str, max_word_dist: u8, max_phrase_dist: u8, ending_type: EndingType) -> Result<Vec<FuzzyMatchResult>, Box<dyn Error>> {
+        let mut results: Vec<FuzzyMatchResult> = Vec::new();
+        for variant in punctuation_variants(phrase) {
+            let variant = self.segment(variant);
+            if variant.is_empty() {
+                continue;
+            }
+            for result in self.fuzzy_match(&variant, max_word_dist, max_phrase_dist, ending_type)? {
+                keep_best_result(&mut results, result);
+            }
+        }
+        Ok(results)
+    }
+}
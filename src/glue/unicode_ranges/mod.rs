@@ -1,7 +1,7 @@
 #[allow(dead_code)]
 mod tables;
 use self::tables::script_table;
-pub use self::tables::Script;
+pub use self::tables::{Script, get_script};
 
 use std::collections::HashMap;
 
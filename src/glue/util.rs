@@ -21,6 +21,13 @@ pub fn can_fuzzy_match(word: &str, script_regex: &regex::Regex) -> bool {
     }
 }
 
+/// Score a match by how much of the phrase edit distance budget it left unused, on a scale from
+/// 1 (exact) down to (but not including) 0.
+#[inline(always)]
+pub fn distance_score(edit_distance: u8, max_phrase_dist: u8) -> f64 {
+    1.0 - f64::from(edit_distance) / (f64::from(max_phrase_dist) + 1.0)
+}
+
 #[test]
 fn digit_test() {
     assert!(contains_digit_or_pound("1"));
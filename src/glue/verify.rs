@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufReader, Read, Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+
+use serde_json;
+use xxhash_rust::xxh64::Xxh64;
+
+use super::{FuzzyPhraseSet, FuzzyPhraseSetMetadata, EndingType, OUTPUT_FILES};
+use super::tokenize::punctuation_variants;
+
+/// Why a phrase from the source corpus couldn't be found in a set.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VerificationFailure {
+    /// The phrase doesn't contain any words.
+    Empty,
+    /// These words of the phrase aren't in the set's vocabulary.
+    UnknownWords(Vec<String>),
+    /// The phrase is the beginning of one or more phrases in the set, but not a phrase itself.
+    NotFinal,
+    /// The phrase is in the set, but tokenized differently from how `FuzzyPhraseSet::normalize`
+    /// tokenizes it (as given), which usually means the set was built with different rules.
+    TokenizationDrift(Vec<String>),
+    /// All of the phrase's words are known, but not in that order.
+    Missing,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VerificationIssue {
+    /// The position of the phrase in the corpus.
+    pub index: usize,
+    pub phrase: String,
+    /// The phrase as tokenized by `FuzzyPhraseSet::normalize`.
+    pub tokens: Vec<String>,
+    pub failure: VerificationFailure,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct VerificationReport {
+    pub phrases_checked: usize,
+    pub phrases_found: usize,
+    pub issues: Vec<VerificationIssue>,
+}
+
+impl VerificationReport {
+    /// Whether every phrase was found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+// the hex xxh64 hash of each file the builder wrote to `directory`, other than the metadata
+pub(super) fn file_checksums(directory: &Path) -> io::Result<BTreeMap<String, String>> {
+    let mut checksums: BTreeMap<String, String> = BTreeMap::new();
+    for name in OUTPUT_FILES.iter().filter(|&&name| name != "metadata.json") {
+        let path = directory.join(name);
+        if path.exists() {
+            checksums.insert(name.to_string(), file_checksum(&path)?);
+        }
+    }
+    Ok(checksums)
+}
+
+fn file_checksum(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut hasher = Xxh64::new(0);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+impl FuzzyPhraseSet {
+    /// Hash each of the files in the index at `path` and compare them against the checksums the
+    /// builder recorded in its metadata, failing with a list of the ones that are missing or
+    /// don't match. Returns the names of the files checked, which is none of them for indexes
+    /// built before checksums were recorded. Loading an index only checks that its components
+    /// come from the same build; this also catches files corrupted after the build.
+    pub fn verify_checksums<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Box<dyn Error>> {
+        let directory = path.as_ref();
+        let metadata: FuzzyPhraseSetMetadata = serde_json::from_reader(BufReader::new(fs::File::open(directory.join("metadata.json"))?))?;
+        let mut bad: Vec<&str> = Vec::new();
+        for (name, checksum) in &metadata.checksums {
+            let path = directory.join(name);
+            if !path.exists() || file_checksum(&path)? != *checksum {
+                bad.push(name);
+            }
+        }
+        if !bad.is_empty() {
+            return Err(Box::new(IoError::new(IoErrorKind::InvalidData, format!(
+                "Index files are missing or don't match their checksums: {}", bad.join(", ")
+            ))));
+        }
+        Ok(metadata.checksums.keys().cloned().collect())
+    }
+
+    /// Check that every phrase of a raw source corpus made it into this set exactly (tokenized
+    /// with `normalize`, and matched with no edits), and report the ones that didn't, along with
+    /// the likeliest reason for each. Meant to be run against the corpus a set was built from,
+    /// after building it.
+    pub fn verify_corpus<I, S>(&self, phrases: I) -> Result<VerificationReport, Box<dyn Error>>
+        where I: IntoIterator<Item=S>, S: AsRef<str> {
+        let mut report = VerificationReport::default();
+        for (index, phrase) in phrases.into_iter().enumerate() {
+            let phrase = phrase.as_ref();
+            let tokens = self.normalize(phrase);
+            report.phrases_checked += 1;
+            match self.verify_phrase(phrase, &tokens)? {
+                None => report.phrases_found += 1,
+                Some(failure) => report.issues.push(VerificationIssue { index, phrase: phrase.to_owned(), tokens, failure }),
+            }
+        }
+        Ok(report)
+    }
+
+    fn verify_phrase(&self, phrase: &str, tokens: &[String]) -> Result<Option<VerificationFailure>, Box<dyn Error>> {
+        if tokens.is_empty() {
+            return Ok(Some(VerificationFailure::Empty));
+        }
+        if self.contains(tokens, EndingType::NonPrefix)? {
+            return Ok(None);
+        }
+
+        for variant in punctuation_variants(phrase) {
+            if variant.as_slice() != tokens && !variant.is_empty() && self.contains(&variant, EndingType::NonPrefix)? {
+                return Ok(Some(VerificationFailure::TokenizationDrift(variant)));
+            }
+        }
+
+        let unknown: Vec<String> = tokens.iter()
+            .filter(|word| self.exact_word_id(word).is_none())
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Ok(Some(VerificationFailure::UnknownWords(unknown)));
+        }
+
+        if self.contains(tokens, EndingType::WordBoundaryPrefix)? {
+            Ok(Some(VerificationFailure::NotFinal))
+        } else {
+            Ok(Some(VerificationFailure::Missing))
+        }
+    }
+}
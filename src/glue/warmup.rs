@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::hint::black_box;
+
+use rustc_hash::FxHashSet;
+
+use fst_compat::raw::{CompiledAddr, Fst};
+use super::{FuzzyPhraseSet, EndingType};
+
+// memory-mapped structures are faulted in a page at a time, so one byte per page is enough
+const PAGE_SIZE: usize = 4096;
+
+/// How `FuzzyPhraseSet::warm_up` brings an index's memory-mapped structures into memory.
+#[derive(Debug, PartialEq, Clone)]
+pub enum WarmUpStrategy {
+    /// Read every page of every structure, front to back. Thorough, and the fastest way to load
+    /// an index that fits in memory, but it pays for pages that queries may never touch.
+    Sequential,
+    /// Walk every node within `depth` transitions (bytes) of the roots of the prefix graph, the
+    /// phrase graph, and the fuzzy map, breadth first. Every query starts near the roots, so
+    /// these are the pages that are hottest no matter what's being searched for.
+    HotPrefixes { depth: usize },
+    /// Replay recorded queries (tokenized, as passed to `fuzzy_match`) as prefix matches at the
+    /// set's maximum edit distance, which faults in the pages real traffic is going to want.
+    /// The result cache, if there is one, isn't touched.
+    QueryLog(Vec<Vec<String>>),
+}
+
+/// What a call to `FuzzyPhraseSet::warm_up` did.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct WarmUpReport {
+    /// The bytes of the structures that were read through, for `WarmUpStrategy::Sequential`.
+    pub bytes_read: u64,
+    /// The graph nodes visited, for `WarmUpStrategy::HotPrefixes`.
+    pub nodes_visited: u64,
+    /// The queries replayed, for `WarmUpStrategy::QueryLog`.
+    pub queries_replayed: u64,
+}
+
+impl FuzzyPhraseSet {
+    /// Fault an index's memory-mapped structures into memory before it serves traffic, so that
+    /// the first queries after a deploy don't each stall on page faults. Only the FSTs are
+    /// mapped (everything else is read into memory when the set is loaded), so this is a cheap
+    /// no-op for sets loaded without mmap. See `WarmUpStrategy` for the options.
+    pub fn warm_up(&self, strategy: &WarmUpStrategy) -> Result<WarmUpReport, Box<dyn Error>> {
+        let mut report = WarmUpReport::default();
+        match *strategy {
+            WarmUpStrategy::Sequential => {
+                for fst in self.mapped_fsts() {
+                    report.bytes_read += touch_pages(fst.as_bytes());
+                }
+            },
+            WarmUpStrategy::HotPrefixes { depth } => {
+                for fst in self.mapped_fsts() {
+                    report.nodes_visited += walk_top_levels(fst, depth);
+                }
+            },
+            WarmUpStrategy::QueryLog(ref queries) => {
+                for query in queries.iter().filter(|query| !query.is_empty()) {
+                    self.fuzzy_match_impl(query, self.max_edit_distance, self.max_edit_distance, EndingType::AnyPrefix, None, None)?;
+                    report.queries_replayed += 1;
+                }
+            },
+        }
+        Ok(report)
+    }
+
+    pub(super) fn mapped_fsts(&self) -> Vec<&Fst> {
+        let mut fsts: Vec<&Fst> = vec![self.prefix_set.as_fst(), self.phrase_set.as_fst()];
+        fsts.extend(self.fuzzy_map.as_fst());
+        fsts
+    }
+
+    /// Decode the top `levels` levels of the phrase graph (at most 2) into a table up front, so
+    /// that queries don't each search the root's transitions again, for whole words and
+    /// prefixes alike (see `PhraseSet::preload`). Off by default: two levels take a table of
+    /// 65,536 entries, which only pays off for sets that serve a lot of queries.
+    pub fn preload_phrase_graph(&mut self, levels: usize) {
+        self.phrase_set.preload(levels);
+    }
+}
+
+fn touch_pages(bytes: &[u8]) -> u64 {
+    let mut sum = 0u8;
+    for i in (0..bytes.len()).step_by(PAGE_SIZE) {
+        sum = sum.wrapping_add(bytes[i]);
+    }
+    // keep the reads from being optimized away
+    black_box(sum);
+    bytes.len() as u64
+}
+
+// visit each node within `depth` transitions of the root once (the graphs share suffixes, so
+// the same node can be reached by more than one path), and return how many there were
+fn walk_top_levels(fst: &Fst, depth: usize) -> u64 {
+    let mut seen: FxHashSet<CompiledAddr> = FxHashSet::default();
+    let mut level: Vec<CompiledAddr> = vec![fst.root().addr()];
+    seen.insert(fst.root().addr());
+    for current_depth in 0..=depth {
+        let mut next_level: Vec<CompiledAddr> = Vec::new();
+        for addr in level {
+            let node = fst.node(addr);
+            if current_depth < depth {
+                for transition in node.transitions() {
+                    if seen.insert(transition.addr) {
+                        next_level.push(transition.addr);
+                    }
+                }
+            }
+        }
+        level = next_level;
+    }
+    seen.len() as u64
+}
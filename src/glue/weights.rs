@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use super::records::PhraseMetadata;
+
+// the larger of two weights, ignoring a NaN on the right
+fn larger<T: PartialOrd>(a: T, b: T) -> T {
+    if b > a { b } else { a }
+}
+
+/// The heaviest of a list of weights within any range of positions, in logarithmic time, even
+/// as they change: a binary tree over the weights (stored flat, with the weights themselves as
+/// the second half), in which each node holds the heavier of its two children.
+pub(super) struct MaxTree<T> {
+    nodes: Vec<T>,
+    // anything lighter than this counts as this
+    floor: T,
+}
+
+impl<T: Copy + PartialOrd> MaxTree<T> {
+    pub fn new(weights: &[T], floor: T) -> MaxTree<T> {
+        let len = weights.len();
+        let mut nodes = vec![floor; 2 * len];
+        nodes[len..].copy_from_slice(weights);
+        for i in (1..len).rev() {
+            nodes[i] = larger(nodes[2 * i], nodes[2 * i + 1]);
+        }
+        MaxTree { nodes, floor }
+    }
+
+    pub fn set(&mut self, position: usize, weight: T) {
+        let mut i = position + self.nodes.len() / 2;
+        self.nodes[i] = weight;
+        while i > 1 {
+            i /= 2;
+            self.nodes[i] = larger(self.nodes[2 * i], self.nodes[2 * i + 1]);
+        }
+    }
+
+    /// The heaviest weight at positions `start..end`, or the floor if they're all lighter (or
+    /// there aren't any).
+    pub fn heaviest(&self, start: usize, end: usize) -> T {
+        let len = self.nodes.len() / 2;
+        let (mut lo, mut hi) = (start.min(len) + len, end.min(len) + len);
+        let mut heaviest = self.floor;
+        while lo < hi {
+            if lo % 2 == 1 {
+                heaviest = larger(heaviest, self.nodes[lo]);
+                lo += 1;
+            }
+            if hi % 2 == 1 {
+                hi -= 1;
+                heaviest = larger(heaviest, self.nodes[hi]);
+            }
+            lo /= 2;
+            hi /= 2;
+        }
+        heaviest
+    }
+}
+
+/// The weights phrases were built with (see `PhraseMetadata::weight`), indexed for finding the
+/// heaviest in a range of phrase IDs without visiting each of them. Only the phrases that have a
+/// weight take up space.
+pub(super) struct BuiltWeights {
+    phrase_ids: Vec<u32>,
+    weights: MaxTree<f64>,
+}
+
+impl BuiltWeights {
+    pub fn new(phrase_metadata: &BTreeMap<u32, PhraseMetadata>) -> BuiltWeights {
+        let (phrase_ids, weights): (Vec<u32>, Vec<f64>) = phrase_metadata.iter()
+            .filter_map(|(&phrase_id, metadata)| metadata.weight.map(|weight| (phrase_id, weight)))
+            .unzip();
+        BuiltWeights { phrase_ids, weights: MaxTree::new(&weights, 0.0) }
+    }
+
+    /// The heaviest built weight among phrases `start` to `end` (inclusive), with negative
+    /// weights counting as zero.
+    pub fn heaviest(&self, start: u32, end: u32) -> f64 {
+        let first = self.phrase_ids.partition_point(|&phrase_id| phrase_id < start);
+        let last = self.phrase_ids.partition_point(|&phrase_id| phrase_id <= end);
+        self.weights.heaviest(first, last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_tree_ranges() {
+        let mut tree = MaxTree::new(&[3.0, -1.0, 7.0, 2.0, 5.0], 0.0);
+        assert_eq!(tree.heaviest(0, 5), 7.0);
+        assert_eq!(tree.heaviest(0, 2), 3.0);
+        assert_eq!(tree.heaviest(3, 5), 5.0);
+        assert_eq!(tree.heaviest(1, 2), 0.0);
+        assert_eq!(tree.heaviest(2, 2), 0.0);
+        assert_eq!(tree.heaviest(4, 10), 5.0);
+        tree.set(2, 1.0);
+        assert_eq!(tree.heaviest(0, 5), 5.0);
+        tree.set(1, 9.0);
+        assert_eq!(tree.heaviest(1, 3), 9.0);
+        assert_eq!(MaxTree::new(&[], 0.0).heaviest(0, 1), 0.0);
+
+        let mut metadata: BTreeMap<u32, PhraseMetadata> = BTreeMap::new();
+        metadata.insert(2, PhraseMetadata { weight: Some(4.0), ..Default::default() });
+        metadata.insert(5, PhraseMetadata::default());
+        metadata.insert(9, PhraseMetadata { weight: Some(6.0), ..Default::default() });
+        let built = BuiltWeights::new(&metadata);
+        assert_eq!(built.heaviest(0, 8), 4.0);
+        assert_eq!(built.heaviest(2, 9), 6.0);
+        assert_eq!(built.heaviest(3, 8), 0.0);
+        assert_eq!(built.heaviest(9, 9), 6.0);
+    }
+}
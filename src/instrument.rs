@@ -0,0 +1,178 @@
+//! With the `metrics` feature, queries report what they're doing through the `metrics` crate's
+//! facade, to whatever recorder the application has installed (a Prometheus exporter, say), so
+//! services can see where their time goes without wrapping every call. These are the names of
+//! the metrics reported; without the feature, nothing is.
+
+use glue::QueryLengthPolicy;
+
+/// Histogram of the seconds spent in each stage of a query, labelled `stage`:
+/// `variant_generation` for finding the fuzzy map variants within reach of a query word,
+/// `word_lookup` for finding every query word's possibilities (which includes generating their
+/// variants), and `phrase_traversal` for matching the possibilities against the phrase graph.
+pub const STAGE_SECONDS: &str = "fuzzy_phrase_stage_seconds";
+/// Histogram of the number of results each query returned, labelled `kind`: `match` for
+/// `fuzzy_match`, or `windows` for `fuzzy_match_windows`.
+pub const RESULTS: &str = "fuzzy_phrase_results";
+/// Counter of result cache lookups that found the query's results (see
+/// `FuzzyPhraseSet::enable_result_cache`).
+pub const CACHE_HITS: &str = "fuzzy_phrase_cache_hits_total";
+/// Counter of result cache lookups that didn't.
+pub const CACHE_MISSES: &str = "fuzzy_phrase_cache_misses_total";
+/// Counter of queries over the maximum query length (see `FuzzyPhraseSet::set_max_query_length`),
+/// labelled `policy` with what was done about it: `error`, `truncate_tail`, or `best_window`.
+pub const LIMIT_EXCEEDED: &str = "fuzzy_phrase_limit_exceeded_total";
+
+// times a stage of a query, from `start` until `stop` (or until it's dropped, so that stages cut
+// short by an error still count)
+#[cfg(feature = "metrics")]
+pub(crate) struct StageTimer(&'static str, ::std::time::Instant);
+
+#[cfg(feature = "metrics")]
+impl StageTimer {
+    #[inline(always)]
+    pub(crate) fn start(stage: &'static str) -> StageTimer {
+        StageTimer(stage, ::std::time::Instant::now())
+    }
+
+    #[inline(always)]
+    pub(crate) fn stop(self) {}
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        ::metrics::histogram!(STAGE_SECONDS, "stage" => self.0).record(self.1.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct StageTimer;
+
+#[cfg(not(feature = "metrics"))]
+impl StageTimer {
+    #[inline(always)]
+    pub(crate) fn start(_stage: &'static str) -> StageTimer {
+        StageTimer
+    }
+
+    #[inline(always)]
+    pub(crate) fn stop(self) {}
+}
+
+#[inline(always)]
+pub(crate) fn record_results(_kind: &'static str, _count: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(RESULTS, "kind" => _kind).record(_count as f64);
+}
+
+#[inline(always)]
+pub(crate) fn record_cache_lookup(_hit: bool) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(if _hit { CACHE_HITS } else { CACHE_MISSES }).increment(1);
+}
+
+#[inline(always)]
+pub(crate) fn record_limit_exceeded(_policy: QueryLengthPolicy) {
+    #[cfg(feature = "metrics")]
+    {
+        let policy = match _policy {
+            QueryLengthPolicy::Error => "error",
+            QueryLengthPolicy::TruncateTail => "truncate_tail",
+            QueryLengthPolicy::BestWindow => "best_window",
+        };
+        ::metrics::counter!(LIMIT_EXCEEDED, "policy" => policy).increment(1);
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    extern crate tempfile;
+
+    use std::sync::{Arc, Mutex};
+
+    use metrics::{Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+
+    use super::*;
+    use glue::{FuzzyPhraseSet, FuzzyPhraseSetBuilder, EndingType, ResultCacheConfig};
+
+    // every value reported, as the metric's name and labels, and the value
+    type Events = Arc<Mutex<Vec<(String, f64)>>>;
+
+    struct Event(String, Events);
+
+    impl CounterFn for Event {
+        fn increment(&self, value: u64) {
+            self.1.lock().unwrap().push((self.0.clone(), value as f64));
+        }
+
+        fn absolute(&self, _value: u64) {}
+    }
+
+    impl HistogramFn for Event {
+        fn record(&self, value: f64) {
+            self.1.lock().unwrap().push((self.0.clone(), value));
+        }
+    }
+
+    struct TestRecorder(Events);
+
+    impl TestRecorder {
+        fn event(&self, key: &Key) -> Arc<Event> {
+            let labels = key.labels().map(|label| format!("{}={}", label.key(), label.value())).collect::<Vec<_>>();
+            Arc::new(Event(format!("{}{{{}}}", key.name(), labels.join(",")), self.0.clone()))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+        fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+        fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata) -> Counter {
+            Counter::from_arc(self.event(key))
+        }
+
+        fn register_gauge(&self, _key: &Key, _metadata: &Metadata) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _metadata: &Metadata) -> Histogram {
+            Histogram::from_arc(self.event(key))
+        }
+    }
+
+    #[test]
+    fn query_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(dir.path(), &["100 main street", "200 main street", "300 mlk blvd"]).unwrap();
+        let mut set = FuzzyPhraseSet::from_path(dir.path()).unwrap();
+        set.enable_result_cache(ResultCacheConfig { capacity: 16, ttl: None });
+        set.set_max_query_length(Some(3), QueryLengthPolicy::TruncateTail);
+
+        let events: Events = Arc::new(Mutex::new(Vec::new()));
+        let recorder = TestRecorder(events.clone());
+        ::metrics::with_local_recorder(&recorder, || {
+            set.fuzzy_match(&["100", "mian", "street"], 1, 1, EndingType::NonPrefix).unwrap();
+            set.fuzzy_match(&["100", "mian", "street"], 1, 1, EndingType::NonPrefix).unwrap();
+            set.fuzzy_match_windows(&["100", "main", "street", "springfield"], 1, 1, EndingType::NonPrefix).unwrap();
+        });
+
+        let events = events.lock().unwrap();
+        let names = events.iter().map(|event| event.0.as_str()).collect::<Vec<_>>();
+        let count = |name: &str| names.iter().filter(|&&n| n == name).count();
+        // the second query is answered from the cache, so only the first and the windowed one
+        // reach the index
+        assert_eq!(count("fuzzy_phrase_cache_misses_total{}"), 2);
+        assert_eq!(count("fuzzy_phrase_cache_hits_total{}"), 1);
+        assert_eq!(count("fuzzy_phrase_stage_seconds{stage=word_lookup}"), 2);
+        assert_eq!(count("fuzzy_phrase_stage_seconds{stage=phrase_traversal}"), 2);
+        assert!(count("fuzzy_phrase_stage_seconds{stage=variant_generation}") > 0);
+        assert_eq!(count("fuzzy_phrase_limit_exceeded_total{policy=truncate_tail}"), 1);
+        let results = events.iter().filter(|event| event.0.starts_with("fuzzy_phrase_results")).cloned().collect::<Vec<_>>();
+        assert_eq!(results, vec![
+            ("fuzzy_phrase_results{kind=match}".to_string(), 1.0),
+            ("fuzzy_phrase_results{kind=match}".to_string(), 1.0),
+            ("fuzzy_phrase_results{kind=windows}".to_string(), 1.0),
+        ]);
+    }
+}
@@ -1,9 +1,22 @@
 extern crate fst;
+#[cfg(feature = "fst04")]
+extern crate fst_04;
 extern crate itertools;
+#[cfg(feature = "mmap")]
 extern crate memmap;
 extern crate byteorder;
 extern crate regex;
 extern crate rustc_hash;
+extern crate smallvec;
+extern crate xxhash_rust;
+#[cfg(feature = "roaring")]
+extern crate roaring;
+#[cfg(feature = "compression")]
+extern crate zstd;
+#[cfg(feature = "language-detection")]
+extern crate whatlang;
+#[cfg(feature = "metrics")]
+extern crate metrics;
 
 extern crate serde;
 #[macro_use]
@@ -14,9 +27,14 @@ extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod fst_compat;
+
 mod prefix;
 pub use prefix::PrefixSet;
 pub use prefix::PrefixSetBuilder;
+pub use prefix::PrefixSetDiff;
+pub use prefix::VocabularyMerge;
+pub use prefix::FuzzyPrefixMatch;
 
 pub mod fuzzy;
 pub use fuzzy::FuzzyMap;
@@ -26,6 +44,26 @@ pub mod phrase;
 
 pub use phrase::PhraseSet;
 pub use phrase::PhraseSetBuilder;
-pub use phrase::query::QueryWord;
+pub use phrase::PhraseMatchKind;
+pub use phrase::layered::LayeredPhraseSet;
+pub use phrase::query::{QueryWord, VariantSource};
+pub use phrase::filter::PhraseIdFilter;
+pub use phrase::matcher::PhraseMatcher;
+pub use phrase::louds::{LoudsTrie, LoudsTrieBuilder};
+pub use phrase::word_trie::{WordTrie, WordTrieBuilder};
+pub use phrase::classes::{TokenClass, TokenClassSet, TokenConstraints, TokenPosition};
 
 pub mod glue;
+
+pub mod progress;
+pub use progress::{BuildPhase, BuildProgress, ProgressReporter};
+
+pub mod instrument;
+
+pub mod prelude;
+
+#[cfg(feature = "compression")]
+pub mod compress;
+
+#[cfg(feature = "service")]
+pub mod service;
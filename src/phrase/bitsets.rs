@@ -0,0 +1,56 @@
+use std::collections::BTreeSet;
+
+use super::PhraseSet;
+
+/// A compact set of word IDs, one bit per ID up to the largest one in the set.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WordBitset {
+    blocks: Vec<u64>,
+}
+
+impl WordBitset {
+    pub fn from_ids(ids: &BTreeSet<u32>) -> WordBitset {
+        let mut blocks: Vec<u64> = match ids.iter().next_back() {
+            Some(&max_id) => vec![0; max_id as usize / 64 + 1],
+            None => Vec::new(),
+        };
+        for &id in ids {
+            blocks[id as usize / 64] |= 1 << (id % 64);
+        }
+        WordBitset { blocks }
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, id: u32) -> bool {
+        self.blocks.get(id as usize / 64).is_some_and(|block| block & (1 << (id % 64)) != 0)
+    }
+
+    /// Whether any ID in the inclusive range `id_range` is in the set.
+    pub fn contains_range(&self, id_range: (u32, u32)) -> bool {
+        let (start, end) = (id_range.0 as usize, id_range.1 as usize);
+        if start > end || start / 64 >= self.blocks.len() {
+            return false;
+        }
+        let last_block = (end / 64).min(self.blocks.len() - 1);
+        (start / 64..=last_block).any(|i| {
+            let mut mask = !0u64;
+            if i == start / 64 {
+                mask &= !0u64 << (start % 64);
+            }
+            if i == end / 64 {
+                mask &= !0u64 >> (63 - end % 64);
+            }
+            self.blocks[i] & mask != 0
+        })
+    }
+}
+
+impl PhraseSet {
+    /// The IDs of the words that begin at least one phrase, read off the top of the graph.
+    pub fn first_words(&self) -> WordBitset {
+        match self.collect_word_depths(1).first() {
+            Some(ids) => WordBitset::from_ids(ids),
+            None => WordBitset::default(),
+        }
+    }
+}
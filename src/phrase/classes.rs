@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use super::query::QueryWord;
+
+/// A coarse category of word, assigned at build time, that queries can require in particular
+/// positions (see `TokenConstraints`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum TokenClass {
+    Number,
+    Direction,
+    StreetType,
+    Name,
+}
+
+impl TokenClass {
+    #[inline(always)]
+    fn bit(self) -> u8 {
+        match self {
+            TokenClass::Number => 1,
+            TokenClass::Direction => 1 << 1,
+            TokenClass::StreetType => 1 << 2,
+            TokenClass::Name => 1 << 3,
+        }
+    }
+}
+
+/// A set of `TokenClass`es, stored as a bitmask.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub struct TokenClassSet(u8);
+
+impl TokenClassSet {
+    pub fn empty() -> TokenClassSet {
+        TokenClassSet(0)
+    }
+
+    pub fn of(classes: &[TokenClass]) -> TokenClassSet {
+        let mut set = TokenClassSet::empty();
+        for class in classes {
+            set.insert(*class);
+        }
+        set
+    }
+
+    pub fn from_bits(bits: u8) -> TokenClassSet {
+        TokenClassSet(bits)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn insert(&mut self, class: TokenClass) {
+        self.0 |= class.bit();
+    }
+
+    pub fn contains(self, class: TokenClass) -> bool {
+        self.0 & class.bit() != 0
+    }
+
+    pub fn intersects(self, other: TokenClassSet) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<TokenClass> for TokenClassSet {
+    fn from(class: TokenClass) -> TokenClassSet {
+        TokenClassSet(class.bit())
+    }
+}
+
+/// The classes of each word ID. Words that were never tagged have no classes.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct WordClasses(BTreeMap<u32, TokenClassSet>);
+
+impl WordClasses {
+    pub fn new() -> WordClasses {
+        WordClasses::default()
+    }
+
+    pub fn insert(&mut self, word_id: u32, class: TokenClass) {
+        self.0.entry(word_id).or_default().insert(class);
+    }
+
+    pub fn get(&self, word_id: u32) -> TokenClassSet {
+        self.0.get(&word_id).cloned().unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(u32, TokenClassSet)> + '_ {
+        self.0.iter().map(|(id, classes)| (*id, *classes))
+    }
+}
+
+/// A token position within a match, counted from the first token the match covers (for windowed
+/// matches, that's the start of the window rather than the start of the query).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TokenPosition {
+    Index(usize),
+    /// The last query token the match covers.
+    Last,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct TokenConstraint {
+    position: TokenPosition,
+    classes: TokenClassSet,
+    allow_prefix: bool,
+}
+
+/// Requirements on the classes of the words a query can match in particular positions; e.g.,
+/// that the last token of a window has to be a street type. A word satisfies a requirement if it
+/// has any of the required classes. The matchers check constraints as they explore the phrase
+/// graph, so words that can't satisfy them prune whole branches of the search.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct TokenConstraints(Vec<TokenConstraint>);
+
+impl TokenConstraints {
+    pub fn new() -> TokenConstraints {
+        TokenConstraints::default()
+    }
+
+    /// Require the word at `position` to have one of `classes`.
+    pub fn require<C: Into<TokenClassSet>>(mut self, position: TokenPosition, classes: C) -> TokenConstraints {
+        self.0.push(TokenConstraint { position, classes: classes.into(), allow_prefix: false });
+        self
+    }
+
+    /// Same as `require`, but also accept a partial word (prefix) at `position`, which can't be
+    /// classified until it's been completed.
+    pub fn require_or_prefix<C: Into<TokenClassSet>>(mut self, position: TokenPosition, classes: C) -> TokenConstraints {
+        self.0.push(TokenConstraint { position, classes: classes.into(), allow_prefix: true });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn has_last(&self) -> bool {
+        self.0.iter().any(|constraint| constraint.position == TokenPosition::Last)
+    }
+
+    /// Check `word` against the constraints on `position`; if `is_last` is set, the constraints on
+    /// `TokenPosition::Last` apply too.
+    pub fn allows(&self, word: &QueryWord, position: usize, is_last: bool, word_classes: &WordClasses) -> bool {
+        self.0.iter().all(|constraint| {
+            let applies = match constraint.position {
+                TokenPosition::Index(i) => i == position,
+                TokenPosition::Last => is_last,
+            };
+            !applies || match *word {
+                QueryWord::Full { id, .. } => word_classes.get(id).intersects(constraint.classes),
+                QueryWord::Prefix { .. } => constraint.allow_prefix,
+            }
+        })
+    }
+
+    /// Drop the words that can't satisfy the constraints on their positions. If `last_is_known`
+    /// is set, the last slot is taken to be the last token of any match (as it is for
+    /// non-windowed matching); otherwise, constraints on `TokenPosition::Last` are left to be
+    /// checked during matching.
+    pub(crate) fn prune(&self, word_possibilities: &[Vec<QueryWord>], last_is_known: bool, word_classes: &WordClasses) -> Vec<Vec<QueryWord>> {
+        word_possibilities.iter().enumerate().map(|(position, words)| {
+            let is_last = last_is_known && position == word_possibilities.len() - 1;
+            words.iter().filter(|word| self.allows(word, position, is_last, word_classes)).cloned().collect()
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_class_set() {
+        let mut set = TokenClassSet::of(&[TokenClass::Number, TokenClass::Name]);
+        assert!(set.contains(TokenClass::Number));
+        assert!(!set.contains(TokenClass::StreetType));
+        set.insert(TokenClass::StreetType);
+        assert!(set.contains(TokenClass::StreetType));
+        assert!(set.intersects(TokenClass::StreetType.into()));
+        assert!(!set.intersects(TokenClass::Direction.into()));
+        assert_eq!(TokenClassSet::from_bits(set.bits()), set);
+        assert!(TokenClassSet::empty().is_empty());
+    }
+
+    #[test]
+    fn token_constraints() {
+        let mut word_classes = WordClasses::new();
+        word_classes.insert(0, TokenClass::Number);
+        word_classes.insert(1, TokenClass::StreetType);
+        word_classes.insert(2, TokenClass::Name);
+
+        let constraints = TokenConstraints::new()
+            .require(TokenPosition::Index(0), TokenClass::Number)
+            .require_or_prefix(TokenPosition::Last, TokenClass::StreetType);
+        let number = QueryWord::new_full(0, 0);
+        let street_type = QueryWord::new_full(1, 0);
+        let name = QueryWord::new_full(2, 0);
+        let prefix = QueryWord::new_prefix((0, 2));
+
+        assert!(constraints.allows(&number, 0, false, &word_classes));
+        assert!(!constraints.allows(&name, 0, false, &word_classes));
+        assert!(constraints.allows(&name, 1, false, &word_classes));
+        assert!(!constraints.allows(&name, 1, true, &word_classes));
+        assert!(constraints.allows(&street_type, 1, true, &word_classes));
+        assert!(constraints.allows(&prefix, 2, true, &word_classes));
+        assert!(!TokenConstraints::new().require(TokenPosition::Last, TokenClass::StreetType).allows(&prefix, 2, true, &word_classes));
+
+        let possibilities = vec![vec![number, name], vec![name], vec![name, street_type]];
+        assert_eq!(constraints.prune(&possibilities, true, &word_classes), vec![vec![number], vec![name], vec![street_type]]);
+        assert_eq!(constraints.prune(&possibilities, false, &word_classes), vec![vec![number], vec![name], vec![name, street_type]]);
+    }
+}
@@ -0,0 +1,194 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use fst_compat::raw::{CompiledAddr, Output};
+
+use super::{PhraseSet, Combination, materialize_path};
+use super::step::WordStep;
+use super::query::QueryWord;
+use super::util::PhraseSetError;
+
+// a word slot being worked through: where the words before it led, and the next of its
+// possibilities to try
+struct Frame {
+    addr: CompiledAddr,
+    output: Output,
+    budget_remaining: u8,
+    next: usize,
+}
+
+/// The future returned by `PhraseSet::match_combinations_async`.
+pub struct MatchCombinations<'a> {
+    set: &'a PhraseSet,
+    possibilities: &'a [Vec<QueryWord>],
+    yield_every: usize,
+    // one frame per slot being matched, and the possibility chosen for each slot before the last
+    stack: Vec<Frame>,
+    path: Vec<u32>,
+    out: Vec<Combination>,
+}
+
+impl PhraseSet {
+    /// Same as `match_combinations`, but as a future that hands control back to the executor
+    /// (waking itself up to be polled again) after every `yield_every` steps it takes through the
+    /// phrase graph (each possibility it tries, whether the graph has it or not, and each slot it
+    /// backs out of), so that matching a long or very ambiguous query doesn't tie up an async
+    /// runtime's worker thread for its whole duration. It finds the same combinations in the same
+    /// order. The graph is walked with an explicit stack rather than by recursion, so the match
+    /// can be suspended anywhere.
+    pub fn match_combinations_async<'a>(&'a self, word_possibilities: &'a [Vec<QueryWord>], max_phrase_dist: u8, yield_every: usize) -> MatchCombinations<'a> {
+        let mut stack = Vec::with_capacity(word_possibilities.len());
+        if !word_possibilities.is_empty() {
+            stack.push(Frame { addr: self.0.root().addr(), output: Output::zero(), budget_remaining: max_phrase_dist, next: 0 });
+        }
+        MatchCombinations {
+            set: self,
+            possibilities: word_possibilities,
+            yield_every: yield_every.max(1),
+            stack,
+            path: Vec::with_capacity(word_possibilities.len()),
+            out: Vec::new(),
+        }
+    }
+}
+
+impl<'a> MatchCombinations<'a> {
+    // advance the search by one possibility, or out of a finished slot; false once there's
+    // nothing left to try
+    fn step(&mut self) -> Result<bool, PhraseSetError> {
+        let set = self.set;
+        let position = match self.stack.len() {
+            0 => return Ok(false),
+            len => len - 1,
+        };
+        let is_last = position == self.possibilities.len() - 1;
+        let (addr, output, budget_remaining, idx) = {
+            let frame = self.stack.last_mut().expect("the stack isn't empty");
+            let idx = frame.next;
+            frame.next += 1;
+            (frame.addr, frame.output, frame.budget_remaining, idx)
+        };
+
+        // this slot is done, so go back to the one before it
+        if idx >= self.possibilities[position].len() {
+            self.stack.pop();
+            self.path.pop();
+            return Ok(true);
+        }
+
+        let (key, edit_distance) = match self.possibilities[position][idx] {
+            QueryWord::Full { key, edit_distance, .. } => (key, edit_distance),
+            _ => return Err(PhraseSetError::new(
+                "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
+            )),
+        };
+        let step = set.step_word(&set.0.node(addr), output, &key, edit_distance, budget_remaining, None);
+        if let WordStep::OverBudget = step {
+            // possibilities are sorted by edit distance, so none of the rest will fit either
+            self.stack.last_mut().expect("the stack isn't empty").next = self.possibilities[position].len();
+            return Ok(true);
+        }
+
+        match step {
+            WordStep::Found(search_node, output, budget) if !is_last => {
+                self.path.push(idx as u32);
+                self.stack.push(Frame { addr: search_node.addr(), output, budget_remaining: budget, next: 0 });
+            },
+            WordStep::Found(search_node, output, _) if search_node.is_final() => {
+                let final_output = output.cat(search_node.final_output());
+                self.path.push(idx as u32);
+                self.out.push(Combination { phrase: materialize_path(self.possibilities, &self.path), output_range: (final_output, final_output) });
+                self.path.pop();
+            },
+            _ => {},
+        }
+        Ok(true)
+    }
+}
+
+impl<'a> Future for MatchCombinations<'a> {
+    type Output = Result<Vec<Combination>, PhraseSetError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        for _ in 0..this.yield_every {
+            match this.step() {
+                Ok(true) => {},
+                Ok(false) => return Poll::Ready(Ok(::std::mem::take(&mut this.out))),
+                Err(e) => {
+                    this.stack.clear();
+                    return Poll::Ready(Err(e));
+                },
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::task::{RawWaker, RawWakerVTable, Waker};
+
+    use super::*;
+    use phrase::PhraseSetBuilder;
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+
+    // poll `future` to completion, counting how many times it yielded along the way
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = Box::pin(future);
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut yields = 0;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn match_combinations_async_matches_sync() {
+        let mut build = PhraseSetBuilder::memory();
+        for phrase in &[[1u32, 2, 3], [1, 2, 4], [1, 5, 3], [6, 2, 3]] {
+            build.insert(phrase).unwrap();
+        }
+        let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+        let word_possibilities = vec![
+            vec![QueryWord::new_full(1, 0), QueryWord::new_full(6, 1)],
+            vec![QueryWord::new_full(2, 0), QueryWord::new_full(5, 1)],
+            vec![QueryWord::new_full(3, 0), QueryWord::new_full(4, 1)],
+        ];
+        for max_phrase_dist in 0..3 {
+            let expected = set.match_combinations(&word_possibilities, max_phrase_dist).unwrap();
+            let (found, yields) = block_on(set.match_combinations_async(&word_possibilities, max_phrase_dist, 1));
+            assert_eq!(found.unwrap(), expected);
+            assert!(yields > 0);
+            let (found, yields) = block_on(set.match_combinations_async(&word_possibilities, max_phrase_dist, 1000));
+            assert_eq!(found.unwrap(), expected);
+            assert_eq!(yields, 0);
+        }
+
+        // steps that find nothing count towards yielding too
+        let over_budget = vec![vec![QueryWord::new_full(1, 1), QueryWord::new_full(6, 1)]];
+        let (found, yields) = block_on(set.match_combinations_async(&over_budget, 0, 1));
+        assert_eq!(found.unwrap(), vec![]);
+        assert!(yields > 0);
+
+        let prefix = vec![vec![QueryWord::new_prefix((1, 2))]];
+        assert!(block_on(set.match_combinations_async(&prefix, 1, 1)).0.is_err());
+        assert_eq!(block_on(set.match_combinations_async(&[], 1, 1)).0.unwrap(), vec![]);
+    }
+}
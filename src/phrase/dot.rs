@@ -0,0 +1,88 @@
+use std::fmt::Write;
+
+use fst_compat::raw::{Node, Output};
+
+use super::{PhraseSet, PhraseSetMatchState};
+use super::util::{three_byte_decode, three_byte_encode};
+
+impl PhraseSet {
+    /// Render the part of the phrase graph under the phrase prefix `prefix` (as word IDs), down
+    /// to `depth` words further, as a Graphviz DOT graph, for looking at how phrases are laid
+    /// out and which IDs a prefix covers. `id_to_string` gives each word's label. The graph is
+    /// drawn as a tree of words rather than of the bytes of their keys, but each edge's label
+    /// also shows the key's three bytes, and each node the range of phrase IDs under it (if the
+    /// set stores IDs); final nodes, where a phrase ends, are double circles. Nodes the graph
+    /// shares between different paths are drawn once per path, since the IDs under them differ.
+    /// Returns `None` if no phrase starts with `prefix`.
+    pub fn to_dot<'a, F: Fn(u32) -> &'a str>(&self, prefix: &[u32], depth: usize, id_to_string: F) -> Option<String> {
+        let fst = &self.0;
+        let mut node = fst.root();
+        let mut output = Output::zero();
+        for &id in prefix {
+            let (next, incr_output) = self.follow_key(&node, &three_byte_encode(id).ok()?)?;
+            node = next;
+            output = output.cat(incr_output);
+        }
+
+        let mut dot = DotWriter { out: String::from("digraph phrases {\n    node [shape=circle];\n"), next_id: 0 };
+        let root_label = prefix.iter().map(|&id| id_to_string(id)).collect::<Vec<&str>>().join(" ");
+        let root = dot.node(&self.node_label(&root_label, node, output), node.is_final());
+        self.dot_recurse(&mut dot, root, node, output, depth, &id_to_string);
+        dot.out.push_str("}\n");
+        Some(dot.out)
+    }
+
+    fn dot_recurse<'a, F: Fn(u32) -> &'a str>(&self, dot: &mut DotWriter, parent: usize, node: Node, output: Output, depth: usize, id_to_string: &F) {
+        if depth == 0 {
+            return;
+        }
+        let fst = &self.0;
+        // every word is three transitions long
+        for t0 in node.transitions() {
+            for t1 in fst.node(t0.addr).transitions() {
+                for t2 in fst.node(t1.addr).transitions() {
+                    let key = [t0.inp, t1.inp, t2.inp];
+                    let id = three_byte_decode(&key);
+                    let next = fst.node(t2.addr);
+                    let next_output = output.cat(t0.out).cat(t1.out).cat(t2.out);
+                    let child = dot.node(&self.node_label("", next, next_output), next.is_final());
+                    dot.edge(parent, child, &format!("{} ({})\n{:02x} {:02x} {:02x}", id_to_string(id), id, key[0], key[1], key[2]));
+                    self.dot_recurse(dot, child, next, next_output, depth - 1, id_to_string);
+                }
+            }
+        }
+    }
+
+    // the phrase IDs reachable from a node, under whatever `words` led to it
+    fn node_label(&self, words: &str, node: Node, output: Output) -> String {
+        if !self.has_ids() {
+            return words.to_string();
+        }
+        let (start, end) = (PhraseSetMatchState::EndsInFullWord { node, output }).prefix_range(&self.0);
+        let range = if start == end { format!("{}", start.value()) } else { format!("{}..{}", start.value(), end.value()) };
+        if words.is_empty() { range } else { format!("{}\n{}", words, range) }
+    }
+}
+
+struct DotWriter {
+    out: String,
+    next_id: usize,
+}
+
+impl DotWriter {
+    fn node(&mut self, label: &str, is_final: bool) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let shape = if is_final { ", shape=doublecircle" } else { "" };
+        let _ = writeln!(self.out, "    n{} [label=\"{}\"{}];", id, escape(label), shape);
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str) {
+        let _ = writeln!(self.out, "    n{} -> n{} [label=\"{}\"];", from, to, escape(label));
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
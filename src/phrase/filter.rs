@@ -0,0 +1,70 @@
+use std::collections::BTreeSet;
+
+#[cfg(feature = "roaring")]
+use roaring::RoaringBitmap;
+
+/// A restriction on which phrase IDs a matcher is allowed to return.
+///
+/// Matchers deal in ranges of phrase IDs rather than individual IDs (a prefix match covers every
+/// phrase reachable from the matched position), so filters are asked about inclusive ranges: a
+/// range is allowed if at least one phrase ID within it is allowed. Because phrase IDs are
+/// assigned in lexicographic order, the range of every phrase reachable from a given point in the
+/// phrase graph is known up front, which lets the matchers skip whole subgraphs that contain no
+/// allowed phrases.
+pub trait PhraseIdFilter {
+    fn allows_range(&self, range: (u32, u32)) -> bool;
+}
+
+impl<F: Fn((u32, u32)) -> bool> PhraseIdFilter for F {
+    #[inline(always)]
+    fn allows_range(&self, range: (u32, u32)) -> bool {
+        self(range)
+    }
+}
+
+impl PhraseIdFilter for BTreeSet<u32> {
+    #[inline(always)]
+    fn allows_range(&self, range: (u32, u32)) -> bool {
+        self.range(range.0..=range.1).next().is_some()
+    }
+}
+
+#[cfg(feature = "roaring")]
+impl PhraseIdFilter for RoaringBitmap {
+    #[inline(always)]
+    fn allows_range(&self, range: (u32, u32)) -> bool {
+        self.range_cardinality(range.0..=range.1) > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btreeset_filter() {
+        let allowed: BTreeSet<u32> = [3u32, 10, 11].iter().cloned().collect();
+        assert!(allowed.allows_range((3, 3)));
+        assert!(allowed.allows_range((0, 3)));
+        assert!(allowed.allows_range((4, 10)));
+        assert!(!allowed.allows_range((4, 9)));
+        assert!(!allowed.allows_range((12, 100)));
+    }
+
+    #[test]
+    fn closure_filter() {
+        let filter = |(start, end): (u32, u32)| start <= 5 && end >= 5;
+        assert!(filter.allows_range((5, 5)));
+        assert!(filter.allows_range((0, 10)));
+        assert!(!filter.allows_range((6, 10)));
+    }
+
+    #[cfg(feature = "roaring")]
+    #[test]
+    fn roaring_filter() {
+        let allowed: RoaringBitmap = [3u32, 10, 11].iter().cloned().collect();
+        assert!(allowed.allows_range((3, 3)));
+        assert!(allowed.allows_range((4, 10)));
+        assert!(!allowed.allows_range((4, 9)));
+    }
+}
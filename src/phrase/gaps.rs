@@ -0,0 +1,145 @@
+use fst_compat::raw::{Node, Output};
+
+use super::{PhraseSet, CombinationWindow};
+use super::step::WordStep;
+use super::query::QueryWord;
+use super::util::PhraseSetError;
+
+/// A window found by `PhraseSet::match_combinations_as_windows_with_gaps`, and the query slots it
+/// passed over to get there.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GappedWindow {
+    /// The matched words, without the skipped ones.
+    pub window: CombinationWindow,
+    /// The slots skipped, in ascending order, counting from the start of the window.
+    pub skipped: Vec<usize>,
+}
+
+// the possibility picked from each slot so far (`None` for skipped ones), so the next slot is the
+// one at its length, along with the limits of the search
+struct GapPath {
+    chosen: Vec<Option<usize>>,
+    skips_remaining: u8,
+    skip_penalty: u8,
+    ends_in_prefix: bool,
+}
+
+impl GapPath {
+    fn to_result(&self, possibilities: &[Vec<QueryWord>], output_range: (Output, Output), ends_in_prefix: bool) -> GappedWindow {
+        GappedWindow {
+            window: CombinationWindow {
+                phrase: self.chosen.iter().enumerate().filter_map(|(slot, idx)| idx.map(|idx| possibilities[slot][idx])).collect(),
+                output_range,
+                ends_in_prefix,
+            },
+            skipped: self.chosen.iter().enumerate().filter(|&(_, idx)| idx.is_none()).map(|(slot, _)| slot).collect(),
+        }
+    }
+}
+
+impl PhraseSet {
+    /// Same as `match_combinations_as_windows`, but a window can skip over up to `max_skips` of
+    /// the query's slots, for when something that isn't part of any phrase has been interjected
+    /// into one ("59 old apt 4 new milford rd" for "59 old new milford rd"). Each skip costs
+    /// `skip_penalty` from the same budget as the words' edit distances, `max_phrase_dist`, and
+    /// skipped slots are reported with each window. Skips are only ever taken between matched
+    /// words: a window never starts or ends with one, since it would be the same as the window
+    /// starting later or ending sooner. Skipped slots can be empty (a word that matched nothing
+    /// at all), so unlike the other matchers, this accepts those. With `max_skips` at zero, this
+    /// finds the same windows as `match_combinations_as_windows`.
+    pub fn match_combinations_as_windows_with_gaps(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        ends_in_prefix: bool,
+        max_skips: u8,
+        skip_penalty: u8
+    ) -> Result<Vec<GappedWindow>, PhraseSetError> {
+        let mut out: Vec<GappedWindow> = Vec::new();
+        if word_possibilities.is_empty() {
+            return Ok(out);
+        }
+        let mut path = GapPath { chosen: Vec::with_capacity(word_possibilities.len()), skips_remaining: max_skips, skip_penalty, ends_in_prefix };
+        self.gap_recurse(word_possibilities, &self.0.root(), Output::zero(), max_phrase_dist, &mut path, &mut out)?;
+        Ok(out)
+    }
+
+    fn gap_recurse(
+        &self,
+        possibilities: &[Vec<QueryWord>],
+        node: &Node,
+        output_so_far: Output,
+        budget_remaining: u8,
+        path: &mut GapPath,
+        out: &mut Vec<GappedWindow>,
+    ) -> Result<(), PhraseSetError> {
+        let position = path.chosen.len();
+        let is_last = position == possibilities.len() - 1;
+        // skipped slots don't count toward the position of a word within the phrase
+        let depth = path.chosen.iter().filter(|idx| idx.is_some()).count();
+
+        for (idx, word) in possibilities[position].iter().enumerate() {
+            match *word {
+                QueryWord::Full { key, edit_distance, .. } => {
+                    let (search_node, output, budget) = match self.step_word(node, output_so_far, &key, edit_distance, budget_remaining, None) {
+                        WordStep::OverBudget => break,
+                        WordStep::Missing => continue,
+                        WordStep::Found(search_node, output, budget) => (search_node, output, budget),
+                    };
+                    path.chosen.push(Some(idx));
+                    if !is_last {
+                        // a complete phrase on the way to something longer
+                        if search_node.is_final() {
+                            let final_output = output.cat(search_node.final_output());
+                            out.push(path.to_result(possibilities, (final_output, final_output), false));
+                        }
+                        self.gap_recurse(possibilities, &search_node, output, budget, path, out)?;
+                    } else if path.ends_in_prefix {
+                        let range = self.reachable_range(search_node, output);
+                        out.push(path.to_result(possibilities, range, true));
+                    } else if search_node.is_final() {
+                        let final_output = output.cat(search_node.final_output());
+                        out.push(path.to_result(possibilities, (final_output, final_output), false));
+                    }
+                    path.chosen.pop();
+                },
+                QueryWord::Prefix { key_range, edit_distance, .. } => {
+                    if !path.ends_in_prefix {
+                        return Err(PhraseSetError::new(
+                            "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
+                        ))
+                    }
+                    if position + 2 < possibilities.len() {
+                        return Err(PhraseSetError::new(
+                            "Only the last two slots of a prefix query may have a QueryWord::Prefix"
+                        ))
+                    } else if edit_distance > budget_remaining {
+                        continue
+                    } else if !is_last {
+                        // a prefix followed by one more word at the end of the query, as in
+                        // `match_combinations_as_windows`
+                        for (search_node, incr_output) in self.words_in_range(node, key_range) {
+                            path.chosen.push(Some(idx));
+                            self.gap_recurse(possibilities, &search_node, output_so_far.cat(incr_output), budget_remaining - edit_distance, path, out)?;
+                            path.chosen.pop();
+                        }
+                    } else if let Some(range) = self.step_final_prefix(node, output_so_far, key_range) {
+                        path.chosen.push(Some(idx));
+                        out.push(path.to_result(possibilities, range, true));
+                        path.chosen.pop();
+                    }
+                },
+            }
+        }
+
+        // or pass over this slot, if there's a word before it and something after it to match
+        if depth > 0 && !is_last && path.skips_remaining > 0 && path.skip_penalty <= budget_remaining {
+            path.chosen.push(None);
+            path.skips_remaining -= 1;
+            self.gap_recurse(possibilities, node, output_so_far, budget_remaining - path.skip_penalty, path, out)?;
+            path.skips_remaining += 1;
+            path.chosen.pop();
+        }
+        Ok(())
+    }
+}
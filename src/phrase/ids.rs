@@ -0,0 +1,154 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use fst_compat::raw::Output;
+
+use super::{PhraseSet, PhraseSetLookupResult, Combination, CombinationWindow, IndexedCombination};
+
+/// The ID of a phrase in a `PhraseSet`. IDs are assigned in insertion order, which is also
+/// lexicographic order by word ID.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct PhraseId(pub u32);
+
+impl PhraseId {
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    // `PhraseSetBuilder` won't number more phrases than this can hold
+    #[inline(always)]
+    pub(crate) fn from_output(output: Output) -> PhraseId {
+        PhraseId(u32::try_from(output.value()).expect("phrase IDs fit in 32 bits"))
+    }
+}
+
+impl From<u32> for PhraseId {
+    fn from(id: u32) -> PhraseId {
+        PhraseId(id)
+    }
+}
+
+impl fmt::Display for PhraseId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An inclusive range of phrase IDs, like the one covering every phrase reachable from some
+/// point in the phrase graph.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct PhraseIdRange {
+    pub start: PhraseId,
+    pub end: PhraseId,
+}
+
+impl PhraseIdRange {
+    pub fn new(start: u32, end: u32) -> PhraseIdRange {
+        PhraseIdRange { start: PhraseId(start), end: PhraseId(end) }
+    }
+
+    pub fn contains(&self, id: PhraseId) -> bool {
+        self.start <= id && id <= self.end
+    }
+
+    /// How many phrase IDs the range covers.
+    pub fn len(&self) -> usize {
+        if self.end < self.start {
+            0
+        } else {
+            (self.end.0 - self.start.0) as usize + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The range as a pair of bare IDs, the way `PhraseIdFilter`s and the glue layer's results
+    /// take them.
+    pub fn as_tuple(&self) -> (u32, u32) {
+        (self.start.0, self.end.0)
+    }
+
+    #[inline(always)]
+    pub(crate) fn from_outputs(range: (Output, Output)) -> PhraseIdRange {
+        PhraseIdRange { start: PhraseId::from_output(range.0), end: PhraseId::from_output(range.1) }
+    }
+}
+
+impl From<(u32, u32)> for PhraseIdRange {
+    fn from(range: (u32, u32)) -> PhraseIdRange {
+        PhraseIdRange::new(range.0, range.1)
+    }
+}
+
+impl PhraseSet {
+    /// Same as `get_by_id`, but taking a `PhraseId`.
+    pub fn get_by_phrase_id(&self, id: PhraseId) -> Option<Vec<u32>> {
+        self.get_by_id(Output::new(u64::from(id.0)))
+    }
+
+    /// Same as `get_max_id`, as a `PhraseId`.
+    pub fn max_phrase_id(&self) -> PhraseId {
+        PhraseId::from_output(self.get_max_id())
+    }
+
+    /// How many leading words the phrases `a` and `b` have in common. Like the other
+    /// relationships between stored phrases below, this works from the phrases' words as
+    /// reconstructed by `get_by_phrase_id`, so it's `None` if either ID isn't in the set, or if
+    /// the set doesn't store IDs.
+    pub fn shared_prefix_len(&self, a: PhraseId, b: PhraseId) -> Option<usize> {
+        let (a, b) = self.phrase_pair(a, b)?;
+        Some(a.iter().zip(b.iter()).take_while(|&(x, y)| x == y).count())
+    }
+
+    /// Whether the phrase `a` is the beginning of the longer phrase `b` ("100 main" of "100 main
+    /// st"). A phrase isn't a prefix of itself.
+    pub fn is_prefix_of(&self, a: PhraseId, b: PhraseId) -> Option<bool> {
+        let (a, b) = self.phrase_pair(a, b)?;
+        Some(a.len() < b.len() && b.starts_with(&a))
+    }
+
+    /// Whether the phrase `a` carries on from the shorter phrase `b`, the other way around from
+    /// `is_prefix_of`.
+    pub fn is_extension_of(&self, a: PhraseId, b: PhraseId) -> Option<bool> {
+        self.is_prefix_of(b, a)
+    }
+
+    fn phrase_pair(&self, a: PhraseId, b: PhraseId) -> Option<(Vec<u32>, Vec<u32>)> {
+        Some((self.get_by_phrase_id(a)?, self.get_by_phrase_id(b)?))
+    }
+}
+
+impl<'a> PhraseSetLookupResult<'a> {
+    /// Same as `id`, as a `PhraseId`.
+    pub fn phrase_id(&self) -> Option<PhraseId> {
+        self.id().map(PhraseId::from_output)
+    }
+
+    /// Same as `range`, as a `PhraseIdRange`.
+    pub fn phrase_id_range(&self) -> Option<PhraseIdRange> {
+        self.range().map(PhraseIdRange::from_outputs)
+    }
+}
+
+impl Combination {
+    /// The IDs of the phrases this combination matched.
+    pub fn phrase_id_range(&self) -> PhraseIdRange {
+        PhraseIdRange::from_outputs(self.output_range)
+    }
+}
+
+impl CombinationWindow {
+    /// The IDs of the phrases this window matched.
+    pub fn phrase_id_range(&self) -> PhraseIdRange {
+        PhraseIdRange::from_outputs(self.output_range)
+    }
+}
+
+impl IndexedCombination {
+    /// The IDs of the phrases this combination matched.
+    pub fn phrase_id_range(&self) -> PhraseIdRange {
+        PhraseIdRange::from_outputs(self.output_range)
+    }
+}
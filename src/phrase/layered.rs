@@ -0,0 +1,170 @@
+use std::collections::{BTreeSet, HashSet};
+
+use fst_compat::raw::Output;
+
+use super::{PhraseSet, Combination, CombinationWindow};
+use super::query::QueryWord;
+use super::util::PhraseSetError;
+use super::filter::PhraseIdFilter;
+
+/// One `PhraseSet` in a `LayeredPhraseSet`, along with the IDs of any of its phrases that have
+/// since been deleted.
+struct Layer {
+    set: PhraseSet,
+    tombstones: BTreeSet<u32>,
+}
+
+// a range of phrase IDs survives tombstoning if at least one of the IDs within it isn't a tombstone
+impl PhraseIdFilter for Layer {
+    #[inline(always)]
+    fn allows_range(&self, range: (u32, u32)) -> bool {
+        if range.0 > range.1 {
+            return false;
+        }
+        let width = (range.1 - range.0) as usize + 1;
+        self.tombstones.range(range.0..=range.1).count() < width
+    }
+}
+
+/// An ordered stack of `PhraseSet`s that can be queried as though it were a single set: a base
+/// layer, plus any number of (typically much smaller) overlays that add phrases to it, and
+/// tombstones that mask out phrases in any layer. This allows corrections to be shipped without
+/// rebuilding or redistributing the base set.
+///
+/// All layers must be built against the same vocabulary (word IDs). Phrase IDs are only unique
+/// within a layer, so results are returned along with the index of the layer they came from
+/// (0 is the base).
+pub struct LayeredPhraseSet {
+    layers: Vec<Layer>,
+}
+
+impl Layer {
+    // how many of the layer's phrases haven't been tombstoned
+    fn live_count(&self) -> usize {
+        let count = self.set.0.len();
+        count - self.tombstones.range(..(count.min(u32::MAX as usize) as u32)).count()
+    }
+}
+
+impl LayeredPhraseSet {
+    pub fn new(base: PhraseSet) -> LayeredPhraseSet {
+        LayeredPhraseSet { layers: vec![Layer { set: base, tombstones: BTreeSet::new() }] }
+    }
+
+    /// Add a layer on top of the existing ones, and return its index.
+    pub fn push_layer(&mut self, set: PhraseSet) -> usize {
+        self.layers.push(Layer { set, tombstones: BTreeSet::new() });
+        self.layers.len() - 1
+    }
+
+    /// Mask out phrases with the given IDs in the given layer.
+    pub fn tombstone<I: IntoIterator<Item=u32>>(&mut self, layer: usize, phrase_ids: I) -> Result<(), PhraseSetError> {
+        match self.layers.get_mut(layer) {
+            Some(l) => {
+                l.tombstones.extend(phrase_ids);
+                Ok(())
+            },
+            None => Err(PhraseSetError::new(&format!("Layer {} does not exist", layer))),
+        }
+    }
+
+    /// The number of layers, including the base.
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Whether there are no live phrases in any layer.
+    pub fn is_empty(&self) -> bool {
+        self.layers.iter().all(|layer| layer.live_count() == 0)
+    }
+
+    /// Look up a complete phrase, returning the layer and phrase ID of the topmost live copy of
+    /// it, if there is one.
+    pub fn lookup(&self, phrase: &[QueryWord]) -> Option<(usize, Output)> {
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            if let Some(id) = layer.set.lookup(phrase).id() {
+                if !layer.tombstones.contains(&(id.value() as u32)) {
+                    return Some((i, id));
+                }
+            }
+        }
+        None
+    }
+
+    /// Check whether any layer has a live phrase that starts with the given words.
+    pub fn contains_prefix(&self, phrase: &[QueryWord]) -> bool {
+        self.layers.iter().rev().any(|layer| match layer.set.lookup(phrase).range() {
+            Some((start, end)) => layer.allows_range((start.value() as u32, end.value() as u32)),
+            None => false,
+        })
+    }
+
+    /// Same as `PhraseSet::match_combinations`, but across all layers. If the same phrase is
+    /// live in more than one layer, only the topmost copy is returned.
+    pub fn match_combinations(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8
+    ) -> Result<Vec<(usize, Combination)>, PhraseSetError> {
+        let mut out: Vec<(usize, Combination)> = Vec::new();
+        // the word IDs of the phrases already found in higher layers
+        let mut seen: HashSet<Vec<u32>> = HashSet::new();
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            for combination in layer.set.match_combinations(word_possibilities, max_phrase_dist)? {
+                if layer.tombstones.contains(&(combination.output_range.0.value() as u32)) {
+                    continue;
+                }
+                let word_ids = combination.phrase.iter().map(|word| match *word {
+                    QueryWord::Full { id, .. } => id,
+                    QueryWord::Prefix { id_range, .. } => id_range.0,
+                }).collect();
+                if seen.insert(word_ids) {
+                    out.push((i, combination));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Same as `PhraseSet::match_combinations_as_prefixes`, but across all layers. Results whose
+    /// phrase ID ranges have been entirely tombstoned are dropped. Each layer's phrase IDs are
+    /// its own, so a prefix that's live in more than one layer is returned once for each, with
+    /// the range of phrases it covers there.
+    pub fn match_combinations_as_prefixes(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8
+    ) -> Result<Vec<(usize, Combination)>, PhraseSetError> {
+        let mut out: Vec<(usize, Combination)> = Vec::new();
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            for combination in layer.set.match_combinations_as_prefixes(word_possibilities, max_phrase_dist)? {
+                let range = (combination.output_range.0.value() as u32, combination.output_range.1.value() as u32);
+                if layer.allows_range(range) {
+                    out.push((i, combination));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Same as `PhraseSet::match_combinations_as_windows`, but across all layers. Tombstones are
+    /// applied as the graph is explored, so fully-tombstoned branches are skipped. As with
+    /// prefixes, a window that's live in more than one layer is returned once for each.
+    pub fn match_combinations_as_windows(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        ends_in_prefix: bool
+    ) -> Result<Vec<(usize, CombinationWindow)>, PhraseSetError> {
+        let mut out: Vec<(usize, CombinationWindow)> = Vec::new();
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let windows = if layer.tombstones.is_empty() {
+                layer.set.match_combinations_as_windows(word_possibilities, max_phrase_dist, ends_in_prefix)?
+            } else {
+                layer.set.match_combinations_as_windows_filtered(word_possibilities, max_phrase_dist, ends_in_prefix, layer)?
+            };
+            out.extend(windows.into_iter().map(|window| (i, window)));
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use fst_compat::raw::{CompiledAddr, Fst, Output};
+
+use super::PhraseSet;
+use super::util::key_to_word_ids;
+
+// every word ID takes up three bytes of key
+const BYTES_PER_WORD: usize = 3;
+
+/// Iterator over the phrases in a `PhraseSet` that have a given number of words, as (word IDs,
+/// phrase ID) pairs, in phrase ID order. Created by `PhraseSet::phrases_of_length`.
+pub struct PhrasesOfLength<'a> {
+    fst: &'a Fst,
+    depth: usize,
+    // (node, index of the next transition to follow, output so far) for each node on the path
+    stack: Vec<(CompiledAddr, usize, Output)>,
+    key: Vec<u8>,
+}
+
+impl<'a> Iterator for PhrasesOfLength<'a> {
+    type Item = (Vec<u32>, Output);
+
+    fn next(&mut self) -> Option<(Vec<u32>, Output)> {
+        while let Some(frame) = self.stack.last_mut() {
+            let node = self.fst.node(frame.0);
+            if frame.1 >= node.len() {
+                self.stack.pop();
+                self.key.pop();
+                continue;
+            }
+            let transition = node.transition(frame.1);
+            frame.1 += 1;
+            let output = frame.2.cat(transition.out);
+            self.key.push(transition.inp);
+
+            if self.key.len() < self.depth {
+                self.stack.push((transition.addr, 0, output));
+                continue;
+            }
+            // we're as deep as we're going to go, so don't descend any further
+            let child = self.fst.node(transition.addr);
+            let found = if child.is_final() {
+                Some((key_to_word_ids(&self.key).expect("Keys are walked a whole word at a time"), output.cat(child.final_output())))
+            } else {
+                None
+            };
+            self.key.pop();
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
+
+impl PhraseSet {
+    /// Count the phrases in the set by number of words.
+    pub fn length_histogram(&self) -> BTreeMap<usize, u64> {
+        let mut histogram: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut stack: Vec<(CompiledAddr, usize)> = vec![(self.0.root().addr(), 0)];
+        while let Some((addr, depth)) = stack.pop() {
+            let node = self.0.node(addr);
+            if node.is_final() {
+                *histogram.entry(depth / BYTES_PER_WORD).or_insert(0) += 1;
+            }
+            for transition in node.transitions() {
+                stack.push((transition.addr, depth + 1));
+            }
+        }
+        histogram
+    }
+
+    /// Iterate over the phrases that have exactly `words` words. Only the top `words` levels of
+    /// the graph are explored, so this is much cheaper than filtering a full stream when the
+    /// phrases sought are short.
+    pub fn phrases_of_length<'a>(&'a self, words: usize) -> PhrasesOfLength<'a> {
+        let stack = if words == 0 {
+            Vec::new()
+        } else {
+            vec![(self.0.root().addr(), 0, Output::zero())]
+        };
+        PhrasesOfLength { fst: &self.0, depth: words * BYTES_PER_WORD, stack, key: Vec::new() }
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use fst_compat::Streamer;
+
+use super::{PhraseSet, Combination, CombinationWindow, PhraseId, PhraseIdRange, WordKey};
+use super::matcher::PhraseMatcher;
+use super::trie::{self, BitVector, TrieBuilder, TrieGraph};
+use super::query::QueryWord;
+use super::util::{PhraseSetError, word_ids_to_key, key_to_word_ids};
+
+const MAGIC: &[u8; 8] = b"FPLOUDS1";
+
+/// A phrase graph stored as a LOUDS (level-order unary degree sequence) trie over the same
+/// three-byte word keys as a `PhraseSet`. The shape of the trie takes a little over two bits per
+/// node, navigated by rank and select, plus a byte per node for its label and a bit for whether a
+/// phrase ends there; each node also keeps the range of phrase IDs below it, so that prefix
+/// ranges can be read off directly rather than found by walking out to the edge of the graph as
+/// the FST does. Phrases are numbered as `PhraseSetBuilder` numbers them, so a `LoudsTrie` built
+/// from the same phrases as a `PhraseSet` gives the same results through `PhraseMatcher`.
+///
+/// Unlike a `PhraseSet`, the trie is held in memory rather than memory-mapped. It only
+/// implements the matchers in `PhraseMatcher`, without filters, limits or constraints.
+#[derive(Debug, Clone)]
+pub struct LoudsTrie {
+    // "10" for a virtual super-root, then, for each node in breadth-first order, a one for each
+    // of its children followed by a zero; node n is the nth one
+    louds: BitVector,
+    // the label of the edge leading into each node but the root, in breadth-first order
+    labels: Vec<u8>,
+    terminals: BitVector,
+    first_ids: Vec<u32>,
+    last_ids: Vec<u32>,
+    len: usize,
+    build_id: u64,
+}
+
+impl LoudsTrie {
+    /// Build a trie with the same phrases (and phrase IDs) as `set`.
+    pub fn from_phrase_set(set: &PhraseSet) -> Result<LoudsTrie, PhraseSetError> {
+        let mut builder = LoudsTrieBuilder::new_with_build_id(set.build_id());
+        let mut stream = set.as_fst().stream();
+        while let Some((key, _)) = stream.next() {
+            let phrase = key_to_word_ids(key).map_err(|e| PhraseSetError::new(&e.to_string()))?;
+            builder.insert(&phrase)?;
+        }
+        Ok(builder.finish())
+    }
+
+    /// The number of phrases in the trie.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The build ID the trie was created with (see `PhraseSetBuilder::new_with_build_id`).
+    pub fn build_id(&self) -> u64 {
+        self.build_id
+    }
+
+    /// Serialize the trie, to be read back by `from_bytes` or `from_path`.
+    pub fn write<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        wtr.write_all(MAGIC)?;
+        wtr.write_u64::<LittleEndian>(self.build_id)?;
+        wtr.write_u64::<LittleEndian>(self.len as u64)?;
+        wtr.write_u64::<LittleEndian>(self.first_ids.len() as u64)?;
+        self.louds.write(&mut wtr)?;
+        self.terminals.write(&mut wtr)?;
+        wtr.write_all(&self.labels)?;
+        for id in self.first_ids.iter().chain(self.last_ids.iter()) {
+            wtr.write_u32::<LittleEndian>(*id)?;
+        }
+        wtr.flush()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes).expect("writing to a vector can't fail");
+        bytes
+    }
+
+    /// Read a trie serialized by `write`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<LoudsTrie> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 32 || &bytes[..8] != MAGIC {
+            return Err(invalid("Not a LOUDS trie"));
+        }
+        let mut rdr = Cursor::new(&bytes[8..]);
+        let build_id = rdr.read_u64::<LittleEndian>()?;
+        let len = rdr.read_u64::<LittleEndian>()? as usize;
+        let node_count = rdr.read_u64::<LittleEndian>()? as usize;
+        if node_count == 0 || node_count > bytes.len() {
+            return Err(invalid("LOUDS trie has an invalid node count"));
+        }
+        // check the size up front, so a corrupt header can't make us allocate too much
+        let louds_len = 2 * node_count + 1;
+        let expected = 32 + louds_len.div_ceil(64) * 8 + node_count.div_ceil(64) * 8 + (node_count - 1) + node_count * 8;
+        if bytes.len() != expected {
+            return Err(invalid("LOUDS trie is truncated or has trailing data"));
+        }
+        let louds = BitVector::read(&mut rdr, louds_len)?;
+        let terminals = BitVector::read(&mut rdr, node_count)?;
+        let mut labels = vec![0u8; node_count - 1];
+        rdr.read_exact(&mut labels)?;
+        let mut first_ids = vec![0u32; node_count];
+        rdr.read_u32_into::<LittleEndian>(&mut first_ids)?;
+        let mut last_ids = vec![0u32; node_count];
+        rdr.read_u32_into::<LittleEndian>(&mut last_ids)?;
+        if louds.rank1(louds_len) != node_count {
+            return Err(invalid("LOUDS trie has an inconsistent shape"));
+        }
+        Ok(LoudsTrie { louds, labels, terminals, first_ids, last_ids, len, build_id })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<LoudsTrie> {
+        LoudsTrie::from_bytes(&fs::read(path)?)
+    }
+
+    // the first child of a node, and how many children it has
+    #[inline(always)]
+    fn children(&self, node: u32) -> (u32, u32) {
+        let start = self.louds.select0(node as usize);
+        let end = self.louds.select0(node as usize + 1);
+        ((start - node as usize) as u32, (end - start - 1) as u32)
+    }
+
+    #[inline(always)]
+    fn child_labels(&self, first: u32, count: u32) -> &[u8] {
+        &self.labels[(first - 1) as usize..(first - 1 + count) as usize]
+    }
+
+    #[inline(always)]
+    fn child(&self, node: u32, label: u8) -> Option<u32> {
+        let (first, count) = self.children(node);
+        self.child_labels(first, count).binary_search(&label).ok().map(|i| first + i as u32)
+    }
+
+    #[inline(always)]
+    fn follow_key(&self, node: u32, key: &WordKey) -> Option<u32> {
+        key.iter().try_fold(node, |node, label| self.child(node, *label))
+    }
+
+    // each byte only needs to be checked against the bounds while the bytes before it are still
+    // equal to theirs, which is what `tight` tracks
+    fn visit_words<F: FnMut(u32) -> Result<(), PhraseSetError>>(&self, node: u32, depth: usize, key_range: &(WordKey, WordKey), tight: (bool, bool), f: &mut F) -> Result<(), PhraseSetError> {
+        if depth == 3 {
+            return f(node);
+        }
+        let (first, count) = self.children(node);
+        let low = if tight.0 { key_range.0[depth] } else { 0 };
+        let high = if tight.1 { key_range.1[depth] } else { u8::MAX };
+        let labels = self.child_labels(first, count);
+        for (i, label) in labels.iter().enumerate().skip(labels.partition_point(|label| *label < low)) {
+            if *label > high {
+                break
+            }
+            self.visit_words(first + i as u32, depth + 1, key_range, (tight.0 && *label == low, tight.1 && *label == high), f)?;
+        }
+        Ok(())
+    }
+
+    // the first (or last) whole word within `key_range` that continues from `node`; this only has
+    // to backtrack along the edges of the range, since anything strictly inside it is a word
+    fn boundary_word(&self, node: u32, depth: usize, key_range: &(WordKey, WordKey), tight: (bool, bool), last: bool) -> Option<u32> {
+        if depth == 3 {
+            return Some(node);
+        }
+        let (first, count) = self.children(node);
+        let low = if tight.0 { key_range.0[depth] } else { 0 };
+        let high = if tight.1 { key_range.1[depth] } else { u8::MAX };
+        let labels = self.child_labels(first, count);
+        let start = labels.partition_point(|label| *label < low);
+        let end = labels.partition_point(|label| *label <= high).max(start);
+        let search = |i: usize| {
+            let label = labels[i];
+            self.boundary_word(first + i as u32, depth + 1, key_range, (tight.0 && label == low, tight.1 && label == high), last)
+        };
+        if last {
+            (start..end).rev().find_map(search)
+        } else {
+            (start..end).find_map(search)
+        }
+    }
+}
+
+impl TrieGraph for LoudsTrie {
+    fn phrase_count(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    fn follow_word(&self, node: u32, _id: u32, key: &WordKey) -> Option<u32> {
+        self.follow_key(node, key)
+    }
+
+    fn for_each_word_in_range<F: FnMut(u32) -> Result<(), PhraseSetError>>(&self, node: u32, _id_range: (u32, u32), key_range: (WordKey, WordKey), mut f: F) -> Result<(), PhraseSetError> {
+        self.visit_words(node, 0, &key_range, (true, true), &mut f)
+    }
+
+    fn range_through(&self, node: u32, _id_range: (u32, u32), key_range: (WordKey, WordKey)) -> Option<(u32, u32)> {
+        let min = self.boundary_word(node, 0, &key_range, (true, true), false)?;
+        let max = self.boundary_word(node, 0, &key_range, (true, true), true)?;
+        Some((self.first_ids[min as usize], self.last_ids[max as usize]))
+    }
+
+    #[inline(always)]
+    fn is_final(&self, node: u32) -> bool {
+        self.terminals.get(node as usize)
+    }
+
+    #[inline(always)]
+    fn id_range(&self, node: u32) -> (u32, u32) {
+        (self.first_ids[node as usize], self.last_ids[node as usize])
+    }
+}
+
+impl PhraseMatcher for LoudsTrie {
+    fn phrase_id(&self, phrase: &[QueryWord]) -> Option<PhraseId> {
+        trie::phrase_id(self, phrase)
+    }
+
+    fn prefix_range(&self, phrase: &[QueryWord]) -> Option<PhraseIdRange> {
+        trie::prefix_range(self, phrase)
+    }
+
+    fn match_combinations(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        trie::match_combinations(self, word_possibilities, max_phrase_dist)
+    }
+
+    fn match_combinations_as_prefixes(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        trie::match_combinations_as_prefixes(self, word_possibilities, max_phrase_dist)
+    }
+
+    fn match_combinations_as_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        trie::match_combinations_as_windows(self, word_possibilities, max_phrase_dist, ends_in_prefix)
+    }
+}
+
+/// Builds a `LoudsTrie`. As with `PhraseSetBuilder`, phrases must be inserted in lexicographic
+/// order by word ID, and each is numbered by the order it was inserted in.
+pub struct LoudsTrieBuilder {
+    trie: TrieBuilder<u8>,
+    build_id: u64,
+}
+
+impl Default for LoudsTrieBuilder {
+    fn default() -> LoudsTrieBuilder {
+        LoudsTrieBuilder::new()
+    }
+}
+
+impl LoudsTrieBuilder {
+    pub fn new() -> LoudsTrieBuilder {
+        LoudsTrieBuilder::new_with_build_id(0)
+    }
+
+    pub fn new_with_build_id(build_id: u64) -> LoudsTrieBuilder {
+        LoudsTrieBuilder { trie: TrieBuilder::new(), build_id }
+    }
+
+    pub fn insert(&mut self, phrase: &[u32]) -> Result<(), PhraseSetError> {
+        let key = word_ids_to_key(phrase).map_err(|e| PhraseSetError::new(&e.to_string()))?;
+        self.trie.insert(&key)
+    }
+
+    pub fn finish(self) -> LoudsTrie {
+        let layout = self.trie.finish();
+        let mut louds = vec![true, false];
+        for count in &layout.child_counts {
+            louds.extend((0..*count).map(|_| true));
+            louds.push(false);
+        }
+        LoudsTrie {
+            louds: BitVector::from_bits(&louds),
+            labels: layout.labels,
+            terminals: BitVector::from_bits(&layout.terminals),
+            first_ids: layout.first_ids,
+            last_ids: layout.last_ids,
+            len: layout.len,
+            build_id: self.build_id,
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use super::{PhraseSet, Combination, CombinationWindow, PhraseId, PhraseIdRange};
+use super::query::QueryWord;
+use super::util::PhraseSetError;
+
+/// The operations a phrase matching engine has to support for the glue layer's query pipeline
+/// to run against it. `PhraseSet` is the standard engine; others (a succinct trie, say) can be
+/// used with `FuzzyPhraseSet::fuzzy_match_with` and `FuzzyPhraseSet::fuzzy_match_windows_with`,
+/// so long as they're built over the same vocabulary (word IDs) as the `FuzzyPhraseSet`, and
+/// number phrases the way `PhraseSetBuilder` does (in order, by word ID), so that phrase IDs
+/// mean the same thing whichever engine found them.
+pub trait PhraseMatcher {
+    /// The ID of the phrase made up of exactly the words of `phrase`, if there is one.
+    fn phrase_id(&self, phrase: &[QueryWord]) -> Option<PhraseId>;
+
+    /// The IDs of every phrase that begins with `phrase` (the last word of which may be a
+    /// prefix), if there are any.
+    fn prefix_range(&self, phrase: &[QueryWord]) -> Option<PhraseIdRange>;
+
+    /// See `PhraseSet::match_combinations`.
+    fn match_combinations(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError>;
+
+    /// See `PhraseSet::match_combinations_as_prefixes`.
+    fn match_combinations_as_prefixes(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError>;
+
+    /// See `PhraseSet::match_combinations_as_windows`.
+    fn match_combinations_as_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError>;
+}
+
+impl PhraseMatcher for PhraseSet {
+    fn phrase_id(&self, phrase: &[QueryWord]) -> Option<PhraseId> {
+        self.lookup(phrase).phrase_id()
+    }
+
+    fn prefix_range(&self, phrase: &[QueryWord]) -> Option<PhraseIdRange> {
+        self.lookup(phrase).phrase_id_range()
+    }
+
+    fn match_combinations(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        PhraseSet::match_combinations(self, word_possibilities, max_phrase_dist)
+    }
+
+    fn match_combinations_as_prefixes(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        PhraseSet::match_combinations_as_prefixes(self, word_possibilities, max_phrase_dist)
+    }
+
+    fn match_combinations_as_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        PhraseSet::match_combinations_as_windows(self, word_possibilities, max_phrase_dist, ends_in_prefix)
+    }
+}
@@ -1,24 +1,68 @@
 pub mod util;
 pub mod query;
+pub mod filter;
+pub mod classes;
+pub mod layered;
+pub mod matcher;
+pub mod louds;
+pub mod word_trie;
+mod trie;
+mod remap;
+mod lengths;
+mod order;
+mod bitsets;
+mod root;
+mod range;
+mod step;
+mod ids;
+mod reorder;
+mod gaps;
+mod dot;
+#[cfg(feature = "async")]
+mod cooperative;
 
 use std::io;
-#[cfg(feature = "mmap")]
+use std::cmp::Ordering;
 use std::path::Path;
 
-use fst;
-use fst::IntoStreamer;
-use fst::raw::{CompiledAddr, Node, Fst, Builder, Output};
+use fst_compat;
+use fst_compat::{IntoStreamer, Streamer};
+use fst_compat::raw::{CompiledAddr, Node, Fst, Builder, Output};
 use byteorder::{BigEndian, ReadBytesExt};
+use smallvec::SmallVec;
 
-use self::util::{word_ids_to_key};
+use self::util::{word_ids_to_key, key_to_word_ids};
 use self::util::PhraseSetError;
-use self::query::QueryWord;
+use self::query::{QueryWord, VariantSource};
+use self::filter::PhraseIdFilter;
+use self::classes::{TokenConstraints, WordClasses};
+use self::root::RootTable;
+use self::step::WordStep;
+pub use self::lengths::PhrasesOfLength;
+pub use self::order::WordDepths;
+pub use self::bitsets::WordBitset;
+pub use self::ids::{PhraseId, PhraseIdRange};
+pub use self::reorder::ReorderedCombination;
+pub use self::gaps::GappedWindow;
+#[cfg(feature = "async")]
+pub use self::cooperative::MatchCombinations;
+pub use self::matcher::PhraseMatcher;
+use ::progress::{BuildPhase, ProgressReporter, ProgressTracker, check_cancelled};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 #[cfg(test)] mod tests;
 
 type WordKey = [u8; 3];
 
-pub struct PhraseSet(Fst);
+// the words matched so far along the current path through the graph, as the index of the
+// chosen possibility at each position; the matchers share a single one of these across their
+// whole traversal (pushing and popping as they go), and since queries rarely have more than a
+// handful of words, it almost never has to touch the heap
+type WordPath = SmallVec<[u32; 8]>;
+
+// the last field is whether the set stores phrase IDs (see `PhraseSetBuilder::set_store_ids`)
+pub struct PhraseSet(Fst, Option<RootTable>, bool);
 
 /// PhraseSet is a lexicographically ordered set of phrases.
 ///
@@ -42,14 +86,12 @@ impl PhraseSet {
         for word in phrase {
             match word {
                 QueryWord::Full { key, .. } => {
-                    for b in key.into_iter() {
-                        node = if let Some(i) = node.find_input(*b) {
-                            let t = node.transition(i);
-                            output = output.cat(t.out);
-                            fst.node(t.addr)
-                        } else {
-                            return PhraseSetLookupResult::NotFound;
-                        }
+                    match self.follow_key(&node, key) {
+                        Some((next_node, incr_output)) => {
+                            node = next_node;
+                            output = output.cat(incr_output);
+                        },
+                        None => return PhraseSetLookupResult::NotFound,
                     }
                 },
                 QueryWord::Prefix { key_range, .. } => {
@@ -72,6 +114,12 @@ impl PhraseSet {
         PhraseSetLookupResult::Found { fst, match_state: PhraseSetMatchState::EndsInFullWord { node, output } }
     }
 
+    /// Whether `phrase` is a complete phrase in the set, the beginning of longer ones, or both
+    /// (see `PhraseMatchKind`), or `None` if it's neither.
+    pub fn match_kind(&self, phrase: &[QueryWord]) -> Option<PhraseMatchKind> {
+        self.lookup(phrase).match_kind()
+    }
+
     /// Recursively explore the phrase graph looking for combinations of candidate words to see
     /// which ones match actual phrases in the phrase graph
     ///
@@ -82,80 +130,76 @@ impl PhraseSet {
         &self,
         word_possibilities: &[Vec<QueryWord>],
         max_phrase_dist: u8
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        self.match_combinations_limit(word_possibilities, max_phrase_dist, usize::MAX)
+    }
+
+    /// Same as `match_combinations`, but stops exploring the graph as soon as `limit` matching
+    /// combinations have been found. This is useful for callers that only need to know whether
+    /// any (or at least K) phrases match, rather than needing all of them.
+    pub fn match_combinations_limit(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        limit: usize
     ) -> Result<Vec<Combination>, PhraseSetError> {
         // this is just a thin wrapper around a private recursive function, with most of the
         // arguments prefilled
         let fst = &self.0;
         let root = fst.root();
         let mut out: Vec<Combination> = Vec::new();
-        self.exact_recurse(word_possibilities, 0, &root, max_phrase_dist, Vec::new(), Output::zero(), &mut out)?;
+        if limit == 0 {
+            return Ok(out);
+        }
+        self.exact_recurse(&mut Search { limit, ..Search::new(word_possibilities, &mut out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())?;
         Ok(out)
     }
 
-    fn exact_recurse(
+    fn exact_recurse<T: MatchOutput>(
         &self,
-        possibilities: &[Vec<QueryWord>],
+        search: &mut Search<T>,
         position: usize,
         node: &Node,
         budget_remaining: u8,
-        words_so_far: Vec<QueryWord>,
+        words_so_far: &mut WordPath,
         output_so_far: Output,
-        out: &mut Vec<Combination>,
     ) -> Result<(), PhraseSetError> {
-        let fst = &self.0;
+        let (possibilities, filter) = (search.possibilities, search.filter);
+        let allowed = |range: (Output, Output)| match filter {
+            Some(f) => f.allows_range((range.0.value() as u32, range.1.value() as u32)),
+            None => true,
+        };
 
-        for word in possibilities[position].iter() {
+        for (idx, word) in possibilities[position].iter().enumerate() {
+            // bail as soon as we've collected as many results as the caller asked for
+            if search.out.len() >= search.limit {
+                break
+            }
             let (key, edit_distance) = match word {
                 QueryWord::Full { key, edit_distance, .. } => (*key, *edit_distance),
                 _ => return Err(PhraseSetError::new(
                     "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
                 )),
             };
-            if edit_distance > budget_remaining {
-                break
-            }
-
-            // can we find the next word from our current position?
-            let mut found = true;
-            // make a mutable copy to traverse
-            let mut search_node = node.to_owned();
-            let mut incr_output = Output::zero();
-            for b in key.into_iter() {
-                if let Some(i) = search_node.find_input(*b) {
-                    let trans = search_node.transition(i);
-                    incr_output = incr_output.cat(trans.out);
-                    search_node = fst.node(trans.addr);
-                } else {
-                    found = false;
-                    break;
-                }
-            }
+            // can we find the next word from our current position? (with a filter, skip it if
+            // none of the phrases reachable from there are allowed)
+            let (search_node, output, budget) = match self.step_word(node, output_so_far, &key, edit_distance, budget_remaining, filter) {
+                WordStep::OverBudget => break,
+                WordStep::Missing => continue,
+                WordStep::Found(search_node, output, budget) => (search_node, output, budget),
+            };
 
-            // only recurse or add a result if the current word is in the graph in this position
-            if found {
-                let mut rec_so_far = words_so_far.clone();
-                rec_so_far.push(word.clone());
-                if position < possibilities.len() - 1 {
-                    self.exact_recurse(
-                        possibilities,
-                        position + 1,
-                        &search_node,
-                        budget_remaining - edit_distance,
-                        rec_so_far,
-                        output_so_far.cat(incr_output),
-                        out,
-                    )?;
-                } else {
-                    // if we're at the end of the line, we'll only keep this result if it's final
-                    if search_node.is_final() {
-                        let final_output = output_so_far.cat(incr_output).cat(search_node.final_output());
-                        out.push(Combination {
-                            phrase: rec_so_far,
-                            output_range: (final_output, final_output)
-                        });
-                    }
+            words_so_far.push(idx as u32);
+            if position < possibilities.len() - 1 {
+                self.exact_recurse(search, position + 1, &search_node, budget, words_so_far, output)?;
+            } else if search_node.is_final() {
+                // if we're at the end of the line, we'll only keep this result if it's final
+                let final_output = output.cat(search_node.final_output());
+                if allowed((final_output, final_output)) {
+                    search.out.push(T::from_path(possibilities, words_so_far, (final_output, final_output), false));
                 }
             }
+            words_so_far.pop();
         }
         Ok(())
     }
@@ -164,7 +208,9 @@ impl PhraseSet {
     /// which ones match prefixes of actual phrases in the phrase graph.
     ///
     /// As above, it's a list of word slots, but the last one might be a range of word IDs
-    /// encompassing all words that start with a given prefix. The outputs it matches will be at
+    /// encompassing all words that start with a given prefix. So might the one before it (as
+    /// when input leaves two trailing partial words), in which case there's a separate match for
+    /// each word in that first range that leads on to the last slot. The outputs it matches will be at
     /// least as long as the number of slots in the input, but maybe longer, and it may match
     /// either phrase prefixes that end in a word prefix (so, prefixes that start with some number
     /// of whole words plus a partial word) or phrase prefixes comprised only of whole words (so.
@@ -173,89 +219,163 @@ impl PhraseSet {
         &self,
         word_possibilities: &[Vec<QueryWord>],
         max_phrase_dist: u8
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        self.match_combinations_as_prefixes_limit(word_possibilities, max_phrase_dist, usize::MAX)
+    }
+
+    /// Same as `match_combinations_as_prefixes`, but stops exploring the graph as soon as `limit`
+    /// matching combinations have been found.
+    pub fn match_combinations_as_prefixes_limit(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        limit: usize
     ) -> Result<Vec<Combination>, PhraseSetError> {
         // this is just a thin wrapper around a private recursive function, with most of the
         // arguments prefilled
         let fst = &self.0;
         let root = fst.root();
         let mut out: Vec<Combination> = Vec::new();
-        self.prefix_recurse(word_possibilities, 0, &root, max_phrase_dist, Vec::new(), Output::zero(), &mut out)?;
+        if limit == 0 {
+            return Ok(out);
+        }
+        self.prefix_recurse(&mut Search { limit, ..Search::new(word_possibilities, &mut out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())?;
         Ok(out)
     }
 
-    fn prefix_recurse(
+    /// Whether `match_combinations_as_prefixes` would find anything. This stops at the first
+    /// match, and doesn't work out the range of phrases it covers: a word prefix at the end only
+    /// has to be checked for the first word within it, not the last.
+    pub fn has_prefix_match(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8
+    ) -> Result<bool, PhraseSetError> {
+        let root = self.0.root();
+        let mut out: Vec<AnyMatch> = Vec::new();
+        self.prefix_recurse(&mut Search { limit: 1, ..Search::new(word_possibilities, &mut out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(!out.is_empty())
+    }
+
+    /// Same as `match_combinations_limit`, but favors the phrases allowed by `bias` (e.g., phrases
+    /// near the user's location) when deciding which combinations to keep: the graph is first
+    /// explored only along branches that can reach a favored phrase, and then, if that didn't
+    /// turn up `limit` combinations, explored again to fill the remaining space with the rest.
+    /// Favored combinations come first in the output.
+    pub fn match_combinations_biased(
         &self,
-        possibilities: &[Vec<QueryWord>],
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        bias: &dyn PhraseIdFilter,
+        limit: usize
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        let root = self.0.root();
+        biased_search(limit, bias, |filter, limit, out| {
+            self.exact_recurse(&mut Search { filter, limit, ..Search::new(word_possibilities, out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())
+        })
+    }
+
+    /// Same as `match_combinations_as_prefixes_limit`, but favors the phrases allowed by `bias`, as
+    /// in `match_combinations_biased`. A prefix match counts as favored if any of the phrases it
+    /// covers are.
+    pub fn match_combinations_as_prefixes_biased(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        bias: &dyn PhraseIdFilter,
+        limit: usize
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        let root = self.0.root();
+        biased_search(limit, bias, |filter, limit, out| {
+            self.prefix_recurse(&mut Search { filter, limit, ..Search::new(word_possibilities, out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())
+        })
+    }
+
+    fn prefix_recurse<T: MatchOutput>(
+        &self,
+        search: &mut Search<T>,
         position: usize,
         node: &Node,
         budget_remaining: u8,
-        words_so_far: Vec<QueryWord>,
+        words_so_far: &mut WordPath,
         output_so_far: Output,
-        out: &mut Vec<Combination>,
     ) -> Result<(), PhraseSetError> {
-        let fst = &self.0;
+        let (possibilities, filter) = (search.possibilities, search.filter);
+        let allowed = |range: (Output, Output)| match filter {
+            Some(f) => f.allows_range((range.0.value() as u32, range.1.value() as u32)),
+            None => true,
+        };
 
-        for word in possibilities[position].iter() {
+        for (idx, word) in possibilities[position].iter().enumerate() {
+            if search.out.len() >= search.limit {
+                break
+            }
             match word {
                 QueryWord::Full { key, edit_distance, .. } => {
-                    if *edit_distance > budget_remaining {
-                        break
-                    }
+                    // only recurse or add a result if the current word is in the graph in this
+                    // position
+                    let (search_node, output, budget) = match self.step_word(node, output_so_far, key, *edit_distance, budget_remaining, filter) {
+                        WordStep::OverBudget => break,
+                        WordStep::Missing => continue,
+                        WordStep::Found(search_node, output, budget) => (search_node, output, budget),
+                    };
 
-                    let mut found = true;
-                    // make a mutable copy to traverse
-                    let mut search_node = node.to_owned();
-                    let mut incr_output = Output::zero();
-                    for b in key.into_iter() {
-                        if let Some(i) = search_node.find_input(*b) {
-                            let trans = search_node.transition(i);
-                            incr_output = incr_output.cat(trans.out);
-                            search_node = fst.node(trans.addr);
-                        } else {
-                            found = false;
-                            break;
-                        }
+                    words_so_far.push(idx as u32);
+                    if position < possibilities.len() - 1 {
+                        self.prefix_recurse(search, position + 1, &search_node, budget, words_so_far, output)?;
+                    } else if !T::NEEDS_RANGE {
+                        search.out.push(T::from_path(possibilities, words_so_far, (Output::zero(), Output::zero()), true));
+                    } else {
+                        let range = self.reachable_range(search_node, output);
+                        search.out.push(T::from_path(possibilities, words_so_far, range, true));
                     }
-
-                    // only recurse or add a result if we the current word is in the graph in
-                    // this position
-                    if found {
-                        let mut rec_so_far = words_so_far.clone();
-                        rec_so_far.push(word.clone());
-                        if position < possibilities.len() - 1 {
+                    words_so_far.pop();
+                },
+                QueryWord::Prefix { key_range, edit_distance, .. } => {
+                    if position + 2 < possibilities.len() {
+                        return Err(PhraseSetError::new(
+                            "Only the last two slots of a prefix query may have a QueryWord::Prefix"
+                        ))
+                    } else if *edit_distance > budget_remaining {
+                        // a fuzzy prefix costs what it's away from what was typed, like a word
+                        continue
+                    } else if position + 2 == possibilities.len() {
+                        // a prefix followed by one more word at the end of the query: try each word
+                        // it could be on its own, since the phrases that go on to match the last
+                        // one won't be contiguous across all of them
+                        for (search_node, incr_output) in self.words_in_range(node, *key_range) {
+                            if search.out.len() >= search.limit {
+                                break
+                            }
+                            if !self.reaches_allowed(search_node, output_so_far.cat(incr_output), filter) {
+                                continue
+                            }
+                            words_so_far.push(idx as u32);
                             self.prefix_recurse(
-                                possibilities,
+                                search,
                                 position + 1,
                                 &search_node,
                                 budget_remaining - edit_distance,
-                                rec_so_far,
+                                words_so_far,
                                 output_so_far.cat(incr_output),
-                                out,
                             )?;
-                        } else {
-                            let range = (PhraseSetMatchState::EndsInFullWord { node: search_node, output: output_so_far.cat(incr_output) }).prefix_range(fst);
-                            out.push(Combination {
-                                phrase: rec_so_far,
-                                output_range: range
-                            });
+                            words_so_far.pop();
+                        }
+                    } else if !T::NEEDS_RANGE && filter.is_none() {
+                        // without a range to find (or filter by), any word in the prefix will do
+                        if self.has_word_in_range(node, *key_range) {
+                            words_so_far.push(idx as u32);
+                            search.out.push(T::from_path(possibilities, words_so_far, (Output::zero(), Output::zero()), true));
+                            words_so_far.pop();
+                        }
+                    } else if let Some(range) = self.step_final_prefix(node, output_so_far, *key_range) {
+                        // the prefix is at the end, so we don't need to consider the possibility
+                        // of recursing, just of being done
+                        if allowed(range) {
+                            words_so_far.push(idx as u32);
+                            search.out.push(T::from_path(possibilities, words_so_far, range, true));
+                            words_so_far.pop();
                         }
-                    }
-                },
-                QueryWord::Prefix { key_range, .. } => {
-                    if let WordPrefixMatchResult::Found(state) = self.matches_prefix_range(
-                        node.addr(),
-                        output_so_far,
-                        *key_range
-                    ) {
-                        // presumably the prefix is at the end, so we don't need to consider the
-                        // possibility of recursing, just of being done
-                        let mut rec_so_far = words_so_far.clone();
-                        rec_so_far.push(word.clone());
-                        let range = (PhraseSetMatchState::EndsInPrefix(state)).prefix_range(fst);
-                        out.push(Combination {
-                            phrase: rec_so_far,
-                            output_range: range
-                        });
                     }
                 },
             }
@@ -282,130 +402,158 @@ impl PhraseSet {
         let fst = &self.0;
         let root = fst.root();
         let mut out: Vec<CombinationWindow> = Vec::new();
-        self.window_recurse(word_possibilities, 0, &root, max_phrase_dist, ends_in_prefix, Vec::new(), Output::zero(), &mut out)?;
+        self.window_recurse(&mut Search { ends_in_prefix, ..Search::new(word_possibilities, &mut out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(out)
+    }
+
+    /// Same as `match_combinations_as_windows`, but only returns windows whose phrase ID range is
+    /// allowed by `filter`. Rather than filtering after the fact, the filter is consulted as the
+    /// graph is explored, and any branch from which no allowed phrase is reachable is skipped
+    /// entirely.
+    pub fn match_combinations_as_windows_filtered(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        ends_in_prefix: bool,
+        filter: &dyn PhraseIdFilter
+    ) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        let fst = &self.0;
+        let root = fst.root();
+        let mut out: Vec<CombinationWindow> = Vec::new();
+        self.window_recurse(&mut Search { ends_in_prefix, filter: Some(filter), ..Search::new(word_possibilities, &mut out) }, 0, &root, max_phrase_dist, &mut WordPath::new(), Output::zero())?;
         Ok(out)
     }
 
-    fn window_recurse(
+    fn window_recurse<T: MatchOutput>(
         &self,
-        possibilities: &[Vec<QueryWord>],
+        search: &mut Search<T>,
         position: usize,
         node: &Node,
         budget_remaining: u8,
-        ends_in_prefix: bool,
-        words_so_far: Vec<QueryWord>,
+        words_so_far: &mut WordPath,
         output_so_far: Output,
-        out: &mut Vec<CombinationWindow>,
     ) -> Result<(), PhraseSetError> {
-        let fst = &self.0;
+        let (possibilities, ends_in_prefix) = (search.possibilities, search.ends_in_prefix);
+        let (filter, constraints) = (search.filter, search.constraints);
+        let allowed = |range: (Output, Output)| match filter {
+            Some(f) => f.allows_range((range.0.value() as u32, range.1.value() as u32)),
+            None => true,
+        };
+        // any constraints on particular positions have already been applied by pruning the word
+        // possibilities, but we only know which word is last when we're about to emit a result
+        let last_allowed = |word: &QueryWord| match constraints {
+            Some((constraints, word_classes)) => constraints.allows(word, position, true, word_classes),
+            None => true,
+        };
 
         // This function can reach four different states in which it might produce output,
         // described individually below
-        for word in possibilities[position].iter() {
+        for (idx, word) in possibilities[position].iter().enumerate() {
             match word {
                 QueryWord::Full { key, edit_distance, .. } => {
-                    if *edit_distance > budget_remaining {
-                        break
-                    }
+                    // only recurse or add a result if the current word is in the graph in this
+                    // position (and, if we've been given a filter, some of the phrases reachable
+                    // from there are allowed); at this stage, output will be the output state
+                    // beyond the start of the graph, based on having traversed one particular
+                    // path from the input node
+                    let (search_node, output, budget) = match self.step_word(node, output_so_far, key, *edit_distance, budget_remaining, filter) {
+                        WordStep::OverBudget => break,
+                        WordStep::Missing => continue,
+                        WordStep::Found(search_node, output, budget) => (search_node, output, budget),
+                    };
 
-                    let mut found = true;
-                    // make a mutable copy to traverse
-                    let mut search_node = node.to_owned();
-                    let mut incr_output = Output::zero();
-                    for b in key.into_iter() {
-                        if let Some(i) = search_node.find_input(*b) {
-                            let trans = search_node.transition(i);
-                            incr_output = incr_output.cat(trans.out);
-                            search_node = fst.node(trans.addr);
-                        } else {
-                            found = false;
-                            break;
+                    // we want to add a result if we're at the end OR if we've hit a final
+                    // node OR we're at the end of the phrase
+                    words_so_far.push(idx as u32);
+                    // if we're keeping track of partial matches, the words so far are one,
+                    // whether or not they go on to match anything complete
+                    if let Some(ref mut partials) = search.partials {
+                        let depth = position + 1;
+                        if depth >= partials.depth {
+                            let range = self.reachable_range(search_node, output);
+                            partials.offer(depth, T::from_path(possibilities, words_so_far, range, true));
                         }
                     }
-                    // at this stage, incr_output will be the additional output state beyond
-                    // what it was at the start of this function, based on having traversed one
-                    // particular path from the intput node
-
-                    // only recurse or add a result if we the current word is in the graph in
-                    // this position
-                    if found {
-                        // we want to add a result if we're at the end OR if we've hit a final
-                        // node OR we're at the end of the phrase
-                        let mut rec_so_far = words_so_far.clone();
-                        rec_so_far.push(word.clone());
-                        if position < possibilities.len() - 1 {
-                            if search_node.is_final() {
-                                let final_output = output_so_far.cat(incr_output).cat(search_node.final_output());
-                                // possibility number 1: we're not at the end of our input, but
-                                // we've seen an entire phrase represented by input we've seen so
-                                // far -- we've reached a final node in the graph
-                                out.push(CombinationWindow {
-                                    phrase: rec_so_far.clone(),
-                                    output_range: (final_output, final_output),
-                                    ends_in_prefix: false
-                                });
+                    if position < possibilities.len() - 1 {
+                        if search_node.is_final() {
+                            let final_output = output.cat(search_node.final_output());
+                            // possibility number 1: we're not at the end of our input, but
+                            // we've seen an entire phrase represented by input we've seen so
+                            // far -- we've reached a final node in the graph
+                            if allowed((final_output, final_output)) && last_allowed(word) {
+                                search.out.push(T::from_path(possibilities, words_so_far, (final_output, final_output), false));
                             }
-                            self.window_recurse(
-                                possibilities,
-                                position + 1,
-                                &search_node,
-                                budget_remaining - edit_distance,
-                                ends_in_prefix,
-                                rec_so_far,
-                                output_so_far.cat(incr_output),
-                                out,
-                            )?;
-                        } else {
-                            // if we're at the end, require final node unless autocomplete is on
-                            if ends_in_prefix {
-                                let range = (PhraseSetMatchState::EndsInFullWord { node: search_node, output: output_so_far.cat(incr_output) }).prefix_range(fst);
-                                // possibility number 2: we *are* at the end of our input, and are
-                                // doing prefix matching, so we're okay returning whatever partial
-                                // phrase we happen to have found so far
-                                out.push(CombinationWindow {
-                                    phrase: rec_so_far,
-                                    output_range: range,
-                                    ends_in_prefix: true
-                                });
-                            } else if search_node.is_final() {
-                                let final_output = output_so_far.cat(incr_output).cat(search_node.final_output());
-                                // possibility number 3: we're at the end of our input, and not
-                                // doing prefix matching, but that's okay because we've ended
-                                // on a final node
-                                out.push(CombinationWindow {
-                                    phrase: rec_so_far,
-                                    output_range: (final_output, final_output),
-                                    ends_in_prefix: false
-                                });
+                        }
+                        self.window_recurse(search, position + 1, &search_node, budget, words_so_far, output)?;
+                    } else {
+                        // if we're at the end, require final node unless autocomplete is on
+                        if ends_in_prefix {
+                            let range = self.reachable_range(search_node, output);
+                            // possibility number 2: we *are* at the end of our input, and are
+                            // doing prefix matching, so we're okay returning whatever partial
+                            // phrase we happen to have found so far
+                            if last_allowed(word) {
+                                search.out.push(T::from_path(possibilities, words_so_far, range, true));
+                            }
+                        } else if search_node.is_final() {
+                            let final_output = output.cat(search_node.final_output());
+                            // possibility number 3: we're at the end of our input, and not
+                            // doing prefix matching, but that's okay because we've ended
+                            // on a final node
+                            if allowed((final_output, final_output)) && last_allowed(word) {
+                                search.out.push(T::from_path(possibilities, words_so_far, (final_output, final_output), false));
                             }
                         }
                     }
+                    words_so_far.pop();
                 },
-                QueryWord::Prefix { key_range, .. } => {
+                QueryWord::Prefix { key_range, edit_distance, .. } => {
                     if !ends_in_prefix {
                         return Err(PhraseSetError::new(
                             "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
                         ))
                     }
-                    if let WordPrefixMatchResult::Found(state) = self.matches_prefix_range(
-                        node.addr(),
-                        output_so_far,
-                        *key_range
-                    ) {
+                    if position + 2 < possibilities.len() {
+                        return Err(PhraseSetError::new(
+                            "Only the last two slots of a prefix query may have a QueryWord::Prefix"
+                        ))
+                    } else if *edit_distance > budget_remaining {
+                        continue
+                    } else if position + 2 == possibilities.len() {
+                        // a prefix followed by one more word at the end of the query: try each word
+                        // it could be on its own, and only produce windows that carry on through
+                        // the last position
+                        // (partial windows aren't tracked through the words of a prefix, so they're
+                        // set aside while we try them)
+                        let partials = search.partials.take();
+                        for (search_node, incr_output) in self.words_in_range(node, *key_range) {
+                            if !self.reaches_allowed(search_node, output_so_far.cat(incr_output), filter) {
+                                continue
+                            }
+                            words_so_far.push(idx as u32);
+                            self.window_recurse(
+                                search,
+                                position + 1,
+                                &search_node,
+                                budget_remaining - edit_distance,
+                                words_so_far,
+                                output_so_far.cat(incr_output),
+                            )?;
+                            words_so_far.pop();
+                        }
+                        search.partials = partials;
+                    } else if let Some(range) = self.step_final_prefix(node, output_so_far, *key_range) {
                         // presumably the prefix is at the end, so we don't need to consider the
                         // possibility of recursing, just of being done; we can also assume AC is on
-                        let mut rec_so_far = words_so_far.clone();
-                        rec_so_far.push(word.clone());
-                        let range = (PhraseSetMatchState::EndsInPrefix(state)).prefix_range(fst);
                         // possibility number 4: we're doing prefix matching, and we're at the end
                         // of our input and we're ending with a word range instead of a single word,
                         // so we've explored all the possible terminations that are reachable from
                         // this range and are pushing an output state that represents all of them
-                        out.push(CombinationWindow {
-                            phrase: rec_so_far,
-                            output_range: range,
-                            ends_in_prefix: true
-                        });
+                        if allowed(range) && last_allowed(word) {
+                            words_so_far.push(idx as u32);
+                            search.out.push(T::from_path(possibilities, words_so_far, range, true));
+                            words_so_far.pop();
+                        }
                     }
                 },
             }
@@ -413,12 +561,115 @@ impl PhraseSet {
         Ok(())
     }
 
+    /// Same as `match_combinations`, but only matches words whose classes (as given by
+    /// `word_classes`) satisfy `constraints`. Since every match covers all of the slots, the last
+    /// slot is the `TokenPosition::Last` one.
+    pub fn match_combinations_constrained(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        constraints: &TokenConstraints,
+        word_classes: &WordClasses
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        let word_possibilities = constraints.prune(word_possibilities, true, word_classes);
+        self.match_combinations(&word_possibilities, max_phrase_dist)
+    }
+
+    /// Same as `match_combinations_as_prefixes`, but only matches words whose classes satisfy
+    /// `constraints`, as in `match_combinations_constrained`.
+    pub fn match_combinations_as_prefixes_constrained(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        constraints: &TokenConstraints,
+        word_classes: &WordClasses
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        let word_possibilities = constraints.prune(word_possibilities, true, word_classes);
+        self.match_combinations_as_prefixes(&word_possibilities, max_phrase_dist)
+    }
+
+    /// Same as `match_combinations_as_windows`, but only matches words whose classes satisfy
+    /// `constraints`, with positions counted from the start of each window and
+    /// `TokenPosition::Last` referring to the window's last token. Windows can additionally be
+    /// restricted with a `filter`, as in `match_combinations_as_windows_filtered`.
+    pub fn match_combinations_as_windows_constrained(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        ends_in_prefix: bool,
+        constraints: &TokenConstraints,
+        word_classes: &WordClasses,
+        filter: Option<&dyn PhraseIdFilter>
+    ) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        let word_possibilities = constraints.prune(word_possibilities, false, word_classes);
+        let last_constraints = if constraints.has_last() { Some((constraints, word_classes)) } else { None };
+        let mut out: Vec<CombinationWindow> = Vec::new();
+        let mut search = Search { ends_in_prefix, filter, constraints: last_constraints, ..Search::new(&word_possibilities, &mut out) };
+        self.window_recurse(&mut search, 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(out)
+    }
+
+    /// A fallback for when `match_combinations_as_windows` doesn't find anything: find the
+    /// longest windows starting at the first slot that are prefixes of some phrase, whether or
+    /// not any phrase is ever completed. Every window returned covers the same number of slots,
+    /// and has `ends_in_prefix` set, with an output range covering all the phrases it could be
+    /// the beginning of.
+    pub fn match_combinations_as_partial_windows(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        ends_in_prefix: bool
+    ) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        let mut partials = BestPartials { depth: 0, found: Vec::new() };
+        let mut out: Vec<CombinationWindow> = Vec::new();
+        let mut search = Search { ends_in_prefix, partials: Some(&mut partials), ..Search::new(word_possibilities, &mut out) };
+        self.window_recurse(&mut search, 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(partials.found)
+    }
+
+    /// Same as `match_combinations`, but returns `IndexedCombination`s.
+    pub fn match_combinations_indexed(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8
+    ) -> Result<Vec<IndexedCombination>, PhraseSetError> {
+        let mut out: Vec<IndexedCombination> = Vec::new();
+        self.exact_recurse(&mut Search::new(word_possibilities, &mut out), 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(out)
+    }
+
+    /// Same as `match_combinations_as_prefixes`, but returns `IndexedCombination`s.
+    pub fn match_combinations_as_prefixes_indexed(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8
+    ) -> Result<Vec<IndexedCombination>, PhraseSetError> {
+        let mut out: Vec<IndexedCombination> = Vec::new();
+        self.prefix_recurse(&mut Search::new(word_possibilities, &mut out), 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(out)
+    }
+
+    /// Same as `match_combinations_as_windows`, but returns `IndexedCombination`s.
+    pub fn match_combinations_as_windows_indexed(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        ends_in_prefix: bool
+    ) -> Result<Vec<IndexedCombination>, PhraseSetError> {
+        let mut out: Vec<IndexedCombination> = Vec::new();
+        self.window_recurse(&mut Search { ends_in_prefix, ..Search::new(word_possibilities, &mut out) }, 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        Ok(out)
+    }
+
     /// Go from ID to set of word IDs, rather than the other way around. The approach is to start
     /// with the sought ID and subtract as we go, until we get to zero, at which point we wait
     /// until we hit a final state and then return. At each juncture, we're looking for the
     /// transition with the largest output that's still smaller than what we have left in our
     /// target ID
     pub fn get_by_id(&self, mut id: Output) -> Option<Vec<u32>> {
+        if !self.2 {
+            return None;
+        }
         let fst = &self.0;
         let mut node = fst.root();
 
@@ -477,25 +728,67 @@ impl PhraseSet {
     /// prefix. This function is used within several different phrase graph exploration methods
     /// to do end-of-query checking in query modes where terminal partial words are allowed.
     ///
-    /// The strategy is essentially: given a range, find the first word ID (three-byte sequence)
-    /// that's greater than or equal to the lower bound of the range. If there is such an ID,
-    /// and it's less than or equal to the upper bound, we've successfully found a match.
+    /// Specifically: if no word continuing from `start_position` has a key within `key_range`
+    /// (inclusive at both ends), the result is `NotFound`. Otherwise it's `Found`, with the
+    /// `min_prefix_*` fields describing the smallest such word and the `max_prefix_*` fields the
+    /// largest (which may be the same word): the node each one leads to, and the output
+    /// accumulated from the root to get there (`start_output` plus the word's own transitions).
+    /// The minimum and maximum phrase IDs reachable given the constraints of our range can be
+    /// read off from those.
     ///
-    /// If we have a match, we'll also want to find the maximum viable ID (the biggest one less
-    /// than or equal to the upper bound of our range), in addition to the minimum viable ID, so
-    /// that we can ascertain the minimum and maximum phrase IDs that are reachable from our
-    /// current position given the constraints of our range.
+    /// The strategy is to find the first word ID (three-byte sequence) that's greater than or
+    /// equal to the lower bound of the range; if there is one, and it's less than or equal to the
+    /// upper bound, the last word ID less than or equal to the upper bound is found the same way,
+    /// mirror-imaged.
     fn matches_prefix_range(&self, start_position: CompiledAddr, start_output: Output, key_range: (WordKey, WordKey)) -> WordPrefixMatchResult {
         let (sought_min_key, sought_max_key) = key_range;
+        debug_assert!(sought_min_key <= sought_max_key, "Prefix key range {:?} is empty", key_range);
 
-		// self as fst
-        let fst = &self.0;
+        let node0 = self.0.node(start_position);
+
+        // get the first word greater than or equal to the sought min
+        let (next_after_min, min_node, min_output) = match self.first_word_gte(&node0, sought_min_key) {
+            Some(word) => word,
+            None => return WordPrefixMatchResult::NotFound,
+        };
+        if next_after_min > sought_max_key {
+            return WordPrefixMatchResult::NotFound;
+        }
+
+        // we found the first triple after the minimum, but we also need the last before the
+        // maximum. This is guaranteed to succeed, since next_after_min is itself a plausible
+        // last_before_max, so there's at least one valid one
+        let (last_before_max, max_node, max_output) = self.last_word_lte(&node0, sought_max_key)
+            .expect("the first word within a prefix range is also a candidate for the last");
+        debug_assert!(next_after_min <= last_before_max);
+
+        WordPrefixMatchResult::Found(WordPrefixMatchState {
+            min_prefix_node: min_node,
+            min_prefix_output: start_output.cat(min_output),
+            max_prefix_node: max_node,
+            max_prefix_output: start_output.cat(max_output)
+        })
+    }
+
+    /// Whether any word within `key_range` continues from `node`. This is the first half of
+    /// `matches_prefix_range`, which stops at the first word it finds rather than going on to
+    /// look for the last one, for callers that don't need the range of phrases beyond it.
+    #[inline(always)]
+    fn has_word_in_range(&self, node: &Node, key_range: (WordKey, WordKey)) -> bool {
+        self.first_word_gte(node, key_range.0).is_some_and(|(key, _, _)| key <= key_range.1)
+    }
 
-        // get min value greater than or equal to the sought min
-        let node0 = fst.node(start_position);
-        let range0 = match self.find_first_gte(&node0, sought_min_key[0]) {
+    /// The first word continuing from `node` whose key is at least `min_key`, along with the node
+    /// it leads to and the output accumulated along the way.
+    fn first_word_gte<'f>(&'f self, node0: &Node<'f>, min_key: WordKey) -> Option<(WordKey, Node<'f>, Output)> {
+        if let Some(found) = self.first_word_gte_from_table(node0, min_key) {
+            return found;
+        }
+        let fst = &self.0;
+        let sought_min_key = min_key;
+        let range0 = match self.find_first_gte(node0, sought_min_key[0]) {
             Some(idx) => idx..node0.len(),
-            None => { return WordPrefixMatchResult::NotFound; }
+            None => { return None; }
         };
         for i0 in range0 {
             let t0 = node0.transition(i0);
@@ -542,72 +835,61 @@ impl PhraseSet {
 
                 // we've got three bytes! woohoo!
                 let next_after_min = [t0.inp, t1.inp, t2.inp];
+                return Some((next_after_min, fst.node(t2.addr), t0.out.cat(t1.out).cat(t2.out)));
+            }
+        }
+        None
+    }
 
-                if next_after_min <= sought_max_key {
-                    // we found the first triple after the minimum,
-                    // but we also need the last before the maximum
-                    //
-                    // here we'll do the same as above: a three-byte walk, but mirror-imaged
-                    // this should be guaranteed to succeed, since the next_after_min could also
-                    // be a plausible last_before_max, so there's at least one valid one
-
-                    let max_node0 = fst.node(start_position);
-                    let max_range0 = match self.find_last_lte(&max_node0, sought_max_key[0]) {
-                        Some(idx) => (0..(idx + 1)).rev(),
-                        None => { return WordPrefixMatchResult::NotFound; }
-                    };
-                    for max_i0 in max_range0 {
-                        let max_t0 = max_node0.transition(max_i0);
-
-                        let max_node1 = fst.node(max_t0.addr);
-                        let max_must_skip1 = max_t0.inp == sought_max_key[0];
-                        let max_range1 = if max_must_skip1 {
-                            match self.find_last_lte(&max_node1, sought_max_key[1]) {
-                                Some(idx) => (0..(idx + 1)).rev(),
-                                None => { continue; }
-                            }
-                        } else {
-                            (0..max_node1.len()).rev()
-                        };
-                        for max_i1 in max_range1 {
-                            let max_t1 = max_node1.transition(max_i1);
-
-                            let max_node2 = fst.node(max_t1.addr);
-                            let max_must_skip2 = max_must_skip1 && max_t1.inp == sought_max_key[1];
-                            let max_i2 = if max_node2.len() == 0 {
-                                continue;
-                            } else if max_must_skip2 {
-                                match self.find_last_lte(&max_node2, sought_max_key[2]) {
-                                    Some(idx) => idx,
-                                    None => { continue; }
-                                }
-                            } else {
-                                max_node2.len() - 1
-                            };
-                            let max_t2 = max_node2.transition(max_i2);
-
-                            // we've got three bytes! woohoo!
-                            return WordPrefixMatchResult::Found(WordPrefixMatchState {
-                                min_prefix_node: fst.node(t2.addr),
-                                min_prefix_output: start_output.cat(t0.out).cat(t1.out).cat(t2.out),
-                                max_prefix_node: fst.node(max_t2.addr),
-                                max_prefix_output: start_output.cat(max_t0.out).cat(max_t1.out).cat(max_t2.out)
-                            });
-                        }
-                    }
+    /// The last word continuing from `node` whose key is at most `max_key`, along with the node it
+    /// leads to and the output accumulated along the way. This is `first_word_gte` mirror-imaged:
+    /// each byte is only bounded by `max_key` while the bytes before it are equal to its own, and
+    /// a branch that turns out to have nothing low enough further down is backed out of in favor
+    /// of the next one below it.
+    fn last_word_lte<'f>(&'f self, node0: &Node<'f>, max_key: WordKey) -> Option<(WordKey, Node<'f>, Output)> {
+        if let Some(found) = self.last_word_lte_from_table(node0, max_key) {
+            return found;
+        }
+        let fst = &self.0;
+        let range0 = match self.find_last_lte(node0, max_key[0]) {
+            Some(idx) => (0..(idx + 1)).rev(),
+            None => { return None; }
+        };
+        for i0 in range0 {
+            let t0 = node0.transition(i0);
 
-                    return WordPrefixMatchResult::Found(WordPrefixMatchState {
-                        min_prefix_node: fst.node(t2.addr),
-                        min_prefix_output: start_output.cat(t0.out).cat(t1.out).cat(t2.out),
-                        max_prefix_node: fst.node(t2.addr),
-                        max_prefix_output: start_output.cat(t0.out).cat(t1.out).cat(t2.out)
-                    });
-                } else {
-                    return WordPrefixMatchResult::NotFound;
+            let node1 = fst.node(t0.addr);
+            let must_skip1 = t0.inp == max_key[0];
+            let range1 = if must_skip1 {
+                match self.find_last_lte(&node1, max_key[1]) {
+                    Some(idx) => (0..(idx + 1)).rev(),
+                    None => { continue; }
                 }
+            } else {
+                (0..node1.len()).rev()
+            };
+            for i1 in range1 {
+                let t1 = node1.transition(i1);
+
+                let node2 = fst.node(t1.addr);
+                let must_skip2 = must_skip1 && t1.inp == max_key[1];
+                let i2 = if node2.len() == 0 {
+                    continue;
+                } else if must_skip2 {
+                    match self.find_last_lte(&node2, max_key[2]) {
+                        Some(idx) => idx,
+                        None => { continue; }
+                    }
+                } else {
+                    node2.len() - 1
+                };
+                let t2 = node2.transition(i2);
+
+                let last_before_max = [t0.inp, t1.inp, t2.inp];
+                return Some((last_before_max, fst.node(t2.addr), t0.out.cat(t1.out).cat(t2.out)));
             }
         }
-        WordPrefixMatchResult::NotFound
+        None
     }
 
     // given a state in an FST, this finds the transition out with the smallest input that's at least
@@ -688,6 +970,20 @@ impl PhraseSet {
         &self.0
     }
 
+    /// The build identifier this set was stamped with by `PhraseSetBuilder::new_with_build_id`
+    /// (or 0, if it wasn't).
+    pub fn build_id(&self) -> u64 {
+        self.0.fst_type()
+    }
+
+    /// Whether the set stores phrase IDs. Sets built without them (see
+    /// `PhraseSetBuilder::set_store_ids`) still answer membership and prefix queries, but
+    /// `get_by_id` and the IDs of lookup results are unavailable, and the ID ranges of matched
+    /// combinations are all `(1, 1)`, which doesn't mean anything.
+    pub fn has_ids(&self) -> bool {
+        self.2
+    }
+
     pub fn get_max_id(&self) -> Output {
         // chase the maximum ID down the phrase tree
         let mut max_node: Node = self.0.root();
@@ -700,27 +996,155 @@ impl PhraseSet {
         max_output.cat(max_node.final_output())
     }
 
+    /// Compare this set against another one by streaming both in lockstep, and return the
+    /// phrases (as word ID sequences) that are only in `other` (added) or only in this set
+    /// (removed). Phrases are compared by word ID, so this is only meaningful if both sets were
+    /// built against the same vocabulary; `FuzzyPhraseSet::diff` compares phrases by their words
+    /// instead.
+    pub fn diff(&self, other: &PhraseSet) -> PhraseSetDiff {
+        let mut diff = PhraseSetDiff { added: Vec::new(), removed: Vec::new() };
+
+        let mut ours = self.0.stream();
+        let mut theirs = other.0.stream();
+        let mut a: Option<Vec<u8>> = ours.next().map(|(k, _)| k.to_vec());
+        let mut b: Option<Vec<u8>> = theirs.next().map(|(k, _)| k.to_vec());
+        loop {
+            let ord = match (&a, &b) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(x), Some(y)) => x.cmp(y),
+            };
+            match ord {
+                Ordering::Less => {
+                    diff.removed.push(key_to_word_ids(a.as_ref().unwrap()).expect("Phrase keys are made of whole words"));
+                    a = ours.next().map(|(k, _)| k.to_vec());
+                },
+                Ordering::Greater => {
+                    diff.added.push(key_to_word_ids(b.as_ref().unwrap()).expect("Phrase keys are made of whole words"));
+                    b = theirs.next().map(|(k, _)| k.to_vec());
+                },
+                Ordering::Equal => {
+                    a = ours.next().map(|(k, _)| k.to_vec());
+                    b = theirs.next().map(|(k, _)| k.to_vec());
+                },
+            }
+        }
+        diff
+    }
+
     /// Create from a raw byte sequence, which must be written by `PhraseSetBuilder`.
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, fst::Error> {
-        Fst::from_bytes(bytes).map(PhraseSet)
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, fst_compat::Error> {
+        #[cfg(feature = "compression")]
+        let bytes = ::compress::decompress_if_framed(bytes)?;
+        fst_compat::from_bytes(bytes).map(PhraseSet::from_fst)
     }
 
-    #[cfg(feature = "mmap")]
-    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, fst::Error> {
-        Fst::from_path(path).map(PhraseSet)
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, fst_compat::Error> {
+        #[cfg(feature = "compression")]
+        return ::compress::fst_from_path(path).map(PhraseSet::from_fst);
+        #[cfg(not(feature = "compression"))]
+        return fst_compat::from_path(path).map(PhraseSet::from_fst);
     }
 
+    fn from_fst(fst: Fst) -> PhraseSet {
+        let has_ids = stores_ids(&fst);
+        PhraseSet(fst, None, has_ids)
+    }
+}
+
+// Whether a set's phrases are numbered by ID. A set with IDs always numbers its first phrase 0,
+// while `PhraseSetBuilder` gives every phrase of a set without them an output of 1 instead; that
+// costs no more than an output on each of the root's transitions (everything below them is 0),
+// and it's there in the set itself, so it can be read off the root without looking any further.
+fn stores_ids(fst: &Fst) -> bool {
+    let root = fst.root();
+    if root.is_final() {
+        root.final_output().value() == 0
+    } else {
+        root.is_empty() || root.transition(0).out.value() == 0
+    }
 }
 
 impl<'s, 'a> IntoStreamer<'a> for &'s PhraseSet {
-    type Item = (&'a [u8], fst::raw::Output);
-    type Into = fst::raw::Stream<'s>;
+    type Item = (&'a [u8], fst_compat::raw::Output);
+    type Into = fst_compat::raw::Stream<'s>;
 
     fn into_stream(self) -> Self::Into {
         self.0.stream()
     }
 }
 
+// the parts of a graph search that stay the same as it recurses: the slots being matched, what
+// a match has to satisfy, and where matches go
+struct Search<'a, T: 'a> {
+    possibilities: &'a [Vec<QueryWord>],
+    // whether the end of the query may be the beginning of a phrase (windows only)
+    ends_in_prefix: bool,
+    filter: Option<&'a dyn PhraseIdFilter>,
+    // for checking the last word of each window
+    constraints: Option<(&'a TokenConstraints, &'a WordClasses)>,
+    partials: Option<&'a mut BestPartials<T>>,
+    // stop once this many matches have been found (exact and prefix matches only)
+    limit: usize,
+    out: &'a mut Vec<T>,
+}
+
+impl<'a, T> Search<'a, T> {
+    // an unfiltered, unconstrained and unlimited search
+    fn new(possibilities: &'a [Vec<QueryWord>], out: &'a mut Vec<T>) -> Search<'a, T> {
+        Search {
+            possibilities,
+            ends_in_prefix: false,
+            filter: None,
+            constraints: None,
+            partials: None,
+            limit: usize::MAX,
+            out,
+        }
+    }
+}
+
+// the deepest dead ends seen so far in a window search, for `match_combinations_as_partial_windows`
+struct BestPartials<T> {
+    depth: usize,
+    found: Vec<T>,
+}
+
+impl<T> BestPartials<T> {
+    fn offer(&mut self, depth: usize, candidate: T) {
+        if depth > self.depth {
+            self.depth = depth;
+            self.found.clear();
+        }
+        if depth == self.depth {
+            self.found.push(candidate);
+        }
+    }
+}
+
+// run a search twice: once restricted to the bias set, and once unrestricted to fill whatever
+// room is left. If the first pass didn't hit the limit, it found every favored combination,
+// so the second pass only has to skip those; it can turn up at most as many of them as the
+// first pass did, so the limit it's given is enough to fill the remaining space.
+fn biased_search<F>(limit: usize, bias: &dyn PhraseIdFilter, search: F) -> Result<Vec<Combination>, PhraseSetError>
+    where F: Fn(Option<&dyn PhraseIdFilter>, usize, &mut Vec<Combination>) -> Result<(), PhraseSetError> {
+    let mut out: Vec<Combination> = Vec::new();
+    if limit == 0 {
+        return Ok(out);
+    }
+    search(Some(bias), limit, &mut out)?;
+    if out.len() < limit {
+        let mut rest: Vec<Combination> = Vec::new();
+        search(None, limit, &mut rest)?;
+        let remaining = limit - out.len();
+        out.extend(rest.into_iter()
+            .filter(|c| !bias.allows_range((c.output_range.0.value() as u32, c.output_range.1.value() as u32)))
+            .take(remaining));
+    }
+    Ok(out)
+}
+
 #[derive(Debug)]
 pub struct WordPrefixMatchState<'a> {
     min_prefix_node: Node<'a>,
@@ -775,6 +1199,20 @@ impl<'a> PhraseSetMatchState<'a> {
     }
 }
 
+/// How a looked-up phrase relates to the phrases in a set, for callers (like autocomplete) that
+/// need to treat a query that's a complete phrase differently depending on whether it could also
+/// be the start of longer ones.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PhraseMatchKind {
+    /// The query is a phrase in the set, and no longer phrase begins with it.
+    ExactOnly,
+    /// The query is a phrase in the set, and the beginning of longer ones as well.
+    ExactWithContinuations,
+    /// The query is only the beginning of longer phrases (or ends in a word prefix, which can't
+    /// match a phrase exactly).
+    PrefixOnly,
+}
+
 pub enum PhraseSetLookupResult<'a> {
     NotFound,
     Found { fst: &'a Fst, match_state: PhraseSetMatchState<'a> }
@@ -802,10 +1240,12 @@ impl<'a> PhraseSetLookupResult<'a> {
         }
     }
 
-    /// Returns the ID (accumulated output) of the current state we've reached given the input
+    /// Returns the ID (accumulated output) of the current state we've reached given the input,
+    /// if the set stores IDs
     pub fn id(&self) -> Option<Output> {
         match self {
             PhraseSetLookupResult::NotFound => None,
+            PhraseSetLookupResult::Found { fst, .. } if !stores_ids(fst) => None,
             PhraseSetLookupResult::Found { match_state, .. } => {
                 match match_state {
                     PhraseSetMatchState::EndsInFullWord { node, output } => {
@@ -821,14 +1261,38 @@ impl<'a> PhraseSetLookupResult<'a> {
         }
     }
 
-    /// Returns the range of output IDs reachable assuming the current state is a prefix
+    /// Returns the range of output IDs reachable assuming the current state is a prefix, if the
+    /// set stores IDs
     pub fn range(&self) -> Option<(Output, Output)> {
         match self {
             PhraseSetLookupResult::NotFound => None,
+            PhraseSetLookupResult::Found { fst, .. } if !stores_ids(fst) => None,
             PhraseSetLookupResult::Found { fst, match_state } => Some(match_state.prefix_range(fst))
         }
     }
 
+    /// Returns how many phrases begin with the input (counting the input itself, if it's a
+    /// complete phrase), if the set stores IDs. Since phrases are numbered in order, these are
+    /// exactly the ones in `range`, so this is cheap enough to call on every keystroke, e.g. to
+    /// decide whether there are still too many candidates to be worth showing.
+    pub fn phrase_count(&self) -> Option<u64> {
+        self.range().map(|(start, end)| end.value() - start.value() + 1)
+    }
+
+    /// Combines `found_final` and `has_continuations` into a single `PhraseMatchKind`, or returns
+    /// `None` if nothing was found.
+    pub fn match_kind(&self) -> Option<PhraseMatchKind> {
+        if !self.found() {
+            None
+        } else if !self.found_final() {
+            Some(PhraseMatchKind::PrefixOnly)
+        } else if self.has_continuations() {
+            Some(PhraseMatchKind::ExactWithContinuations)
+        } else {
+            Some(PhraseMatchKind::ExactOnly)
+        }
+    }
+
     /// Returns true if the current state is a valid prefix of other, longer phrases
     pub fn has_continuations(&self) -> bool {
         match self {
@@ -847,35 +1311,97 @@ impl<'a> PhraseSetLookupResult<'a> {
 
 pub struct PhraseSetBuilder<W> {
     builder: Builder<W>,
-    count: u64
+    count: u64,
+    store_ids: bool,
+    progress: Option<ProgressTracker>,
+    cancel: Option<Arc<AtomicBool>>
 }
 
 impl PhraseSetBuilder<Vec<u8>> {
     pub fn memory() -> Self {
-        PhraseSetBuilder { builder: Builder::memory(), count: 0 }
+        PhraseSetBuilder { builder: Builder::memory(), count: 0, store_ids: true, progress: None, cancel: None }
     }
 }
 
 impl<W: io::Write> PhraseSetBuilder<W> {
-    pub fn new(wtr: W) -> Result<PhraseSetBuilder<W>, fst::Error> {
-        Ok(PhraseSetBuilder { builder: Builder::new_type(wtr, 0)?, count: 0 })
+    pub fn new(wtr: W) -> Result<PhraseSetBuilder<W>, fst_compat::Error> {
+        PhraseSetBuilder::new_with_build_id(wtr, 0)
     }
 
-    /// Insert a phrase, specified as an array of word identifiers.
-    pub fn insert(&mut self, phrase: &[u32]) -> Result<(), fst::Error> {
-        let key = word_ids_to_key(phrase);
-        self.builder.insert(key, self.count)?;
+    /// Record `build_id` in the phrase graph's FST header (in its type field, which is otherwise
+    /// unused), where `PhraseSet::build_id` reads it back. A phrase set only makes sense with the
+    /// word IDs it was built from, so this is how `FuzzyPhraseSet::from_path` catches a graph
+    /// copied over from some other build.
+    pub fn new_with_build_id(wtr: W, build_id: u64) -> Result<PhraseSetBuilder<W>, fst_compat::Error> {
+        Ok(PhraseSetBuilder { builder: Builder::new_type(wtr, build_id)?, count: 0, store_ids: true, progress: None, cancel: None })
+    }
+
+    /// Whether to store each phrase's ID in the set (the default). Callers that only need to
+    /// know whether phrases (or prefixes of them) are in the set can leave them out, which makes
+    /// the set considerably smaller; see `PhraseSet::has_ids` for what's lost. Has to be set
+    /// before any phrases are inserted.
+    pub fn set_store_ids(&mut self, store_ids: bool) -> Result<(), fst_compat::Error> {
+        if self.count > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can't change whether IDs are stored after phrases are inserted").into());
+        }
+        self.store_ids = store_ids;
+        Ok(())
+    }
+
+    /// Call `reporter` every `interval` phrases inserted.
+    pub fn set_progress_reporter<R: ProgressReporter + Send + 'static>(&mut self, reporter: R, interval: u64) {
+        self.progress = Some(ProgressTracker::new(reporter, interval));
+    }
+
+    pub(crate) fn set_progress_tracker(&mut self, progress: Option<ProgressTracker>) {
+        self.progress = progress;
+    }
+
+    /// Make `insert` fail (with an `Interrupted` error) once `cancel` is set. Since the builder
+    /// doesn't own its writer's destination, cleaning that up is left to the caller.
+    pub fn set_cancellation_flag(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+
+    /// Insert a phrase, specified as an array of word identifiers. Fails without inserting
+    /// anything if any of them is too large to be a word ID (see `MAX_WORD_ID`), or if the set
+    /// stores IDs and already has as many phrases as a `PhraseId` can number.
+    pub fn insert(&mut self, phrase: &[u32]) -> Result<(), fst_compat::Error> {
+        check_cancelled(&self.cancel)?;
+        if self.store_ids && self.count > u64::from(u32::MAX) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Too many phrases for 32-bit phrase IDs").into());
+        }
+        let key = word_ids_to_key(phrase).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // see `stores_ids` for the marker left in sets without IDs
+        self.builder.insert(key, if self.store_ids { self.count } else { 1 })?;
         self.count += 1;
+        if let Some(ref progress) = self.progress {
+            progress.tick(BuildPhase::Phrases, self.count, self.builder.bytes_written());
+        }
         Ok(())
     }
 
-    pub fn into_inner(self) -> Result<W, fst::Error> {
+    pub fn into_inner(self) -> Result<W, fst_compat::Error> {
+        self.report_done();
         self.builder.into_inner()
     }
 
-    pub fn finish(self) -> Result<(), fst::Error> {
+    pub fn finish(self) -> Result<(), fst_compat::Error> {
+        self.report_done();
         self.builder.finish()
     }
+
+    fn report_done(&self) {
+        if let Some(ref progress) = self.progress {
+            progress.report(BuildPhase::Phrases, self.count, self.builder.bytes_written());
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PhraseSetDiff {
+    pub added: Vec<Vec<u32>>,
+    pub removed: Vec<Vec<u32>>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -890,3 +1416,89 @@ pub struct CombinationWindow {
     pub output_range: (Output, Output),
     pub ends_in_prefix: bool
 }
+
+impl Combination {
+    /// How many of the combination's words came from `source`. Alternates and token replacements
+    /// are zero distance from the query, like the words as typed, so this is how ranking can
+    /// still prefer matches that needed fewer substitutions (or tell them from typo corrections).
+    pub fn variant_count(&self, source: VariantSource) -> usize {
+        self.phrase.iter().filter(|word| word.variant_source() == source).count()
+    }
+}
+
+impl CombinationWindow {
+    /// Same as `Combination::variant_count`.
+    pub fn variant_count(&self, source: VariantSource) -> usize {
+        self.phrase.iter().filter(|word| word.variant_source() == source).count()
+    }
+}
+
+/// A `Combination` that records which word possibility was chosen at each position (as an index
+/// into that position's list) instead of copying the `QueryWord`s themselves. Up to eight words'
+/// worth of choices are stored inline, so for broad queries with many matches these take up a
+/// small fraction of the memory of full combinations. Use `materialize` to get the words back.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndexedCombination {
+    choices: SmallVec<[u32; 8]>,
+    pub output_range: (Output, Output),
+    pub ends_in_prefix: bool
+}
+
+impl IndexedCombination {
+    /// The index of the chosen possibility at each position, starting from the first.
+    pub fn choices(&self) -> &[u32] {
+        &self.choices
+    }
+
+    /// Look up the chosen words in the possibilities the combination was matched against.
+    pub fn materialize(&self, word_possibilities: &[Vec<QueryWord>]) -> Vec<QueryWord> {
+        materialize_path(word_possibilities, &self.choices)
+    }
+}
+
+#[inline(always)]
+fn materialize_path(word_possibilities: &[Vec<QueryWord>], path: &[u32]) -> Vec<QueryWord> {
+    path.iter().enumerate().map(|(position, idx)| word_possibilities[position][*idx as usize]).collect()
+}
+
+// the kinds of results the recursive matchers can produce, built from the path they've taken
+trait MatchOutput {
+    // whether the result records the range of phrases it matched; if not, the matchers are free
+    // to pass a dummy range rather than work it out
+    const NEEDS_RANGE: bool = true;
+
+    fn from_path(possibilities: &[Vec<QueryWord>], path: &[u32], output_range: (Output, Output), ends_in_prefix: bool) -> Self;
+}
+
+impl MatchOutput for Combination {
+    #[inline(always)]
+    fn from_path(possibilities: &[Vec<QueryWord>], path: &[u32], output_range: (Output, Output), _ends_in_prefix: bool) -> Self {
+        Combination { phrase: materialize_path(possibilities, path), output_range }
+    }
+}
+
+impl MatchOutput for CombinationWindow {
+    #[inline(always)]
+    fn from_path(possibilities: &[Vec<QueryWord>], path: &[u32], output_range: (Output, Output), ends_in_prefix: bool) -> Self {
+        CombinationWindow { phrase: materialize_path(possibilities, path), output_range, ends_in_prefix }
+    }
+}
+
+// a result that only records that there was one, for `has_prefix_match`
+struct AnyMatch;
+
+impl MatchOutput for AnyMatch {
+    const NEEDS_RANGE: bool = false;
+
+    #[inline(always)]
+    fn from_path(_possibilities: &[Vec<QueryWord>], _path: &[u32], _output_range: (Output, Output), _ends_in_prefix: bool) -> Self {
+        AnyMatch
+    }
+}
+
+impl MatchOutput for IndexedCombination {
+    #[inline(always)]
+    fn from_path(_possibilities: &[Vec<QueryWord>], path: &[u32], output_range: (Output, Output), ends_in_prefix: bool) -> Self {
+        IndexedCombination { choices: SmallVec::from_slice(path), output_range, ends_in_prefix }
+    }
+}
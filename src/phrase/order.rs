@@ -0,0 +1,163 @@
+use std::collections::BTreeSet;
+
+use fst_compat::raw::{CompiledAddr, Output};
+use rustc_hash::FxHashSet;
+
+use super::{PhraseSet, Combination, Search, WordPath};
+use super::query::QueryWord;
+use super::util::{three_byte_decode, PhraseSetError};
+
+/// The word IDs that occur at each position of the phrases in a `PhraseSet`, for ruling out
+/// possibilities that can't appear where they are before the graph is explored (see
+/// `PhraseSet::match_combinations_pruned`). Built from the phrases themselves with
+/// `WordDepths::from_phrases`, which the glue builder does when asked to (see
+/// `FuzzyPhraseSetBuilder::set_store_word_depths`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct WordDepths {
+    depths: Vec<BTreeSet<u32>>,
+}
+
+impl WordDepths {
+    /// Collect the word IDs at each position of `phrases`.
+    pub fn from_phrases<'a, I: IntoIterator<Item = &'a [u32]>>(phrases: I) -> WordDepths {
+        let mut depths: Vec<BTreeSet<u32>> = Vec::new();
+        for phrase in phrases {
+            if depths.len() < phrase.len() {
+                depths.resize(phrase.len(), BTreeSet::new());
+            }
+            for (depth, &id) in phrase.iter().enumerate() {
+                depths[depth].insert(id);
+            }
+        }
+        WordDepths { depths }
+    }
+
+    /// Whether any phrase has the word `id` at position `depth`.
+    pub fn contains(&self, depth: usize, id: u32) -> bool {
+        self.depths.get(depth).is_some_and(|ids| ids.contains(&id))
+    }
+
+    /// Whether any phrase has a word in the inclusive range `id_range` at position `depth`.
+    pub fn contains_range(&self, depth: usize, id_range: (u32, u32)) -> bool {
+        self.depths.get(depth).is_some_and(|ids| ids.range(id_range.0..=id_range.1).next().is_some())
+    }
+
+    /// The number of positions covered, which is the length of the longest phrase.
+    pub fn len(&self) -> usize {
+        self.depths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.depths.is_empty()
+    }
+
+    /// Drop the possibilities that don't occur at their slot's position in any phrase, starting
+    /// with the slot with the fewest possibilities, and give up as soon as a slot runs out. The
+    /// possibilities that remain keep their order.
+    pub fn prune(&self, word_possibilities: &[Vec<QueryWord>]) -> Option<Vec<Vec<QueryWord>>> {
+        let mut pruned: Vec<Vec<QueryWord>> = word_possibilities.to_vec();
+        for position in pruning_order(word_possibilities) {
+            pruned[position].retain(|word| self.allows(position, word));
+            if pruned[position].is_empty() {
+                return None;
+            }
+        }
+        Some(pruned)
+    }
+
+    /// Prune the possibilities of a window that begins a phrase, as `prune` does. A window can
+    /// stop short of a slot nothing fits in, so rather than giving up, the slots from the first
+    /// empty one on are dropped; the result is empty if the first slot is.
+    pub fn prune_window(&self, word_possibilities: &[Vec<QueryWord>]) -> Vec<Vec<QueryWord>> {
+        let mut pruned: Vec<Vec<QueryWord>> = Vec::with_capacity(word_possibilities.len());
+        for (position, words) in word_possibilities.iter().enumerate() {
+            let words: Vec<QueryWord> = words.iter().filter(|word| self.allows(position, word)).cloned().collect();
+            if words.is_empty() {
+                break;
+            }
+            pruned.push(words);
+        }
+        pruned
+    }
+
+    fn allows(&self, depth: usize, word: &QueryWord) -> bool {
+        match *word {
+            QueryWord::Full { id, .. } => self.contains(depth, id),
+            QueryWord::Prefix { id_range, .. } => self.contains_range(depth, id_range),
+        }
+    }
+}
+
+// the order to check slots in: the one with the fewest possibilities first (the earliest, if
+// there's a tie), then the rest left to right, so a query with a slot nothing fits in is usually
+// given up on after checking only a few possibilities
+fn pruning_order(word_possibilities: &[Vec<QueryWord>]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..word_possibilities.len()).collect();
+    if let Some(smallest) = (0..word_possibilities.len()).min_by_key(|&i| word_possibilities[i].len()) {
+        order.remove(smallest);
+        order.insert(0, smallest);
+    }
+    order
+}
+
+impl PhraseSet {
+    // the word IDs at each of the first `max_depth` positions, read off the graph
+    pub(super) fn collect_word_depths(&self, max_depth: usize) -> Vec<BTreeSet<u32>> {
+        let fst = &self.0;
+        let mut depths: Vec<BTreeSet<u32>> = Vec::new();
+        let mut visited: FxHashSet<(CompiledAddr, usize)> = FxHashSet::default();
+        let mut stack: Vec<(CompiledAddr, usize)> = vec![(fst.root().addr(), 0)];
+        while let Some((addr, depth)) = stack.pop() {
+            if depth >= max_depth || !visited.insert((addr, depth)) {
+                continue;
+            }
+            // every word is three transitions long
+            for t0 in fst.node(addr).transitions() {
+                for t1 in fst.node(t0.addr).transitions() {
+                    for t2 in fst.node(t1.addr).transitions() {
+                        if depths.len() <= depth {
+                            depths.resize(depth + 1, BTreeSet::new());
+                        }
+                        depths[depth].insert(three_byte_decode(&[t0.inp, t1.inp, t2.inp]));
+                        stack.push((t2.addr, depth + 1));
+                    }
+                }
+            }
+        }
+        depths
+    }
+
+    /// Same as `match_combinations`, but first drops the possibilities that `word_depths` says
+    /// can't occur at their slot's position, checking the slot with the fewest possibilities
+    /// first, and gives up without exploring the graph if any slot is left empty. The graph is
+    /// still explored left to right, so this only helps when the query has words that exist
+    /// but can't appear where they are. Results are the same, and in the same order, as
+    /// `match_combinations`'s.
+    pub fn match_combinations_pruned(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        word_depths: &WordDepths
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        let mut out: Vec<Combination> = Vec::new();
+        if let Some(word_possibilities) = word_depths.prune(word_possibilities) {
+            self.exact_recurse(&mut Search::new(&word_possibilities, &mut out), 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        }
+        Ok(out)
+    }
+
+    /// Same as `match_combinations_as_prefixes`, but pruned by `word_depths` first, as in
+    /// `match_combinations_pruned`.
+    pub fn match_combinations_as_prefixes_pruned(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        word_depths: &WordDepths
+    ) -> Result<Vec<Combination>, PhraseSetError> {
+        let mut out: Vec<Combination> = Vec::new();
+        if let Some(word_possibilities) = word_depths.prune(word_possibilities) {
+            self.prefix_recurse(&mut Search::new(&word_possibilities, &mut out), 0, &self.0.root(), max_phrase_dist, &mut WordPath::new(), Output::zero())?;
+        }
+        Ok(out)
+    }
+}
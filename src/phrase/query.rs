@@ -1,37 +1,140 @@
 use super::util;
+use super::util::PhraseSetError;
 use super::WordKey;
 
-/// An abstraction over full words and prefixes.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// An abstraction over full words and prefixes. Two words are equal if they match the same
+/// thing at the same distance; where a full word came from (its `source`) doesn't count.
+#[derive(Copy, Clone, Debug)]
 pub enum QueryWord {
     /// A `Full` word is a word that has an identifier and is one of the members of a PrefixSet.
     Full {
         id: u32,
         key: WordKey,
         edit_distance: u8,
+        source_position: Option<u16>,
+        /// Where the word came from (see `VariantSource`). Construct full words with `new_full`
+        /// or `new_full_from` rather than by hand, so that they'll keep working if more such
+        /// annotations are added.
+        source: VariantSource,
     },
 
     /// A `Prefix` is a string that is the prefix to more than one full word, and includes an id_range field,
-    /// which of identifiers.
+    /// which of identifiers. Its edit distance is zero for what was typed, and more for the
+    /// beginnings of words that are only close to it (see `new_fuzzy_prefix`).
     Prefix {
         id_range: (u32, u32),
         key_range: (WordKey, WordKey),
+        edit_distance: u8,
+        source_position: Option<u16>,
     },
 }
 
+/// Where a `QueryWord::Full` came from, relative to what was typed, so that ranking can tell a
+/// typo correction from a deliberate substitution even when both are the same edit distance
+/// from the query (an alternate or a token replacement is always at distance zero).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub enum VariantSource {
+    /// The word as typed.
+    #[default]
+    Exact,
+    /// A fuzzy match for the word as typed.
+    Typo,
+    /// The target of a token replacement (see `FuzzyPhraseSetBuilder::load_word_replacements`).
+    Replacement,
+    /// An alternative supplied with the query (see `QueryBuilder::alternates`).
+    Alternate,
+}
+
+/// The largest word ID that fits in a word key.
+pub const MAX_WORD_ID: u32 = 16_777_215;
+
 impl QueryWord
 {
 
     pub fn new_full(id:u32, edit_distance:u8) -> QueryWord {
-        let key: [u8; 3] = util::three_byte_encode(id);
-        QueryWord::Full { id, edit_distance, key }
+        QueryWord::new_full_from(id, edit_distance, VariantSource::Exact)
+    }
+
+    /// Same as `new_full`, but for a word that came from `source` rather than being typed.
+    pub fn new_full_from(id: u32, edit_distance: u8, source: VariantSource) -> QueryWord {
+        let key: [u8; 3] = util::three_byte_encode(id).expect("Word ID out of range; use try_new_full to check");
+        QueryWord::Full { id, edit_distance, key, source_position: None, source }
     }
 
     pub fn new_prefix(id_range: (u32, u32)) -> QueryWord {
-        let min_key: [u8; 3] = util::three_byte_encode(id_range.0);
-        let max_key: [u8; 3] = util::three_byte_encode(id_range.1);
+        QueryWord::new_fuzzy_prefix(id_range, 0)
+    }
+
+    /// Same as `new_prefix`, but for words that begin with something `edit_distance` edits away
+    /// from what was typed. The matchers charge that against the phrase edit distance, as they
+    /// do a full word's.
+    pub fn new_fuzzy_prefix(id_range: (u32, u32), edit_distance: u8) -> QueryWord {
+        let min_key: [u8; 3] = util::three_byte_encode(id_range.0).expect("Word ID out of range; use try_new_prefix to check");
+        let max_key: [u8; 3] = util::three_byte_encode(id_range.1).expect("Word ID out of range; use try_new_prefix to check");
         let key_range = (min_key, max_key);
-        QueryWord::Prefix { id_range, key_range }
+        QueryWord::Prefix { id_range, key_range, edit_distance, source_position: None }
+    }
+
+    /// Same as `new_full`, but fails if `id` is too large to be a word ID.
+    pub fn try_new_full(id: u32, edit_distance: u8) -> Result<QueryWord, PhraseSetError> {
+        if id > MAX_WORD_ID {
+            return Err(PhraseSetError::new(&format!("Word ID {} is larger than the maximum of {}", id, MAX_WORD_ID)));
+        }
+        Ok(QueryWord::new_full(id, edit_distance))
+    }
+
+    /// Same as `new_prefix`, but fails if the range is empty (its start is after its end) or
+    /// extends past the largest possible word ID, rather than producing a prefix that silently
+    /// matches nothing.
+    pub fn try_new_prefix(id_range: (u32, u32)) -> Result<QueryWord, PhraseSetError> {
+        if id_range.0 > id_range.1 {
+            return Err(PhraseSetError::new(&format!("Prefix range {}..={} is empty", id_range.0, id_range.1)));
+        }
+        if id_range.1 > MAX_WORD_ID {
+            return Err(PhraseSetError::new(&format!("Prefix range {}..={} extends past the maximum word ID of {}", id_range.0, id_range.1, MAX_WORD_ID)));
+        }
+        Ok(QueryWord::new_prefix(id_range))
+    }
+
+    /// Record which token of the original query this word came from, so that matched phrases
+    /// can be mapped back onto the query even when windows slide or tokens are skipped.
+    pub fn with_source_position(mut self, position: u16) -> QueryWord {
+        match self {
+            QueryWord::Full { ref mut source_position, .. } |
+            QueryWord::Prefix { ref mut source_position, .. } => *source_position = Some(position),
+        }
+        self
+    }
+
+    pub fn source_position(&self) -> Option<u16> {
+        match self {
+            &QueryWord::Full { source_position, .. } |
+            &QueryWord::Prefix { source_position, .. } => source_position,
+        }
+    }
+
+    /// How far this word (or, for a prefix, the beginning it stands for) is from what was typed.
+    pub fn edit_distance(&self) -> u8 {
+        match *self {
+            QueryWord::Full { edit_distance, .. } |
+            QueryWord::Prefix { edit_distance, .. } => edit_distance,
+        }
+    }
+
+    /// Tag a full word with where it came from. Prefixes are always what was typed, so they're
+    /// left as they are.
+    pub fn with_variant_source(mut self, variant_source: VariantSource) -> QueryWord {
+        if let QueryWord::Full { ref mut source, .. } = self {
+            *source = variant_source;
+        }
+        self
+    }
+
+    pub fn variant_source(&self) -> VariantSource {
+        match *self {
+            QueryWord::Full { source, .. } => source,
+            QueryWord::Prefix { .. } => VariantSource::Exact,
+        }
     }
 
     pub fn to_string<'a, T:Fn(u32) -> &'a str>(&self, id_to_string: T) -> String {
@@ -50,16 +153,74 @@ impl QueryWord
     }
 }
 
+impl PartialEq for QueryWord {
+    fn eq(&self, other: &QueryWord) -> bool {
+        match (*self, *other) {
+            (
+                QueryWord::Full { id, key, edit_distance, source_position, .. },
+                QueryWord::Full { id: other_id, key: other_key, edit_distance: other_distance, source_position: other_position, .. },
+            ) => id == other_id && key == other_key && edit_distance == other_distance && source_position == other_position,
+            (
+                QueryWord::Prefix { id_range, key_range, edit_distance, source_position },
+                QueryWord::Prefix { id_range: other_range, key_range: other_keys, edit_distance: other_distance, source_position: other_position },
+            ) => id_range == other_range && key_range == other_keys && edit_distance == other_distance && source_position == other_position,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for QueryWord {}
+
 impl Default for QueryWord {
     fn default() -> QueryWord {
         QueryWord::Full {
             id: 0,
             key: [255u8, 255u8, 255u8],
             edit_distance: 99,
+            source_position: None,
+            source: VariantSource::Exact,
         }
     }
 }
 
+/// Check that a list of word slots is shaped the way the `PhraseSet` matchers expect: no slot is
+/// empty, the variants within each slot are sorted by ascending edit distance (the matchers stop
+/// looking at a slot as soon as they hit a variant that's over budget), and a `Prefix` only
+/// appears in the last two slots (a prefix can be followed by one more word at the end of the
+/// query, but no further), and only if the query is allowed to end in a prefix.
+pub fn validate_word_possibilities(word_possibilities: &[Vec<QueryWord>], ends_in_prefix: bool) -> Result<(), PhraseSetError> {
+    let last_idx = word_possibilities.len().saturating_sub(1);
+    for (i, slot) in word_possibilities.iter().enumerate() {
+        if slot.is_empty() {
+            return Err(PhraseSetError::new(&format!("Word slot {} has no possibilities", i)));
+        }
+        let mut last_distance = 0u8;
+        for word in slot {
+            match word {
+                QueryWord::Full { edit_distance, .. } => {
+                    if *edit_distance < last_distance {
+                        return Err(PhraseSetError::new(&format!(
+                            "Word slot {} is not sorted by ascending edit distance", i
+                        )));
+                    }
+                    last_distance = *edit_distance;
+                },
+                QueryWord::Prefix { id_range, .. } => {
+                    if !ends_in_prefix || i + 1 < last_idx {
+                        return Err(PhraseSetError::new(&format!(
+                            "Word slot {} has a QueryWord::Prefix, but only the last two slots of a prefix query may", i
+                        )));
+                    }
+                    if id_range.0 > id_range.1 {
+                        return Err(PhraseSetError::new(&format!("Word slot {} has an empty prefix range", i)));
+                    }
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +259,69 @@ mod tests {
         let s = query_word.to_string(id_to_string_closure);
         assert_eq!(String::from("main"), s);
     }
+
+    #[test]
+    fn query_word_source_position() {
+        let word = QueryWord::new_full(1u32, 0);
+        assert_eq!(word.source_position(), None);
+        assert_eq!(word.with_source_position(3).source_position(), Some(3));
+
+        let prefix = QueryWord::new_prefix((1u32, 5u32)).with_source_position(7);
+        assert_eq!(prefix.source_position(), Some(7));
+    }
+
+    #[test]
+    fn query_word_variant_source() {
+        let word = QueryWord::new_full(1u32, 0);
+        assert_eq!(word.variant_source(), VariantSource::Exact);
+        let alternate = word.with_variant_source(VariantSource::Alternate);
+        assert_eq!(alternate.variant_source(), VariantSource::Alternate);
+        // the same word, wherever it came from
+        assert!(word == alternate);
+        assert_eq!(QueryWord::new_full_from(1u32, 0, VariantSource::Alternate).variant_source(), VariantSource::Alternate);
+        assert!(word != QueryWord::new_full(1u32, 1));
+
+        let prefix = QueryWord::new_prefix((1u32, 5u32)).with_variant_source(VariantSource::Typo);
+        assert_eq!(prefix.variant_source(), VariantSource::Exact);
+    }
+
+    #[test]
+    fn checked_query_words() {
+        assert_eq!(QueryWord::try_new_full(MAX_WORD_ID, 1).unwrap(), QueryWord::new_full(MAX_WORD_ID, 1));
+        assert!(QueryWord::try_new_full(MAX_WORD_ID + 1, 0).is_err());
+
+        assert_eq!(QueryWord::try_new_prefix((5, 5)).unwrap(), QueryWord::new_prefix((5, 5)));
+        assert_eq!(QueryWord::try_new_prefix((0, MAX_WORD_ID)).unwrap(), QueryWord::new_prefix((0, MAX_WORD_ID)));
+        assert!(QueryWord::try_new_prefix((6, 5)).is_err());
+        assert!(QueryWord::try_new_prefix((5, MAX_WORD_ID + 1)).is_err());
+    }
+
+    #[test]
+    fn validate_word_possibilities_test() {
+        let good = vec![
+            vec![QueryWord::new_full(1, 0), QueryWord::new_full(5, 1)],
+            vec![QueryWord::new_prefix((10, 20)), QueryWord::new_full(3, 1)],
+        ];
+        assert!(validate_word_possibilities(&good, true).is_ok());
+        // a prefix is only allowed if the query ends in one
+        assert!(validate_word_possibilities(&good, false).is_err());
+
+        let unsorted = vec![vec![QueryWord::new_full(1, 1), QueryWord::new_full(5, 0)]];
+        assert!(validate_word_possibilities(&unsorted, false).is_err());
+
+        let early_prefix = vec![vec![QueryWord::new_prefix((10, 20))], vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_full(2, 0)]];
+        assert!(validate_word_possibilities(&early_prefix, true).is_err());
+
+        // but one can be followed by one more word at the end of the query, and no further
+        let two_prefixes = vec![vec![QueryWord::new_prefix((10, 20))], vec![QueryWord::new_prefix((1, 5))]];
+        assert!(validate_word_possibilities(&two_prefixes, true).is_ok());
+        assert!(validate_word_possibilities(&two_prefixes, false).is_err());
+        let prefix_then_full = vec![vec![QueryWord::new_prefix((10, 20))], vec![QueryWord::new_full(1, 0)]];
+        assert!(validate_word_possibilities(&prefix_then_full, true).is_ok());
+        let three_prefixes = vec![vec![QueryWord::new_prefix((10, 20))]; 3];
+        assert!(validate_word_possibilities(&three_prefixes, true).is_err());
+
+        let empty_slot = vec![vec![QueryWord::new_full(1, 0)], vec![]];
+        assert!(validate_word_possibilities(&empty_slot, false).is_err());
+    }
 }
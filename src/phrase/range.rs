@@ -0,0 +1,117 @@
+use fst_compat::raw::{Fst, Node, Output};
+
+use super::{PhraseSet, WordKey};
+use super::root::RootTable;
+
+// one level of a `WordsInRange` walk: the node whose transitions are being tried, the next one to
+// try, the output accumulated on the way to the node, and whether the key bytes before it are
+// still equal to the low (and high) bound's, which is the only time its own have to be checked
+// against them
+struct Level<'f> {
+    node: Node<'f>,
+    next: usize,
+    output: Output,
+    low: bool,
+    high: bool,
+}
+
+/// Every whole word within a range of keys that continues from a node, in order, along with the
+/// node it leads to and the output accumulated along the way (as `follow_key` would give for each
+/// of them). Keys are only three bytes long, so the walk keeps its place in a fixed stack rather
+/// than allocating; from the top of a preloaded graph, it starts from each of the table's entries
+/// within the range instead of the root's transitions.
+pub(super) struct WordsInRange<'f> {
+    fst: &'f Fst,
+    key_range: (WordKey, WordKey),
+    // the table and the indexes of its entries still to walk below, if the walk started from it
+    table: Option<(&'f RootTable, &'f [u32])>,
+    // the key byte the bottom of the stack chooses
+    start_depth: usize,
+    stack: [Option<Level<'f>>; 3],
+    len: usize,
+}
+
+impl<'f> WordsInRange<'f> {
+    fn push(&mut self, level: Level<'f>) {
+        self.stack[self.len] = Some(level);
+        self.len += 1;
+    }
+}
+
+impl<'f> Iterator for WordsInRange<'f> {
+    type Item = (Node<'f>, Output);
+
+    fn next(&mut self) -> Option<(Node<'f>, Output)> {
+        let (min_key, max_key) = self.key_range;
+        loop {
+            if self.len == 0 {
+                // start below the next of the table's entries, if there are any left
+                let (table, indexes) = self.table.as_mut()?;
+                let (&index, rest) = indexes.split_first()?;
+                *indexes = rest;
+                let (addr, output) = table.entry(index as usize).expect("only entries that lead somewhere are listed");
+                let level = Level {
+                    node: self.fst.node(addr),
+                    next: 0,
+                    output,
+                    low: index as usize == table.index(&min_key),
+                    high: index as usize == table.index(&max_key),
+                };
+                self.push(level);
+                continue;
+            }
+
+            let depth = self.start_depth + self.len - 1;
+            let level = self.stack[self.len - 1].as_mut().expect("levels below the length are filled");
+            if level.next >= level.node.len() {
+                self.len -= 1;
+                continue;
+            }
+            let t = level.node.transition(level.next);
+            level.next += 1;
+            if level.low && t.inp < min_key[depth] {
+                continue
+            } else if level.high && t.inp > max_key[depth] {
+                level.next = level.node.len();
+                continue
+            }
+
+            let next = Level {
+                node: self.fst.node(t.addr),
+                next: 0,
+                output: level.output.cat(t.out),
+                low: level.low && t.inp == min_key[depth],
+                high: level.high && t.inp == max_key[depth],
+            };
+            if depth == min_key.len() - 1 {
+                return Some((next.node, next.output));
+            }
+            self.push(next);
+        }
+    }
+}
+
+impl PhraseSet {
+    /// Every whole word within `key_range` that continues from `node` (see `WordsInRange`). This
+    /// is how a prefix that's followed by one more word at the end of the query is matched: unlike
+    /// one at the end, which can stand for all of the phrases reachable through any of its words at
+    /// once, it has to be followed one word at a time.
+    pub(super) fn words_in_range<'f>(&'f self, node: &Node<'f>, key_range: (WordKey, WordKey)) -> WordsInRange<'f> {
+        let mut words = WordsInRange {
+            fst: &self.0,
+            key_range,
+            table: None,
+            start_depth: 0,
+            stack: [None, None, None],
+            len: 0,
+        };
+        match self.root_table_at(node) {
+            Some(table) => {
+                words.table = Some((table, table.entries_within(key_range)));
+                words.start_depth = table.levels();
+            },
+            None => words.push(Level { node: *node, next: 0, output: Output::zero(), low: true, high: true }),
+        }
+        words
+    }
+}
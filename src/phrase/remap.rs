@@ -0,0 +1,69 @@
+use std::io;
+
+use fst_compat::Streamer;
+use fst_compat::raw::Stream;
+
+use super::{PhraseSet, PhraseSetBuilder};
+use super::util::{key_to_word_ids, PhraseSetError};
+
+// rewrite one phrase's word IDs according to a map from old IDs to new ones
+fn remap_key(key: &[u8], id_map: &[u32]) -> Result<Vec<u32>, PhraseSetError> {
+    let word_ids = key_to_word_ids(key).map_err(|e| PhraseSetError::new(&e.to_string()))?;
+    word_ids.into_iter().map(|id| {
+        id_map.get(id as usize).cloned().ok_or_else(|| {
+            PhraseSetError::new(&format!("Word ID {} is not covered by the ID map", id))
+        })
+    }).collect()
+}
+
+fn next_remapped(stream: &mut Stream, id_map: &[u32]) -> Result<Option<Vec<u32>>, PhraseSetError> {
+    match stream.next() {
+        Some((key, _)) => remap_key(key, id_map).map(Some),
+        None => Ok(None),
+    }
+}
+
+impl PhraseSet {
+    /// Rewrite this set's phrases under new word IDs, inserting them into `builder`. `id_map`
+    /// maps each old word ID to a new one, and has to preserve word ID order (as the maps from
+    /// `PrefixSet::merge_vocabularies` do), since phrases are streamed straight through without
+    /// being re-sorted. Phrase IDs are assigned afresh by the builder.
+    pub fn remap_word_ids<W: io::Write>(&self, id_map: &[u32], builder: &mut PhraseSetBuilder<W>) -> Result<(), PhraseSetError> {
+        PhraseSet::merge_remapped(&[(self, id_map)], builder)
+    }
+
+    /// Merge several sets, each with its own map from its word IDs to those of a shared merged
+    /// vocabulary, into `builder`, by streaming all of them in lockstep. Phrases that appear in
+    /// more than one set are only inserted once. Each map has to preserve word ID order; see
+    /// `remap_word_ids`.
+    pub fn merge_remapped<W: io::Write>(sets: &[(&PhraseSet, &[u32])], builder: &mut PhraseSetBuilder<W>) -> Result<(), PhraseSetError> {
+        let mut streams: Vec<Stream> = sets.iter().map(|(set, _)| set.0.stream()).collect();
+        let mut heads: Vec<Option<Vec<u32>>> = Vec::with_capacity(sets.len());
+        for (stream, (_, id_map)) in streams.iter_mut().zip(sets.iter()) {
+            heads.push(next_remapped(stream, id_map)?);
+        }
+
+        let mut last: Option<Vec<u32>> = None;
+        loop {
+            // the number of sets being merged is small, so a linear scan for the lowest phrase is
+            // cheaper than maintaining a heap
+            let lowest = heads.iter().enumerate()
+                .filter_map(|(i, head)| head.as_ref().map(|phrase| (i, phrase)))
+                .min_by(|a, b| a.1.cmp(b.1))
+                .map(|(i, _)| i);
+            let i = match lowest {
+                Some(i) => i,
+                None => break,
+            };
+
+            let phrase = heads[i].take().unwrap();
+            heads[i] = next_remapped(&mut streams[i], sets[i].1)?;
+            if last.as_ref() == Some(&phrase) {
+                continue;
+            }
+            builder.insert(&phrase).map_err(|e| PhraseSetError::new(&format!("Couldn't insert remapped phrase: {}", e)))?;
+            last = Some(phrase);
+        }
+        Ok(())
+    }
+}
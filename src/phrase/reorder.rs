@@ -0,0 +1,173 @@
+use fst_compat::raw::{Node, Output};
+
+use super::{PhraseSet, Combination};
+use super::step::WordStep;
+use super::query::QueryWord;
+use super::util::PhraseSetError;
+
+/// A match found with some of the query's words out of order (see
+/// `PhraseSet::match_combinations_reordered`).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReorderedCombination {
+    /// The matched words, in the phrase's order.
+    pub combination: Combination,
+    /// For each of the phrase's words, the query slot it came from.
+    pub slots: Vec<usize>,
+    /// How many swaps of adjacent query words it takes to put them in the phrase's order.
+    pub swaps: u8,
+}
+
+// the slots used so far, which possibility was picked from each (in phrase order), and how many
+// swaps it took to get them in that order, along with the limits of the search
+struct ReorderPath {
+    used: Vec<bool>,
+    chosen: Vec<(usize, usize)>,
+    swaps: Vec<u8>,
+    max_swaps: u8,
+    ends_in_prefix: bool,
+}
+
+impl ReorderPath {
+    fn swaps(&self) -> u8 {
+        self.swaps.last().cloned().unwrap_or(0)
+    }
+
+    fn push(&mut self, slot: usize, idx: usize, swaps: u8) {
+        let total = self.swaps() + swaps;
+        self.used[slot] = true;
+        self.chosen.push((slot, idx));
+        self.swaps.push(total);
+    }
+
+    fn pop(&mut self) {
+        if let Some((slot, _)) = self.chosen.pop() {
+            self.used[slot] = false;
+            self.swaps.pop();
+        }
+    }
+
+    fn to_result(&self, possibilities: &[Vec<QueryWord>], output_range: (Output, Output)) -> ReorderedCombination {
+        ReorderedCombination {
+            combination: Combination {
+                phrase: self.chosen.iter().map(|&(slot, idx)| possibilities[slot][idx]).collect(),
+                output_range,
+            },
+            slots: self.chosen.iter().map(|&(slot, _)| slot).collect(),
+            swaps: self.swaps(),
+        }
+    }
+}
+
+impl PhraseSet {
+    /// Same as `match_combinations`, but also matches phrases that have the query's words in a
+    /// different order ("main 100 street" for "100 main street"), as long as it takes no more
+    /// than `max_swaps` swaps of adjacent words to put the query in the phrase's order. Swaps
+    /// are counted against their own budget, separate from `max_phrase_dist`, and reported with
+    /// each result so callers can penalize them as they see fit. At each position of the phrase,
+    /// the recursion tries the next unused slot of the query, as the plain matcher would, but
+    /// also the ones after it, with taking the one `n` unused slots ahead costing `n` swaps.
+    /// With `max_swaps` at zero, this finds the same matches as `match_combinations`.
+    pub fn match_combinations_reordered(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        max_swaps: u8
+    ) -> Result<Vec<ReorderedCombination>, PhraseSetError> {
+        self.match_reordered(word_possibilities, max_phrase_dist, max_swaps, false)
+    }
+
+    /// Same as `match_combinations_as_prefixes`, but allows reordering as in
+    /// `match_combinations_reordered`. A word prefix is only ever matched at the end of the
+    /// phrase, since it's the word the user is still typing, and unlike in
+    /// `match_combinations_as_prefixes`, it's never expanded into the words it could be when it
+    /// lands anywhere else.
+    pub fn match_combinations_as_prefixes_reordered(
+        &self,
+        word_possibilities: &[Vec<QueryWord>],
+        max_phrase_dist: u8,
+        max_swaps: u8
+    ) -> Result<Vec<ReorderedCombination>, PhraseSetError> {
+        self.match_reordered(word_possibilities, max_phrase_dist, max_swaps, true)
+    }
+
+    fn match_reordered(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, max_swaps: u8, ends_in_prefix: bool) -> Result<Vec<ReorderedCombination>, PhraseSetError> {
+        let mut out: Vec<ReorderedCombination> = Vec::new();
+        if word_possibilities.is_empty() {
+            return Ok(out);
+        }
+        let mut path = ReorderPath {
+            used: vec![false; word_possibilities.len()],
+            chosen: Vec::with_capacity(word_possibilities.len()),
+            swaps: Vec::with_capacity(word_possibilities.len()),
+            max_swaps,
+            ends_in_prefix,
+        };
+        self.reorder_recurse(word_possibilities, &self.0.root(), Output::zero(), max_phrase_dist, &mut path, &mut out)?;
+        Ok(out)
+    }
+
+    fn reorder_recurse(
+        &self,
+        possibilities: &[Vec<QueryWord>],
+        node: &Node,
+        output_so_far: Output,
+        budget_remaining: u8,
+        path: &mut ReorderPath,
+        out: &mut Vec<ReorderedCombination>,
+    ) -> Result<(), PhraseSetError> {
+        let position = path.chosen.len();
+        let is_last = position == possibilities.len() - 1;
+        let swaps_remaining = (path.max_swaps - path.swaps()) as usize;
+
+        // every unused slot passed over to get to this one would have to be swapped past it
+        let unused: Vec<usize> = (0..possibilities.len()).filter(|&slot| !path.used[slot]).take(swaps_remaining + 1).collect();
+        for (skipped, slot) in unused.into_iter().enumerate() {
+            let skipped = skipped as u8;
+            for (idx, word) in possibilities[slot].iter().enumerate() {
+                match *word {
+                    QueryWord::Full { key, edit_distance, .. } => {
+                        let (search_node, output, budget) = match self.step_word(node, output_so_far, &key, edit_distance, budget_remaining, None) {
+                            WordStep::OverBudget => break,
+                            WordStep::Missing => continue,
+                            WordStep::Found(search_node, output, budget) => (search_node, output, budget),
+                        };
+                        path.push(slot, idx, skipped);
+                        if !is_last {
+                            self.reorder_recurse(
+                                possibilities,
+                                &search_node,
+                                output,
+                                budget,
+                                path,
+                                out,
+                            )?;
+                        } else if path.ends_in_prefix {
+                            let range = self.reachable_range(search_node, output);
+                            out.push(path.to_result(possibilities, range));
+                        } else if search_node.is_final() {
+                            let final_output = output.cat(search_node.final_output());
+                            out.push(path.to_result(possibilities, (final_output, final_output)));
+                        }
+                        path.pop();
+                    },
+                    QueryWord::Prefix { key_range, edit_distance, .. } => {
+                        if !path.ends_in_prefix {
+                            return Err(PhraseSetError::new(
+                                "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
+                            ));
+                        }
+                        if !is_last || edit_distance > budget_remaining {
+                            continue
+                        }
+                        if let Some(range) = self.step_final_prefix(node, output_so_far, key_range) {
+                            path.push(slot, idx, skipped);
+                            out.push(path.to_result(possibilities, range));
+                            path.pop();
+                        }
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use fst_compat::raw::{CompiledAddr, Node, Output};
+
+use super::{PhraseSet, WordKey};
+
+// the transitions out of the top levels of the graph, decoded up front into a flat table indexed
+// by the key bytes that lead to them: `b0` for one level, `b0 * 256 + b1` for two
+pub(super) struct RootTable {
+    root_addr: CompiledAddr,
+    levels: usize,
+    entries: Vec<Option<(CompiledAddr, Output)>>,
+    // the indexes of the entries that lead somewhere, in order, for finding words within a range
+    present: Vec<u32>,
+}
+
+impl RootTable {
+    #[inline(always)]
+    pub(super) fn index(&self, key: &WordKey) -> usize {
+        key[..self.levels].iter().fold(0usize, |index, &b| (index << 8) | b as usize)
+    }
+
+    // the key bytes that lead to the entry at `index`
+    fn key_prefix(&self, index: usize) -> WordKey {
+        let mut key: WordKey = [0; 3];
+        for (level, b) in key[..self.levels].iter_mut().enumerate() {
+            *b = (index >> (8 * (self.levels - 1 - level))) as u8;
+        }
+        key
+    }
+
+    #[inline(always)]
+    pub(super) fn levels(&self) -> usize {
+        self.levels
+    }
+
+    #[inline(always)]
+    pub(super) fn entry(&self, index: usize) -> Option<(CompiledAddr, Output)> {
+        self.entries[index]
+    }
+
+    // the indexes of the entries that lead somewhere and could begin a word within the range
+    pub(super) fn entries_within(&self, key_range: (WordKey, WordKey)) -> &[u32] {
+        let (start, end) = (self.index(&key_range.0), self.index(&key_range.1));
+        let from = self.present.partition_point(|&index| (index as usize) < start);
+        let to = self.present.partition_point(|&index| (index as usize) <= end).max(from);
+        &self.present[from..to]
+    }
+}
+
+impl PhraseSet {
+    /// Decode the transitions out of the top `levels` levels of the graph (at most 2, which
+    /// makes for a table of 65,536 entries) into a flat table, so that every word looked up from
+    /// the top of the graph -- by `lookup` and by all of the matchers, whether it's a whole word
+    /// or a prefix's range of words -- skips straight past them instead of searching the root's
+    /// transitions again each time. Sets don't have a table until this is called; pass 0 to drop
+    /// it again.
+    pub fn preload(&mut self, levels: usize) {
+        let levels = levels.min(2);
+        if levels == 0 {
+            self.1 = None;
+            return;
+        }
+        let fst = &self.0;
+        let root = fst.root();
+        let mut entries: Vec<Option<(CompiledAddr, Output)>> = vec![None; 1 << (8 * levels)];
+        for t0 in root.transitions() {
+            if levels == 1 {
+                entries[t0.inp as usize] = Some((t0.addr, t0.out));
+            } else {
+                for t1 in fst.node(t0.addr).transitions() {
+                    entries[((t0.inp as usize) << 8) | t1.inp as usize] = Some((t1.addr, t0.out.cat(t1.out)));
+                }
+            }
+        }
+        let present = (0..entries.len()).filter(|&index| entries[index].is_some()).map(|index| index as u32).collect();
+        self.1 = Some(RootTable { root_addr: root.addr(), levels, entries, present });
+    }
+
+    /// How many levels of the graph have been decoded by `preload`.
+    pub fn preloaded_levels(&self) -> usize {
+        self.1.as_ref().map_or(0, |table| table.levels)
+    }
+
+    /// Follow a word's key down from `node`, returning the node it leads to and the output
+    /// accumulated along the way, or `None` if the word doesn't continue from there.
+    #[inline(always)]
+    pub(super) fn follow_key<'f>(&'f self, node: &Node<'f>, key: &WordKey) -> Option<(Node<'f>, Output)> {
+        let fst = &self.0;
+        let (mut search_node, mut output, rest) = match self.1 {
+            Some(ref table) if node.addr() == table.root_addr => {
+                let (addr, output) = table.entries[table.index(key)]?;
+                (fst.node(addr), output, &key[table.levels..])
+            },
+            _ => (node.to_owned(), Output::zero(), &key[..]),
+        };
+        for b in rest {
+            let i = search_node.find_input(*b)?;
+            let trans = search_node.transition(i);
+            output = output.cat(trans.out);
+            search_node = fst.node(trans.addr);
+        }
+        Some((search_node, output))
+    }
+
+    // the table, if it was decoded from `node`
+    #[inline(always)]
+    pub(super) fn root_table_at(&self, node: &Node) -> Option<&RootTable> {
+        self.1.as_ref().filter(|table| node.addr() == table.root_addr)
+    }
+
+    /// `first_word_gte` from the top of the graph, with the top levels read off the table.
+    /// `None` if there's no table for `node`.
+    pub(super) fn first_word_gte_from_table<'f>(&'f self, node: &Node<'f>, min_key: WordKey) -> Option<Option<(WordKey, Node<'f>, Output)>> {
+        let table = self.root_table_at(node)?;
+        let start = table.index(&min_key);
+        let from = table.present.partition_point(|&index| (index as usize) < start);
+        for &index in &table.present[from..] {
+            let (addr, output) = table.entries[index as usize].expect("only entries that lead somewhere are listed");
+            let mut key = table.key_prefix(index as usize);
+            let bound = if index as usize == start { Some(&min_key) } else { None };
+            if let Some((node, rest)) = self.descend_gte(self.0.node(addr), table.levels, bound, &mut key) {
+                return Some(Some((key, node, output.cat(rest))));
+            }
+        }
+        Some(None)
+    }
+
+    /// `last_word_lte` from the top of the graph, with the top levels read off the table.
+    /// `None` if there's no table for `node`.
+    pub(super) fn last_word_lte_from_table<'f>(&'f self, node: &Node<'f>, max_key: WordKey) -> Option<Option<(WordKey, Node<'f>, Output)>> {
+        let table = self.root_table_at(node)?;
+        let end = table.index(&max_key);
+        let to = table.present.partition_point(|&index| (index as usize) <= end);
+        for &index in table.present[..to].iter().rev() {
+            let (addr, output) = table.entries[index as usize].expect("only entries that lead somewhere are listed");
+            let mut key = table.key_prefix(index as usize);
+            let bound = if index as usize == end { Some(&max_key) } else { None };
+            if let Some((node, rest)) = self.descend_lte(self.0.node(addr), table.levels, bound, &mut key) {
+                return Some(Some((key, node, output.cat(rest))));
+            }
+        }
+        Some(None)
+    }
+
+    // the first word below `node`, whose key bytes from `depth` on are at least `min`'s if it's
+    // given (which it is while the bytes before are equal to its own); the bytes are filled in
+    // as they're found
+    fn descend_gte<'f>(&'f self, node: Node<'f>, depth: usize, min: Option<&WordKey>, key: &mut WordKey) -> Option<(Node<'f>, Output)> {
+        if depth == key.len() {
+            return Some((node, Output::zero()));
+        }
+        let start = match min {
+            Some(min) => self.find_first_gte(&node, min[depth])?,
+            None => 0,
+        };
+        for i in start..node.len() {
+            let t = node.transition(i);
+            key[depth] = t.inp;
+            let min = min.filter(|min| t.inp == min[depth]);
+            if let Some((found, rest)) = self.descend_gte(self.0.node(t.addr), depth + 1, min, key) {
+                return Some((found, t.out.cat(rest)));
+            }
+        }
+        None
+    }
+
+    // `descend_gte` mirror-imaged
+    fn descend_lte<'f>(&'f self, node: Node<'f>, depth: usize, max: Option<&WordKey>, key: &mut WordKey) -> Option<(Node<'f>, Output)> {
+        if depth == key.len() {
+            return Some((node, Output::zero()));
+        }
+        let end = match max {
+            Some(max) => self.find_last_lte(&node, max[depth])? + 1,
+            None => node.len(),
+        };
+        for i in (0..end).rev() {
+            let t = node.transition(i);
+            key[depth] = t.inp;
+            let max = max.filter(|max| t.inp == max[depth]);
+            if let Some((found, rest)) = self.descend_lte(self.0.node(t.addr), depth + 1, max, key) {
+                return Some((found, t.out.cat(rest)));
+            }
+        }
+        None
+    }
+}
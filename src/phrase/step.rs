@@ -0,0 +1,70 @@
+use fst_compat::raw::{Node, Output};
+
+use super::{PhraseSet, PhraseSetMatchState, WordPrefixMatchResult, WordKey};
+use super::filter::PhraseIdFilter;
+
+// where taking one of a slot's whole-word possibilities from a node leads
+pub(super) enum WordStep<'f> {
+    // it costs more than is left of the budget; possibilities are sorted by edit distance, so
+    // all of the slot's others do too
+    OverBudget,
+    // it doesn't continue from the node, or nothing the filter allows is reachable through it
+    Missing,
+    // the node it leads to, the output accumulated on the way there, and the budget left after it
+    Found(Node<'f>, Output, u8),
+}
+
+impl PhraseSet {
+    // The step every one of the matchers takes for a whole word, whatever it goes on to do with
+    // where it leads: the recursions for whole queries, prefixes, windows, reordered words and
+    // gapped windows, and the cooperative matcher's explicit stack.
+    pub(super) fn step_word<'f>(
+        &'f self,
+        node: &Node<'f>,
+        output_so_far: Output,
+        key: &WordKey,
+        edit_distance: u8,
+        budget_remaining: u8,
+        filter: Option<&dyn PhraseIdFilter>,
+    ) -> WordStep<'f> {
+        if edit_distance > budget_remaining {
+            return WordStep::OverBudget;
+        }
+        match self.follow_key(node, key) {
+            Some((search_node, incr_output)) => {
+                let output = output_so_far.cat(incr_output);
+                if self.reaches_allowed(search_node, output, filter) {
+                    WordStep::Found(search_node, output, budget_remaining - edit_distance)
+                } else {
+                    WordStep::Missing
+                }
+            },
+            None => WordStep::Missing,
+        }
+    }
+
+    // the range of phrases that begin with the words that led to `node`
+    pub(super) fn reachable_range(&self, node: Node, output: Output) -> (Output, Output) {
+        (PhraseSetMatchState::EndsInFullWord { node, output }).prefix_range(&self.0)
+    }
+
+    // whether any of the phrases reachable from `node` are allowed by `filter` (without one, they
+    // all are)
+    pub(super) fn reaches_allowed(&self, node: Node, output: Output, filter: Option<&dyn PhraseIdFilter>) -> bool {
+        match filter {
+            Some(f) => {
+                let range = self.reachable_range(node, output);
+                f.allows_range((range.0.value() as u32, range.1.value() as u32))
+            },
+            None => true,
+        }
+    }
+
+    // the range of phrases a word prefix in the query's last slot matches from `node`, if any
+    pub(super) fn step_final_prefix(&self, node: &Node, output_so_far: Output, key_range: (WordKey, WordKey)) -> Option<(Output, Output)> {
+        match self.matches_prefix_range(node.addr(), output_so_far, key_range) {
+            WordPrefixMatchResult::Found(state) => Some((PhraseSetMatchState::EndsInPrefix(state)).prefix_range(&self.0)),
+            WordPrefixMatchResult::NotFound => None,
+        }
+    }
+}
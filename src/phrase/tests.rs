@@ -2,12 +2,12 @@ extern crate lazy_static;
 extern crate strsim;
 extern crate regex;
 use std::fs::File;
-use fst::Streamer;
-use std::collections::BTreeMap;
+use fst_compat::Streamer;
+use std::collections::{BTreeMap, BTreeSet};
 use self::strsim::osa_distance;
 use self::regex::Regex;
 use super::*;
-use self::query::QueryWord;
+use self::query::{QueryWord, MAX_WORD_ID};
 use self::util::three_byte_decode;
 
 // the first chunk of tests assess the structure directly, with numerical inputs
@@ -147,6 +147,33 @@ fn contains_prefix_query() {
     assert_eq!(false, phrase_set.lookup(&missing_phrase).found());
 }
 
+#[test]
+fn lookup_phrase_count() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 4u32]).unwrap();
+    build.insert(&[1u32, 5u32]).unwrap();
+    build.insert(&[6u32]).unwrap();
+    let phrase_set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let count = |words: &[QueryWord]| phrase_set.lookup(words).phrase_count();
+    assert_eq!(count(&[QueryWord::new_full(1, 0)]), Some(4));
+    assert_eq!(count(&[QueryWord::new_full(1, 0), QueryWord::new_full(2, 0)]), Some(3));
+    assert_eq!(count(&[QueryWord::new_full(1, 0), QueryWord::new_full(2, 0), QueryWord::new_full(4, 0)]), Some(1));
+    assert_eq!(count(&[QueryWord::new_full(1, 0), QueryWord::new_prefix((2, 5))]), Some(4));
+    assert_eq!(count(&[QueryWord::new_full(1, 0), QueryWord::new_prefix((3, 5))]), Some(1));
+    assert_eq!(count(&[QueryWord::new_prefix((1, 6))]), Some(5));
+    assert_eq!(count(&[QueryWord::new_full(7, 0)]), None);
+
+    let mut build = PhraseSetBuilder::memory();
+    build.set_store_ids(false).unwrap();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 3u32]).unwrap();
+    let phrase_set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    assert_eq!(phrase_set.lookup(&[QueryWord::new_full(1, 0)]).phrase_count(), None);
+}
+
 #[test]
 fn contains_prefix_range() {
     let mut build = PhraseSetBuilder::memory();
@@ -685,6 +712,102 @@ fn sample_match_combinations_as_prefixes() {
     assert!(typo2 != vec![correct2.clone()]);
 }
 
+#[test]
+fn sample_match_combinations_limit() {
+    // a limited search should return exactly the first K results the unlimited search would have
+    for phrase in ["53# Country View Dr", "53# County View Dr"].iter() {
+        let variants = get_full_variants(phrase);
+        let all = SET.match_combinations(&variants, 2).unwrap();
+        for limit in 0..(all.len() + 2) {
+            let limited = SET.match_combinations_limit(&variants, 2, limit).unwrap();
+            assert_eq!(&all[..limit.min(all.len())], &limited[..]);
+        }
+    }
+
+    for phrase in ["53# Country V", "8"].iter() {
+        let variants = get_prefix_variants(phrase);
+        let all = SET.match_combinations_as_prefixes(&variants, 2).unwrap();
+        assert!(all.len() > 0);
+        for limit in 0..(all.len() + 2) {
+            let limited = SET.match_combinations_as_prefixes_limit(&variants, 2, limit).unwrap();
+            assert_eq!(&all[..limit.min(all.len())], &limited[..]);
+        }
+    }
+}
+
+#[test]
+fn sample_match_combinations_biased() {
+    // biased results should be the favored subset of the unlimited results followed by the rest,
+    // each in their original order, cut off at the limit
+    let split = |all: &[Combination], bias: &BTreeSet<u32>| {
+        let in_bias = |c: &&Combination| bias.allows_range((c.output_range.0.value() as u32, c.output_range.1.value() as u32));
+        let mut expected: Vec<Combination> = all.iter().filter(in_bias).cloned().collect();
+        expected.extend(all.iter().filter(|c| !in_bias(c)).cloned());
+        expected
+    };
+
+    for phrase in ["53# Country View Dr", "53# County View Dr"].iter() {
+        let variants = get_full_variants(phrase);
+        let all = SET.match_combinations(&variants, 2).unwrap();
+        let bias: BTreeSet<u32> = all.last().map(|c| c.output_range.0.value() as u32).into_iter().collect();
+        let expected = split(&all, &bias);
+        for limit in 0..(all.len() + 2) {
+            let biased = SET.match_combinations_biased(&variants, 2, &bias, limit).unwrap();
+            assert_eq!(&expected[..limit.min(all.len())], &biased[..]);
+        }
+    }
+
+    for phrase in ["53# Country V", "8"].iter() {
+        let variants = get_prefix_variants(phrase);
+        let all = SET.match_combinations_as_prefixes(&variants, 2).unwrap();
+        let bias: BTreeSet<u32> = all.last().map(|c| c.output_range.1.value() as u32).into_iter().collect();
+        let expected = split(&all, &bias);
+        for limit in 0..(all.len() + 2) {
+            let biased = SET.match_combinations_as_prefixes_biased(&variants, 2, &bias, limit).unwrap();
+            assert_eq!(&expected[..limit.min(all.len())], &biased[..]);
+        }
+    }
+}
+
+#[test]
+fn sample_match_combinations_indexed() {
+    // indexed results should line up one-to-one with the full ones, and materialize to the same
+    // words
+    for phrase in ["53# Country View Dr", "53# County View Dr"].iter() {
+        let variants = get_full_variants(phrase);
+        let full = SET.match_combinations(&variants, 2).unwrap();
+        let indexed = SET.match_combinations_indexed(&variants, 2).unwrap();
+        assert!(full.len() > 0);
+        assert_eq!(full.len(), indexed.len());
+        for (f, i) in full.iter().zip(indexed.iter()) {
+            assert_eq!(f.phrase, i.materialize(&variants));
+            assert_eq!(f.output_range, i.output_range);
+        }
+    }
+
+    for phrase in ["53# Country V", "84# Gleason Hollow Rd"].iter() {
+        let variants = get_prefix_variants(phrase);
+        let full = SET.match_combinations_as_prefixes(&variants, 2).unwrap();
+        let indexed = SET.match_combinations_as_prefixes_indexed(&variants, 2).unwrap();
+        assert_eq!(full.len(), indexed.len());
+        for (f, i) in full.iter().zip(indexed.iter()) {
+            assert_eq!(f.phrase, i.materialize(&variants));
+            assert_eq!(f.output_range, i.output_range);
+            assert_eq!(i.choices().len(), f.phrase.len());
+        }
+
+        let full = SET.match_combinations_as_windows(&variants, 2, true).unwrap();
+        let indexed = SET.match_combinations_as_windows_indexed(&variants, 2, true).unwrap();
+        assert!(full.len() > 0);
+        assert_eq!(full.len(), indexed.len());
+        for (f, i) in full.iter().zip(indexed.iter()) {
+            assert_eq!(f.phrase, i.materialize(&variants));
+            assert_eq!(f.output_range, i.output_range);
+            assert_eq!(f.ends_in_prefix, i.ends_in_prefix);
+        }
+    }
+}
+
 #[test]
 fn sample_contains_windows_simple() {
     // just test everything
@@ -751,6 +874,25 @@ fn sample_match_combinations_as_windows_all_prefix() {
     }
 }
 
+#[test]
+fn sample_match_combinations_as_partial_windows() {
+    let unknown = QueryWord::new_full(WORDS.len() as u32 + 1, 0);
+    for phrase in PHRASES.iter() {
+        let words: Vec<&str> = phrase.split(' ').collect();
+        if words.len() < 2 {
+            continue;
+        }
+        // the beginning of a phrase, followed by a word that doesn't continue it
+        let start = words[..(words.len() - 1)].join(" ");
+        let mut word_possibilities = get_full_variants(&start);
+        word_possibilities.push(vec![unknown]);
+
+        let results = SET.match_combinations_as_partial_windows(&word_possibilities, 2, false).unwrap();
+        assert!(results.iter().any(|r| r == &get_full_window(&start, true)));
+        assert!(results.iter().all(|r| r.phrase.len() == words.len() - 1 && r.ends_in_prefix));
+    }
+}
+
 #[test]
 fn sample_prefix_contains_windows_overlap() {
     let word_possibilities = get_prefix_variants("84# Gleason Hollow Rd");
@@ -853,4 +995,850 @@ fn sample_get_by_id() {
     for (id, phrase) in ID_PHRASES.iter().enumerate() {
         assert_eq!(&SET.get_by_id(Output::new(id as u64)).unwrap(), phrase);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn diff_phrase_sets() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 4u32]).unwrap();
+    build.insert(&[5u32, 6u32]).unwrap();
+    let old_set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[5u32, 6u32]).unwrap();
+    build.insert(&[7u32]).unwrap();
+    let new_set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let diff = old_set.diff(&new_set);
+    assert_eq!(diff.added, vec![vec![1u32, 2u32], vec![7u32]]);
+    assert_eq!(diff.removed, vec![vec![1u32, 2u32, 4u32]]);
+
+    let diff = new_set.diff(&new_set);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+}
+
+#[test]
+fn phrase_lengths() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32]).unwrap();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 4u32]).unwrap();
+    build.insert(&[5u32, 6u32]).unwrap();
+    build.insert(&[70_000u32, 2u32, 4u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let histogram: Vec<(usize, u64)> = set.length_histogram().into_iter().collect();
+    assert_eq!(histogram, vec![(1, 1), (2, 2), (3, 3)]);
+
+    let phrases: Vec<(Vec<u32>, u64)> = set.phrases_of_length(2).map(|(p, id)| (p, id.value())).collect();
+    assert_eq!(phrases, vec![(vec![1, 2], 1), (vec![5, 6], 4)]);
+    let phrases: Vec<(Vec<u32>, u64)> = set.phrases_of_length(3).map(|(p, id)| (p, id.value())).collect();
+    assert_eq!(phrases, vec![(vec![1, 2, 3], 2), (vec![1, 2, 4], 3), (vec![70_000, 2, 4], 5)]);
+    assert_eq!(set.phrases_of_length(0).count(), 0);
+    assert_eq!(set.phrases_of_length(4).count(), 0);
+}
+
+#[test]
+fn pruned_matching() {
+    let phrases: Vec<Vec<u32>> = vec![vec![1, 2, 3], vec![1, 4, 3], vec![5, 2], vec![70_000, 2, 4]];
+    let mut build = PhraseSetBuilder::memory();
+    for phrase in &phrases {
+        build.insert(phrase).unwrap();
+    }
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let depths = WordDepths::from_phrases(phrases.iter().map(|phrase| &phrase[..]));
+    assert_eq!(depths.len(), 3);
+    assert!(depths.contains(0, 70_000) && depths.contains(1, 2) && depths.contains(2, 4));
+    assert!(!depths.contains(0, 2) && !depths.contains(3, 1));
+    assert!(depths.contains_range(1, (3, 4)) && !depths.contains_range(2, (5, 69_999)));
+    assert!(WordDepths::from_phrases(Vec::<&[u32]>::new()).is_empty());
+
+    let full = |id: u32, edit_distance: u8| QueryWord::new_full(id, edit_distance);
+    let word_possibilities = vec![
+        vec![full(1, 0), full(5, 1), full(70_000, 1)],
+        vec![full(2, 0), full(4, 1), full(3, 1)],
+        vec![full(3, 0), full(4, 0)],
+    ];
+    let expected = set.match_combinations(&word_possibilities, 2).unwrap();
+    assert_eq!(expected.len(), 3);
+    assert_eq!(set.match_combinations_pruned(&word_possibilities, 2, &depths).unwrap(), expected);
+
+    let word_possibilities = vec![
+        vec![full(1, 0), full(70_000, 1)],
+        vec![full(2, 0), full(4, 1)],
+        vec![QueryWord::new_prefix((3, 4))],
+    ];
+    let expected = set.match_combinations_as_prefixes(&word_possibilities, 2).unwrap();
+    assert_eq!(expected.len(), 3);
+    assert_eq!(set.match_combinations_as_prefixes_pruned(&word_possibilities, 2, &depths).unwrap(), expected);
+
+    // the smallest slot has no word that occurs at its position, so nothing is explored
+    let word_possibilities = vec![vec![full(1, 0), full(5, 0)], vec![full(2, 0), full(4, 0)], vec![full(1, 0)]];
+    assert!(set.match_combinations_pruned(&word_possibilities, 2, &depths).unwrap().is_empty());
+}
+
+#[test]
+fn pruned_window_matching() {
+    let phrases: Vec<Vec<u32>> = vec![vec![1, 2], vec![1, 200, 3], vec![5, 2], vec![70_000, 2, 4, 9]];
+    let mut build = PhraseSetBuilder::memory();
+    for phrase in &phrases {
+        build.insert(phrase).unwrap();
+    }
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let depths = WordDepths::from_phrases(phrases.iter().map(|phrase| &phrase[..]));
+
+    let full = |id: u32, edit_distance: u8| QueryWord::new_full(id, edit_distance);
+    let queries = vec![
+        vec![vec![full(1, 0), full(2, 0), full(5, 1)], vec![full(2, 0), full(200, 1), full(1, 1)]],
+        vec![vec![full(1, 0), full(70_000, 1)], vec![full(200, 0), full(2, 0)], vec![full(3, 0), full(4, 0)]],
+        vec![vec![full(70_000, 0)], vec![full(2, 0)], vec![full(4, 0)], vec![full(9, 0), full(1, 0)]],
+        vec![vec![full(1, 0)], vec![full(2, 0)], vec![full(1, 0)], vec![full(2, 0)]],
+        vec![vec![full(2, 0)], vec![full(1, 0)]],
+    ];
+    for q in &queries[..4] {
+        let pruned = depths.prune_window(q);
+        assert_eq!(set.match_combinations_as_windows(&pruned, 2, false).unwrap(), set.match_combinations_as_windows(q, 2, false).unwrap());
+    }
+    // the second 1 can't be third in any phrase, so the window stops before it
+    assert_eq!(depths.prune_window(&queries[3]).len(), 2);
+    assert!(depths.prune_window(&queries[4]).is_empty());
+    assert_eq!(depths.prune_window(&queries[0])[1], vec![full(2, 0), full(200, 1)]);
+}
+
+#[test]
+fn preloaded_root_transitions() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 200u32, 3u32]).unwrap();
+    build.insert(&[300u32, 2u32]).unwrap();
+    build.insert(&[70_000u32, 2u32, 4u32]).unwrap();
+    build.insert(&[70_100u32]).unwrap();
+    let mut set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let full = |id: u32, edit_distance: u8| QueryWord::new_full(id, edit_distance);
+    let queries = vec![
+        vec![vec![full(1, 0), full(300, 1), full(70_100, 1)], vec![full(2, 0), full(200, 1)]],
+        vec![vec![full(1, 0), full(70_000, 1)], vec![full(200, 0), full(2, 0)], vec![full(3, 0), full(4, 0)]],
+        vec![vec![full(70_100, 0), full(70_000, 0)], vec![full(2, 0), full(5, 0)]],
+        vec![vec![full(2, 0)], vec![full(1, 0)]],
+    ];
+    let run = |set: &PhraseSet| queries.iter().map(|q| (
+        set.match_combinations(q, 2).unwrap(),
+        set.match_combinations_as_prefixes(q, 2).unwrap(),
+        set.match_combinations_as_windows(q, 2, true).unwrap(),
+        q.iter().map(|slot| set.lookup(&[slot[0]]).id()).collect::<Vec<_>>(),
+    )).collect::<Vec<_>>();
+    let expected = run(&set);
+    assert_eq!(expected.iter().map(|e| e.0.len()).collect::<Vec<_>>(), vec![2, 2, 0, 0]);
+
+    // prefixes from the top of the graph search the table for the ends of their ranges, and
+    // ones followed by more words take every word in between
+    let prefix = |range: (u32, u32)| QueryWord::new_prefix(range);
+    let prefix_queries = vec![
+        vec![vec![prefix((0, 250))]],
+        vec![vec![prefix((2, 70_050))]],
+        vec![vec![prefix((301, 69_999))]],
+        vec![vec![prefix((65_000, 80_000))]],
+        vec![vec![prefix((1, 70_000))], vec![full(2, 0)]],
+        vec![vec![prefix((255, 70_000))], vec![prefix((2, 300))]],
+    ];
+    let run_prefixes = |set: &PhraseSet| prefix_queries.iter().map(|q| (
+        set.match_combinations_as_prefixes(q, 2).unwrap(),
+        set.match_combinations_as_windows(q, 2, true).unwrap(),
+        set.lookup(&q[0]).range(),
+    )).collect::<Vec<_>>();
+    let expected_prefixes = run_prefixes(&set);
+    assert_eq!(expected_prefixes.iter().map(|e| e.0.len()).collect::<Vec<_>>(), vec![1, 1, 0, 1, 3, 2]);
+
+    for levels in &[1, 2, 3, 0] {
+        set.preload(*levels);
+        assert_eq!(set.preloaded_levels(), (*levels).min(2));
+        assert_eq!(run(&set), expected);
+        assert_eq!(run_prefixes(&set), expected_prefixes);
+    }
+    assert_eq!(set.lookup(&[full(70_100, 0)]).id(), Some(Output::new(4)));
+}
+
+#[test]
+fn first_word_bitset() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[63u32, 2u32, 3u32]).unwrap();
+    build.insert(&[64u32]).unwrap();
+    build.insert(&[70_000u32, 1u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let first_words = set.first_words();
+    for &id in &[1u32, 63, 64, 70_000] {
+        assert!(first_words.contains(id));
+    }
+    for &id in &[0u32, 2, 3, 65, 69_999, 70_001, 5_000_000] {
+        assert!(!first_words.contains(id));
+    }
+    assert!(first_words.contains_range((2, 63)));
+    assert!(first_words.contains_range((64, 64)));
+    assert!(first_words.contains_range((65, 100_000)));
+    assert!(!first_words.contains_range((2, 62)));
+    assert!(!first_words.contains_range((65, 69_999)));
+    assert!(!first_words.contains_range((70_001, u32::MAX)));
+    assert!(!first_words.contains_range((64, 1)));
+
+    assert!(!PhraseSet::from_bytes(PhraseSetBuilder::memory().into_inner().unwrap()).unwrap().first_words().contains(0));
+}
+
+#[test]
+fn trailing_prefix_pairs() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 10u32]).unwrap();
+    build.insert(&[1u32, 20u32, 5u32]).unwrap();
+    build.insert(&[2u32, 11u32]).unwrap();
+    build.insert(&[3u32, 10u32]).unwrap();
+    build.insert(&[300u32, 12u32]).unwrap();
+    build.insert(&[70_000u32, 11u32]).unwrap();
+    let bytes = build.into_inner().unwrap();
+    let set = PhraseSet::from_bytes(bytes.clone()).unwrap();
+
+    let ranges = |combinations: Vec<Combination>| combinations.iter()
+        .map(|c| (c.output_range.0.value(), c.output_range.1.value()))
+        .collect::<Vec<_>>();
+
+    // each word the first prefix could be gets a result of its own
+    let possibilities = vec![vec![QueryWord::new_prefix((1, 300))], vec![QueryWord::new_prefix((10, 11))]];
+    assert_eq!(ranges(set.match_combinations_as_prefixes(&possibilities, 0).unwrap()), vec![(0, 0), (2, 2), (3, 3)]);
+    let possibilities = vec![vec![QueryWord::new_prefix((1, 70_000))], vec![QueryWord::new_prefix((11, 20))]];
+    assert_eq!(ranges(set.match_combinations_as_prefixes(&possibilities, 0).unwrap()), vec![(1, 1), (2, 2), (4, 4), (5, 5)]);
+    assert_eq!(ranges(set.match_combinations_as_prefixes_limit(&possibilities, 0, 2).unwrap()), vec![(1, 1), (2, 2)]);
+    // the last word can be a whole one, too
+    let possibilities = vec![vec![QueryWord::new_prefix((1, 3))], vec![QueryWord::new_full(10, 0)]];
+    assert_eq!(ranges(set.match_combinations_as_prefixes(&possibilities, 0).unwrap()), vec![(0, 0), (3, 3)]);
+    let possibilities = vec![vec![QueryWord::new_prefix((4, 299))], vec![QueryWord::new_prefix((0, 100))]];
+    assert_eq!(set.match_combinations_as_prefixes(&possibilities, 0).unwrap(), vec![]);
+
+    let possibilities = vec![vec![QueryWord::new_prefix((1, 300))], vec![QueryWord::new_prefix((10, 11))]];
+    let windows = set.match_combinations_as_windows(&possibilities, 0, true).unwrap();
+    assert_eq!(windows.iter().map(|w| (w.output_range.0.value(), w.output_range.1.value())).collect::<Vec<_>>(), vec![(0, 0), (2, 2), (3, 3)]);
+    assert!(windows.iter().all(|w| w.ends_in_prefix && w.phrase.len() == 2));
+
+    // the same words are found below the preloaded table as through the root's transitions
+    let mut preloaded = PhraseSet::from_bytes(bytes).unwrap();
+    for levels in 1..3 {
+        preloaded.preload(levels);
+        let possibilities = vec![vec![QueryWord::new_prefix((1, 70_000))], vec![QueryWord::new_prefix((11, 20))]];
+        assert_eq!(ranges(preloaded.match_combinations_as_prefixes(&possibilities, 0).unwrap()), vec![(1, 1), (2, 2), (4, 4), (5, 5)]);
+    }
+
+    // a fuzzy prefix is charged its distance wherever it is
+    let possibilities = vec![vec![QueryWord::new_fuzzy_prefix((1, 3), 1)], vec![QueryWord::new_fuzzy_prefix((10, 11), 1)]];
+    assert_eq!(set.match_combinations_as_prefixes(&possibilities, 1).unwrap(), vec![]);
+    assert_eq!(ranges(set.match_combinations_as_prefixes(&possibilities, 2).unwrap()), vec![(0, 0), (2, 2), (3, 3)]);
+    assert_eq!(set.match_combinations_as_windows(&possibilities, 1, true).unwrap(), vec![]);
+    assert_eq!(set.match_combinations_as_windows(&possibilities, 2, true).unwrap().len(), 3);
+
+    // but a prefix can't come any earlier than that
+    let possibilities = vec![vec![QueryWord::new_prefix((1, 3))], vec![QueryWord::new_full(20, 0)], vec![QueryWord::new_full(5, 0)]];
+    assert!(set.match_combinations_as_prefixes(&possibilities, 0).is_err());
+    assert!(set.match_combinations_as_windows(&possibilities, 0, true).is_err());
+}
+
+#[test]
+fn has_prefix_match() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 10u32]).unwrap();
+    build.insert(&[1u32, 20u32, 5u32]).unwrap();
+    build.insert(&[2u32, 11u32]).unwrap();
+    build.insert(&[300u32, 12u32]).unwrap();
+    build.insert(&[70_000u32, 11u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let queries = vec![
+        vec![vec![QueryWord::new_prefix((1, 300))], vec![QueryWord::new_prefix((10, 11))]],
+        vec![vec![QueryWord::new_prefix((1, 70_000))], vec![QueryWord::new_prefix((12, 20))]],
+        vec![vec![QueryWord::new_prefix((4, 299))], vec![QueryWord::new_prefix((0, 100))]],
+        vec![vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_prefix((11, 19))]],
+        vec![vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_prefix((11, 20))]],
+        vec![vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_full(20, 0)]],
+        vec![vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_full(5, 0)]],
+        vec![vec![QueryWord::new_full(1, 0), QueryWord::new_full(2, 1)], vec![QueryWord::new_full(11, 0)]],
+        vec![vec![QueryWord::new_prefix((70_000, 80_000))]],
+        vec![vec![QueryWord::new_prefix((70_001, 80_000))]],
+    ];
+    for query in &queries {
+        for &max_phrase_dist in &[0u8, 1u8] {
+            let expected = !set.match_combinations_as_prefixes(query, max_phrase_dist).unwrap().is_empty();
+            assert_eq!(set.has_prefix_match(query, max_phrase_dist).unwrap(), expected, "{:?}", query);
+        }
+    }
+}
+
+#[test]
+fn reordered_matches() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 4u32]).unwrap();
+    build.insert(&[1u32, 3u32, 2u32]).unwrap();
+    build.insert(&[2u32, 4u32]).unwrap();
+    build.insert(&[3u32, 1u32, 2u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let full = |ids: &[u32]| -> Vec<Vec<QueryWord>> { ids.iter().map(|&id| vec![QueryWord::new_full(id, 0)]).collect() };
+
+    // "2 1 3" is a swap away from "1 2 3"
+    assert!(set.match_combinations_reordered(&full(&[2, 1, 3]), 0, 0).unwrap().is_empty());
+    let found = set.match_combinations_reordered(&full(&[2, 1, 3]), 0, 1).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].combination.phrase, vec![QueryWord::new_full(1, 0), QueryWord::new_full(2, 0), QueryWord::new_full(3, 0)]);
+    assert_eq!(found[0].slots, vec![1, 0, 2]);
+    assert_eq!(found[0].swaps, 1);
+
+    // reversing three words takes three swaps, and all of "3 2 1"'s orderings are phrases
+    let found = set.match_combinations_reordered(&full(&[3, 2, 1]), 0, 3).unwrap();
+    let mut swaps: Vec<(Vec<usize>, u8)> = found.iter().map(|c| (c.slots.clone(), c.swaps)).collect();
+    swaps.sort();
+    assert_eq!(swaps, vec![(vec![0, 2, 1], 1), (vec![2, 0, 1], 2), (vec![2, 1, 0], 3)]);
+
+    // a prefix stays at the end, and the rest can move around it
+    let query = vec![vec![QueryWord::new_full(2, 0)], vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_prefix((3, 4))]];
+    let found = set.match_combinations_as_prefixes_reordered(&query, 0, 2).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].slots, vec![1, 0, 2]);
+    assert_eq!(found[0].combination.output_range, (Output::new(0), Output::new(1)));
+    assert!(set.match_combinations_reordered(&query, 0, 2).is_err());
+
+    // against every ordering of the slots that's within the swap budget, matched as is
+    let queries = vec![
+        full(&[2, 1, 3]),
+        full(&[2, 3, 1]),
+        full(&[4, 2]),
+        vec![vec![QueryWord::new_full(3, 0), QueryWord::new_full(4, 1)], vec![QueryWord::new_full(2, 0)], vec![QueryWord::new_full(1, 0), QueryWord::new_full(3, 1)]],
+    ];
+    let orderings = |len: usize| -> Vec<Vec<usize>> {
+        match len {
+            2 => vec![vec![0, 1], vec![1, 0]],
+            _ => vec![vec![0, 1, 2], vec![0, 2, 1], vec![1, 0, 2], vec![1, 2, 0], vec![2, 0, 1], vec![2, 1, 0]],
+        }
+    };
+    for query in &queries {
+        for max_swaps in 0..4u8 {
+            for &max_phrase_dist in &[0u8, 1u8] {
+                let mut expected: Vec<(Vec<QueryWord>, Output)> = Vec::new();
+                for ordering in orderings(query.len()) {
+                    let inversions = (0..ordering.len()).flat_map(|i| (i + 1..ordering.len()).map(move |j| (i, j)))
+                        .filter(|&(i, j)| ordering[i] > ordering[j])
+                        .count();
+                    if inversions > max_swaps as usize {
+                        continue;
+                    }
+                    let reordered: Vec<Vec<QueryWord>> = ordering.iter().map(|&slot| query[slot].clone()).collect();
+                    for combination in set.match_combinations(&reordered, max_phrase_dist).unwrap() {
+                        expected.push((combination.phrase, combination.output_range.0));
+                    }
+                }
+                let mut found: Vec<(Vec<QueryWord>, Output)> = set.match_combinations_reordered(query, max_phrase_dist, max_swaps).unwrap()
+                    .into_iter()
+                    .map(|c| (c.combination.phrase, c.combination.output_range.0))
+                    .collect();
+                expected.sort_by_key(|&(_, output)| output.value());
+                found.sort_by_key(|&(_, output)| output.value());
+                assert_eq!(found, expected, "{:?} with {} swaps", query, max_swaps);
+            }
+        }
+    }
+}
+
+#[test]
+fn windows_with_gaps() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 4u32, 5u32]).unwrap();
+    build.insert(&[2u32, 3u32]).unwrap();
+    build.insert(&[6u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let slots = |ids: &[u32]| -> Vec<Vec<QueryWord>> {
+        // zero stands for a word that matched nothing
+        ids.iter().map(|&id| if id == 0 { vec![] } else { vec![QueryWord::new_full(id, 0)] }).collect()
+    };
+    let phrases = |windows: &[GappedWindow]| -> Vec<(Vec<QueryWord>, Vec<usize>)> {
+        windows.iter().map(|w| (w.window.phrase.clone(), w.skipped.clone())).collect()
+    };
+
+    // "1 2 x y 4 5", with the x and y in the way of "1 2 4 5"
+    let query = slots(&[1, 2, 6, 0, 4, 5]);
+    assert!(set.match_combinations_as_windows_with_gaps(&query, 2, false, 1, 1).unwrap().is_empty());
+    let found = set.match_combinations_as_windows_with_gaps(&query, 2, false, 2, 1).unwrap();
+    assert_eq!(phrases(&found), vec![(slots(&[1, 2, 4, 5]).into_iter().flatten().collect(), vec![2, 3])]);
+    assert_eq!(found[0].window.output_range, (Output::new(1), Output::new(1)));
+    // the skips are charged against the phrase distance
+    assert!(set.match_combinations_as_windows_with_gaps(&query, 1, false, 2, 1).unwrap().is_empty());
+    assert_eq!(set.match_combinations_as_windows_with_gaps(&query, 0, false, 2, 0).unwrap().len(), 1);
+
+    // a window doesn't start with a skip, and a prefix at the end still works after one
+    let query = vec![vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_full(6, 0)], vec![QueryWord::new_full(2, 0)], vec![QueryWord::new_prefix((3, 4))]];
+    let found = set.match_combinations_as_windows_with_gaps(&query, 1, true, 1, 1).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].skipped, vec![1]);
+    assert_eq!(found[0].window.output_range, (Output::new(0), Output::new(1)));
+    assert!(found[0].window.ends_in_prefix);
+    let found = set.match_combinations_as_windows_with_gaps(&query[1..], 1, true, 1, 1).unwrap();
+    assert_eq!(phrases(&found), vec![(vec![QueryWord::new_full(6, 0)], vec![])]);
+
+    // without skips, the same as plain windows
+    let queries = vec![
+        slots(&[1, 2, 3]),
+        slots(&[2, 3, 1]),
+        slots(&[1, 2, 4, 5, 6]),
+        vec![vec![QueryWord::new_full(1, 0), QueryWord::new_full(2, 1)], vec![QueryWord::new_full(2, 0), QueryWord::new_full(3, 1)], vec![QueryWord::new_prefix((3, 4))]],
+    ];
+    for query in &queries {
+        for &max_phrase_dist in &[0u8, 1u8] {
+            let windows = set.match_combinations_as_windows(query, max_phrase_dist, true).unwrap();
+            let gapped: Vec<CombinationWindow> = set.match_combinations_as_windows_with_gaps(query, max_phrase_dist, true, 0, 1).unwrap()
+                .into_iter()
+                .map(|w| w.window)
+                .collect();
+            assert_eq!(gapped, windows, "{:?}", query);
+        }
+    }
+}
+
+#[test]
+fn phrase_relationships() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 3u32, 4u32]).unwrap();
+    build.insert(&[1u32, 5u32]).unwrap();
+    build.insert(&[6u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let id = PhraseId;
+
+    assert_eq!(set.shared_prefix_len(id(0), id(2)), Some(2));
+    assert_eq!(set.shared_prefix_len(id(2), id(1)), Some(3));
+    assert_eq!(set.shared_prefix_len(id(1), id(3)), Some(1));
+    assert_eq!(set.shared_prefix_len(id(3), id(4)), Some(0));
+    assert_eq!(set.shared_prefix_len(id(2), id(2)), Some(4));
+
+    assert_eq!(set.is_prefix_of(id(0), id(2)), Some(true));
+    assert_eq!(set.is_prefix_of(id(1), id(2)), Some(true));
+    assert_eq!(set.is_prefix_of(id(2), id(1)), Some(false));
+    assert_eq!(set.is_prefix_of(id(0), id(3)), Some(false));
+    assert_eq!(set.is_prefix_of(id(0), id(0)), Some(false));
+    assert_eq!(set.is_extension_of(id(2), id(0)), Some(true));
+    assert_eq!(set.is_extension_of(id(0), id(2)), Some(false));
+
+    // IDs that aren't in the set
+    assert_eq!(set.shared_prefix_len(id(0), id(5)), None);
+    assert_eq!(set.is_prefix_of(id(5), id(0)), None);
+    assert_eq!(set.is_extension_of(id(0), id(5)), None);
+}
+
+#[test]
+fn phrase_set_to_dot() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 4u32]).unwrap();
+    build.insert(&[300u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let words: BTreeMap<u32, String> = [1u32, 2, 3, 4, 300].iter().map(|&id| (id, format!("w\"{}", id))).collect();
+    let label = |id: u32| words[&id].as_str();
+
+    let dot = set.to_dot(&[1], 1, &label).unwrap();
+    assert_eq!(dot, concat!(
+        "digraph phrases {\n",
+        "    node [shape=circle];\n",
+        "    n0 [label=\"w\\\"1\\n0..2\"];\n",
+        "    n1 [label=\"0..1\", shape=doublecircle];\n",
+        "    n0 -> n1 [label=\"w\\\"2 (2)\\n00 00 02\"];\n",
+        "    n2 [label=\"2\", shape=doublecircle];\n",
+        "    n0 -> n2 [label=\"w\\\"4 (4)\\n00 00 04\"];\n",
+        "}\n",
+    ));
+
+    // the whole graph, from the root
+    let dot = set.to_dot(&[], 3, &label).unwrap();
+    assert_eq!(dot.matches(" -> ").count(), 5);
+    assert_eq!(dot.matches("doublecircle").count(), 4);
+    assert!(dot.contains("(300)\\n00 01 2c"));
+    assert_eq!(set.to_dot(&[1, 3], 1, &label), None);
+}
+
+#[test]
+fn prefix_range_boundaries() {
+    // every set of words drawn from keys made of the bytes 1 and 3, queried with every range
+    // whose bounds are made of the bytes 0 to 4, so that each byte of each bound lands below,
+    // on, between and above the bytes actually in the graph
+    let keys = |bytes: &[u8]| {
+        let mut keys: Vec<WordKey> = Vec::new();
+        for b0 in bytes {
+            for b1 in bytes {
+                for b2 in bytes {
+                    keys.push([*b0, *b1, *b2]);
+                }
+            }
+        }
+        keys
+    };
+    let word_keys = keys(&[1, 3]);
+    let bounds = keys(&[0, 1, 2, 3, 4]);
+    let word_id = |key: &WordKey| u32::from_be_bytes([0, key[0], key[1], key[2]]);
+
+    for subset in 1u32..(1 << word_keys.len()) {
+        let words: Vec<WordKey> = word_keys.iter().enumerate()
+            .filter(|&(i, _)| subset & (1 << i) != 0)
+            .map(|(_, key)| *key)
+            .collect();
+
+        // the words are searched for after a first word, with phrases on either side of them
+        // so that neither the start output nor the phrase IDs are zero
+        let mut build = PhraseSetBuilder::memory();
+        build.insert(&[0u32, 0u32]).unwrap();
+        for key in &words {
+            build.insert(&[1u32, word_id(key)]).unwrap();
+        }
+        build.insert(&[2u32, 0u32]).unwrap();
+        let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+        let root = set.as_fst().root();
+        let (start, start_output) = set.follow_key(&root, &[0u8, 0u8, 1u8]).unwrap();
+
+        for min_key in &bounds {
+            for max_key in bounds.iter().filter(|max_key| *max_key >= min_key) {
+                let in_range: Vec<usize> = words.iter().enumerate()
+                    .filter(|&(_, key)| key >= min_key && key <= max_key)
+                    .map(|(i, _)| i + 1)
+                    .collect();
+                match set.matches_prefix_range(start.addr(), start_output, (*min_key, *max_key)) {
+                    WordPrefixMatchResult::NotFound => {
+                        assert!(in_range.is_empty(), "{:?}: {:?} to {:?} should have found {:?}", words, min_key, max_key, in_range);
+                    },
+                    WordPrefixMatchResult::Found(state) => {
+                        assert!(!in_range.is_empty(), "{:?}: {:?} to {:?} shouldn't have found anything", words, min_key, max_key);
+                        let (min_id, max_id) = PhraseSetMatchState::EndsInPrefix(state).prefix_range(set.as_fst());
+                        assert_eq!(
+                            (min_id.value() as usize, max_id.value() as usize),
+                            (in_range[0], in_range[in_range.len() - 1]),
+                            "{:?}: {:?} to {:?}", words, min_key, max_key
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn out_of_range_word_ids() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    assert!(build.insert(&[3u32, MAX_WORD_ID + 1]).is_err());
+    build.insert(&[3u32, MAX_WORD_ID]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    assert_eq!(set.get_max_id().value(), 1);
+    assert_eq!(set.get_by_id(Output::new(1)), Some(vec![3u32, MAX_WORD_ID]));
+}
+
+#[test]
+fn phrase_id_wrappers() {
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32]).unwrap();
+    build.insert(&[1u32, 3u32]).unwrap();
+    build.insert(&[4u32]).unwrap();
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    assert_eq!(set.max_phrase_id(), PhraseId(2));
+    assert_eq!(set.get_by_phrase_id(PhraseId(1)), Some(vec![1u32, 3u32]));
+    assert_eq!(set.lookup(&[QueryWord::new_full(4, 0)]).phrase_id(), Some(PhraseId(2)));
+    let range = set.lookup(&[QueryWord::new_full(1, 0)]).phrase_id_range().unwrap();
+    assert_eq!(range, PhraseIdRange::new(0, 1));
+    assert_eq!((range.len(), range.as_tuple()), (2, (0, 1)));
+    assert!(range.contains(PhraseId(1)) && !range.contains(PhraseId(2)));
+    assert_eq!(set.lookup(&[QueryWord::new_full(5, 0)]).phrase_id_range(), None);
+
+    let combinations = set.match_combinations_as_prefixes(&[vec![QueryWord::new_prefix((0, 3))]], 0).unwrap();
+    assert_eq!(combinations[0].phrase_id_range(), PhraseIdRange::from((0, 1)));
+}
+
+#[test]
+fn phrase_set_without_ids() {
+    let phrases: Vec<Vec<u32>> = (0..200u32).map(|i| vec![i / 10, 1000 + i]).collect();
+    let build = |store_ids: bool| {
+        let mut build = PhraseSetBuilder::memory();
+        build.set_store_ids(store_ids).unwrap();
+        for phrase in &phrases {
+            build.insert(phrase).unwrap();
+        }
+        assert!(build.set_store_ids(true).is_err());
+        build.into_inner().unwrap()
+    };
+    let (with_ids, without_ids) = (build(true), build(false));
+    assert!(without_ids.len() < with_ids.len());
+
+    let set = PhraseSet::from_bytes(without_ids).unwrap();
+    assert!(!set.has_ids());
+    assert!(PhraseSet::from_bytes(with_ids).unwrap().has_ids());
+
+    // membership and prefix checks still work, but there are no IDs to be had
+    let found = set.lookup(&[QueryWord::new_full(3, 0), QueryWord::new_full(1035, 0)]);
+    assert!(found.found_final());
+    assert_eq!(found.id(), None);
+    let prefix = set.lookup(&[QueryWord::new_full(3, 0)]);
+    assert!(prefix.found() && prefix.has_continuations());
+    assert_eq!(prefix.range(), None);
+    assert!(!set.lookup(&[QueryWord::new_full(3, 0), QueryWord::new_full(1045, 0)]).found());
+    assert_eq!(set.get_by_id(Output::new(0)), None);
+    let combinations = set.match_combinations(&[vec![QueryWord::new_full(19, 0)], vec![QueryWord::new_full(1199, 0)]], 0).unwrap();
+    assert_eq!(combinations.len(), 1);
+
+    // whether a set has IDs is stored in it, so it's known even when there's only one phrase
+    for &store_ids in &[true, false] {
+        let mut build = PhraseSetBuilder::memory();
+        build.set_store_ids(store_ids).unwrap();
+        build.insert(&[1u32]).unwrap();
+        let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+        assert_eq!(set.has_ids(), store_ids);
+        let expected = if store_ids { Some(Output::new(0)) } else { None };
+        assert_eq!(set.lookup(&[QueryWord::new_full(1, 0)]).id(), expected);
+    }
+    assert!(PhraseSet::from_bytes(PhraseSetBuilder::memory().into_inner().unwrap()).unwrap().has_ids());
+}
+
+#[test]
+fn tries_match_phrase_set() {
+    // a deterministic jumble of phrases of one to four words, over word IDs that differ in each of
+    // their three bytes, some of which are prefixes of others
+    let mut seed: u64 = 7;
+    let mut next = |n: u32| {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        ((seed >> 33) % u64::from(n)) as u32
+    };
+    let vocabulary: Vec<u32> = vec![1, 2, 3, 255, 256, 300, 65_535, 65_536, 70_000, 561_528];
+    let mut phrases: BTreeSet<Vec<u32>> = BTreeSet::new();
+    for _ in 0..400 {
+        let len = 1 + next(4) as usize;
+        phrases.insert((0..len).map(|_| vocabulary[next(vocabulary.len() as u32) as usize]).collect());
+    }
+
+    let mut build = PhraseSetBuilder::memory();
+    let mut louds_build = louds::LoudsTrieBuilder::new();
+    for phrase in &phrases {
+        build.insert(phrase).unwrap();
+        louds_build.insert(phrase).unwrap();
+    }
+    let set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let trie = louds_build.finish();
+    assert_eq!(trie.len(), phrases.len());
+    let word_trie = word_trie::WordTrie::from_phrase_set(&set).unwrap();
+    assert_eq!(word_trie.len(), phrases.len());
+    let word_trie = word_trie::WordTrie::from_bytes(&word_trie.to_bytes()).unwrap();
+    assert!(word_trie::WordTrie::from_bytes(&word_trie.to_bytes()[..40]).is_err());
+    assert_eq!(louds::LoudsTrie::from_phrase_set(&set).unwrap().to_bytes(), trie.to_bytes());
+
+    // and it has to survive a round trip
+    let trie = louds::LoudsTrie::from_bytes(&trie.to_bytes()).unwrap();
+    assert!(louds::LoudsTrie::from_bytes(&trie.to_bytes()[..40]).is_err());
+
+    let matchers: [&dyn PhraseMatcher; 2] = [&trie, &word_trie];
+    let word = |next: &mut dyn FnMut(u32) -> u32| QueryWord::new_full(vocabulary[next(vocabulary.len() as u32) as usize], 0);
+    for matcher in matchers.iter() {
+        for _ in 0..300 {
+            let len = 1 + next(4) as usize;
+            let full: Vec<Vec<QueryWord>> = (0..len).map(|_| {
+                let mut slot = vec![word(&mut next), word(&mut next)];
+                slot.push(QueryWord::new_full(vocabulary[next(vocabulary.len() as u32) as usize], 1));
+                slot
+            }).collect();
+            assert_eq!(matcher.match_combinations(&full, 1).unwrap(), set.match_combinations(&full, 1).unwrap());
+            assert_eq!(matcher.match_combinations_as_prefixes(&full, 1).unwrap(), set.match_combinations_as_prefixes(&full, 1).unwrap());
+            for ends_in_prefix in &[true, false] {
+                assert_eq!(
+                    matcher.match_combinations_as_windows(&full, 1, *ends_in_prefix).unwrap(),
+                    set.match_combinations_as_windows(&full, 1, *ends_in_prefix).unwrap()
+                );
+            }
+            let first: Vec<QueryWord> = full.iter().map(|slot| slot[0]).collect();
+            assert_eq!(matcher.phrase_id(&first), set.lookup(&first).phrase_id());
+            assert_eq!(matcher.prefix_range(&first), set.lookup(&first).phrase_id_range());
+
+            // and with one or two word prefixes at the end
+            let start = next(vocabulary.len() as u32) as usize;
+            let end = start + next((vocabulary.len() - start) as u32) as usize;
+            let mut prefixed = full.clone();
+            let prefix_slots = 1 + next(2) as usize;
+            for slot in prefixed.iter_mut().rev().take(prefix_slots) {
+                *slot = vec![QueryWord::new_prefix((vocabulary[start], vocabulary[end]))];
+            }
+            assert_eq!(matcher.match_combinations_as_prefixes(&prefixed, 1).unwrap(), set.match_combinations_as_prefixes(&prefixed, 1).unwrap());
+            assert_eq!(
+                matcher.match_combinations_as_windows(&prefixed, 1, true).unwrap(),
+                set.match_combinations_as_windows(&prefixed, 1, true).unwrap()
+            );
+            // prefixes are only an error if a full match gets as far as them
+            assert_eq!(matcher.match_combinations(&prefixed, 1).is_err(), set.match_combinations(&prefixed, 1).is_err());
+            let first: Vec<QueryWord> = prefixed.iter().map(|slot| slot[0]).collect();
+            assert_eq!(matcher.prefix_range(&first), set.lookup(&first).phrase_id_range());
+            assert_eq!(matcher.phrase_id(&first), None);
+
+            // any earlier than the last two slots, they're an error as soon as a match gets to them
+            if prefixed.len() > 2 {
+                let early = prefixed.len() - 3;
+                prefixed[early] = vec![QueryWord::new_prefix((vocabulary[start], vocabulary[end]))];
+                assert_eq!(matcher.match_combinations_as_prefixes(&prefixed, 1).is_err(), set.match_combinations_as_prefixes(&prefixed, 1).is_err());
+                assert_eq!(
+                    matcher.match_combinations_as_windows(&prefixed, 1, true).is_err(),
+                    set.match_combinations_as_windows(&prefixed, 1, true).is_err()
+                );
+            }
+        }
+    }
+
+    let mut louds_build = louds::LoudsTrieBuilder::new();
+    louds_build.insert(&[2, 1]).unwrap();
+    assert!(louds_build.insert(&[2, 1]).is_err());
+    assert!(louds_build.insert(&[1, 3]).is_err());
+    assert!(louds_build.insert(&[]).is_err());
+}
+
+#[test]
+fn merge_remapped_phrase_sets() {
+    use ::prefix::PrefixSet;
+
+    // "100 main street", "100 main ave"
+    let us_words = PrefixSet::from_iter(vec!["100", "ave", "main", "street"]).unwrap();
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[0u32, 2u32, 1u32]).unwrap();
+    build.insert(&[0u32, 2u32, 3u32]).unwrap();
+    let us_set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    // "100 main street", "12 rue main"
+    let fr_words = PrefixSet::from_iter(vec!["100", "12", "main", "rue", "street"]).unwrap();
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[0u32, 2u32, 4u32]).unwrap();
+    build.insert(&[1u32, 3u32, 2u32]).unwrap();
+    let fr_set = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let merge = PrefixSet::merge_vocabularies(&[&us_words, &fr_words]);
+    assert_eq!(merge.words, vec!["100", "12", "ave", "main", "rue", "street"]);
+
+    let mut build = PhraseSetBuilder::memory();
+    us_set.remap_word_ids(&merge.id_maps[0], &mut build).unwrap();
+    let remapped = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let mut stream = remapped.into_stream();
+    let mut phrases: Vec<Vec<u32>> = Vec::new();
+    while let Some((key, _)) = stream.next() {
+        phrases.push(util::key_to_word_ids(key).unwrap());
+    }
+    assert_eq!(phrases, vec![vec![0, 3, 2], vec![0, 3, 5]]);
+
+    let mut build = PhraseSetBuilder::memory();
+    PhraseSet::merge_remapped(&[(&us_set, &merge.id_maps[0]), (&fr_set, &merge.id_maps[1])], &mut build).unwrap();
+    let merged = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let mut stream = merged.into_stream();
+    let mut phrases: Vec<(Vec<u32>, u64)> = Vec::new();
+    while let Some((key, id)) = stream.next() {
+        phrases.push((util::key_to_word_ids(key).unwrap(), id.value()));
+    }
+    // "100 main street" is in both, but only shows up once
+    assert_eq!(phrases, vec![(vec![0, 3, 2], 0), (vec![0, 3, 5], 1), (vec![1, 4, 3], 2)]);
+
+    // maps that don't cover every word are rejected
+    let mut build = PhraseSetBuilder::memory();
+    assert!(fr_set.remap_word_ids(&[0, 1], &mut build).is_err());
+}
+
+#[test]
+fn layered_prefixes_from_every_layer() {
+    use self::layered::LayeredPhraseSet;
+
+    // "100 main street" in the base, "100 main blvd" added by the overlay
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    let base = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 4u32]).unwrap();
+    let overlay = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+    let mut layered = LayeredPhraseSet::new(base);
+    layered.push_layer(overlay);
+
+    // "100 main" leads to a phrase in each layer, and both are reachable
+    let one_hundred_main = [vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_full(2, 0)]];
+    let prefixes = layered.match_combinations_as_prefixes(&one_hundred_main, 0).unwrap();
+    assert_eq!(
+        prefixes.iter().map(|(i, c)| (*i, c.output_range)).collect::<Vec<_>>(),
+        vec![(1, (Output::new(0), Output::new(0))), (0, (Output::new(0), Output::new(0)))]
+    );
+    let one_hundred_ma = [vec![QueryWord::new_full(1, 0)], vec![QueryWord::new_prefix((2, 2))]];
+    let windows = layered.match_combinations_as_windows(&one_hundred_ma, 0, true).unwrap();
+    assert_eq!(windows.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 0]);
+}
+
+#[test]
+fn layered_phrase_set() {
+    use self::layered::LayeredPhraseSet;
+
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 3u32]).unwrap();
+    build.insert(&[1u32, 2u32, 4u32]).unwrap();
+    build.insert(&[5u32, 6u32]).unwrap();
+    let base = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let mut build = PhraseSetBuilder::memory();
+    build.insert(&[1u32, 2u32, 5u32]).unwrap();
+    build.insert(&[5u32, 6u32]).unwrap();
+    let overlay = PhraseSet::from_bytes(build.into_inner().unwrap()).unwrap();
+
+    let mut layered = LayeredPhraseSet::new(base);
+    let overlay_idx = layered.push_layer(overlay);
+    assert_eq!(overlay_idx, 1);
+    assert!(!layered.is_empty());
+
+    // [1, 2, 4] is phrase 1 in the base layer
+    layered.tombstone(0, vec![1u32]).unwrap();
+    assert!(layered.tombstone(2, vec![0u32]).is_err());
+
+    let full = |ids: &[u32]| ids.iter().map(|id| QueryWord::new_full(*id, 0)).collect::<Vec<_>>();
+
+    assert_eq!(layered.lookup(&full(&[1, 2, 3])), Some((0, Output::new(0))));
+    assert_eq!(layered.lookup(&full(&[1, 2, 4])), None);
+    assert_eq!(layered.lookup(&full(&[1, 2, 5])), Some((1, Output::new(0))));
+    // phrases present in more than one layer resolve to the topmost
+    assert_eq!(layered.lookup(&full(&[5, 6])), Some((1, Output::new(1))));
+    assert!(layered.contains_prefix(&full(&[1, 2])));
+
+    let word_possibilities = vec![
+        vec![QueryWord::new_full(1, 0)],
+        vec![QueryWord::new_full(2, 0)],
+        vec![QueryWord::new_full(3, 0), QueryWord::new_full(4, 0), QueryWord::new_full(5, 0)],
+    ];
+    let matches = layered.match_combinations(&word_possibilities, 0).unwrap();
+    let mut matched: Vec<(usize, Vec<QueryWord>)> = matches.into_iter().map(|(i, c)| (i, c.phrase)).collect();
+    matched.sort_by_key(|m| m.0);
+    assert_eq!(matched, vec![(0, full(&[1, 2, 3])), (1, full(&[1, 2, 5]))]);
+
+    let windows = layered.match_combinations_as_windows(&word_possibilities, 0, false).unwrap();
+    assert_eq!(windows.len(), 2);
+    assert!(windows.iter().all(|(_, w)| w.phrase != full(&[1, 2, 4])));
+
+    // [5, 6] is live in both layers: as a phrase it's only reported from the overlay, but as a
+    // window or prefix, from each, since their phrase ID ranges differ
+    let five_six = [vec![QueryWord::new_full(5, 0)], vec![QueryWord::new_full(6, 0)]];
+    let matches = layered.match_combinations(&five_six, 0).unwrap();
+    assert_eq!(matches.iter().map(|(i, c)| (*i, c.phrase.clone())).collect::<Vec<_>>(), vec![(1, full(&[5, 6]))]);
+    let windows = layered.match_combinations_as_windows(&five_six, 0, false).unwrap();
+    assert_eq!(windows.iter().map(|(i, w)| (*i, w.phrase.clone())).collect::<Vec<_>>(), vec![(1, full(&[5, 6])), (0, full(&[5, 6]))]);
+    let prefixes = layered.match_combinations_as_prefixes(&[vec![QueryWord::new_full(5, 0)]], 0).unwrap();
+    assert_eq!(prefixes.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 0]);
+
+    // tombstoning the rest of the base layer's [1, 2, ...] phrases masks the whole prefix there
+    layered.tombstone(0, vec![0u32]).unwrap();
+    let prefixes = layered.match_combinations_as_prefixes(&[vec![QueryWord::new_full(1, 0)]], 0).unwrap();
+    assert_eq!(prefixes.len(), 1);
+    assert_eq!(prefixes[0].0, 1);
+
+    // once every phrase in every layer is tombstoned, there's nothing left
+    layered.tombstone(0, vec![2u32]).unwrap();
+    layered.tombstone(1, vec![0u32, 1u32]).unwrap();
+    assert!(layered.is_empty());
+}
@@ -0,0 +1,424 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use fst_compat::raw::Output;
+
+use super::{Combination, CombinationWindow, MatchOutput, PhraseId, PhraseIdRange, WordKey, WordPath};
+use super::query::QueryWord;
+use super::util::PhraseSetError;
+
+// the navigation an in-memory phrase trie (`LoudsTrie`, `WordTrie`) has to provide for the
+// matchers below to run over it; nodes are numbered, with the root as 0, and each one knows the
+// range of phrase IDs below it, a terminal node's own phrase being the first of them
+pub(super) trait TrieGraph {
+    fn phrase_count(&self) -> usize;
+
+    // the node reached from `node` by the word with this ID and key, if any
+    fn follow_word(&self, node: u32, id: u32, key: &WordKey) -> Option<u32>;
+
+    // call `f` with every node reached from `node` by a word within the range, in order, stopping
+    // at the first error
+    fn for_each_word_in_range<F: FnMut(u32) -> Result<(), PhraseSetError>>(&self, node: u32, id_range: (u32, u32), key_range: (WordKey, WordKey), f: F) -> Result<(), PhraseSetError>;
+
+    // the range of phrases through any word within the range that continues from `node`
+    fn range_through(&self, node: u32, id_range: (u32, u32), key_range: (WordKey, WordKey)) -> Option<(u32, u32)>;
+
+    fn is_final(&self, node: u32) -> bool;
+
+    fn id_range(&self, node: u32) -> (u32, u32);
+}
+
+#[inline(always)]
+fn outputs(range: (u32, u32)) -> (Output, Output) {
+    (Output::new(u64::from(range.0)), Output::new(u64::from(range.1)))
+}
+
+#[inline(always)]
+fn final_output<G: TrieGraph>(graph: &G, node: u32) -> (Output, Output) {
+    let id = graph.id_range(node).0;
+    outputs((id, id))
+}
+
+pub(super) fn phrase_id<G: TrieGraph>(graph: &G, phrase: &[QueryWord]) -> Option<PhraseId> {
+    let mut node = 0;
+    for word in phrase {
+        match word {
+            QueryWord::Full { id, key, .. } => node = graph.follow_word(node, *id, key)?,
+            QueryWord::Prefix { .. } => return None,
+        }
+    }
+    if graph.is_final(node) {
+        Some(PhraseId(graph.id_range(node).0))
+    } else {
+        None
+    }
+}
+
+pub(super) fn prefix_range<G: TrieGraph>(graph: &G, phrase: &[QueryWord]) -> Option<PhraseIdRange> {
+    if graph.phrase_count() == 0 {
+        return None;
+    }
+    let mut node = 0;
+    for word in phrase {
+        match word {
+            QueryWord::Full { id, key, .. } => node = graph.follow_word(node, *id, key)?,
+            // as in `PhraseSet::lookup`, a prefix ends the lookup wherever it is
+            QueryWord::Prefix { id_range, key_range, .. } => {
+                return graph.range_through(node, *id_range, *key_range).map(|range| PhraseIdRange::new(range.0, range.1));
+            },
+        }
+    }
+    let range = graph.id_range(node);
+    Some(PhraseIdRange::new(range.0, range.1))
+}
+
+pub(super) fn match_combinations<G: TrieGraph>(graph: &G, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+    let mut out = Vec::new();
+    if !word_possibilities.is_empty() {
+        exact_recurse(graph, word_possibilities, 0, max_phrase_dist, &mut WordPath::new(), &mut out)?;
+    }
+    Ok(out)
+}
+
+pub(super) fn match_combinations_as_prefixes<G: TrieGraph>(graph: &G, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+    let mut out = Vec::new();
+    if !word_possibilities.is_empty() {
+        prefix_recurse(graph, word_possibilities, 0, max_phrase_dist, &mut WordPath::new(), &mut out)?;
+    }
+    Ok(out)
+}
+
+pub(super) fn match_combinations_as_windows<G: TrieGraph>(graph: &G, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+    let mut out = Vec::new();
+    if !word_possibilities.is_empty() {
+        window_recurse(graph, word_possibilities, 0, max_phrase_dist, ends_in_prefix, &mut WordPath::new(), &mut out)?;
+    }
+    Ok(out)
+}
+
+// these follow `PhraseSet`'s matchers of the same names (without filters, limits or
+// constraints), and produce the same results in the same order; the position in the query is
+// always the length of the path so far
+fn exact_recurse<G: TrieGraph, T: MatchOutput>(
+    graph: &G,
+    possibilities: &[Vec<QueryWord>],
+    node: u32,
+    budget_remaining: u8,
+    words_so_far: &mut WordPath,
+    out: &mut Vec<T>,
+) -> Result<(), PhraseSetError> {
+    let position = words_so_far.len();
+    for (idx, word) in possibilities[position].iter().enumerate() {
+        let (id, key, edit_distance) = match word {
+            QueryWord::Full { id, key, edit_distance, .. } => (*id, key, *edit_distance),
+            _ => return Err(PhraseSetError::new(
+                "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
+            )),
+        };
+        if edit_distance > budget_remaining {
+            break
+        }
+        if let Some(next) = graph.follow_word(node, id, key) {
+            words_so_far.push(idx as u32);
+            if position < possibilities.len() - 1 {
+                exact_recurse(graph, possibilities, next, budget_remaining - edit_distance, words_so_far, out)?;
+            } else if graph.is_final(next) {
+                out.push(T::from_path(possibilities, words_so_far, final_output(graph, next), false));
+            }
+            words_so_far.pop();
+        }
+    }
+    Ok(())
+}
+
+fn prefix_recurse<G: TrieGraph, T: MatchOutput>(
+    graph: &G,
+    possibilities: &[Vec<QueryWord>],
+    node: u32,
+    budget_remaining: u8,
+    words_so_far: &mut WordPath,
+    out: &mut Vec<T>,
+) -> Result<(), PhraseSetError> {
+    let position = words_so_far.len();
+    for (idx, word) in possibilities[position].iter().enumerate() {
+        match word {
+            QueryWord::Full { id, key, edit_distance, .. } => {
+                if *edit_distance > budget_remaining {
+                    break
+                }
+                if let Some(next) = graph.follow_word(node, *id, key) {
+                    words_so_far.push(idx as u32);
+                    if position < possibilities.len() - 1 {
+                        prefix_recurse(graph, possibilities, next, budget_remaining - edit_distance, words_so_far, out)?;
+                    } else {
+                        out.push(T::from_path(possibilities, words_so_far, outputs(graph.id_range(next)), true));
+                    }
+                    words_so_far.pop();
+                }
+            },
+            QueryWord::Prefix { id_range, key_range, edit_distance, .. } => {
+                if position + 2 < possibilities.len() {
+                    return Err(PhraseSetError::new(
+                        "Only the last two slots of a prefix query may have a QueryWord::Prefix"
+                    ))
+                } else if *edit_distance > budget_remaining {
+                    continue
+                } else if position + 2 == possibilities.len() {
+                    graph.for_each_word_in_range(node, *id_range, *key_range, |next| {
+                        words_so_far.push(idx as u32);
+                        prefix_recurse(graph, possibilities, next, budget_remaining - edit_distance, words_so_far, out)?;
+                        words_so_far.pop();
+                        Ok(())
+                    })?;
+                } else if let Some(range) = graph.range_through(node, *id_range, *key_range) {
+                    words_so_far.push(idx as u32);
+                    out.push(T::from_path(possibilities, words_so_far, outputs(range), true));
+                    words_so_far.pop();
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+fn window_recurse<G: TrieGraph, T: MatchOutput>(
+    graph: &G,
+    possibilities: &[Vec<QueryWord>],
+    node: u32,
+    budget_remaining: u8,
+    ends_in_prefix: bool,
+    words_so_far: &mut WordPath,
+    out: &mut Vec<T>,
+) -> Result<(), PhraseSetError> {
+    let position = words_so_far.len();
+    for (idx, word) in possibilities[position].iter().enumerate() {
+        match word {
+            QueryWord::Full { id, key, edit_distance, .. } => {
+                if *edit_distance > budget_remaining {
+                    break
+                }
+                if let Some(next) = graph.follow_word(node, *id, key) {
+                    words_so_far.push(idx as u32);
+                    if position < possibilities.len() - 1 {
+                        // a whole phrase before the end of the query
+                        if graph.is_final(next) {
+                            out.push(T::from_path(possibilities, words_so_far, final_output(graph, next), false));
+                        }
+                        window_recurse(graph, possibilities, next, budget_remaining - edit_distance, ends_in_prefix, words_so_far, out)?;
+                    } else if ends_in_prefix {
+                        out.push(T::from_path(possibilities, words_so_far, outputs(graph.id_range(next)), true));
+                    } else if graph.is_final(next) {
+                        out.push(T::from_path(possibilities, words_so_far, final_output(graph, next), false));
+                    }
+                    words_so_far.pop();
+                }
+            },
+            QueryWord::Prefix { id_range, key_range, edit_distance, .. } => {
+                if !ends_in_prefix {
+                    return Err(PhraseSetError::new(
+                        "The query submitted has a QueryWord::Prefix. This function only accepts QueryWord:Full"
+                    ))
+                }
+                if position + 2 < possibilities.len() {
+                    return Err(PhraseSetError::new(
+                        "Only the last two slots of a prefix query may have a QueryWord::Prefix"
+                    ))
+                } else if *edit_distance > budget_remaining {
+                    continue
+                } else if position + 2 == possibilities.len() {
+                    graph.for_each_word_in_range(node, *id_range, *key_range, |next| {
+                        words_so_far.push(idx as u32);
+                        window_recurse(graph, possibilities, next, budget_remaining - edit_distance, ends_in_prefix, words_so_far, out)?;
+                        words_so_far.pop();
+                        Ok(())
+                    })?;
+                } else if let Some(range) = graph.range_through(node, *id_range, *key_range) {
+                    words_so_far.push(idx as u32);
+                    out.push(T::from_path(possibilities, words_so_far, outputs(range), true));
+                    words_so_far.pop();
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+// a fixed-size bit vector with the number of ones before each 64-bit word, which is enough for
+// constant-time rank and logarithmic-time select
+#[derive(Debug, Clone)]
+pub(super) struct BitVector {
+    words: Vec<u64>,
+    len: usize,
+    ranks: Vec<u32>,
+}
+
+impl BitVector {
+    pub(super) fn from_bits(bits: &[bool]) -> BitVector {
+        let mut words = vec![0u64; bits.len().div_ceil(64)];
+        for (i, bit) in bits.iter().enumerate() {
+            if *bit {
+                words[i / 64] |= 1 << (i % 64);
+            }
+        }
+        BitVector::from_words(words, bits.len())
+    }
+
+    pub(super) fn from_words(words: Vec<u64>, len: usize) -> BitVector {
+        let mut ranks = Vec::with_capacity(words.len());
+        let mut ones = 0u32;
+        for word in &words {
+            ranks.push(ones);
+            ones += word.count_ones();
+        }
+        BitVector { words, len, ranks }
+    }
+
+    #[inline(always)]
+    pub(super) fn get(&self, i: usize) -> bool {
+        (self.words[i / 64] >> (i % 64)) & 1 == 1
+    }
+
+    // the number of ones before position i
+    #[inline(always)]
+    pub(super) fn rank1(&self, i: usize) -> usize {
+        if i == self.len && i.is_multiple_of(64) {
+            return self.ranks.last().map_or(0, |rank| *rank as usize + self.words[self.words.len() - 1].count_ones() as usize);
+        }
+        let mask = (1u64 << (i % 64)) - 1;
+        self.ranks[i / 64] as usize + (self.words[i / 64] & mask).count_ones() as usize
+    }
+
+    // the position of the kth (from zero) zero
+    #[inline(always)]
+    pub(super) fn select0(&self, k: usize) -> usize {
+        let zeros_before = |w: usize| w * 64 - self.ranks[w] as usize;
+        // find the last word that starts with at most k zeros before it
+        let (mut low, mut high) = (0, self.words.len());
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if zeros_before(mid) <= k {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        let mut zeros = !self.words[low];
+        for _ in 0..(k - zeros_before(low)) {
+            zeros &= zeros - 1;
+        }
+        low * 64 + zeros.trailing_zeros() as usize
+    }
+
+    pub(super) fn write<W: Write>(&self, wtr: &mut W) -> io::Result<()> {
+        for word in &self.words {
+            wtr.write_u64::<LittleEndian>(*word)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn read<R: Read>(rdr: &mut R, len: usize) -> io::Result<BitVector> {
+        let mut words = vec![0u64; len.div_ceil(64)];
+        rdr.read_u64_into::<LittleEndian>(&mut words)?;
+        Ok(BitVector::from_words(words, len))
+    }
+}
+
+// a node of the pointer-based trie the builders assemble before laying it out level by level
+struct BuildNode<L> {
+    children: Vec<(L, usize)>,
+    phrase_id: Option<u32>,
+}
+
+impl<L> BuildNode<L> {
+    fn new() -> BuildNode<L> {
+        BuildNode { children: Vec::new(), phrase_id: None }
+    }
+}
+
+// assembles a trie from phrases (as sequences of labels) inserted in lexicographic order, each
+// numbered by the order it was inserted in
+pub(super) struct TrieBuilder<L> {
+    nodes: Vec<BuildNode<L>>,
+    last: Vec<L>,
+    len: u32,
+}
+
+// a trie laid out breadth-first: node 0 is the root, and the children of each node come in order
+// right after those of the node before it, so the nth label leads to node n + 1
+pub(super) struct TrieLayout<L> {
+    pub(super) child_counts: Vec<u32>,
+    pub(super) labels: Vec<L>,
+    pub(super) terminals: Vec<bool>,
+    pub(super) first_ids: Vec<u32>,
+    pub(super) last_ids: Vec<u32>,
+    pub(super) len: usize,
+}
+
+impl<L: Copy + Ord> TrieBuilder<L> {
+    pub(super) fn new() -> TrieBuilder<L> {
+        TrieBuilder { nodes: vec![BuildNode::new()], last: Vec::new(), len: 0 }
+    }
+
+    pub(super) fn insert(&mut self, phrase: &[L]) -> Result<(), PhraseSetError> {
+        if phrase.is_empty() {
+            return Err(PhraseSetError::new("Empty phrases can't be inserted"));
+        }
+        if self.len > 0 && phrase <= &self.last[..] {
+            return Err(PhraseSetError::new("Phrases must be inserted in lexicographic order, without duplicates"));
+        }
+        let mut node = 0;
+        for label in phrase {
+            // since phrases arrive in order, a new child always goes after the existing ones
+            node = match self.nodes[node].children.last() {
+                Some(&(last_label, child)) if last_label == *label => child,
+                _ => {
+                    let child = self.nodes.len();
+                    self.nodes.push(BuildNode::new());
+                    self.nodes[node].children.push((*label, child));
+                    child
+                },
+            };
+        }
+        self.nodes[node].phrase_id = Some(self.len);
+        self.len += 1;
+        self.last = phrase.to_vec();
+        Ok(())
+    }
+
+    pub(super) fn finish(self) -> TrieLayout<L> {
+        // the ID ranges below each node; children come after their parents, so working
+        // backwards sees every child before its parent
+        let mut first_ids = vec![0u32; self.nodes.len()];
+        let mut last_ids = vec![0u32; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate().rev() {
+            let first_child = node.children.first().map(|&(_, child)| first_ids[child]);
+            let last_child = node.children.last().map(|&(_, child)| last_ids[child]);
+            first_ids[i] = node.phrase_id.or(first_child).unwrap_or(0);
+            last_ids[i] = last_child.or(node.phrase_id).unwrap_or(0);
+        }
+
+        let mut layout = TrieLayout {
+            child_counts: Vec::with_capacity(self.nodes.len()),
+            labels: Vec::with_capacity(self.nodes.len() - 1),
+            terminals: Vec::with_capacity(self.nodes.len()),
+            first_ids: Vec::with_capacity(self.nodes.len()),
+            last_ids: Vec::with_capacity(self.nodes.len()),
+            len: self.len as usize,
+        };
+        let mut order = vec![0];
+        let mut i = 0;
+        while i < order.len() {
+            let node = &self.nodes[order[i]];
+            for &(label, child) in &node.children {
+                layout.labels.push(label);
+                order.push(child);
+            }
+            layout.child_counts.push(node.children.len() as u32);
+            layout.terminals.push(node.phrase_id.is_some());
+            layout.first_ids.push(first_ids[order[i]]);
+            layout.last_ids.push(last_ids[order[i]]);
+            i += 1;
+        }
+        layout
+    }
+}
@@ -3,6 +3,7 @@ use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
 use std::fmt;
 use std::error;
 use super::WordKey;
+use super::query::MAX_WORD_ID;
 
 pub fn chop_int(num: u32) -> Vec<u8> {
     let mut wtr = vec![];
@@ -10,12 +11,34 @@ pub fn chop_int(num: u32) -> Vec<u8> {
     wtr
 }
 
-pub fn three_byte_encode(num: u32) -> WordKey {
-    debug_assert!(num < 16_777_216);
+/// Why a word ID or phrase key couldn't be converted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WordKeyError {
+    /// The word ID doesn't fit in three bytes (it's larger than `MAX_WORD_ID`).
+    WordIdTooLarge(u32),
+    /// The key's length (given) isn't a multiple of three, so it isn't made of whole words.
+    PartialWordKey(usize),
+}
+
+impl fmt::Display for WordKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WordKeyError::WordIdTooLarge(id) => write!(f, "Word ID {} is larger than the maximum of {}", id, MAX_WORD_ID),
+            WordKeyError::PartialWordKey(len) => write!(f, "Key of {} bytes isn't made of whole three-byte words", len),
+        }
+    }
+}
+
+impl error::Error for WordKeyError {}
+
+pub fn three_byte_encode(num: u32) -> Result<WordKey, WordKeyError> {
+    if num > MAX_WORD_ID {
+        return Err(WordKeyError::WordIdTooLarge(num));
+    }
     let chopped: Vec<u8> = chop_int(num);
     let mut three_bytes: WordKey = Default::default();
     three_bytes.copy_from_slice(&chopped[1..4]);
-    three_bytes
+    Ok(three_bytes)
 }
 
 // Do not call this function without either ensuring the vector is long enough, or updating to rm the unwrap call.
@@ -26,24 +49,20 @@ pub fn three_byte_decode(three_bytes: &[u8]) -> u32 {
     reader.read_u32::<BigEndian>().unwrap()
 }
 
-pub fn word_ids_to_key(phrase: &[u32]) -> Vec<u8> {
-    let mut phrase_key: Vec<u8> = Vec::new();
+pub fn word_ids_to_key(phrase: &[u32]) -> Result<Vec<u8>, WordKeyError> {
+    let mut phrase_key: Vec<u8> = Vec::with_capacity(phrase.len() * 3);
     for word_id in phrase {
-        let word_key: WordKey = three_byte_encode(*word_id);
+        let word_key: WordKey = three_byte_encode(*word_id)?;
         phrase_key.extend_from_slice(&word_key);
     }
-    phrase_key
+    Ok(phrase_key)
 }
 
-pub fn key_to_word_ids(key: &[u8]) -> Vec<u32> {
-    let mut phrase: Vec<u32> = vec![];
-    let mut i = 0;
-    while i < (key.len() - 2) {
-        let word = &key[i..i+3];
-        phrase.push(three_byte_decode(&word));
-        i += 3;
+pub fn key_to_word_ids(key: &[u8]) -> Result<Vec<u32>, WordKeyError> {
+    if !key.len().is_multiple_of(3) {
+        return Err(WordKeyError::PartialWordKey(key.len()));
     }
-    phrase
+    Ok(key.chunks(3).map(three_byte_decode).collect())
 }
 
 #[derive(Debug, Clone)]
@@ -109,7 +128,7 @@ mod tests {
     fn medium_integer_to_three_bytes() {
         // the number we're using is arbitrary.
         let n: u32 = 61_528;
-        let three_bytes: WordKey = three_byte_encode(n);
+        let three_bytes: WordKey = three_byte_encode(n).unwrap();
         assert_eq!(
             vec![ 0u8, 240u8, 88u8],
             three_bytes
@@ -121,7 +140,7 @@ mod tests {
         // the number we're using is arbitrary. happens to be the number of distinct words in
         // us-address, so gives us an idea of the cardinality we're dealing with.
         let n: u32 = 561_528;
-        let three_bytes: WordKey = three_byte_encode(n);
+        let three_bytes: WordKey = three_byte_encode(n).unwrap();
         assert_eq!(
             vec![ 8u8, 145u8, 120u8],
             three_bytes
@@ -129,11 +148,12 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn integer_is_to_large() {
-        // we should panic if we try to encode something larger than (2^24 - 1)
+        // we should fail if we try to encode something larger than (2^24 - 1)
         let n: u32 = 16_777_216;
-        three_byte_encode(n);
+        assert_eq!(three_byte_encode(n), Err(WordKeyError::WordIdTooLarge(n)));
+        assert_eq!(three_byte_encode(n - 1), Ok([255u8, 255u8, 255u8]));
+        assert_eq!(word_ids_to_key(&[1u32, n]), Err(WordKeyError::WordIdTooLarge(n)));
     }
 
     #[test]
@@ -149,7 +169,7 @@ mod tests {
     #[test]
     fn convert_word_ids_to_key() {
         let word_ids = [61_528_u32, 561_528u32, 1u32];
-        let key = word_ids_to_key(&word_ids);
+        let key = word_ids_to_key(&word_ids).unwrap();
         assert_eq!(
             vec![
                 0u8, 240u8, 88u8,    // 61_528
@@ -167,11 +187,13 @@ mod tests {
             8u8, 145u8, 120u8,   // 561_528
             0u8, 0u8,   1u8      // 1
         ];
-        let word_ids = key_to_word_ids(&key);
+        let word_ids = key_to_word_ids(&key).unwrap();
         assert_eq!(
             vec![61_528_u32, 561_528u32, 1u32],
             word_ids
         );
+        assert_eq!(key_to_word_ids(&[]), Ok(vec![]));
+        assert_eq!(key_to_word_ids(&key[..8]), Err(WordKeyError::PartialWordKey(8)));
     }
 
 }
@@ -0,0 +1,288 @@
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use fst_compat::Streamer;
+
+use super::{PhraseSet, Combination, CombinationWindow, PhraseId, PhraseIdRange, WordKey};
+use super::matcher::PhraseMatcher;
+use super::query::QueryWord;
+use super::trie::{self, BitVector, TrieBuilder, TrieGraph};
+use super::util::{PhraseSetError, key_to_word_ids};
+
+const MAGIC: &[u8; 8] = b"FPWTRIE1";
+
+/// An experimental phrase graph whose edges are whole words rather than the bytes of their
+/// three-byte keys: where a `PhraseSet` (or `LoudsTrie`) takes three transitions, and three
+/// nodes, to get through each word of a phrase, this takes one, found by binary search over the
+/// sorted word IDs of a node's children. A word prefix at the end of a query is a contiguous run
+/// of those children, so prefix ranges don't have to be pieced together byte by byte either.
+/// Serialized, each node's children are delta-coded as varints. Phrases are numbered as
+/// `PhraseSetBuilder` numbers them, so it gives the same results through `PhraseMatcher` as a
+/// `PhraseSet` with the same phrases; see the `phrase` benchmarks for how the two compare.
+#[derive(Debug, Clone)]
+pub struct WordTrie {
+    // for each node in breadth-first order, the index in `words` of its first child, plus one
+    // past the end; the edge at index e leads to node e + 1
+    offsets: Vec<u32>,
+    words: Vec<u32>,
+    terminals: BitVector,
+    first_ids: Vec<u32>,
+    last_ids: Vec<u32>,
+    len: usize,
+    build_id: u64,
+}
+
+impl WordTrie {
+    /// Build a trie with the same phrases (and phrase IDs) as `set`.
+    pub fn from_phrase_set(set: &PhraseSet) -> Result<WordTrie, PhraseSetError> {
+        let mut builder = WordTrieBuilder::new_with_build_id(set.build_id());
+        let mut stream = set.as_fst().stream();
+        while let Some((key, _)) = stream.next() {
+            let phrase = key_to_word_ids(key).map_err(|e| PhraseSetError::new(&e.to_string()))?;
+            builder.insert(&phrase)?;
+        }
+        Ok(builder.finish())
+    }
+
+    /// The number of phrases in the trie.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of nodes in the trie, counting the root.
+    pub fn node_count(&self) -> usize {
+        self.first_ids.len()
+    }
+
+    /// The build ID the trie was created with (see `PhraseSetBuilder::new_with_build_id`).
+    pub fn build_id(&self) -> u64 {
+        self.build_id
+    }
+
+    /// Serialize the trie, to be read back by `from_bytes` or `from_path`.
+    pub fn write<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        wtr.write_all(MAGIC)?;
+        wtr.write_u64::<LittleEndian>(self.build_id)?;
+        wtr.write_u64::<LittleEndian>(self.len as u64)?;
+        wtr.write_u64::<LittleEndian>(self.node_count() as u64)?;
+        for node in 0..self.node_count() {
+            let children = self.children(node as u32);
+            write_varint(&mut wtr, children.len() as u32)?;
+            let mut previous = 0;
+            for word in children {
+                write_varint(&mut wtr, word - previous)?;
+                previous = *word;
+            }
+        }
+        self.terminals.write(&mut wtr)?;
+        for id in self.first_ids.iter().chain(self.last_ids.iter()) {
+            wtr.write_u32::<LittleEndian>(*id)?;
+        }
+        wtr.flush()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes).expect("writing to a vector can't fail");
+        bytes
+    }
+
+    /// Read a trie serialized by `write`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<WordTrie> {
+        if bytes.len() < 32 || &bytes[..8] != MAGIC {
+            return Err(invalid("Not a word trie"));
+        }
+        let mut rdr = Cursor::new(&bytes[8..]);
+        let build_id = rdr.read_u64::<LittleEndian>()?;
+        let len = rdr.read_u64::<LittleEndian>()? as usize;
+        let node_count = rdr.read_u64::<LittleEndian>()? as usize;
+        // every node takes at least a byte, so a corrupt count can't make us allocate too much
+        if node_count == 0 || node_count > bytes.len() {
+            return Err(invalid("Word trie has an invalid node count"));
+        }
+        let mut offsets = Vec::with_capacity(node_count + 1);
+        let mut words = Vec::with_capacity(node_count - 1);
+        offsets.push(0);
+        for _ in 0..node_count {
+            let count = read_varint(&mut rdr)?;
+            if words.len() + count as usize >= node_count {
+                return Err(invalid("Word trie has an inconsistent shape"));
+            }
+            let mut previous = 0u32;
+            for i in 0..count {
+                let delta = read_varint(&mut rdr)?;
+                if i > 0 && delta == 0 {
+                    return Err(invalid("Word trie has unsorted children"));
+                }
+                previous = previous.checked_add(delta).ok_or_else(|| invalid("Word trie has an invalid word ID"))?;
+                words.push(previous);
+            }
+            offsets.push(words.len() as u32);
+        }
+        if words.len() != node_count - 1 {
+            return Err(invalid("Word trie has an inconsistent shape"));
+        }
+        let terminals = BitVector::read(&mut rdr, node_count)?;
+        let mut first_ids = vec![0u32; node_count];
+        rdr.read_u32_into::<LittleEndian>(&mut first_ids)?;
+        let mut last_ids = vec![0u32; node_count];
+        rdr.read_u32_into::<LittleEndian>(&mut last_ids)?;
+        if rdr.position() as usize != bytes.len() - 8 {
+            return Err(invalid("Word trie has trailing data"));
+        }
+        Ok(WordTrie { offsets, words, terminals, first_ids, last_ids, len, build_id })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<WordTrie> {
+        WordTrie::from_bytes(&fs::read(path)?)
+    }
+
+    #[inline(always)]
+    fn children(&self, node: u32) -> &[u32] {
+        &self.words[self.offsets[node as usize] as usize..self.offsets[node as usize + 1] as usize]
+    }
+
+    // the nodes reached from `node` by the words in `id_range`, as a range of node numbers
+    #[inline(always)]
+    fn child_range(&self, node: u32, id_range: (u32, u32)) -> (u32, u32) {
+        let children = self.children(node);
+        let start = children.partition_point(|word| *word < id_range.0);
+        let end = children.partition_point(|word| *word <= id_range.1).max(start);
+        let first = self.offsets[node as usize] + 1;
+        (first + start as u32, first + end as u32)
+    }
+}
+
+impl TrieGraph for WordTrie {
+    fn phrase_count(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    fn follow_word(&self, node: u32, id: u32, _key: &WordKey) -> Option<u32> {
+        self.children(node).binary_search(&id).ok().map(|i| self.offsets[node as usize] + 1 + i as u32)
+    }
+
+    fn for_each_word_in_range<F: FnMut(u32) -> Result<(), PhraseSetError>>(&self, node: u32, id_range: (u32, u32), _key_range: (WordKey, WordKey), f: F) -> Result<(), PhraseSetError> {
+        let (start, end) = self.child_range(node, id_range);
+        (start..end).try_for_each(f)
+    }
+
+    fn range_through(&self, node: u32, id_range: (u32, u32), _key_range: (WordKey, WordKey)) -> Option<(u32, u32)> {
+        let (start, end) = self.child_range(node, id_range);
+        if start == end {
+            None
+        } else {
+            Some((self.first_ids[start as usize], self.last_ids[end as usize - 1]))
+        }
+    }
+
+    #[inline(always)]
+    fn is_final(&self, node: u32) -> bool {
+        self.terminals.get(node as usize)
+    }
+
+    #[inline(always)]
+    fn id_range(&self, node: u32) -> (u32, u32) {
+        (self.first_ids[node as usize], self.last_ids[node as usize])
+    }
+}
+
+impl PhraseMatcher for WordTrie {
+    fn phrase_id(&self, phrase: &[QueryWord]) -> Option<PhraseId> {
+        trie::phrase_id(self, phrase)
+    }
+
+    fn prefix_range(&self, phrase: &[QueryWord]) -> Option<PhraseIdRange> {
+        trie::prefix_range(self, phrase)
+    }
+
+    fn match_combinations(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        trie::match_combinations(self, word_possibilities, max_phrase_dist)
+    }
+
+    fn match_combinations_as_prefixes(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8) -> Result<Vec<Combination>, PhraseSetError> {
+        trie::match_combinations_as_prefixes(self, word_possibilities, max_phrase_dist)
+    }
+
+    fn match_combinations_as_windows(&self, word_possibilities: &[Vec<QueryWord>], max_phrase_dist: u8, ends_in_prefix: bool) -> Result<Vec<CombinationWindow>, PhraseSetError> {
+        trie::match_combinations_as_windows(self, word_possibilities, max_phrase_dist, ends_in_prefix)
+    }
+}
+
+/// Builds a `WordTrie`. As with `PhraseSetBuilder`, phrases must be inserted in lexicographic
+/// order by word ID, and each is numbered by the order it was inserted in.
+pub struct WordTrieBuilder {
+    trie: TrieBuilder<u32>,
+    build_id: u64,
+}
+
+impl Default for WordTrieBuilder {
+    fn default() -> WordTrieBuilder {
+        WordTrieBuilder::new()
+    }
+}
+
+impl WordTrieBuilder {
+    pub fn new() -> WordTrieBuilder {
+        WordTrieBuilder::new_with_build_id(0)
+    }
+
+    pub fn new_with_build_id(build_id: u64) -> WordTrieBuilder {
+        WordTrieBuilder { trie: TrieBuilder::new(), build_id }
+    }
+
+    pub fn insert(&mut self, phrase: &[u32]) -> Result<(), PhraseSetError> {
+        self.trie.insert(phrase)
+    }
+
+    pub fn finish(self) -> WordTrie {
+        let layout = self.trie.finish();
+        let mut offsets = Vec::with_capacity(layout.child_counts.len() + 1);
+        offsets.push(0);
+        for count in &layout.child_counts {
+            let last = offsets[offsets.len() - 1];
+            offsets.push(last + count);
+        }
+        WordTrie {
+            offsets,
+            words: layout.labels,
+            terminals: BitVector::from_bits(&layout.terminals),
+            first_ids: layout.first_ids,
+            last_ids: layout.last_ids,
+            len: layout.len,
+            build_id: self.build_id,
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// LEB128: seven bits at a time, least significant first, with the high bit set on all but the last
+fn write_varint<W: Write>(wtr: &mut W, mut value: u32) -> io::Result<()> {
+    while value >= 0x80 {
+        wtr.write_u8((value as u8 & 0x7f) | 0x80)?;
+        value >>= 7;
+    }
+    wtr.write_u8(value as u8)
+}
+
+fn read_varint<R: Read>(rdr: &mut R) -> io::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = rdr.read_u8()?;
+        value |= u32::from(byte & 0x7f).checked_shl(shift).filter(|_| shift < 28 || byte < 0x10).ok_or_else(|| invalid("Varint is too large"))?;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("Varint is too large"))
+}
@@ -1,11 +1,10 @@
 use std::fmt;
 use std::io::prelude::*;
-#[cfg(feature = "mmap")]
 use std::path::Path;
-use fst::Streamer;
-use fst::raw;
-use fst::Error as FstError;
-use fst::automaton::{Automaton, AlwaysMatch};
+use fst_compat::Streamer;
+use fst_compat::raw;
+use fst_compat::Error as FstError;
+use fst_compat::{Automaton, AlwaysMatch};
 
 // pretty much everything in this file is copied from either upstream fst::Set or upstream
 // fst:Map, so it's quarantined in its own file to separate it from stuff we're actually building
@@ -15,13 +14,17 @@ pub struct PrefixSet(raw::Fst);
 
 impl PrefixSet {
     // these are lifted from upstream Set
-    #[cfg(feature = "mmap")]
     pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, FstError> {
-        raw::Fst::from_path(path).map(PrefixSet)
+        #[cfg(feature = "compression")]
+        return ::compress::fst_from_path(path).map(PrefixSet);
+        #[cfg(not(feature = "compression"))]
+        return ::fst_compat::from_path(path).map(PrefixSet);
     }
 
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, FstError> {
-        raw::Fst::from_bytes(bytes).map(PrefixSet)
+        #[cfg(feature = "compression")]
+        let bytes = ::compress::decompress_if_framed(bytes)?;
+        ::fst_compat::from_bytes(bytes).map(PrefixSet)
     }
 
     pub fn from_iter<T, I>(iter: I) -> Result<Self, FstError>
@@ -74,7 +77,14 @@ impl PrefixSetBuilder<Vec<u8>> {
 
 impl<W: Write> PrefixSetBuilder<W> {
     pub fn new(wtr: W) -> Result<PrefixSetBuilder<W>, FstError> {
-        Ok(PrefixSetBuilder { builder: raw::Builder::new_type(wtr, 0)?, count: 0 })
+        PrefixSetBuilder::new_with_build_id(wtr, 0)
+    }
+
+    /// As `new`, but the FST's type field holds `build_id` rather than 0 (see
+    /// `PrefixSet::build_id`). Word IDs are the prefix set's outputs, so everything that refers to
+    /// words by ID should carry the same build ID as the prefix set they came from.
+    pub fn new_with_build_id(wtr: W, build_id: u64) -> Result<PrefixSetBuilder<W>, FstError> {
+        Ok(PrefixSetBuilder { builder: raw::Builder::new_type(wtr, build_id)?, count: 0 })
     }
 
     pub fn insert<K: AsRef<[u8]>>(&mut self, key: K) -> Result<(), FstError> {
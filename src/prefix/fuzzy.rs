@@ -0,0 +1,123 @@
+use std::str;
+
+use fst_compat::raw;
+use super::{PrefixSet, PrefixSetLookupResult};
+
+/// A range of word IDs whose words all begin with something within some edit distance of a
+/// query prefix (see `PrefixSet::fuzzy_prefix_ranges`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FuzzyPrefixMatch {
+    pub id_range: (u64, u64),
+    /// The distance from the query to the shortest beginning the words share that's within
+    /// range; longer beginnings of some of them may be closer.
+    pub edit_distance: u8,
+}
+
+impl PrefixSet {
+    /// Find the words that begin with something within `max_distance` edits of `prefix`
+    /// (counting an adjacent transposition as one edit, like the `FuzzyMap` does), as ranges of
+    /// word IDs. The graph is walked character by character, tracking the edit distance from
+    /// `prefix` to the path so far, and abandoning branches once every alignment is over budget;
+    /// as soon as a path is itself within range, every word under it is, so its whole range is
+    /// reported and the walk doesn't go any further down that branch. The ranges are disjoint,
+    /// in ascending order, and include the one `lookup` would give for `prefix` itself, if any.
+    pub fn fuzzy_prefix_ranges(&self, prefix: &str, max_distance: u8) -> Vec<FuzzyPrefixMatch> {
+        if self.len() == 0 {
+            return Vec::new();
+        }
+        let query: Vec<char> = prefix.chars().collect();
+        let first_row: Vec<u32> = (0..(query.len() as u32 + 1)).collect();
+        let mut search = FuzzyPrefixSearch {
+            fst: self.as_fst(),
+            query,
+            max_distance: u32::from(max_distance),
+            rows: vec![first_row],
+            chars: Vec::new(),
+            pending: Vec::new(),
+            out: Vec::new(),
+        };
+        let root = search.fst.root();
+        if search.query.len() as u32 <= search.max_distance {
+            search.emit(root, raw::Output::zero(), search.query.len() as u32);
+        } else {
+            search.walk(root, raw::Output::zero());
+        }
+        search.out
+    }
+}
+
+struct FuzzyPrefixSearch<'a> {
+    fst: &'a raw::Fst,
+    query: Vec<char>,
+    max_distance: u32,
+    // the edit distances from each prefix of the query to the path so far, for every complete
+    // character of the path (plus the empty path); transpositions need the row before last too
+    rows: Vec<Vec<u32>>,
+    chars: Vec<char>,
+    // the bytes of a character that's only partly spelled out by the path so far
+    pending: Vec<u8>,
+    out: Vec<FuzzyPrefixMatch>,
+}
+
+impl<'a> FuzzyPrefixSearch<'a> {
+    fn walk(&mut self, node: raw::Node<'a>, output: raw::Output) {
+        for t in node.transitions() {
+            let next = self.fst.node(t.addr);
+            let next_output = output.cat(t.out);
+            self.pending.push(t.inp);
+            let c = match str::from_utf8(&self.pending) {
+                Ok(s) => s.chars().next(),
+                Err(_) => None,
+            };
+            match c {
+                Some(c) => {
+                    let pending = ::std::mem::take(&mut self.pending);
+                    let row = self.next_row(c);
+                    let distance = row[self.query.len()];
+                    let closest = *row.iter().min().expect("rows are never empty");
+                    self.rows.push(row);
+                    self.chars.push(c);
+                    if distance <= self.max_distance {
+                        self.emit(next, next_output, distance);
+                    } else if closest <= self.max_distance {
+                        self.walk(next, next_output);
+                    }
+                    self.rows.pop();
+                    self.chars.pop();
+                    self.pending = pending;
+                },
+                // keep going until we have a whole character, as long as we might
+                None if self.pending.len() < 4 => self.walk(next, next_output),
+                None => {},
+            }
+            self.pending.pop();
+        }
+    }
+
+    // the next row of the (optimal string alignment) edit distance table, for the path so far
+    // followed by `c`
+    fn next_row(&self, c: char) -> Vec<u32> {
+        let row = &self.rows[self.rows.len() - 1];
+        let mut next = Vec::with_capacity(row.len());
+        next.push(row[0] + 1);
+        for i in 1..row.len() {
+            let substitution = row[i - 1] + if self.query[i - 1] == c { 0 } else { 1 };
+            let mut distance = (row[i] + 1).min(next[i - 1] + 1).min(substitution);
+            if i > 1 && self.rows.len() > 1 {
+                let previous = self.chars[self.chars.len() - 1];
+                if self.query[i - 1] == previous && self.query[i - 2] == c {
+                    distance = distance.min(self.rows[self.rows.len() - 2][i - 2] + 1);
+                }
+            }
+            next.push(distance);
+        }
+        next
+    }
+
+    fn emit(&mut self, node: raw::Node<'a>, output: raw::Output, distance: u32) {
+        let lookup = PrefixSetLookupResult::Found { fst: self.fst, node, output_so_far: output };
+        if let Some((start, end)) = lookup.range() {
+            self.out.push(FuzzyPrefixMatch { id_range: (start.value(), end.value()), edit_distance: distance as u8 });
+        }
+    }
+}
@@ -1,9 +1,14 @@
-use fst::raw;
+use std::cmp::Ordering;
+use fst_compat::raw;
+use fst_compat::Streamer;
 
 mod boilerplate;
 pub use self::boilerplate::PrefixSet;
 pub use self::boilerplate::PrefixSetBuilder;
 
+mod fuzzy;
+pub use self::fuzzy::FuzzyPrefixMatch;
+
 #[cfg(test)] mod tests;
 
 impl PrefixSet {
@@ -64,6 +69,153 @@ impl PrefixSet {
             }
         }
     }
+
+    /// The range of IDs of the words `w` with `start <= w < end` in byte order (with no upper
+    /// bound if `end` is `None`), or `None` if there aren't any. Since IDs follow sort order,
+    /// they're contiguous. Unlike `lookup`, neither bound has to be a prefix of any word.
+    pub fn range_between(&self, start: &[u8], end: Option<&[u8]>) -> Option<(raw::Output, raw::Output)> {
+        let first = self.first_at_or_after(start)?;
+        let last = match end.map(|end| self.first_at_or_after(end)) {
+            Some(Some(after)) => after.value().checked_sub(1)?,
+            _ => (self.len() as u64).checked_sub(1)?,
+        };
+        if first.value() > last {
+            return None;
+        }
+        Some((first, raw::Output::new(last)))
+    }
+
+    // the ID of the first word that sorts at or after `key`, if any
+    fn first_at_or_after(&self, key: &[u8]) -> Option<raw::Output> {
+        let fst = &self.as_fst();
+        let mut node = fst.root();
+        let mut out = raw::Output::zero();
+        // the nearest subtree that sorts after the key, for if the key leaves the graph
+        let mut after: Option<(raw::Node, raw::Output)> = None;
+        for &b in key {
+            let mut next = None;
+            for t in node.transitions() {
+                if t.inp == b {
+                    next = Some(t);
+                } else if t.inp > b {
+                    after = Some((fst.node(t.addr), out.cat(t.out)));
+                    break;
+                }
+            }
+            match next {
+                Some(t) => {
+                    out = out.cat(t.out);
+                    node = fst.node(t.addr);
+                },
+                None => {
+                    let (node, out) = after?;
+                    return first_under(fst, node, out);
+                }
+            }
+        }
+        // everything under the key's own node sorts after it (or is it)
+        first_under(fst, node, out)
+    }
+
+    /// The build identifier this set was stamped with by `PrefixSetBuilder::new_with_build_id`
+    /// (or 0, if it wasn't).
+    pub fn build_id(&self) -> u64 {
+        self.as_fst().fst_type()
+    }
+
+    /// Compare this set's vocabulary against another's by streaming both in lockstep. Returns
+    /// the words (with their IDs) that are only in `other` (added) or only in this set
+    /// (removed), plus an (old ID, new ID) pair for every word in both whose ID changed.
+    pub fn diff(&self, other: &PrefixSet) -> PrefixSetDiff {
+        let mut diff = PrefixSetDiff { added: Vec::new(), removed: Vec::new(), remapped: Vec::new() };
+
+        let mut ours = self.as_fst().stream();
+        let mut theirs = other.as_fst().stream();
+        let mut a = ours.next().map(|(k, v)| (k.to_vec(), v.value() as u32));
+        let mut b = theirs.next().map(|(k, v)| (k.to_vec(), v.value() as u32));
+        loop {
+            let ord = match (&a, &b) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((x, _)), Some((y, _))) => x.cmp(y),
+            };
+            match ord {
+                Ordering::Less => {
+                    let (word, id) = a.take().unwrap();
+                    diff.removed.push((String::from_utf8_lossy(&word).into_owned(), id));
+                    a = ours.next().map(|(k, v)| (k.to_vec(), v.value() as u32));
+                },
+                Ordering::Greater => {
+                    let (word, id) = b.take().unwrap();
+                    diff.added.push((String::from_utf8_lossy(&word).into_owned(), id));
+                    b = theirs.next().map(|(k, v)| (k.to_vec(), v.value() as u32));
+                },
+                Ordering::Equal => {
+                    let old_id = a.as_ref().unwrap().1;
+                    let new_id = b.as_ref().unwrap().1;
+                    if old_id != new_id {
+                        diff.remapped.push((old_id, new_id));
+                    }
+                    a = ours.next().map(|(k, v)| (k.to_vec(), v.value() as u32));
+                    b = theirs.next().map(|(k, v)| (k.to_vec(), v.value() as u32));
+                },
+            }
+        }
+        diff
+    }
+
+    /// Merge the vocabularies of several sets (built separately, say, per country) by streaming
+    /// them together. Returns the sorted union of their words, which can be fed straight to a
+    /// `PrefixSetBuilder`, along with a map from each input set's word IDs to IDs in the merged
+    /// vocabulary (`id_maps[i][old_id] == new_id`). Since both old and new IDs follow sort
+    /// order, the maps are monotonic, so phrases can be rewritten without re-sorting them; see
+    /// `PhraseSet::merge_remapped`.
+    pub fn merge_vocabularies(sets: &[&PrefixSet]) -> VocabularyMerge {
+        let mut merge = VocabularyMerge {
+            words: Vec::new(),
+            id_maps: sets.iter().map(|set| vec![0; set.len()]).collect(),
+        };
+
+        let mut op = raw::OpBuilder::new();
+        for set in sets {
+            op.push(set.as_fst().stream());
+        }
+        let mut union = op.union();
+        while let Some((word, outputs)) = union.next() {
+            let new_id = merge.words.len() as u32;
+            for output in outputs {
+                merge.id_maps[output.index][output.value as usize] = new_id;
+            }
+            merge.words.push(String::from_utf8_lossy(word).into_owned());
+        }
+        merge
+    }
+}
+
+// the ID of the first word under `node`, reached with output `out`
+fn first_under<'a>(fst: &'a raw::Fst, mut node: raw::Node<'a>, mut out: raw::Output) -> Option<raw::Output> {
+    loop {
+        if node.is_final() {
+            return Some(out.cat(node.final_output()));
+        }
+        let t = node.transitions().next()?;
+        out = out.cat(t.out);
+        node = fst.node(t.addr);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VocabularyMerge {
+    pub words: Vec<String>,
+    pub id_maps: Vec<Vec<u32>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PrefixSetDiff {
+    pub added: Vec<(String, u32)>,
+    pub removed: Vec<(String, u32)>,
+    pub remapped: Vec<(u32, u32)>,
 }
 
 pub enum PrefixSetLookupResult<'a> {
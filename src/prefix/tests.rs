@@ -2,7 +2,7 @@ extern crate lazy_static;
 
 use std::collections::BTreeSet;
 use super::PrefixSet;
-use fst::raw;
+use fst_compat::raw;
 
 lazy_static! {
     static ref DATA: [&'static str; 4] = [
@@ -160,4 +160,97 @@ fn get_by_id() {
         SET.get_by_id(raw::Output::new(WORDS.len() as u64)).is_none(),
         "PrefixSet inverse lookup returns none on out of bounds lookup"
     );
-}
\ No newline at end of file
+}
+
+#[test]
+fn diff() {
+    let old_set = PrefixSet::from_iter(vec!["ave", "main", "street"]).unwrap();
+    let new_set = PrefixSet::from_iter(vec!["blvd", "main", "street"]).unwrap();
+
+    let diff = old_set.diff(&new_set);
+    assert_eq!(diff.added, vec![("blvd".to_string(), 0)]);
+    assert_eq!(diff.removed, vec![("ave".to_string(), 0)]);
+    // "main" and "street" keep their IDs because "blvd" took the place of "ave"
+    assert!(diff.remapped.is_empty());
+
+    let new_set = PrefixSet::from_iter(vec!["ave", "blvd", "main", "street"]).unwrap();
+    let diff = old_set.diff(&new_set);
+    assert_eq!(diff.added, vec![("blvd".to_string(), 1)]);
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.remapped, vec![(1, 2), (2, 3)]);
+}
+
+#[test]
+fn merge_vocabularies() {
+    let us = PrefixSet::from_iter(vec!["ave", "main", "street"]).unwrap();
+    let fr = PrefixSet::from_iter(vec!["avenue", "main", "rue"]).unwrap();
+
+    let merge = PrefixSet::merge_vocabularies(&[&us, &fr]);
+    assert_eq!(merge.words, vec!["ave", "avenue", "main", "rue", "street"]);
+    assert_eq!(merge.id_maps, vec![vec![0, 2, 4], vec![1, 2, 3]]);
+}
+
+#[test]
+fn range_between() {
+    // compare against the sorted word list, for bounds in and out of the set and between words
+    let bounds: Vec<Vec<u8>> = vec![
+        b"".to_vec(), b"a".to_vec(), b"ma".to_vec(), b"mb".to_vec(), b"zzzz".to_vec(), vec![0xff],
+        WORDS[0].as_bytes().to_vec(), WORDS[WORDS.len() / 2].as_bytes().to_vec(), WORDS[WORDS.len() - 1].as_bytes().to_vec(),
+        "\u{43c}".as_bytes().to_vec(), "\u{43c}\u{43e}".as_bytes().to_vec(), "\u{645}".as_bytes().to_vec(),
+    ];
+    for start in &bounds {
+        for end in bounds.iter().map(Some).chain(Some(None)) {
+            let expected = WORDS.iter().enumerate()
+                .filter(|&(_, w)| w.as_bytes() >= &start[..] && end.is_none_or(|end| w.as_bytes() < &end[..]))
+                .map(|(i, _)| i as u64)
+                .collect::<Vec<u64>>();
+            let expected = if expected.is_empty() { None } else { Some((expected[0], expected[expected.len() - 1])) };
+            let found = SET.range_between(start, end.map(|end| &end[..])).map(|(a, b)| (a.value(), b.value()));
+            assert_eq!(found, expected, "range_between {:?} and {:?}", start, end);
+        }
+    }
+    assert!(PrefixSet::from_iter(Vec::<&str>::new()).unwrap().range_between(b"", None).is_none());
+}
+
+#[test]
+fn fuzzy_prefix_ranges() {
+    extern crate strsim;
+
+    // a word is in range if any of its beginnings is close enough to the query
+    let closest_beginning = |query: &str, word: &str| {
+        let chars: Vec<char> = word.chars().collect();
+        (0..(chars.len() + 1))
+            .map(|end| strsim::osa_distance(query, &chars[..end].iter().collect::<String>()))
+            .min()
+            .unwrap()
+    };
+
+    let mut queries: Vec<String> = vec!["mian".to_string(), "stret".to_string(), "x".to_string(), "".to_string()];
+    for word in WORDS.iter().step_by(WORDS.len() / 10) {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() > 3 {
+            let mut typo: Vec<char> = chars[..(chars.len() - 1)].to_vec();
+            typo.swap(1, 2);
+            queries.push(typo.into_iter().collect());
+        }
+    }
+
+    for query in &queries {
+        for &max_distance in &[1u8, 2u8] {
+            let matches = SET.fuzzy_prefix_ranges(query, max_distance);
+            let mut found: Vec<u64> = Vec::new();
+            for m in &matches {
+                assert!(found.last().is_none_or(|&last| last < m.id_range.0), "ranges for {:?} aren't disjoint and ascending", query);
+                assert!(m.edit_distance <= max_distance);
+                found.extend(m.id_range.0..(m.id_range.1 + 1));
+            }
+            let expected: Vec<u64> = WORDS.iter().enumerate()
+                .filter(|&(_, word)| closest_beginning(query, word) <= max_distance as usize)
+                .map(|(id, _)| id as u64)
+                .collect();
+            assert_eq!(found, expected, "fuzzy prefix ranges for {:?} within {}", query, max_distance);
+        }
+    }
+
+    assert_eq!(SET.fuzzy_prefix_ranges("x", 1)[0].id_range.0, 0, "a short enough query matches everything");
+}
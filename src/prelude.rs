@@ -0,0 +1,23 @@
+//! The types most programs need, for importing all at once with `use fuzzy_phrase::prelude::*`.
+//!
+//! The glue layer (`FuzzyPhraseSet` and the types around it) doesn't expose anything from the
+//! `fst` crate, so code that only uses it keeps compiling when the version of `fst` used
+//! internally changes. The lower layers exported here do still expose some: `PhraseSet`,
+//! `PrefixSet` and `FuzzyMap` have `as_fst` accessors, return `fst` errors from building and
+//! loading, and give IDs as `fst` outputs: `PrefixSet`'s word IDs (from `get_by_id`,
+//! `range_between`, and its lookup results' `id` and `range`), and `PhraseSet`'s phrase IDs (from
+//! `get_max_id`, `get_by_id`, its lookup results' `id` and `range`, and the matchers'
+//! `output_range`s). Each of the phrase ID accessors has a counterpart that gives `PhraseId`s and
+//! `PhraseIdRange`s instead (`max_phrase_id`, `get_by_phrase_id`, `phrase_id`,
+//! `phrase_id_range`), which code that wants to be insulated from `fst` should use.
+
+pub use glue::{
+    FuzzyPhraseSet, FuzzyPhraseSetBuilder, FuzzyMatchResult, FuzzyWindowResult, EndingType,
+    WordReplacement, IndexOptions, QueryBuilder, TokenizerConfig, JoinRule,
+};
+pub use phrase::{PhraseSet, PhraseSetBuilder, PhraseId, PhraseIdRange};
+pub use phrase::query::{QueryWord, VariantSource};
+pub use phrase::filter::PhraseIdFilter;
+pub use prefix::{PrefixSet, PrefixSetBuilder};
+pub use fuzzy::{FuzzyMap, FuzzyMapBuilder};
+pub use progress::{BuildPhase, BuildProgress, ProgressReporter};
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The stage of an index build a progress report refers to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BuildPhase {
+    /// Generating the deletion variants of each word for the fuzzy map (`FuzzyMapBuilder::insert`);
+    /// items are words.
+    FuzzyVariants,
+    /// Sorting the fuzzy map's variants and writing them out (`FuzzyMapBuilder::finish`); items
+    /// are distinct variants.
+    FuzzyMap,
+    /// Writing out the phrase graph (`PhraseSetBuilder::insert`); items are phrases.
+    Phrases,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BuildProgress {
+    pub phase: BuildPhase,
+    pub items_processed: u64,
+    /// Bytes written so far by the structure being built, where it's known (0 otherwise).
+    pub bytes_written: u64,
+}
+
+/// Receives progress reports from the builders. Any `FnMut(&BuildProgress)` closure will do.
+pub trait ProgressReporter {
+    fn report(&mut self, progress: &BuildProgress);
+}
+
+impl<F: FnMut(&BuildProgress)> ProgressReporter for F {
+    fn report(&mut self, progress: &BuildProgress) {
+        self(progress)
+    }
+}
+
+/// A reporter plus how often to call it; it's shared so that a builder can hand it down to the
+/// builders for its components.
+#[derive(Clone)]
+pub(crate) struct ProgressTracker {
+    reporter: Arc<Mutex<dyn ProgressReporter + Send>>,
+    interval: u64,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new<R: ProgressReporter + Send + 'static>(reporter: R, interval: u64) -> ProgressTracker {
+        ProgressTracker { reporter: Arc::new(Mutex::new(reporter)), interval: interval.max(1) }
+    }
+
+    /// Report progress if `items_processed` has reached the next reporting interval.
+    #[inline(always)]
+    pub(crate) fn tick(&self, phase: BuildPhase, items_processed: u64, bytes_written: u64) {
+        if items_processed.is_multiple_of(self.interval) {
+            self.report(phase, items_processed, bytes_written);
+        }
+    }
+
+    /// Report progress unconditionally (e.g., at the end of a phase).
+    pub(crate) fn report(&self, phase: BuildPhase, items_processed: u64, bytes_written: u64) {
+        if let Ok(mut reporter) = self.reporter.lock() {
+            reporter.report(&BuildProgress { phase, items_processed, bytes_written });
+        }
+    }
+}
+
+impl fmt::Debug for ProgressTracker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressTracker {{ interval: {} }}", self.interval)
+    }
+}
+
+/// Fail with an `Interrupted` error if a build's cancellation flag has been set.
+#[inline(always)]
+pub(crate) fn check_cancelled(cancel: &Option<Arc<AtomicBool>>) -> Result<(), IoError> {
+    match *cancel {
+        Some(ref flag) if flag.load(Ordering::Relaxed) => Err(IoError::new(IoErrorKind::Interrupted, "Build cancelled")),
+        _ => Ok(()),
+    }
+}
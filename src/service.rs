@@ -0,0 +1,200 @@
+//! A minimal query executor for embedding a `FuzzyPhraseSet` in a server: a pool of worker
+//! threads (one per core by default) share the set, take queries off a channel in batches, and
+//! send each result back to whoever asked. Queries can carry a deadline, past which they're
+//! answered with an error instead of being run. It's deliberately small, and meant as much as a
+//! reference for doing this inside other executors as for use on its own.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use glue::{FuzzyPhraseSet, FuzzyMatchResult, EndingType};
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Query {
+    pub phrase: Vec<String>,
+    pub max_word_dist: u8,
+    pub max_phrase_dist: u8,
+    pub ending_type: EndingType,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum QueryError {
+    /// The query's deadline passed before a worker got to it.
+    DeadlineExceeded,
+    /// The set returned an error for the query.
+    Failed(String),
+    /// The service shut down before answering.
+    ShutDown,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryError::DeadlineExceeded => write!(f, "Query deadline exceeded"),
+            QueryError::Failed(ref message) => write!(f, "Query failed: {}", message),
+            QueryError::ShutDown => write!(f, "Query service shut down"),
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+pub type QueryResult = Result<Vec<FuzzyMatchResult>, QueryError>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceConfig {
+    /// How many worker threads to run.
+    pub threads: usize,
+    /// The most queries a worker takes off the queue at once. Identical queries in a batch are
+    /// only run once.
+    pub max_batch_size: usize,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> ServiceConfig {
+        ServiceConfig {
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            max_batch_size: 16,
+        }
+    }
+}
+
+struct Job {
+    query: Query,
+    deadline: Option<Instant>,
+    reply: Sender<QueryResult>,
+}
+
+/// A handle for a submitted query's result.
+pub struct PendingQuery(Receiver<QueryResult>);
+
+impl PendingQuery {
+    /// Block until the query has been answered.
+    pub fn wait(self) -> QueryResult {
+        self.0.recv().unwrap_or(Err(QueryError::ShutDown))
+    }
+}
+
+/// Runs queries against a shared set on a pool of worker threads. Dropping it stops taking new
+/// queries, finishes the queued ones, and waits for the workers to exit.
+pub struct QueryService {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl QueryService {
+    pub fn new(set: Arc<FuzzyPhraseSet>, config: ServiceConfig) -> QueryService {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let max_batch_size = config.max_batch_size.max(1);
+        let workers = (0..config.threads.max(1)).map(|_| {
+            let set = set.clone();
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                while let Some(batch) = next_batch(&receiver, max_batch_size) {
+                    run_batch(&set, batch);
+                }
+            })
+        }).collect();
+        QueryService { sender: Some(sender), workers }
+    }
+
+    /// Queue a query, to be answered unless `deadline` passes first.
+    pub fn submit(&self, query: Query, deadline: Option<Instant>) -> PendingQuery {
+        let (reply, receiver) = mpsc::channel();
+        if let Some(ref sender) = self.sender {
+            // if the workers are gone, the reply sender gets dropped along with the job, and
+            // waiting on the result reports that the service shut down
+            let _ = sender.send(Job { query, deadline, reply });
+        }
+        PendingQuery(receiver)
+    }
+
+    /// Queue a query and wait for its result.
+    pub fn query(&self, query: Query, deadline: Option<Instant>) -> QueryResult {
+        self.submit(query, deadline).wait()
+    }
+}
+
+impl Drop for QueryService {
+    fn drop(&mut self) {
+        // closing the channel lets the workers drain the queue and stop
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// wait for a job, then grab whatever else is already queued, up to the batch size; None once the
+// channel has been closed and drained
+fn next_batch(receiver: &Mutex<Receiver<Job>>, max_batch_size: usize) -> Option<Vec<Job>> {
+    let receiver = receiver.lock().ok()?;
+    let mut batch = vec![receiver.recv().ok()?];
+    while batch.len() < max_batch_size {
+        match receiver.try_recv() {
+            Ok(job) => batch.push(job),
+            Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn run_batch(set: &FuzzyPhraseSet, batch: Vec<Job>) {
+    let mut answered: HashMap<Query, QueryResult> = HashMap::new();
+    for job in batch {
+        if job.deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            let _ = job.reply.send(Err(QueryError::DeadlineExceeded));
+            continue;
+        }
+        let result = answered.entry(job.query.clone()).or_insert_with(|| {
+            let query = &job.query;
+            set.fuzzy_match(&query.phrase, query.max_word_dist, query.max_phrase_dist, query.ending_type)
+                .map_err(|e| QueryError::Failed(e.to_string()))
+        });
+        let _ = job.reply.send(result.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use std::time::Duration;
+
+    use super::*;
+    use glue::FuzzyPhraseSetBuilder;
+
+    fn query(phrase: &str, ending_type: EndingType) -> Query {
+        Query { phrase: phrase.split(' ').map(|w| w.to_string()).collect(), max_word_dist: 1, max_phrase_dist: 1, ending_type }
+    }
+
+    #[test]
+    fn service_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        FuzzyPhraseSetBuilder::build_from_raw(dir.path(), &["100 main street", "200 main street", "300 mlk blvd"]).unwrap();
+        let set = Arc::new(FuzzyPhraseSet::from_path(dir.path()).unwrap());
+        let service = QueryService::new(set.clone(), ServiceConfig { threads: 2, max_batch_size: 4 });
+
+        let queries = vec![
+            query("100 main stret", EndingType::NonPrefix),
+            query("300 mlk", EndingType::AnyPrefix),
+            query("100 main stret", EndingType::NonPrefix),
+            query("400 elm st", EndingType::NonPrefix),
+        ];
+        let pending: Vec<PendingQuery> = queries.iter().map(|q| service.submit(q.clone(), None)).collect();
+        for (q, pending) in queries.iter().zip(pending) {
+            assert_eq!(pending.wait().unwrap(), set.fuzzy_match(&q.phrase, 1, 1, q.ending_type).unwrap());
+        }
+
+        let expired = Instant::now() - Duration::from_millis(1);
+        assert_eq!(service.query(queries[0].clone(), Some(expired)), Err(QueryError::DeadlineExceeded));
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(service.query(queries[1].clone(), Some(later)).unwrap().len(), 1);
+    }
+}
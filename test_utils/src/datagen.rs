@@ -0,0 +1,169 @@
+//! Synthetic address-like corpora, for benchmarks and stress tests that need more (or more
+//! controllable) data than the small files checked in under `benches/data`, without having to
+//! download any.
+
+use std::collections::BTreeSet;
+
+use rand::{Rng, SeedableRng};
+use rand::prng::XorShiftRng;
+
+use super::damage_word_with;
+
+/// What to generate. Every corpus made from the same config is the same, so results from
+/// different runs (or different index builds) can be compared.
+#[derive(Debug, Clone)]
+pub struct CorpusConfig {
+    /// How many distinct phrases to generate.
+    pub phrase_count: usize,
+    /// How many distinct street names to draw from. Names are picked with Zipf-like frequencies,
+    /// the way a handful of names ("Main", "Oak") account for a large share of real streets.
+    pub street_names: usize,
+    /// House number shapes: each `#` becomes a digit and each `A` a capital letter, and anything
+    /// else is kept as is.
+    pub house_number_masks: Vec<String>,
+    /// Street suffixes, with their relative frequencies.
+    pub suffixes: Vec<(String, u32)>,
+    /// The share of phrases that get a directional ("N", "SW", etc.) before the street name.
+    pub directional_rate: f64,
+    /// The share of queries made by `damaged_queries` that get a typo in one of their words.
+    pub typo_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for CorpusConfig {
+    fn default() -> CorpusConfig {
+        let suffixes = [
+            ("St", 30), ("Ave", 20), ("Rd", 15), ("Dr", 10), ("Ln", 6), ("Blvd", 5), ("Ct", 5),
+            ("Way", 4), ("Pl", 3), ("Ter", 1), ("Pkwy", 1),
+        ];
+        CorpusConfig {
+            phrase_count: 10_000,
+            street_names: 1_000,
+            house_number_masks: ["#", "##", "###", "###", "####", "####", "#####", "##A", "###-#"]
+                .iter().map(|mask| mask.to_string()).collect(),
+            suffixes: suffixes.iter().map(|&(suffix, weight)| (suffix.to_string(), weight)).collect(),
+            directional_rate: 0.1,
+            typo_rate: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+const DIRECTIONALS: &[&str] = &["N", "S", "E", "W", "NE", "NW", "SE", "SW"];
+const ONSETS: &[&str] = &[
+    "b", "br", "c", "ch", "cl", "d", "f", "g", "gr", "h", "k", "l", "m", "n", "p", "r", "s",
+    "sh", "st", "t", "th", "v", "w", "", "",
+];
+const VOWELS: &[&str] = &["a", "e", "i", "o", "u", "a", "e", "o", "ea", "ie", "oo", "ay"];
+const CODAS: &[&str] = &["", "", "", "n", "r", "l", "s", "t", "ck", "nd", "ll", "rt", "wood", "ton", "field"];
+
+/// Generate `config.phrase_count` distinct address-like phrases ("1234 N Balmoren Ave"), in
+/// the order they were made up in.
+pub fn generate(config: &CorpusConfig) -> Vec<String> {
+    let mut rng = seeded_rng(config.seed);
+    let street_names = street_names(&mut rng, config.street_names);
+    // cumulative weights: 1/1, 1/2, 1/3... for street names, and as given for suffixes
+    let name_weights = cumulative((1..=street_names.len()).map(|rank| 1.0 / rank as f64));
+    let suffix_weights = cumulative(config.suffixes.iter().map(|&(_, weight)| f64::from(weight)));
+
+    let mut seen: BTreeSet<String> = BTreeSet::new();
+    let mut phrases: Vec<String> = Vec::with_capacity(config.phrase_count);
+    // there might not be enough distinct combinations, so don't try forever
+    let mut attempts = 0;
+    while phrases.len() < config.phrase_count && attempts < config.phrase_count * 100 {
+        attempts += 1;
+        let mut words: Vec<String> = Vec::with_capacity(4);
+        let mask = rng.choose(&config.house_number_masks).expect("no house number masks").clone();
+        words.push(house_number(&mut rng, &mask));
+        if rng.gen_bool(config.directional_rate) {
+            words.push(rng.choose(DIRECTIONALS).unwrap().to_string());
+        }
+        words.push(street_names[pick(&mut rng, &name_weights)].clone());
+        if !config.suffixes.is_empty() {
+            words.push(config.suffixes[pick(&mut rng, &suffix_weights)].0.clone());
+        }
+        let phrase = words.join(" ");
+        if seen.insert(phrase.clone()) {
+            phrases.push(phrase);
+        }
+    }
+    phrases
+}
+
+/// Make `count` queries from `phrases`, each paired with the index of the phrase it came from.
+/// A `config.typo_rate` share of them have a typo in one of their words (house numbers and
+/// words of two or fewer letters are left alone, since the fuzzy matcher leaves them alone too).
+pub fn damaged_queries(phrases: &[String], count: usize, config: &CorpusConfig) -> Vec<(String, usize)> {
+    let mut rng = seeded_rng(config.seed.wrapping_add(1));
+    (0..count).map(|_| {
+        let idx = rng.gen_range(0, phrases.len());
+        let mut words: Vec<String> = phrases[idx].split(' ').map(|word| word.to_string()).collect();
+        if rng.gen_bool(config.typo_rate) {
+            let eligible: Vec<usize> = (0..words.len())
+                .filter(|&i| words[i].chars().count() > 2 && words[i].chars().all(|c| c.is_alphabetic()))
+                .collect();
+            if let Some(&i) = rng.choose(&eligible) {
+                words[i] = damage_word_with(&words[i], &mut rng);
+            }
+        }
+        (words.join(" "), idx)
+    }).collect()
+}
+
+// a small, fast generator is plenty here; the seed's bytes are mixed with their positions so
+// that it's never all zeros, which XorShift can't start from
+fn seeded_rng(seed: u64) -> XorShiftRng {
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (seed >> ((i % 8) * 8)) as u8 ^ (i as u8 + 1);
+    }
+    XorShiftRng::from_seed(bytes)
+}
+
+fn street_names<R: Rng>(rng: &mut R, count: usize) -> Vec<String> {
+    let mut seen: BTreeSet<String> = BTreeSet::new();
+    let mut names: Vec<String> = Vec::with_capacity(count);
+    let mut attempts = 0;
+    while names.len() < count && attempts < count * 100 {
+        attempts += 1;
+        let syllables = rng.gen_range(1, 4);
+        let mut name = String::new();
+        for _ in 0..syllables {
+            name.push_str(rng.choose(ONSETS).unwrap());
+            name.push_str(rng.choose(VOWELS).unwrap());
+            name.push_str(rng.choose(CODAS).unwrap());
+        }
+        if name.chars().count() < 3 {
+            continue;
+        }
+        let mut chars = name.chars();
+        let name: String = chars.next().unwrap().to_uppercase().chain(chars).collect();
+        if seen.insert(name.clone()) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+fn house_number<R: Rng>(rng: &mut R, mask: &str) -> String {
+    let digits = b"0123456789";
+    let letters = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    mask.chars().enumerate().map(|(i, c)| match c {
+        // no leading zeros
+        '#' if i == 0 => *rng.choose(&digits[1..]).unwrap() as char,
+        '#' => *rng.choose(digits).unwrap() as char,
+        'A' => *rng.choose(letters).unwrap() as char,
+        c => c,
+    }).collect()
+}
+
+fn cumulative<I: Iterator<Item=f64>>(weights: I) -> Vec<f64> {
+    let mut total = 0.0;
+    weights.map(|weight| { total += weight; total }).collect()
+}
+
+// the index of a weighted random choice, given cumulative weights
+fn pick<R: Rng>(rng: &mut R, cumulative: &[f64]) -> usize {
+    let target = rng.gen::<f64>() * cumulative[cumulative.len() - 1];
+    cumulative.partition_point(|&total| total <= target).min(cumulative.len() - 1)
+}
@@ -10,6 +10,8 @@ use std::fs;
 use std::iter;
 use libflate::gzip::Decoder;
 
+pub mod datagen;
+
 static TMP: &'static str = "/tmp/fuzzy_phrase";
 
 pub fn ensure_data(data_type: &str, country: &str, language: &str, script: &str, sample: bool) -> PathBuf {
@@ -44,6 +46,12 @@ pub fn get_data(data_type: &str, country: &str, language: &str, script: &str, sa
 }
 
 pub fn damage_word(word: &str) -> String {
+    damage_word_with(word, &mut rand::thread_rng())
+}
+
+/// Like `damage_word`, but with the caller's random number generator, so that the damage can be
+/// reproduced.
+pub fn damage_word_with<R: Rng>(word: &str, rng: &mut R) -> String {
     let letters = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z'];
     enum EditType {
         Insert,
@@ -52,8 +60,6 @@ pub fn damage_word(word: &str) -> String {
         Transpose,
     }
 
-    let mut rng = rand::thread_rng();
-
     let indices: Vec<_> = word.char_indices().collect();
 
     let operations = if word.len() > 1 {